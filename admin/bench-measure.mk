@@ -22,3 +22,4 @@ measure: ./target/release/examples/bench
 	$^ handshake TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
 	$^ handshake-resume TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
 	$^ handshake-ticket TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+	$^ latency TLS13_AES_256_GCM_SHA384
@@ -243,6 +243,88 @@ fn bench_handshake(version: rustls::ProtocolVersion,
              f64::from(rounds) / server_time);
 }
 
+/// Write everything `sess` wants to send into a buffer.
+fn write_all_tls(sess: &mut Session) -> Vec<u8> {
+    let mut buf = Vec::new();
+    while sess.wants_write() {
+        sess.write_tls(&mut buf).unwrap();
+    }
+    buf
+}
+
+/// Give all of `buf` to `sess`.
+fn read_all_tls(sess: &mut Session, buf: &[u8]) {
+    let mut rd = buf;
+    while !rd.is_empty() {
+        sess.read_tls(&mut rd).unwrap();
+    }
+}
+
+/// The `pct`th percentile of `times`, which must be sorted.
+fn percentile(times: &[f64], pct: usize) -> f64 {
+    times[(times.len() - 1) * pct / 100]
+}
+
+/// Measure the latency of the first round trip of full TLS1.3
+/// handshakes, with both sides in this process: so these are CPU
+/// costs, without any network delay.
+///
+/// - server: from receiving the ClientHello to having written the
+///   server's flight, up to its Finished.
+/// - client: from writing the ClientHello to having processed the
+///   server's Finished.  This includes the server's time.
+fn bench_pipelined13_handshake(suite: &'static rustls::SupportedCipherSuite) {
+    let version = rustls::ProtocolVersion::TLSv1_3;
+    if !suite.usable_for_version(version) {
+        return;
+    }
+
+    let client_config =
+        Arc::new(make_client_config(version, suite, &ClientAuth::No, &Resumption::No));
+    let server_config = Arc::new(make_server_config(version, &ClientAuth::No, &Resumption::No));
+
+    let rounds = 512;
+    let mut client_times = Vec::new();
+    let mut server_times = Vec::new();
+
+    for _ in 0..rounds {
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut client = ClientSession::new(&client_config, dns_name);
+        let mut server = ServerSession::new(&server_config);
+
+        let client_start = Instant::now();
+        let client_hello = write_all_tls(&mut client);
+
+        let server_start = Instant::now();
+        read_all_tls(&mut server, &client_hello);
+        server.process_new_packets().unwrap();
+        let server_flight = write_all_tls(&mut server);
+        let server_end = Instant::now();
+
+        read_all_tls(&mut client, &server_flight);
+        client.process_new_packets().unwrap();
+        let client_end = Instant::now();
+
+        assert!(!client.is_handshaking());
+        server_times.push(duration_nanos(server_end.duration_since(server_start)) * 1e6);
+        client_times.push(duration_nanos(client_end.duration_since(client_start)) * 1e6);
+
+        do_handshake(&mut client, &mut server);
+    }
+
+    client_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    server_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    for &(side, times) in &[("client", &client_times), ("server", &server_times)] {
+        println!("latency\t{:?}\t{:?}\t{}\tmedian\t{:.0}\tp90\t{:.0}\tus",
+                 version,
+                 suite.suite,
+                 side,
+                 percentile(times, 50),
+                 percentile(times, 90));
+    }
+}
+
 fn do_handshake(client: &mut ClientSession, server: &mut ServerSession) {
     while server.is_handshaking() || client.is_handshaking() {
         transfer(client, server);
@@ -358,6 +440,15 @@ fn selected_tests(mut args: env::Args) {
             }
         }
 
+        "latency" => {
+            match args.next() {
+                Some(suite) => bench_pipelined13_handshake(lookup_suite(&suite)),
+                None => {
+                    panic!("latency needs ciphersuite argument");
+                }
+            }
+        }
+
         _ => {
             panic!("unsupported mode {:?}", mode);
         }
@@ -381,6 +472,12 @@ fn all_tests() {
             bench_handshake(*version, suite, ClientAuth::Yes, Resumption::Tickets);
         }
     }
+
+    for suite in &rustls::ALL_CIPHERSUITES {
+        if suite.sign != SignatureAlgorithm::ECDSA {
+            bench_pipelined13_handshake(suite);
+        }
+    }
 }
 
 fn main() {
@@ -0,0 +1,312 @@
+// This program checks a captured TLS handshake against rustls.
+//
+// It takes the bytes sent by the client and by the server, each in a
+// file of its own (for example, from Wireshark's "Follow TCP Stream",
+// showing one direction and saved as raw data; pcap files aren't read
+// directly).  It then:
+//
+// - decodes each side's records and handshake messages, up to the
+//   point where they are encrypted;
+// - gives the client's ClientHello to a rustls ServerSession, to see
+//   if we would accept it;
+// - checks the server chose a version and cipher suite the client
+//   offered; and
+// - for TLS1.2, where the server's certificates are sent in the clear,
+//   verifies them for the name the client sent with SNI.
+//
+// The rest of the handshake can't be checked: it depends on secrets
+// which aren't in the capture.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+extern crate rustls;
+use rustls::{ServerConfig, ServerSession, Session, NoClientAuth, RootCertStore};
+use rustls::{Certificate, ALL_CIPHERSUITES};
+use rustls::internal::pemfile;
+use rustls::internal::msgs::codec::Codec;
+use rustls::internal::msgs::deframer::MessageDeframer;
+use rustls::internal::msgs::hsjoiner::HandshakeJoiner;
+use rustls::internal::msgs::enums::ContentType;
+use rustls::internal::msgs::handshake::{HandshakePayload, ClientHelloPayload, ServerHelloPayload};
+use rustls::internal::msgs::handshake::ConvertServerNameList;
+use rustls::internal::msgs::message::{Message, MessagePayload};
+
+extern crate webpki;
+extern crate webpki_roots;
+
+const USAGE: &str = "
+Checks a captured TLS handshake against rustls.
+
+Usage:
+  replay [--roots CAFILE] [--certs CERTFILE --key KEYFILE] CLIENTBYTES SERVERBYTES
+
+CLIENTBYTES and SERVERBYTES hold the raw bytes sent by the client and
+server respectively.
+
+Options:
+  --roots CAFILE   Verify the server's certificates against the PEM
+                   certificates in CAFILE, rather than the Mozilla roots.
+  --certs CERTFILE Offer this PEM certificate chain when checking the
+                   ClientHello.  The default is the test-ca RSA chain.
+  --key KEYFILE    The RSA private key for --certs.
+";
+
+struct Args {
+    roots: Option<String>,
+    certs: String,
+    key: String,
+    client_bytes: String,
+    server_bytes: String,
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut roots = None;
+    let mut certs = "test-ca/rsa/end.fullchain".to_string();
+    let mut key = "test-ca/rsa/end.rsa".to_string();
+    let mut files = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--roots" => roots = Some(args.next().unwrap_or_else(|| usage())),
+            "--certs" => certs = args.next().unwrap_or_else(|| usage()),
+            "--key" => key = args.next().unwrap_or_else(|| usage()),
+            "--help" | "-h" => usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.len() != 2 {
+        usage();
+    }
+
+    Args {
+        roots,
+        certs,
+        key,
+        server_bytes: files.pop().unwrap(),
+        client_bytes: files.pop().unwrap(),
+    }
+}
+
+fn read_file(filename: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    fs::File::open(filename)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .unwrap_or_else(|err| {
+            eprintln!("cannot read {}: {}", filename, err);
+            process::exit(2);
+        });
+    bytes
+}
+
+/// Decode the plaintext handshake messages sent by one side, stopping
+/// when its messages are encrypted.
+fn decode_handshake(side: &str, bytes: &[u8]) -> Result<Vec<Message>, String> {
+    let mut deframer = MessageDeframer::new();
+    let mut rd = io::Cursor::new(bytes);
+    while (rd.position() as usize) < bytes.len() {
+        deframer.read(&mut rd)
+            .map_err(|err| format!("{}: cannot read records: {}", side, err))?;
+        if deframer.desynced {
+            return Err(format!("{}: corrupt record", side));
+        }
+    }
+    let mut joiner = HandshakeJoiner::new();
+    let mut messages = Vec::new();
+
+    for msg in deframer.frames.drain(..) {
+        match msg.typ {
+            ContentType::Handshake => {
+                joiner.take_message(msg)
+                    .ok_or_else(|| format!("{}: corrupt handshake message", side))?;
+            }
+            ContentType::ChangeCipherSpec | ContentType::ApplicationData => {
+                println!("{}: {:?} record, anything later is encrypted", side, msg.typ);
+                break;
+            }
+            ContentType::Alert => {
+                let mut msg = msg;
+                if !msg.decode_payload() {
+                    return Err(format!("{}: corrupt alert", side));
+                }
+                if let MessagePayload::Alert(ref alert) = msg.payload {
+                    println!("{}: alert {:?} {:?}", side, alert.level, alert.description);
+                }
+            }
+            typ => return Err(format!("{}: unexpected {:?} record", side, typ)),
+        }
+    }
+
+    if deframer.has_pending() {
+        println!("{}: ignoring incomplete record at end of capture", side);
+    }
+
+    for msg in joiner.frames.drain(..) {
+        if let MessagePayload::Handshake(ref hs) = msg.payload {
+            println!("{}: {:?}", side, hs.typ);
+        }
+        messages.push(msg);
+    }
+
+    if !joiner.is_empty() {
+        println!("{}: ignoring incomplete handshake message at end of capture", side);
+    }
+
+    Ok(messages)
+}
+
+fn find_client_hello(messages: &[Message]) -> Option<(&Message, &ClientHelloPayload)> {
+    messages.iter()
+        .filter_map(|msg| match msg.payload {
+            MessagePayload::Handshake(ref hs) => match hs.payload {
+                HandshakePayload::ClientHello(ref ch) => Some((msg, ch)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .next()
+}
+
+fn find_server_hello(messages: &[Message]) -> Option<&ServerHelloPayload> {
+    messages.iter()
+        .filter_map(|msg| match msg.payload {
+            MessagePayload::Handshake(ref hs) => match hs.payload {
+                HandshakePayload::ServerHello(ref sh) => Some(sh),
+                _ => None,
+            },
+            _ => None,
+        })
+        .next()
+}
+
+fn find_certificates(messages: &[Message]) -> Option<&Vec<Certificate>> {
+    messages.iter()
+        .filter_map(|msg| match msg.payload {
+            MessagePayload::Handshake(ref hs) => match hs.payload {
+                HandshakePayload::Certificate(ref certs) => Some(certs),
+                _ => None,
+            },
+            _ => None,
+        })
+        .next()
+}
+
+/// Would a rustls server, with all our cipher suites and versions,
+/// accept the ClientHello in `msg`?
+fn check_client_hello(args: &Args, msg: &Message) -> Result<(), String> {
+    let certs = pemfile::certs(&mut io::BufReader::new(&read_file(&args.certs)[..]))
+        .map_err(|_| format!("cannot parse {}", args.certs))?;
+    let key = pemfile::rsa_private_keys(&mut io::BufReader::new(&read_file(&args.key)[..]))
+        .ok()
+        .and_then(|mut keys| keys.pop())
+        .ok_or_else(|| format!("cannot parse {}", args.key))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.ciphersuites = ALL_CIPHERSUITES.to_vec();
+    config.set_single_cert(certs, key);
+    let mut server = ServerSession::new(&Arc::new(config));
+
+    let mut bytes = Vec::new();
+    msg.encode(&mut bytes);
+    server.read_tls(&mut &bytes[..])
+        .map_err(|err| format!("cannot give ClientHello to rustls: {}", err))?;
+    server.process_new_packets()
+        .map_err(|err| format!("rustls rejects the ClientHello: {}", err))?;
+
+    match server.get_negotiated_ciphersuite() {
+        Some(suite) => println!("rustls accepts the ClientHello, and would choose {:?}",
+                                suite.suite),
+        None => println!("rustls accepts the ClientHello"),
+    }
+    Ok(())
+}
+
+fn check_server_hello(client_hello: &ClientHelloPayload,
+                      server_hello: &ServerHelloPayload) -> Result<(), String> {
+    if !client_hello.cipher_suites.contains(&server_hello.cipher_suite) {
+        return Err(format!("server chose {:?}, which the client didn't offer",
+                           server_hello.cipher_suite));
+    }
+
+    let version = server_hello.get_supported_versions()
+        .unwrap_or(server_hello.legacy_version);
+    println!("server chose {:?} and {:?}", version, server_hello.cipher_suite);
+    Ok(())
+}
+
+fn check_server_certs(args: &Args,
+                      client_hello: &ClientHelloPayload,
+                      certs: &[Certificate]) -> Result<(), String> {
+    let mut roots = RootCertStore::empty();
+    match args.roots {
+        Some(ref filename) => {
+            roots.add_pem_file(&mut io::BufReader::new(&read_file(filename)[..]))
+                .map_err(|_| format!("cannot parse {}", filename))?;
+        }
+        None => roots.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+    }
+    let anchors = roots.roots.iter()
+        .map(|ta| ta.to_trust_anchor())
+        .collect::<Vec<_>>();
+
+    let name = client_hello.get_sni_extension()
+        .and_then(|sni| sni.get_hostname())
+        .map(|name| {
+            let name: &str = name.into();
+            name.to_string()
+        });
+
+    rustls::verify_certificate_chain(certs, &anchors, SystemTime::now(),
+                                     name.as_ref().map(|name| name.as_str()))
+        .map_err(|err| format!("server certificates don't verify: {}", err))?;
+    match name {
+        Some(name) => println!("server certificates are valid for {}", name),
+        None => println!("server certificates are valid (the client didn't send SNI)"),
+    }
+    Ok(())
+}
+
+fn replay(args: &Args) -> Result<(), String> {
+    let client_msgs = decode_handshake("client", &read_file(&args.client_bytes))?;
+    let server_msgs = decode_handshake("server", &read_file(&args.server_bytes))?;
+
+    let (client_hello_msg, client_hello) = find_client_hello(&client_msgs)
+        .ok_or_else(|| "client didn't send a ClientHello".to_string())?;
+    check_client_hello(args, client_hello_msg)?;
+
+    let server_hello = find_server_hello(&server_msgs)
+        .ok_or_else(|| "server didn't send a ServerHello".to_string())?;
+    check_server_hello(client_hello, server_hello)?;
+
+    match find_certificates(&server_msgs) {
+        Some(certs) => check_server_certs(args, client_hello, certs)?,
+        // Only TLS1.3 (including its drafts) uses supported_versions
+        // in the ServerHello.
+        None if server_hello.get_supported_versions().is_some() => {
+            println!("server certificates are encrypted, so not checked");
+        }
+        None => println!("server didn't send certificates"),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args();
+    if let Err(err) = replay(&args) {
+        println!("FAILED: {}", err);
+        process::exit(1);
+    }
+    println!("OK");
+}
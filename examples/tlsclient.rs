@@ -78,11 +78,16 @@ impl io::Read for TlsClient {
 
 impl TlsClient {
     fn new(sock: TcpStream, hostname: webpki::DNSNameRef, cfg: Arc<rustls::ClientConfig>) -> TlsClient {
+        let mut tls_session = rustls::ClientSession::new(&cfg, hostname);
+        // We write_all all of stdin into the session at once, so
+        // don't limit it.
+        tls_session.set_buffer_limit(0);
+
         TlsClient {
             socket: sock,
             closing: false,
             clean_closure: false,
-            tls_session: rustls::ClientSession::new(&cfg, hostname),
+            tls_session,
         }
     }
 
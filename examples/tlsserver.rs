@@ -67,7 +67,10 @@ impl TlsServer {
             Ok((socket, addr)) => {
                 debug!("Accepting new connection from {:?}", addr);
 
-                let tls_session = rustls::ServerSession::new(&self.tls_config);
+                let mut tls_session = rustls::ServerSession::new(&self.tls_config);
+                // We write_all whatever we read into the session, and
+                // don't stop reading when it's full, so don't limit it.
+                tls_session.set_buffer_limit(0);
                 let mode = self.mode.clone();
 
                 let token = mio::Token(self.next_id);
@@ -2,12 +2,39 @@
 #[macro_use] extern crate libfuzzer_sys;
 extern crate rustls;
 
-use rustls::{ServerConfig, ServerSession, Session};
+use rustls::{ServerConfig, ServerSession, Session, NoClientAuth};
+use rustls::internal::pemfile;
 use std::io;
 use std::sync::Arc;
 
+fn make_config() -> Arc<ServerConfig> {
+    let chain = pemfile::certs(&mut io::Cursor::new(&include_bytes!("../../test-ca/rsa/end.fullchain")[..]))
+        .unwrap();
+    let key = pemfile::rsa_private_keys(&mut io::Cursor::new(&include_bytes!("../../test-ca/rsa/end.rsa")[..]))
+        .unwrap()
+        .remove(0);
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(chain, key);
+    Arc::new(config)
+}
+
 fuzz_target!(|data: &[u8]| {
-    let config = Arc::new(ServerConfig::new());
-    let mut server= ServerSession::new(&config);
-    let _ = server.read_tls(&mut io::Cursor::new(data));
+    let config = make_config();
+    let mut server = ServerSession::new(&config);
+    let mut rd = io::Cursor::new(data);
+
+    loop {
+        match server.read_tls(&mut rd) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+
+        // Errors are fine; panics are not.
+        if server.process_new_packets().is_err() {
+            break;
+        }
+
+        let _ = server.write_tls(&mut io::sink());
+    }
 });
@@ -33,6 +33,10 @@ impl OwnedTrustAnchor {
             name_constraints: self.name_constraints.as_ref().map(|x| x.as_slice()),
         }
     }
+
+    pub(crate) fn subject(&self) -> &[u8] {
+        &self.subject
+    }
 }
 
 /// A container for root certificates able to provide a root-of-trust
@@ -4,7 +4,12 @@ use key;
 use client;
 
 use std::collections;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time;
 
 /// An implementor of `StoresClientSessions` which does nothing.
 pub struct NoClientSessionStorage {}
@@ -21,46 +26,277 @@ impl client::StoresClientSessions for NoClientSessionStorage {
 
 /// An implementor of `StoresClientSessions` that stores everything
 /// in memory.  It enforces a limit on the number of entries
-/// to bound memory usage.
+/// to bound memory usage, evicting the least recently used entry
+/// first.  Optionally, entries also expire a fixed time after
+/// they were stored.
 pub struct ClientSessionMemoryCache {
-    cache: Mutex<collections::HashMap<Vec<u8>, Vec<u8>>>,
+    cache: Mutex<CacheState>,
     max_entries: usize,
+    ttl: Option<time::Duration>,
+}
+
+struct CacheEntry {
+    value: Vec<u8>,
+    stored: time::Instant,
+    last_used: u64,
+}
+
+struct CacheState {
+    entries: collections::HashMap<Vec<u8>, CacheEntry>,
+    uses: u64,
+}
+
+impl CacheState {
+    fn next_use(&mut self) -> u64 {
+        self.uses += 1;
+        self.uses
+    }
 }
 
 impl ClientSessionMemoryCache {
     /// Make a new ClientSessionMemoryCache.  `size` is the
     /// maximum number of stored sessions.
     pub fn new(size: usize) -> Arc<ClientSessionMemoryCache> {
+        ClientSessionMemoryCache::new_inner(size, None)
+    }
+
+    /// Make a new ClientSessionMemoryCache which stores at most
+    /// `size` sessions, and forgets each session `ttl` after
+    /// it was stored.
+    pub fn new_with_ttl(size: usize, ttl: time::Duration) -> Arc<ClientSessionMemoryCache> {
+        ClientSessionMemoryCache::new_inner(size, Some(ttl))
+    }
+
+    fn new_inner(size: usize, ttl: Option<time::Duration>) -> Arc<ClientSessionMemoryCache> {
         debug_assert!(size > 0);
         Arc::new(ClientSessionMemoryCache {
-            cache: Mutex::new(collections::HashMap::new()),
+            cache: Mutex::new(CacheState {
+                entries: collections::HashMap::new(),
+                uses: 0,
+            }),
             max_entries: size,
+            ttl,
         })
     }
 
-    fn limit_size(&self) {
+    fn limit_size(&self, cache: &mut CacheState) {
+        while cache.entries.len() > self.max_entries {
+            let k = cache.entries.iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(k, _)| k.clone())
+                .unwrap();
+            cache.entries.remove(&k);
+        }
+    }
+
+    fn put_at(&self, key: Vec<u8>, value: Vec<u8>, now: time::Instant) -> bool {
         let mut cache = self.cache.lock().unwrap();
-        while cache.len() > self.max_entries {
-            let k = cache.keys().next().unwrap().clone();
-            cache.remove(&k);
+        let last_used = cache.next_use();
+        cache.entries.insert(key, CacheEntry {
+            value,
+            stored: now,
+            last_used,
+        });
+        self.limit_size(&mut cache);
+        true
+    }
+
+    fn get_at(&self, key: &[u8], now: time::Instant) -> Option<Vec<u8>> {
+        let mut cache = self.cache.lock().unwrap();
+        let expired = match (cache.entries.get(key), self.ttl) {
+            (None, _) => { return None; }
+            (Some(entry), Some(ttl)) => now.duration_since(entry.stored) >= ttl,
+            (Some(_), None) => false,
+        };
+
+        if expired {
+            cache.entries.remove(key);
+            return None;
         }
+
+        let last_used = cache.next_use();
+        cache.entries.get_mut(key)
+            .map(|entry| {
+                entry.last_used = last_used;
+                entry.value.clone()
+            })
     }
 }
 
 impl client::StoresClientSessions for ClientSessionMemoryCache {
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
-        self.cache.lock()
-            .unwrap()
-            .insert(key, value);
-        self.limit_size();
-        true
+        self.put_at(key, value, time::Instant::now())
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_at(key, time::Instant::now())
+    }
+}
+
+/// An implementor of `StoresClientSessions` which keeps sessions
+/// in a file, so they can be resumed after the program restarts.
+///
+/// The file has one session per line: the key and then the value,
+/// each hex-encoded, separated by a space.  Every `get` and `put`
+/// reads the file, and `put` rewrites it, so this suits programs
+/// which make few connections.  At most `max_entries` sessions are
+/// kept: when there are more, the least recently stored are dropped.
+///
+/// `put` writes a new file next to the old one and renames it into
+/// place, so the file is never left half-written.  Writers take a
+/// lock on a separate file, the path with `.lock` appended, so
+/// several processes can share it.
+///
+/// The file contains the secrets needed to resume each session.
+/// On unix it is created readable only by its owner.
+pub struct FileClientSessionStorage {
+    path: PathBuf,
+    max_entries: usize,
+}
+
+/// How many sessions `FileClientSessionStorage::open` keeps.
+const DEFAULT_FILE_MAX_ENTRIES: usize = 256;
+
+impl FileClientSessionStorage {
+    /// Use the file at `path`, creating it if it doesn't exist,
+    /// and keeping up to 256 sessions in it.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Arc<FileClientSessionStorage>> {
+        FileClientSessionStorage::with_max_entries(path, DEFAULT_FILE_MAX_ENTRIES)
+    }
+
+    /// Like `open`, but keep up to `max_entries` sessions.
+    /// `max_entries` must be at least one.
+    pub fn with_max_entries<P: AsRef<Path>>(path: P, max_entries: usize)
+                                            -> io::Result<Arc<FileClientSessionStorage>> {
+        if max_entries == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "max_entries must be at least one"));
+        }
+        let path = path.as_ref().to_path_buf();
+        FileClientSessionStorage::open_file(&with_suffix(&path, ".lock"))?;
+        FileClientSessionStorage::open_file(&path)?;
+        Ok(Arc::new(FileClientSessionStorage { path, max_entries }))
+    }
+
+    fn open_file(path: &Path) -> io::Result<fs::File> {
+        let mut options = fs::OpenOptions::new();
+        options.read(true)
+            .write(true)
+            .create(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)
+    }
+
+    fn read_entries(path: &Path) -> io::Result<String> {
+        let mut contents = String::new();
+        match fs::File::open(path) {
+            Ok(mut file) => { file.read_to_string(&mut contents)?; }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(contents)
+    }
+
+    fn try_put(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        let lock = FileClientSessionStorage::open_file(&with_suffix(&self.path, ".lock"))?;
+        lock.lock()?;
+
+        let key_hex = to_hex(key);
+        let entries = FileClientSessionStorage::read_entries(&self.path)?;
+        let mut lines = entries.lines()
+            .filter(|line| line.split(' ').next() != Some(key_hex.as_str()))
+            .collect::<Vec<_>>();
+        let excess = (lines.len() + 1).saturating_sub(self.max_entries);
+        lines.drain(..excess);
+
+        let mut contents = String::new();
+        for line in lines {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+        let _ = writeln!(contents, "{} {}", key_hex, to_hex(value));
+
+        let tmp_path = with_suffix(&self.path, ".tmp");
+        {
+            let mut tmp = FileClientSessionStorage::open_file(&tmp_path)?;
+            tmp.set_len(0)?;
+            tmp.write_all(contents.as_bytes())?;
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn try_get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        // No lock needed: `put` replaces the file in one rename.
+        let key_hex = to_hex(key);
+        let value = FileClientSessionStorage::read_entries(&self.path)?
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                match (fields.next(), fields.next()) {
+                    (Some(k), Some(v)) if k == key_hex => from_hex(v),
+                    _ => None,
+                }
+            })
+            .next();
+        Ok(value)
+    }
+}
+
+impl client::StoresClientSessions for FileClientSessionStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        match self.try_put(&key, &value) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Cannot store session in {:?}: {}", self.path, err);
+                false
+            }
+        }
     }
 
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.cache.lock()
-            .unwrap()
-            .get(key).cloned()
+        match self.try_get(key) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("Cannot read sessions from {:?}: {}", self.path, err);
+                None
+            }
+        }
+    }
+}
+
+/// `path`, with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(hex, "{:02x}", b);
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.as_bytes();
+    if hex.len() & 1 != 0 {
+        return None;
     }
+
+    hex.chunks(2)
+        .map(|pair| {
+            let hi = (pair[0] as char).to_digit(16)?;
+            let lo = (pair[1] as char).to_digit(16)?;
+            Some((hi << 4 | lo) as u8)
+        })
+        .collect()
 }
 
 pub struct FailResolveClientCert {}
@@ -162,4 +398,127 @@ mod test {
 
         assert_eq!(count, 4);
     }
+
+    #[test]
+    fn test_clientsessionmemorycache_evicts_least_recently_used() {
+        let c = ClientSessionMemoryCache::new(2);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.put(vec![0x05], vec![0x06]), true);
+
+        assert_eq!(c.get(&[0x03]), None);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.get(&[0x05]), Some(vec![0x06]));
+    }
+
+    #[test]
+    fn test_clientsessionmemorycache_expires_entries() {
+        let c = ClientSessionMemoryCache::new_with_ttl(4, time::Duration::from_secs(60));
+        let now = time::Instant::now();
+        assert_eq!(c.put_at(vec![0x01], vec![0x02], now), true);
+        assert_eq!(c.put_at(vec![0x03], vec![0x04], now + time::Duration::from_secs(30)), true);
+
+        let later = now + time::Duration::from_secs(59);
+        assert_eq!(c.get_at(&[0x01], later), Some(vec![0x02]));
+        assert_eq!(c.get_at(&[0x03], later), Some(vec![0x04]));
+
+        let later = now + time::Duration::from_secs(60);
+        assert_eq!(c.get_at(&[0x01], later), None);
+        assert_eq!(c.get_at(&[0x03], later), Some(vec![0x04]));
+
+        // expired entries stay gone
+        assert_eq!(c.get_at(&[0x01], now), None);
+    }
+
+    #[test]
+    fn test_clientsessionmemorycache_without_ttl_never_expires() {
+        let c = ClientSessionMemoryCache::new(4);
+        let now = time::Instant::now();
+        assert_eq!(c.put_at(vec![0x01], vec![0x02], now), true);
+        assert_eq!(c.get_at(&[0x01], now + time::Duration::from_secs(86400 * 365)),
+                   Some(vec![0x02]));
+    }
+
+    fn storage_path(name: &str) -> PathBuf {
+        ::std::env::temp_dir()
+            .join(format!("rustls-{}-{}", name, ::std::process::id()))
+    }
+
+    #[test]
+    fn test_fileclientsessionstorage_persists_put() {
+        let path = storage_path("client-sessions-persist");
+        let _ = fs::remove_file(&path);
+
+        let c = FileClientSessionStorage::open(&path).unwrap();
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03; 300], vec![0x04; 7000]), true);
+        assert_eq!(c.put(vec![], vec![0xab]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+
+        // a new instance, as after a restart, sees the same sessions
+        let c = FileClientSessionStorage::open(&path).unwrap();
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.get(&[0x03; 300]), Some(vec![0x04; 7000]));
+        assert_eq!(c.get(&[]), Some(vec![0xab]));
+        assert_eq!(c.get(&[0x02]), None);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(with_suffix(&path, ".lock")).unwrap();
+    }
+
+    #[test]
+    fn test_fileclientsessionstorage_overwrites_put() {
+        let path = storage_path("client-sessions-overwrite");
+        let _ = fs::remove_file(&path);
+
+        let c = FileClientSessionStorage::open(&path).unwrap();
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x05], vec![0x06]), true);
+        assert_eq!(c.put(vec![0x01], vec![0x04]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x04]));
+        assert_eq!(c.get(&[0x05]), Some(vec![0x06]));
+
+        let mut contents = String::new();
+        fs::File::open(&path).unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "05 06\n01 04\n");
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(with_suffix(&path, ".lock")).unwrap();
+    }
+
+    #[test]
+    fn test_fileclientsessionstorage_limits_entries() {
+        let path = storage_path("client-sessions-limit");
+        let _ = fs::remove_file(&path);
+
+        let err = FileClientSessionStorage::with_max_entries(&path, 0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+
+        let c = FileClientSessionStorage::with_max_entries(&path, 2).unwrap();
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        assert_eq!(c.put(vec![0x01], vec![0x05]), true);
+        assert_eq!(c.put(vec![0x06], vec![0x07]), true);
+        assert_eq!(c.get(&[0x03]), None);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x05]));
+        assert_eq!(c.get(&[0x06]), Some(vec![0x07]));
+
+        // Nothing is left behind but the file and its lock.
+        assert!(!with_suffix(&path, ".tmp").exists());
+        assert!(with_suffix(&path, ".lock").exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(with_suffix(&path, ".lock")).unwrap();
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x7f]), "00ab7f");
+        assert_eq!(from_hex("00aB7f"), Some(vec![0x00, 0xab, 0x7f]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("0g"), None);
+    }
 }
@@ -16,6 +16,7 @@ use msgs::handshake::DigitallySignedStruct;
 use msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer, HelloRetryRequest};
 use msgs::handshake::{CertificateStatusRequest, SCTList};
 use msgs::enums::{ClientCertificateType, PSKKeyExchangeMode, ECPointFormat};
+use msgs::enums::MaxFragmentLen;
 use msgs::codec::Codec;
 use msgs::persist;
 use msgs::ccs::ChangeCipherSpecPayload;
@@ -26,6 +27,7 @@ use cipher;
 use suites;
 use hash_hs;
 use verify;
+use key;
 use rand;
 use ticketer;
 use error::TLSError;
@@ -230,6 +232,28 @@ fn compatible_suite(sess: &ClientSessionImpl,
     }
 }
 
+/// Add a padding extension to `chp` so its encoding is at least
+/// `target` bytes.  The padding goes before any PSK offer, which
+/// must come last.
+fn pad_client_hello(chp: &mut HandshakeMessagePayload, target: usize) {
+    let current = chp.get_encoding().len();
+    if current >= target {
+        return;
+    }
+
+    // The extension header is four bytes, so if we're less than
+    // that short we'll overshoot a little.
+    let padding = (target - current).saturating_sub(4);
+
+    if let HandshakePayload::ClientHello(ref mut ch) = chp.payload {
+        let pos = match ch.extensions.last() {
+            Some(&ClientExtension::PresharedKey(_)) => ch.extensions.len() - 1,
+            _ => ch.extensions.len(),
+        };
+        ch.extensions.insert(pos, ClientExtension::Padding(padding));
+    }
+}
+
 fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
                                mut handshake: HandshakeDetails,
                                mut hello: ClientHelloDetails,
@@ -323,11 +347,15 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         exts.push(ClientExtension::PresharedKeyModes(psk_modes));
     }
 
-    if !sess.config.alpn_protocols.is_empty() {
-        exts.push(ClientExtension::Protocols(ProtocolNameList::from_strings(&sess.config
+    if !sess.alpn_protocols.is_empty() {
+        exts.push(ClientExtension::Protocols(ProtocolNameList::from_strings(&sess
             .alpn_protocols)));
     }
 
+    if let Some(mfl) = sess.config.max_fragment_length {
+        exts.push(ClientExtension::MaxFragmentLength(mfl));
+    }
+
 
     let fill_in_binder = if support_tls13 && sess.config.enable_tickets &&
                             resume_version == ProtocolVersion::TLSv1_3 &&
@@ -388,6 +416,10 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         }),
     };
 
+    if let Some(target) = sess.config.client_hello_size {
+        pad_client_hello(&mut chp, target);
+    }
+
     if fill_in_binder {
         fill_in_psk_binder(sess, &mut handshake, &mut chp);
     }
@@ -462,13 +494,32 @@ fn process_alpn_protocol(sess: &mut ClientSessionImpl,
                          -> Result<(), TLSError> {
     sess.alpn_protocol = proto.map(|s| s.to_owned());
     if sess.alpn_protocol.is_some() &&
-        !sess.config.alpn_protocols.contains(sess.alpn_protocol.as_ref().unwrap()) {
+        !sess.alpn_protocols.contains(sess.alpn_protocol.as_ref().unwrap()) {
         return Err(illegal_param(sess, "server sent non-offered ALPN protocol"));
     }
     debug!("ALPN protocol is {:?}", sess.alpn_protocol);
     Ok(())
 }
 
+fn process_max_fragment_length(sess: &mut ClientSessionImpl,
+                               mfl: Option<MaxFragmentLen>)
+                               -> Result<(), TLSError> {
+    let mfl = match mfl {
+        Some(mfl) => mfl,
+        None => return Ok(()),
+    };
+
+    if Some(mfl) != sess.config.max_fragment_length {
+        return Err(illegal_param(sess, "server sent different max_fragment_length"));
+    }
+
+    if let Some(len) = mfl.fragment_len() {
+        debug!("Max fragment length is {}", len);
+        sess.common.message_fragmenter.limit_fragment_len(len);
+    }
+    Ok(())
+}
+
 impl ExpectServerHello {
     fn start_handshake_traffic(&mut self,
                                sess: &mut ClientSessionImpl,
@@ -629,6 +680,7 @@ impl State for ExpectServerHello {
         // Extract ALPN protocol
         if !sess.common.is_tls13() {
             process_alpn_protocol(sess, server_hello.get_alpn_protocol())?;
+            process_max_fragment_length(sess, server_hello.get_max_fragment_length())?;
         }
 
         // If ECPointFormats extension is supplied by the server, it must contain
@@ -919,6 +971,7 @@ impl State for ExpectTLS13EncryptedExtensions {
 
         validate_encrypted_extensions(sess, &self.hello, exts)?;
         process_alpn_protocol(sess, exts.get_alpn_protocol())?;
+        process_max_fragment_length(sess, exts.get_max_fragment_length())?;
 
         if self.handshake.resuming_session.is_some() {
             let certv = verify::ServerCertVerified::assertion();
@@ -1215,6 +1268,29 @@ impl ExpectTLS13CertificateVerify {
     }
 }
 
+/// Verify the server's certificate chain with the configured verifier,
+/// checking it is valid for `dns_name` unless domain fronting is allowed.
+fn verify_server_cert(sess: &ClientSessionImpl,
+                      cert_chain: &[key::Certificate],
+                      dns_name: webpki::DNSNameRef,
+                      ocsp_response: &[u8]) -> Result<verify::ServerCertVerified, TLSError> {
+    let verifier = sess.config.get_verifier();
+
+    #[cfg(feature = "dangerous_configuration")]
+    {
+        if sess.config.allow_domain_fronting {
+            return verifier.verify_server_cert_ignoring_name(&sess.config.root_store,
+                                                             cert_chain,
+                                                             ocsp_response);
+        }
+    }
+
+    verifier.verify_server_cert(&sess.config.root_store,
+                                cert_chain,
+                                dns_name,
+                                ocsp_response)
+}
+
 fn send_cert_error_alert(sess: &mut ClientSessionImpl, err: TLSError) -> TLSError {
     match err {
         TLSError::WebPKIError(webpki::Error::BadDER) => {
@@ -1246,17 +1322,16 @@ impl State for ExpectTLS13CertificateVerify {
             return Err(TLSError::NoCertificatesPresented);
         }
 
-        let certv = sess.config
-            .get_verifier()
-            .verify_server_cert(&sess.config.root_store,
-                                &self.server_cert.cert_chain,
-                                self.handshake.dns_name.as_ref(),
-                                &self.server_cert.ocsp_response)
+        let certv = verify_server_cert(sess,
+                                       &self.server_cert.cert_chain,
+                                       self.handshake.dns_name.as_ref(),
+                                       &self.server_cert.ocsp_response)
             .map_err(|err| send_cert_error_alert(sess, err))?;
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.handshake.transcript.get_current_hash();
-        let sigv = verify::verify_tls13(&self.server_cert.cert_chain[0],
+        let sigv = verify::verify_tls13(sess.config.get_signature_verifier(),
+                                        &self.server_cert.cert_chain[0],
                                         cert_verify,
                                         &handshake_hash,
                                         b"TLS 1.3, server CertificateVerify\x00")
@@ -1432,7 +1507,7 @@ impl State for ExpectTLS12CertificateRequest {
             sess.config.client_auth_cert_resolver.resolve(&canames, &certreq.sigschemes);
 
         if let Some(mut certkey) = maybe_certkey {
-            debug!("Attempting client auth");
+            debug!("Attempting client auth with {} key", certkey.key.key_type_name());
             let maybe_signer = certkey.key.choose_scheme(&certreq.sigschemes);
             client_auth.cert = Some(certkey.take_cert());
             client_auth.signer = maybe_signer;
@@ -1506,7 +1581,7 @@ impl State for ExpectTLS13CertificateRequest {
 
         let mut client_auth = ClientAuthDetails::new();
         if let Some(mut certkey) = maybe_certkey {
-            debug!("Attempting client auth");
+            debug!("Attempting client auth with {} key", certkey.key.key_type_name());
             let maybe_signer = certkey.key.choose_scheme(&compat_sigschemes);
             client_auth.cert = Some(certkey.take_cert());
             client_auth.signer = maybe_signer;
@@ -1627,12 +1702,10 @@ impl State for ExpectTLS12ServerDone {
             return Err(TLSError::NoCertificatesPresented);
         }
 
-        let certv = sess.config
-            .get_verifier()
-            .verify_server_cert(&sess.config.root_store,
-                                &st.server_cert.cert_chain,
-                                st.handshake.dns_name.as_ref(),
-                                &st.server_cert.ocsp_response)
+        let certv = verify_server_cert(sess,
+                                       &st.server_cert.cert_chain,
+                                       st.handshake.dns_name.as_ref(),
+                                       &st.server_cert.ocsp_response)
             .map_err(|err| send_cert_error_alert(sess, err))?;
 
         // 2. Verify any included SCTs.
@@ -1664,9 +1737,8 @@ impl State for ExpectTLS12ServerDone {
                 return Err(TLSError::PeerMisbehavedError(error_message));
             }
 
-            verify::verify_signed_struct(&message,
-                                         &st.server_cert.cert_chain[0],
-                                         sig)
+            sess.config.get_signature_verifier()
+                .verify_tls12_signature(&message, &st.server_cert.cert_chain[0], sig)
                 .map_err(|err| send_cert_error_alert(sess, err))?
         };
 
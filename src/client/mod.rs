@@ -4,7 +4,7 @@ use session::{Session, SessionCommon};
 use suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
 use msgs::handshake::CertificatePayload;
 use msgs::enums::SignatureScheme;
-use msgs::enums::{ContentType, ProtocolVersion};
+use msgs::enums::{ContentType, ProtocolVersion, MaxFragmentLen};
 use msgs::message::Message;
 use verify;
 use anchors;
@@ -15,13 +15,17 @@ use key;
 use std::sync::Arc;
 use std::io;
 use std::fmt;
+use std::time::Duration;
 
 use sct;
 use webpki;
+#[cfg(feature = "debug-transcript")]
+use transcript::{Direction, HandshakeMessageSummary};
 
 mod hs;
 mod common;
 pub mod handy;
+pub mod pool;
 
 /// A trait for the ability to store client session data.
 /// The keys and values are opaque.
@@ -115,8 +119,29 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// If set, we pad our ClientHello messages using the RFC7685
+    /// padding extension so they are at least this many bytes long.
+    ///
+    /// The default is None.
+    pub client_hello_size: Option<usize>,
+
+    /// The maximum fragment length to ask the server for, with
+    /// the max_fragment_length extension.
+    ///
+    /// The default is None, which doesn't send the extension.
+    pub max_fragment_length: Option<MaxFragmentLen>,
+
+    /// Whether to accept a server certificate which isn't valid for
+    /// the name we're connecting to.  See
+    /// `DangerousClientConfig::set_allow_domain_fronting`.
+    #[cfg(feature = "dangerous_configuration")]
+    allow_domain_fronting: bool,
+
     /// How to verify the server certificate chain.
     verifier: Arc<verify::ServerCertVerifier>,
+
+    /// How to verify the server's signatures.
+    signature_verifier: Arc<verify::SignatureVerifier>,
 }
 
 impl ClientConfig {
@@ -137,7 +162,12 @@ impl ClientConfig {
             versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
             ct_logs: None,
             enable_sni: true,
-            verifier: Arc::new(verify::WebPKIVerifier::new())
+            client_hello_size: None,
+            max_fragment_length: None,
+            #[cfg(feature = "dangerous_configuration")]
+            allow_domain_fronting: false,
+            verifier: Arc::new(verify::WebPKIVerifier::new()),
+            signature_verifier: Arc::new(verify::WebPKIVerifier::new()),
         }
     }
 
@@ -146,6 +176,11 @@ impl ClientConfig {
         self.verifier.as_ref()
     }
 
+    #[doc(hidden)]
+    pub fn get_signature_verifier(&self) -> &verify::SignatureVerifier {
+        self.signature_verifier.as_ref()
+    }
+
     /// Set the ALPN protocol list to the given protocol names.
     /// Overwrites any existing configured protocols.
     /// The first element in the `protocols` list is the most
@@ -155,6 +190,13 @@ impl ClientConfig {
         self.alpn_protocols.extend_from_slice(protocols);
     }
 
+    /// Pad ClientHello messages so they are at least `target`
+    /// bytes long.  This works around servers and middleboxes
+    /// which mishandle ClientHellos of certain sizes.
+    pub fn set_client_hello_size(&mut self, target: usize) {
+        self.client_hello_size = Some(target);
+    }
+
     /// Sets persistence layer to `persist`.
     pub fn set_persistence(&mut self, persist: Arc<StoresClientSessions>) {
         self.session_persistence = persist;
@@ -177,6 +219,17 @@ impl ClientConfig {
         }
     }
 
+    /// Ask the server to send TLS records carrying no more than
+    /// `mfl` bytes of plaintext.  This is for constrained clients
+    /// that can't buffer full-size records.  Servers which don't
+    /// support the extension ignore it.
+    ///
+    /// We limit the records we send to the same size once the
+    /// server agrees.
+    pub fn set_max_fragment_length(&mut self, mfl: MaxFragmentLen) {
+        self.max_fragment_length = Some(mfl);
+    }
+
     /// Sets a single client authentication certificate and private key.
     /// This is blindly used for all servers that ask for client auth.
     ///
@@ -203,7 +256,7 @@ pub mod danger {
     use std::sync::Arc;
 
     use super::ClientConfig;
-    use super::verify::ServerCertVerifier;
+    use super::verify::{ServerCertVerifier, SignatureVerifier};
 
     /// Accessor for dangerous configuration options.
     pub struct DangerousClientConfig<'a> {
@@ -217,6 +270,36 @@ pub mod danger {
                                         verifier: Arc<ServerCertVerifier>) {
             self.cfg.verifier = verifier;
         }
+
+        /// Overrides the default `SignatureVerifier` with something else.
+        pub fn set_signature_verifier(&mut self,
+                                      verifier: Arc<SignatureVerifier>) {
+            self.cfg.signature_verifier = verifier;
+        }
+
+        /// Whether to accept a server certificate which isn't valid for
+        /// the name we're connecting to, for domain fronting: where SNI
+        /// names a front server, and the real destination is named
+        /// only inside the connection (for example, in a HTTP `Host`
+        /// header).  The certificate chain is otherwise verified as
+        /// usual.
+        ///
+        /// This means *any* server with a certificate from a trusted CA
+        /// can impersonate the one you meant to connect to, so the
+        /// connection only proves you are talking to *someone* with such
+        /// a certificate.  Only use this if your protocol authenticates
+        /// the real destination some other way, or you don't need it
+        /// authenticated.
+        ///
+        /// The verifier must support this, by implementing
+        /// `ServerCertVerifier::verify_server_cert_ignoring_name`;
+        /// handshakes fail otherwise.
+        ///
+        /// The default is false, which requires the certificate to be
+        /// valid for the name.
+        pub fn set_allow_domain_fronting(&mut self, allow: bool) {
+            self.cfg.allow_domain_fronting = allow;
+        }
     }
 }
 
@@ -227,6 +310,10 @@ pub struct ClientSessionImpl {
     pub error: Option<TLSError>,
     pub state: Option<Box<hs::State + Send + Sync>>,
     pub server_cert_chain: CertificatePayload,
+    pub server_name: webpki::DNSName,
+
+    /// The ALPN protocols we offer: usually `config.alpn_protocols`.
+    pub alpn_protocols: Vec<String>,
 }
 
 impl fmt::Debug for ClientSessionImpl {
@@ -238,6 +325,12 @@ impl fmt::Debug for ClientSessionImpl {
 impl ClientSessionImpl {
     pub fn new(config: &Arc<ClientConfig>, hostname: webpki::DNSName)
                -> ClientSessionImpl {
+        ClientSessionImpl::new_with_alpn(config, hostname, config.alpn_protocols.clone())
+    }
+
+    pub fn new_with_alpn(config: &Arc<ClientConfig>,
+                         hostname: webpki::DNSName,
+                         alpn_protocols: Vec<String>) -> ClientSessionImpl {
         let mut cs = ClientSessionImpl {
             config: config.clone(),
             alpn_protocol: None,
@@ -245,6 +338,8 @@ impl ClientSessionImpl {
             error: None,
             state: None,
             server_cert_chain: Vec::new(),
+            server_name: hostname.clone(),
+            alpn_protocols,
         };
 
         cs.state = Some(hs::start_handshake(&mut cs, hostname));
@@ -331,7 +426,7 @@ impl ClientSessionImpl {
 
         // For alerts, we have separate logic.
         if msg.is_content_type(ContentType::Alert) {
-            return self.common.process_alert(msg);
+            return self.common.process_alert(msg, &[]);
         }
 
         self.process_main_protocol(msg)
@@ -339,6 +434,9 @@ impl ClientSessionImpl {
 
     fn process_new_handshake_messages(&mut self) -> Result<(), TLSError> {
         while let Some(msg) = self.common.handshake_joiner.frames.pop_front() {
+            #[cfg(feature = "debug-transcript")]
+            self.common.transcript.record(Direction::Received, &msg);
+
             self.process_main_protocol(msg)?;
         }
 
@@ -400,7 +498,7 @@ impl ClientSessionImpl {
         Ok(())
     }
 
-    pub fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
+    pub fn get_peer_certificates(&self) -> Option<key::PeerCertificates> {
         if self.server_cert_chain.is_empty() {
             return None;
         }
@@ -410,7 +508,7 @@ impl ClientSessionImpl {
             r.push(cert.clone());
         }
 
-        Some(r)
+        key::PeerCertificates::new(r)
     }
 
     pub fn get_alpn_protocol(&self) -> Option<&str> {
@@ -426,6 +524,82 @@ impl ClientSessionImpl {
     }
 }
 
+/// Per-connection settings for a `ClientSession`.
+///
+/// These override or add to the shared `ClientConfig`, so a
+/// connection which needs (say) a different ALPN protocol list
+/// doesn't need a `ClientConfig` of its own.
+///
+/// ```
+/// # extern crate rustls;
+/// # extern crate webpki;
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// let config = Arc::new(rustls::ClientConfig::new());
+/// let name = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+/// let options = rustls::ConnectOptions::new(name)
+///     .alpn_protocols(vec![ "h2".to_string() ])
+///     .timeout(Duration::from_secs(10));
+/// let session = options.build_session(&config);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    server_name: webpki::DNSName,
+    alpn_protocols: Option<Vec<String>>,
+    timeout: Option<Duration>,
+}
+
+impl ConnectOptions {
+    /// Make options for connecting to `server_name`, which is sent
+    /// with SNI and checked against the server's certificate.
+    /// Everything else comes from the `ClientConfig`.
+    pub fn new(server_name: webpki::DNSNameRef) -> ConnectOptions {
+        ConnectOptions {
+            server_name: server_name.into(),
+            alpn_protocols: None,
+            timeout: None,
+        }
+    }
+
+    /// Connect to `server_name` instead.
+    pub fn server_name(mut self, server_name: webpki::DNSNameRef) -> ConnectOptions {
+        self.server_name = server_name.into();
+        self
+    }
+
+    /// Offer these ALPN protocols, in preference order, rather
+    /// than `ClientConfig::alpn_protocols`.
+    pub fn alpn_protocols(mut self, protocols: Vec<String>) -> ConnectOptions {
+        self.alpn_protocols = Some(protocols);
+        self
+    }
+
+    /// Record a timeout for this connection.
+    ///
+    /// rustls does no IO itself, so this doesn't change the session:
+    /// it is for the code driving the socket, which can get it back
+    /// with `get_timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> ConnectOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The timeout set with `timeout`, if any.
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Make a new `ClientSession` using `config` with these options.
+    pub fn build_session(&self, config: &Arc<ClientConfig>) -> ClientSession {
+        let alpn_protocols = self.alpn_protocols
+            .clone()
+            .unwrap_or_else(|| config.alpn_protocols.clone());
+        ClientSession {
+            imp: ClientSessionImpl::new_with_alpn(config, self.server_name.clone(), alpn_protocols),
+        }
+    }
+}
+
 /// This represents a single TLS client session.
 #[derive(Debug)]
 pub struct ClientSession {
@@ -440,6 +614,13 @@ impl ClientSession {
     pub fn new(config: &Arc<ClientConfig>, hostname: webpki::DNSNameRef) -> ClientSession {
         ClientSession { imp: ClientSessionImpl::new(config, hostname.into()) }
     }
+
+    /// The handshake messages this session has sent and
+    /// received so far, in order, for debugging.
+    #[cfg(feature = "debug-transcript")]
+    pub fn get_handshake_transcript(&self) -> Vec<HandshakeMessageSummary> {
+        self.imp.common.transcript.messages()
+    }
 }
 
 impl Session for ClientSession {
@@ -476,7 +657,7 @@ impl Session for ClientSession {
         self.imp.common.send_close_notify()
     }
 
-    fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
+    fn get_peer_certificates(&self) -> Option<key::PeerCertificates> {
         self.imp.get_peer_certificates()
     }
 
@@ -516,9 +697,10 @@ impl io::Write for ClientSession {
     ///
     /// This function buffers plaintext sent before the
     /// TLS handshake completes, and sends it as soon
-    /// as it can.  This buffer is of *unlimited size* so
-    /// writing much data before it can be sent will
-    /// cause excess memory usage.
+    /// as it can.  This buffer, and the buffer of TLS
+    /// records waiting for `write_tls`, are limited by
+    /// `set_buffer_limit`: once they are full, this fails
+    /// with `WouldBlock`.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.imp.common.send_some_plaintext(buf)
     }
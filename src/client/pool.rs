@@ -0,0 +1,73 @@
+use client::ClientSession;
+use session::Session;
+
+use webpki;
+
+use std::collections;
+
+/// A pool of idle, established `ClientSession`s, so that a
+/// connection to a server can be reused (eg. for HTTP/1.1
+/// keep-alive) rather than starting a new handshake.
+///
+/// The pool only holds sessions: you'll need to keep track of
+/// the transport each session was using.
+pub struct TlsConnectionPool {
+    idle: collections::HashMap<webpki::DNSName, Vec<ClientSession>>,
+    max_idle_per_host: usize,
+}
+
+impl TlsConnectionPool {
+    /// Make an empty pool, which holds up to `max_idle_per_host`
+    /// idle sessions for each server name.
+    pub fn new(max_idle_per_host: usize) -> TlsConnectionPool {
+        TlsConnectionPool {
+            idle: collections::HashMap::new(),
+            max_idle_per_host,
+        }
+    }
+
+    /// Take an idle session for server `name` out of the pool,
+    /// if there is one.  The most recently released session is
+    /// returned first.
+    pub fn acquire(&mut self, name: webpki::DNSNameRef) -> Option<ClientSession> {
+        let name: webpki::DNSName = name.into();
+        let (session, now_empty) = {
+            let sessions = self.idle.get_mut(&name)?;
+            (sessions.pop(), sessions.is_empty())
+        };
+
+        if now_empty {
+            self.idle.remove(&name);
+        }
+
+        session
+    }
+
+    /// Return `session` to the pool once you're done with it.
+    ///
+    /// `session` is dropped instead if it is not usable for
+    /// further requests: if it hasn't completed its handshake,
+    /// has failed, or has received a close_notify from the
+    /// server.  It is also dropped if the pool already holds the
+    /// maximum number of idle sessions for its server.
+    pub fn release(&mut self, session: ClientSession) {
+        if self.max_idle_per_host == 0 ||
+           session.is_handshaking() ||
+           session.imp.error.is_some() ||
+           session.imp.common.connection_at_eof() {
+            return;
+        }
+
+        let sessions = self.idle.entry(session.imp.server_name.clone())
+            .or_default();
+        if sessions.len() < self.max_idle_per_host {
+            sessions.push(session);
+        }
+    }
+
+    /// How many idle sessions are held for server `name`.
+    pub fn idle_count(&self, name: webpki::DNSNameRef) -> usize {
+        let name: webpki::DNSName = name.into();
+        self.idle.get(&name).map_or(0, |sessions| sessions.len())
+    }
+}
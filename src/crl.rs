@@ -0,0 +1,947 @@
+// Certificate revocation lists (RFC5280 section 5).
+//
+// webpki doesn't handle CRLs, so this has just enough DER parsing
+// to read a CRL, check its signature against the issuing certificate,
+// and answer whether a given certificate has been revoked.  The
+// certificate parsing is shared with `pathbuild`.
+
+use std::cmp;
+use std::collections;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::der;
+use untrusted;
+use webpki;
+
+use error::TLSError;
+use key::Certificate;
+
+// Extension OIDs (2.5.29.x), DER content only.
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_CRL_NUMBER: &[u8] = &[0x55, 0x1d, 0x14];
+const OID_REASON_CODE: &[u8] = &[0x55, 0x1d, 0x15];
+const OID_INVALIDITY_DATE: &[u8] = &[0x55, 0x1d, 0x18];
+const OID_DELTA_CRL_INDICATOR: &[u8] = &[0x55, 0x1d, 0x1b];
+const OID_AUTHORITY_KEY_ID: &[u8] = &[0x55, 0x1d, 0x23];
+
+// Authority information access (1.3.6.1.5.5.7.1.1) and its
+// caIssuers access method (1.3.6.1.5.5.7.48.2).
+const OID_AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+const OID_AD_CA_ISSUERS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x02];
+
+// Signature algorithm OIDs.
+const OID_SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+
+/// A parsed and signature-checked X.509 certificate revocation
+/// list.
+///
+/// Serial numbers are compared as the DER contents of the
+/// certificate's serialNumber INTEGER, exactly as they appear in the
+/// certificate.
+///
+/// Indirect CRLs, and CRLs with critical extensions we don't
+/// understand (such as issuingDistributionPoint), are rejected.
+/// CRLs larger than 64KiB are not supported.
+#[derive(Clone, Debug)]
+pub struct CertificateRevocationList {
+    issuer: Vec<u8>,
+    this_update: SystemTime,
+    next_update: Option<SystemTime>,
+    revoked: collections::HashSet<Vec<u8>>,
+    removed: collections::HashSet<Vec<u8>>,
+    crl_number: Option<Vec<u8>>,
+    delta_crl_indicator: Option<Vec<u8>>,
+}
+
+impl CertificateRevocationList {
+    /// Parse the DER-encoded CRL `crl`, and check it was signed by
+    /// the certificate `issuer`.
+    ///
+    /// `issuer` is trusted to be the right certificate: make sure it
+    /// comes from a trustworthy place, for example the same place as
+    /// your root certificates.  Delta CRLs are rejected.
+    pub fn from_der(crl: &[u8], issuer: &Certificate) -> Result<CertificateRevocationList, TLSError> {
+        let crl = CertificateRevocationList::parse_and_verify(crl, issuer)?;
+        if crl.delta_crl_indicator.is_some() {
+            return Err(TLSError::InvalidCRL("delta CRL given where a complete CRL is needed"
+                .to_string()));
+        }
+        Ok(crl)
+    }
+
+    pub(crate) fn parse_and_verify(crl: &[u8], issuer: &Certificate)
+                                   -> Result<CertificateRevocationList, TLSError> {
+        let signed = untrusted::Input::from(crl)
+            .read_all(bad_der(), |r| read_signed_data(r))?;
+
+        let issuer_cert = untrusted::Input::from(&issuer.0)
+            .read_all(bad_der(), |r| read_cert(r))?;
+        if issuer_cert.key_usage_forbids_crl_sign {
+            return Err(TLSError::InvalidCRL("issuer may not sign CRLs".to_string()));
+        }
+
+        verify_signature(&signed, &issuer.0)?;
+
+        let parsed = signed.read_tbs(|r| read_tbs_cert_list(r, signed.algorithm))?;
+        if parsed.issuer != issuer_cert.subject {
+            return Err(TLSError::InvalidCRL("CRL issuer does not match certificate".to_string()));
+        }
+
+        Ok(parsed)
+    }
+
+    /// The time this CRL was issued.
+    pub fn this_update(&self) -> SystemTime {
+        self.this_update
+    }
+
+    /// The time by which the next CRL will be issued, if given.
+    pub fn next_update(&self) -> Option<SystemTime> {
+        self.next_update
+    }
+
+    /// Returns true if this CRL can be relied on at time `now`:
+    /// it has been issued, and hasn't been replaced.
+    pub fn is_current(&self, now: SystemTime) -> bool {
+        self.this_update <= now &&
+            self.next_update.map(|next| now < next).unwrap_or(true)
+    }
+
+    /// Returns true if the certificate with serial number `serial`
+    /// is revoked by this CRL.
+    pub fn is_revoked(&self, serial: &[u8]) -> bool {
+        self.revoked.contains(serial)
+    }
+
+    /// Check `cert` against this CRL at time `now`.
+    ///
+    /// Certificates from other issuers are not covered by this CRL,
+    /// and pass.  Otherwise, this fails with
+    /// `TLSError::CertificateRevoked` if `cert` is revoked, or
+    /// `TLSError::InvalidCRL` if the CRL isn't current.
+    pub fn check_certificate(&self, cert: &Certificate, now: SystemTime) -> Result<(), TLSError> {
+        let parsed = parse_cert(cert)?;
+
+        if parsed.issuer != self.issuer {
+            return Ok(());
+        }
+
+        if !self.is_current(now) {
+            return Err(TLSError::InvalidCRL("CRL is not current".to_string()));
+        }
+
+        if self.is_revoked(&parsed.serial) {
+            return Err(TLSError::CertificateRevoked);
+        }
+
+        Ok(())
+    }
+}
+
+fn bad_der() -> TLSError {
+    TLSError::WebPKIError(webpki::Error::BadDER)
+}
+
+fn nested<'a, F, R>(r: &mut untrusted::Reader<'a>, tag: der::Tag, decoder: F) -> Result<R, TLSError>
+    where F: FnOnce(&mut untrusted::Reader<'a>) -> Result<R, TLSError>
+{
+    der::expect_tag_and_get_value(r, tag)
+        .map_err(|_| bad_der())?
+        .read_all(bad_der(), decoder)
+}
+
+struct SignedData<'a> {
+    tbs: untrusted::Input<'a>, // including tag and length: this is what is signed
+    algorithm: untrusted::Input<'a>,
+    signature: untrusted::Input<'a>,
+}
+
+impl<'a> SignedData<'a> {
+    fn read_tbs<F, R>(&self, decoder: F) -> Result<R, TLSError>
+        where F: FnOnce(&mut untrusted::Reader<'a>) -> Result<R, TLSError>
+    {
+        self.tbs.read_all(bad_der(), |r| nested(r, der::Tag::Sequence, decoder))
+    }
+}
+
+// SEQUENCE { tbs, AlgorithmIdentifier, BIT STRING }
+fn read_signed_data<'a>(r: &mut untrusted::Reader<'a>) -> Result<SignedData<'a>, TLSError> {
+    nested(r, der::Tag::Sequence, |r| {
+        let start = r.mark();
+        der::expect_tag_and_get_value(r, der::Tag::Sequence)
+            .map_err(|_| bad_der())?;
+        let tbs = r.get_input_between_marks(start, r.mark())
+            .map_err(|_| bad_der())?;
+        let algorithm = der::expect_tag_and_get_value(r, der::Tag::Sequence)
+            .map_err(|_| bad_der())?;
+        let signature = der::bit_string_with_no_unused_bits(r)
+            .map_err(|_| bad_der())?;
+        Ok(SignedData { tbs, algorithm, signature })
+    })
+}
+
+fn verify_signature(signed: &SignedData, issuer_der: &[u8]) -> Result<(), TLSError> {
+    let oid = signed.algorithm
+        .read_all(bad_der(), |r| {
+            let oid = der::expect_tag_and_get_value(r, der::Tag::OID)
+                .map_err(|_| bad_der())?;
+            // parameters are absent (ECDSA) or NULL (RSA)
+            if !r.at_end() {
+                der::expect_tag_and_get_value(r, der::Tag::Null)
+                    .map_err(|_| bad_der())?;
+            }
+            Ok(oid)
+        })?;
+
+    let candidates: &[&webpki::SignatureAlgorithm] = match oid.as_slice_less_safe() {
+        OID_SHA256_WITH_RSA => &[&webpki::RSA_PKCS1_2048_8192_SHA256],
+        OID_SHA384_WITH_RSA => &[&webpki::RSA_PKCS1_2048_8192_SHA384],
+        OID_SHA512_WITH_RSA => &[&webpki::RSA_PKCS1_2048_8192_SHA512],
+        OID_ECDSA_WITH_SHA256 => &[&webpki::ECDSA_P256_SHA256, &webpki::ECDSA_P384_SHA256],
+        OID_ECDSA_WITH_SHA384 => &[&webpki::ECDSA_P256_SHA384, &webpki::ECDSA_P384_SHA384],
+        _ => return Err(TLSError::WebPKIError(webpki::Error::UnsupportedSignatureAlgorithm)),
+    };
+
+    let issuer = webpki::EndEntityCert::from(untrusted::Input::from(issuer_der))
+        .map_err(TLSError::WebPKIError)?;
+
+    let mut err = webpki::Error::UnsupportedSignatureAlgorithmForPublicKey;
+    for alg in candidates {
+        match issuer.verify_signature(alg, signed.tbs, signed.signature) {
+            Ok(()) => return Ok(()),
+            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => continue,
+            Err(e) => err = e,
+        }
+    }
+
+    Err(TLSError::WebPKIError(err))
+}
+
+pub(crate) struct ParsedCert {
+    pub serial: Vec<u8>,
+    pub issuer: Vec<u8>,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+    pub subject: Vec<u8>,
+    pub key_usage_forbids_crl_sign: bool,
+    pub ca_issuers: Vec<String>,
+}
+
+pub(crate) fn parse_cert(cert: &Certificate) -> Result<ParsedCert, TLSError> {
+    untrusted::Input::from(&cert.0)
+        .read_all(bad_der(), |r| read_cert(r))
+}
+
+fn read_cert(r: &mut untrusted::Reader) -> Result<ParsedCert, TLSError> {
+    let signed = read_signed_data(r)?;
+    signed.read_tbs(|r| {
+        if r.peek(der::Tag::ContextSpecificConstructed0 as u8) {
+            der::expect_tag_and_get_value(r, der::Tag::ContextSpecificConstructed0)
+                .map_err(|_| bad_der())?;
+        }
+        let serial = read_value(r, der::Tag::Integer)?;
+        read_value(r, der::Tag::Sequence)?; // signature
+        let issuer = read_value(r, der::Tag::Sequence)?;
+        let (not_before, not_after) = nested(r, der::Tag::Sequence, |r| {
+            Ok((read_time(r)?, read_time(r)?))
+        })?;
+        let subject = read_value(r, der::Tag::Sequence)?;
+        read_value(r, der::Tag::Sequence)?; // subjectPublicKeyInfo
+
+        let mut key_usage_forbids_crl_sign = false;
+        let mut ca_issuers = Vec::new();
+        while !r.at_end() {
+            let (tag, value) = der::read_tag_and_get_value(r)
+                .map_err(|_| bad_der())?;
+            if tag != der::Tag::ContextSpecificConstructed3 as u8 {
+                continue; // issuerUniqueID, subjectUniqueID
+            }
+
+            value.read_all(bad_der(), |r| {
+                read_extensions(r, |oid, _critical, value| {
+                    match oid {
+                        OID_KEY_USAGE =>
+                            key_usage_forbids_crl_sign = !key_usage_allows_crl_sign(value)?,
+                        OID_AUTHORITY_INFO_ACCESS =>
+                            ca_issuers = read_ca_issuers(value)?,
+                        _ => (),
+                    }
+                    Ok(())
+                })
+            })?;
+        }
+
+        Ok(ParsedCert {
+            serial,
+            issuer,
+            not_before,
+            not_after,
+            subject,
+            key_usage_forbids_crl_sign,
+            ca_issuers,
+        })
+    })
+}
+
+// Name ::= SEQUENCE OF RelativeDistinguishedName
+// RelativeDistinguishedName ::= SET OF SEQUENCE { OID, ANY }
+//
+// `name` is the contents of the outer SEQUENCE.  This is written
+// as in RFC4514: most specific RDN first, and with the types it
+// names abbreviated.
+pub(crate) fn name_to_string(name: &[u8]) -> Result<String, TLSError> {
+    const TAG_SET: u8 = 0x31;
+
+    let mut rdns = Vec::new();
+    untrusted::Input::from(name).read_all(bad_der(), |r| {
+        while !r.at_end() {
+            let (tag, rdn) = der::read_tag_and_get_value(r)
+                .map_err(|_| bad_der())?;
+            if tag != TAG_SET {
+                return Err(bad_der());
+            }
+
+            let mut attributes = Vec::new();
+            rdn.read_all(bad_der(), |r| {
+                while !r.at_end() {
+                    nested(r, der::Tag::Sequence, |r| {
+                        let oid = read_value(r, der::Tag::OID)?;
+                        let start = r.mark();
+                        let (tag, value) = der::read_tag_and_get_value(r)
+                            .map_err(|_| bad_der())?;
+                        let encoded = r.get_input_between_marks(start, r.mark())
+                            .map_err(|_| bad_der())?;
+                        attributes.push(format!("{}={}",
+                                                attribute_type_name(&oid),
+                                                attribute_value_string(tag,
+                                                                       value.as_slice_less_safe(),
+                                                                       encoded.as_slice_less_safe())));
+                        Ok(())
+                    })?;
+                }
+                Ok(())
+            })?;
+            rdns.push(attributes.join("+"));
+        }
+        Ok(())
+    })?;
+
+    rdns.reverse();
+    Ok(rdns.join(","))
+}
+
+fn attribute_type_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x09] => "STREET".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01] => "UID".to_string(),
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC".to_string(),
+        _ => oid_to_string(oid),
+    }
+}
+
+fn oid_to_string(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for &b in oid {
+        arc = (arc << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = cmp::min(arc / 40, 2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+
+    arcs.iter()
+        .map(|arc| arc.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn attribute_value_string(tag: u8, value: &[u8], encoded: &[u8]) -> String {
+    const TAG_UTF8_STRING: u8 = 0x0c;
+    const TAG_PRINTABLE_STRING: u8 = 0x13;
+    const TAG_TELETEX_STRING: u8 = 0x14;
+    const TAG_IA5_STRING: u8 = 0x16;
+    const TAG_BMP_STRING: u8 = 0x1e;
+
+    let text = match tag {
+        TAG_UTF8_STRING | TAG_PRINTABLE_STRING | TAG_IA5_STRING =>
+            String::from_utf8(value.to_vec()).ok(),
+        // Properly T.61, but in practice Latin-1.
+        TAG_TELETEX_STRING => Some(value.iter().map(|&b| char::from(b)).collect()),
+        TAG_BMP_STRING if value.len() & 1 == 0 => {
+            let units: Vec<u16> = value.chunks(2)
+                .map(|pair| (u16::from(pair[0]) << 8) | u16::from(pair[1]))
+                .collect();
+            String::from_utf16(&units).ok()
+        }
+        _ => None,
+    };
+
+    match text {
+        Some(text) => escape_attribute_value(&text),
+        None => {
+            // Anything else is written as '#' and the hex of its
+            // DER encoding.
+            let mut hex = "#".to_string();
+            for b in encoded {
+                hex.push_str(&format!("{:02x}", b));
+            }
+            hex
+        }
+    }
+}
+
+fn escape_attribute_value(value: &str) -> String {
+    let mut ret = String::new();
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        let special = match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' => true,
+            '#' => i == 0,
+            ' ' => i == 0 || i == last,
+            _ => false,
+        };
+        if special {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+// AuthorityInfoAccessSyntax ::= SEQUENCE OF SEQUENCE { OID, GeneralName }
+//
+// Returns the URIs of caIssuers access descriptions; other methods
+// and name forms are skipped.
+fn read_ca_issuers(value: untrusted::Input) -> Result<Vec<String>, TLSError> {
+    const TAG_URI: u8 = 0x86; // [6] IMPLICIT IA5String
+
+    value.read_all(bad_der(), |r| {
+        nested(r, der::Tag::Sequence, |r| {
+            let mut uris = Vec::new();
+            while !r.at_end() {
+                nested(r, der::Tag::Sequence, |r| {
+                    let method = read_value(r, der::Tag::OID)?;
+                    let (tag, location) = der::read_tag_and_get_value(r)
+                        .map_err(|_| bad_der())?;
+                    if method == OID_AD_CA_ISSUERS && tag == TAG_URI {
+                        let uri = String::from_utf8(location.as_slice_less_safe().to_vec())
+                            .map_err(|_| bad_der())?;
+                        uris.push(uri);
+                    }
+                    Ok(())
+                })?;
+            }
+            Ok(uris)
+        })
+    })
+}
+
+// KeyUsage ::= BIT STRING; cRLSign is bit 6.
+fn key_usage_allows_crl_sign(value: untrusted::Input) -> Result<bool, TLSError> {
+    value.read_all(bad_der(), |r| {
+        let bits = der::expect_tag_and_get_value(r, der::Tag::BitString)
+            .map_err(|_| bad_der())?;
+        let bits = bits.as_slice_less_safe();
+        Ok(bits.len() >= 2 && bits[1] & 0x02 != 0)
+    })
+}
+
+fn read_value(r: &mut untrusted::Reader, tag: der::Tag) -> Result<Vec<u8>, TLSError> {
+    der::expect_tag_and_get_value(r, tag)
+        .map(|value| value.as_slice_less_safe().to_vec())
+        .map_err(|_| bad_der())
+}
+
+// Extensions ::= SEQUENCE OF SEQUENCE { OID, BOOLEAN DEFAULT FALSE, OCTET STRING }
+fn read_extensions<F>(r: &mut untrusted::Reader, mut f: F) -> Result<(), TLSError>
+    where F: FnMut(&[u8], bool, untrusted::Input) -> Result<(), TLSError>
+{
+    nested(r, der::Tag::Sequence, |r| {
+        while !r.at_end() {
+            nested(r, der::Tag::Sequence, |r| {
+                let oid = der::expect_tag_and_get_value(r, der::Tag::OID)
+                    .map_err(|_| bad_der())?;
+                let critical = if r.peek(der::Tag::Boolean as u8) {
+                    let value = read_value(r, der::Tag::Boolean)?;
+                    match value.as_slice() {
+                        [0xff] => true,
+                        _ => return Err(bad_der()), // FALSE must be omitted in DER
+                    }
+                } else {
+                    false
+                };
+                let value = der::expect_tag_and_get_value(r, der::Tag::OctetString)
+                    .map_err(|_| bad_der())?;
+                f(oid.as_slice_less_safe(), critical, value)
+            })?;
+        }
+        Ok(())
+    })
+}
+
+fn unknown_critical_extension(what: &str) -> TLSError {
+    TLSError::InvalidCRL(format!("unsupported critical {} extension", what))
+}
+
+fn read_time(r: &mut untrusted::Reader) -> Result<SystemTime, TLSError> {
+    let (tag, value) = der::read_tag_and_get_value(r)
+        .map_err(|_| bad_der())?;
+    let value = value.as_slice_less_safe();
+
+    let (year, rest) = if tag == der::Tag::UTCTime as u8 && value.len() == 13 {
+        let yy = digits(&value[..2])?;
+        (if yy >= 50 { 1900 + yy } else { 2000 + yy }, &value[2..])
+    } else if tag == der::Tag::GeneralizedTime as u8 && value.len() == 15 {
+        (digits(&value[..4])?, &value[4..])
+    } else {
+        return Err(bad_der());
+    };
+
+    if rest[10] != b'Z' {
+        return Err(bad_der());
+    }
+
+    let month = digits(&rest[0..2])?;
+    let day = digits(&rest[2..4])?;
+    let hours = digits(&rest[4..6])?;
+    let minutes = digits(&rest[6..8])?;
+    let seconds = digits(&rest[8..10])?;
+
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) ||
+        hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(bad_der());
+    }
+
+    let days = days_since_epoch(year, month, day);
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn digits(bytes: &[u8]) -> Result<u64, TLSError> {
+    bytes.iter()
+        .try_fold(0u64, |acc, &b| {
+            if b.is_ascii_digit() {
+                Ok(acc * 10 + u64::from(b - b'0'))
+            } else {
+                Err(bad_der())
+            }
+        })
+}
+
+// Days from 1970-01-01 to the given date in the proleptic Gregorian
+// calendar, for year >= 1970.  This is Howard Hinnant's
+// days_from_civil.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn read_tbs_cert_list(r: &mut untrusted::Reader, outer_algorithm: untrusted::Input)
+                      -> Result<CertificateRevocationList, TLSError> {
+    // version is optional, but must be v2 if there are any extensions.
+    if r.peek(der::Tag::Integer as u8) {
+        let version = der::small_nonnegative_integer(r)
+            .map_err(|_| bad_der())?;
+        if version != 1 {
+            return Err(TLSError::InvalidCRL("unsupported CRL version".to_string()));
+        }
+    }
+
+    let algorithm = der::expect_tag_and_get_value(r, der::Tag::Sequence)
+        .map_err(|_| bad_der())?;
+    if algorithm.as_slice_less_safe() != outer_algorithm.as_slice_less_safe() {
+        return Err(TLSError::InvalidCRL("mismatched signature algorithms".to_string()));
+    }
+
+    let issuer = read_value(r, der::Tag::Sequence)?;
+    let this_update = read_time(r)?;
+    let next_update = if r.peek(der::Tag::UTCTime as u8) ||
+                         r.peek(der::Tag::GeneralizedTime as u8) {
+        Some(read_time(r)?)
+    } else {
+        None
+    };
+
+    let mut revoked = collections::HashSet::new();
+    let mut removed = collections::HashSet::new();
+    if r.peek(der::Tag::Sequence as u8) {
+        nested(r, der::Tag::Sequence, |r| {
+            while !r.at_end() {
+                let (serial, remove) = nested(r, der::Tag::Sequence, |r| {
+                    let serial = read_value(r, der::Tag::Integer)?;
+                    read_time(r)?; // revocationDate
+                    let mut remove = false;
+                    if !r.at_end() {
+                        read_extensions(r, |oid, critical, value| {
+                            match oid {
+                                OID_REASON_CODE => remove = read_reason_code(value)? == REMOVE_FROM_CRL,
+                                OID_INVALIDITY_DATE => (),
+                                _ if critical => return Err(unknown_critical_extension("CRL entry")),
+                                _ => (),
+                            }
+                            Ok(())
+                        })?;
+                    }
+                    Ok((serial, remove))
+                })?;
+
+                if remove {
+                    removed.insert(serial);
+                } else {
+                    revoked.insert(serial);
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    let mut crl_number = None;
+    let mut delta_crl_indicator = None;
+    if !r.at_end() {
+        nested(r, der::Tag::ContextSpecificConstructed0, |r| {
+            read_extensions(r, |oid, critical, value| {
+                match oid {
+                    OID_CRL_NUMBER => crl_number = Some(read_crl_number(value)?),
+                    OID_DELTA_CRL_INDICATOR => delta_crl_indicator = Some(read_crl_number(value)?),
+                    OID_AUTHORITY_KEY_ID => (),
+                    _ if critical => return Err(unknown_critical_extension("CRL")),
+                    _ => (),
+                }
+                Ok(())
+            })
+        })?;
+    }
+
+    Ok(CertificateRevocationList {
+        issuer,
+        this_update,
+        next_update,
+        revoked,
+        removed,
+        crl_number,
+        delta_crl_indicator,
+    })
+}
+
+// CRLNumber ::= INTEGER (0..MAX)
+fn read_crl_number(value: untrusted::Input) -> Result<Vec<u8>, TLSError> {
+    value.read_all(bad_der(), |r| read_value(r, der::Tag::Integer))
+}
+
+// CRLReason ::= ENUMERATED
+const REMOVE_FROM_CRL: u8 = 8;
+const TAG_ENUMERATED: u8 = 0x0a;
+
+fn read_reason_code(value: untrusted::Input) -> Result<u8, TLSError> {
+    value.read_all(bad_der(), |r| {
+        let (tag, reason) = der::read_tag_and_get_value(r)
+            .map_err(|_| bad_der())?;
+        match (tag, reason.as_slice_less_safe()) {
+            (TAG_ENUMERATED, &[reason]) => Ok(reason),
+            _ => Err(bad_der()),
+        }
+    })
+}
+
+// Compare two non-negative DER INTEGER contents numerically.
+fn compare_integers(a: &[u8], b: &[u8]) -> cmp::Ordering {
+    let strip = |x: &[u8]| {
+        let zeros = x.iter().take_while(|&&b| b == 0).count();
+        x[zeros..].to_vec()
+    };
+    let (a, b) = (strip(a), strip(b));
+    a.len().cmp(&b.len())
+        .then_with(|| a.cmp(&b))
+}
+
+/// Maintains the revocation state from a complete ("base") CRL,
+/// updated by delta CRLs (RFC5280 section 5.2.4).
+///
+/// Each delta CRL must be signed by the same issuer, name the base
+/// CRL's CRL number in its deltaCRLIndicator, and be newer than any
+/// delta already applied.  Its revocations are added to the merged
+/// set, and entries with reason removeFromCRL are taken out.  The
+/// merged list takes its thisUpdate and nextUpdate from the latest
+/// delta.
+pub struct DeltaCrlStore {
+    issuer: Certificate,
+    base_number: Vec<u8>,
+    latest_number: Vec<u8>,
+    merged: CertificateRevocationList,
+}
+
+impl DeltaCrlStore {
+    /// Start from the DER-encoded complete CRL `base_crl`, which
+    /// was signed by the certificate `issuer`.  The base CRL
+    /// must have a CRL number.
+    pub fn new(base_crl: &[u8], issuer: Certificate) -> Result<DeltaCrlStore, TLSError> {
+        let merged = CertificateRevocationList::from_der(base_crl, &issuer)?;
+        let base_number = merged.crl_number.clone()
+            .ok_or_else(|| TLSError::InvalidCRL("base CRL has no CRL number".to_string()))?;
+
+        Ok(DeltaCrlStore {
+            issuer,
+            latest_number: base_number.clone(),
+            base_number,
+            merged,
+        })
+    }
+
+    /// Check and apply the DER-encoded delta CRL `delta_crl`.
+    ///
+    /// On error, the store is unchanged.
+    pub fn apply_delta(&mut self, delta_crl: &[u8]) -> Result<(), TLSError> {
+        let delta = CertificateRevocationList::parse_and_verify(delta_crl, &self.issuer)?;
+
+        let base = delta.delta_crl_indicator.as_ref()
+            .ok_or_else(|| TLSError::InvalidCRL("not a delta CRL".to_string()))?;
+        if compare_integers(base, &self.base_number) != cmp::Ordering::Equal {
+            return Err(TLSError::InvalidCRL("delta CRL is for a different base CRL".to_string()));
+        }
+
+        let number = delta.crl_number.as_ref()
+            .ok_or_else(|| TLSError::InvalidCRL("delta CRL has no CRL number".to_string()))?;
+        if compare_integers(number, &self.latest_number) != cmp::Ordering::Greater {
+            return Err(TLSError::InvalidCRL("delta CRL is not newer".to_string()));
+        }
+
+        for serial in &delta.removed {
+            self.merged.revoked.remove(serial);
+        }
+        self.merged.revoked.extend(delta.revoked.iter().cloned());
+        self.merged.this_update = delta.this_update;
+        self.merged.next_update = delta.next_update;
+        self.latest_number = number.clone();
+        Ok(())
+    }
+
+    /// The merged revocation list, for example to give to a
+    /// `CrlCertVerifier`.
+    pub fn crl(&self) -> &CertificateRevocationList {
+        &self.merged
+    }
+
+    /// Returns true if the certificate with serial number `serial`
+    /// is revoked by the base CRL and deltas applied so far.
+    pub fn is_revoked(&self, serial: &[u8]) -> bool {
+        self.merged.is_revoked(serial)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::io::{self, Read};
+    use pemfile;
+
+    fn read_file(path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        fs::File::open(path).unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    fn cert(path: &str) -> Certificate {
+        let pem = read_file(path);
+        pemfile::certs(&mut io::BufReader::new(&pem[..])).unwrap().remove(0)
+    }
+
+    fn crl(name: &str) -> Result<CertificateRevocationList, TLSError> {
+        let der = read_file(&format!("test-ca/rsa/{}.crl.der", name));
+        CertificateRevocationList::from_der(&der, &cert("test-ca/rsa/inter.cert"))
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_days_since_epoch() {
+        assert_eq!(days_since_epoch(1970, 1, 1), 0);
+        assert_eq!(days_since_epoch(2000, 3, 1), 11017);
+        assert_eq!(days_since_epoch(2017, 12, 1), 17501);
+        assert_eq!(days_since_epoch(2049, 12, 31), 29219);
+    }
+
+    #[test]
+    fn test_read_time() {
+        let parse = |der: &[u8]| {
+            untrusted::Input::from(der).read_all(bad_der(), |r| read_time(r))
+        };
+        assert_eq!(parse(b"\x17\x0d171201000000Z").unwrap(), at(1512086400));
+        assert_eq!(parse(b"\x18\x0f20491231000000Z").unwrap(), at(2524521600));
+        assert!(parse(b"\x17\x0d171301000000Z").is_err());
+        assert!(parse(b"\x17\x0d1712010000000").is_err());
+        assert!(parse(b"\x17\x0b1712010000Z").is_err());
+        assert!(parse(b"\x18\x0f19691231000000Z").is_err());
+    }
+
+    #[test]
+    fn test_revoked() {
+        let crl = crl("inter.revoked").unwrap();
+        assert_eq!(crl.this_update(), at(1512086400));
+        assert_eq!(crl.next_update(), Some(at(2524521600)));
+        assert!(crl.is_revoked(&[0x01, 0xc8]));
+        assert!(!crl.is_revoked(&[0x03, 0x15]));
+
+        let now = at(1600000000);
+        assert_eq!(crl.check_certificate(&cert("test-ca/rsa/end.cert"), now),
+                   Err(TLSError::CertificateRevoked));
+        assert_eq!(crl.check_certificate(&cert("test-ca/rsa/client.cert"), now),
+                   Ok(()));
+
+        // not covered by this CRL
+        assert_eq!(crl.check_certificate(&cert("test-ca/rsa/inter.cert"), now),
+                   Ok(()));
+    }
+
+    #[test]
+    fn test_not_revoked() {
+        let crl = crl("inter.empty").unwrap();
+        assert!(!crl.is_revoked(&[0x01, 0xc8]));
+        assert_eq!(crl.check_certificate(&cert("test-ca/rsa/end.cert"), at(1600000000)),
+                   Ok(()));
+    }
+
+    #[test]
+    fn test_currency() {
+        let crl = crl("inter.expired").unwrap();
+        assert!(!crl.is_current(at(1512086399)));
+        assert!(crl.is_current(at(1512086400)));
+        assert!(!crl.is_current(at(1514764800)));
+        assert_eq!(crl.check_certificate(&cert("test-ca/rsa/end.cert"), at(1600000000)),
+                   Err(TLSError::InvalidCRL("CRL is not current".to_string())));
+    }
+
+    #[test]
+    fn test_wrong_issuer() {
+        let der = read_file("test-ca/rsa/inter.revoked.crl.der");
+        assert!(CertificateRevocationList::from_der(&der, &cert("test-ca/rsa/ca.cert")).is_err());
+        assert!(CertificateRevocationList::from_der(&der, &cert("test-ca/rsa/end.cert")).is_err());
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let mut der = read_file("test-ca/rsa/inter.revoked.crl.der");
+        let last = der.len() - 1;
+        der[last] ^= 1;
+        assert_eq!(CertificateRevocationList::from_der(&der, &cert("test-ca/rsa/inter.cert"))
+                       .unwrap_err(),
+                   TLSError::WebPKIError(webpki::Error::InvalidSignatureForPublicKey));
+    }
+
+    fn delta_store() -> DeltaCrlStore {
+        DeltaCrlStore::new(&read_file("test-ca/rsa/inter.empty.crl.der"),
+                           cert("test-ca/rsa/inter.cert"))
+            .unwrap()
+    }
+
+    fn delta(name: &str) -> Vec<u8> {
+        read_file(&format!("test-ca/rsa/{}.crl.der", name))
+    }
+
+    #[test]
+    fn test_delta_crl_rejected_as_complete_crl() {
+        assert_eq!(crl("inter.delta1").unwrap_err(),
+                   TLSError::InvalidCRL("delta CRL given where a complete CRL is needed"
+                                        .to_string()));
+    }
+
+    #[test]
+    fn test_delta_crls_apply() {
+        let end = [0x01, 0xc8];
+        let mut store = delta_store();
+        assert!(!store.is_revoked(&end));
+        assert_eq!(store.crl().this_update(), at(1512086400));
+
+        store.apply_delta(&delta("inter.delta1")).unwrap();
+        assert!(store.is_revoked(&end));
+        assert_eq!(store.crl().this_update(), at(1514764800));
+        assert_eq!(store.crl().check_certificate(&cert("test-ca/rsa/end.cert"), at(1600000000)),
+                   Err(TLSError::CertificateRevoked));
+
+        // delta2 removes end.cert again
+        store.apply_delta(&delta("inter.delta2")).unwrap();
+        assert!(!store.is_revoked(&end));
+        assert_eq!(store.crl().this_update(), at(1517443200));
+    }
+
+    #[test]
+    fn test_delta_crls_must_be_newer() {
+        let mut store = delta_store();
+        store.apply_delta(&delta("inter.delta2")).unwrap();
+        assert_eq!(store.apply_delta(&delta("inter.delta1")),
+                   Err(TLSError::InvalidCRL("delta CRL is not newer".to_string())));
+        assert_eq!(store.apply_delta(&delta("inter.delta2")),
+                   Err(TLSError::InvalidCRL("delta CRL is not newer".to_string())));
+        assert!(!store.is_revoked(&[0x01, 0xc8]));
+    }
+
+    #[test]
+    fn test_delta_crl_for_other_base() {
+        let mut store = delta_store();
+        assert_eq!(store.apply_delta(&delta("inter.delta-wrong-base")),
+                   Err(TLSError::InvalidCRL("delta CRL is for a different base CRL"
+                                            .to_string())));
+    }
+
+    #[test]
+    fn test_complete_crl_is_not_delta() {
+        let mut store = delta_store();
+        assert_eq!(store.apply_delta(&delta("inter.revoked")),
+                   Err(TLSError::InvalidCRL("not a delta CRL".to_string())));
+    }
+
+    #[test]
+    fn test_delta_crl_signature_checked() {
+        let mut store = delta_store();
+        let mut der = delta("inter.delta1");
+        let last = der.len() - 1;
+        der[last] ^= 1;
+        assert!(store.apply_delta(&der).is_err());
+        assert!(!store.is_revoked(&[0x01, 0xc8]));
+    }
+
+    #[test]
+    fn test_compare_integers() {
+        assert_eq!(compare_integers(&[0x01], &[0x00, 0x01]), cmp::Ordering::Equal);
+        assert_eq!(compare_integers(&[0x02], &[0x01]), cmp::Ordering::Greater);
+        assert_eq!(compare_integers(&[0x00, 0x80], &[0x7f]), cmp::Ordering::Greater);
+        assert_eq!(compare_integers(&[0x01, 0x00], &[0xff]), cmp::Ordering::Greater);
+        assert_eq!(compare_integers(&[], &[0x01]), cmp::Ordering::Less);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let der = read_file("test-ca/rsa/inter.revoked.crl.der");
+        for len in 0..der.len() {
+            assert!(CertificateRevocationList::from_der(&der[..len],
+                                                        &cert("test-ca/rsa/inter.cert"))
+                    .is_err());
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use std::fmt;
+use std::io;
 use std::error::Error;
 use msgs::enums::{ContentType, HandshakeType, AlertDescription};
 use webpki;
 use sct;
+use ring;
 
 /// rustls reports protocol errors using this type.
 #[derive(Debug, PartialEq, Clone)]
@@ -61,6 +63,13 @@ pub enum TLSError {
     /// A catch-all error for unlikely errors.
     General(String),
 
+    /// A cryptographic operation, such as signing, failed.
+    CryptoError,
+
+    /// We're already doing as many handshakes as
+    /// `ServerConfig::max_concurrent_handshakes` allows.
+    TooManyHandshakes,
+
     /// We failed to figure out what time it currently is.
     FailedToGetCurrentTime,
 
@@ -73,6 +82,33 @@ pub enum TLSError {
 
     /// The peer sent an oversized record/fragment.
     PeerSentOversizedRecord,
+
+    /// The presented certificate has been revoked.
+    CertificateRevoked,
+
+    /// A certificate revocation list was unusable.
+    /// The parameter gives a hint why.
+    InvalidCRL(String),
+
+    /// This function doesn't work with the negotiated TLS
+    /// version.
+    WrongTlsVersion,
+
+    /// The client offered application protocols with ALPN,
+    /// but none of them were ones the server supports.
+    NoApplicationProtocol {
+        /// The protocols the client offered
+        offered: Vec<Vec<u8>>,
+        /// The protocols the server supports
+        supported: Vec<Vec<u8>>,
+    },
+}
+
+fn join_protocols(protocols: &[Vec<u8>]) -> String {
+    protocols.iter()
+        .map(|p| format!("{:?}", String::from_utf8_lossy(p)))
+        .collect::<Vec<String>>()
+        .join(", ")
 }
 
 fn join<T: fmt::Debug>(items: &[T]) -> String {
@@ -99,17 +135,29 @@ impl fmt::Display for TLSError {
                        got_type,
                        join::<HandshakeType>(expect_types))
             }
+            TLSError::NoApplicationProtocol { ref offered, ref supported } => {
+                write!(f,
+                       "{}: client offered [{}], server supports [{}]",
+                       self.description(),
+                       join_protocols(offered),
+                       join_protocols(supported))
+            }
             TLSError::CorruptMessagePayload(ref typ) => {
                 write!(f, "{} of type {:?}", self.description(), typ)
             }
             TLSError::PeerIncompatibleError(ref why) |
-            TLSError::PeerMisbehavedError(ref why) => write!(f, "{}: {}", self.description(), why),
+            TLSError::PeerMisbehavedError(ref why) |
+            TLSError::InvalidCRL(ref why) => write!(f, "{}: {}", self.description(), why),
             TLSError::AlertReceived(ref alert) => write!(f, "{}: {:?}", self.description(), alert),
             TLSError::WebPKIError(ref err) => write!(f, "{}: {:?}", self.description(), err),
             TLSError::CorruptMessage |
             TLSError::NoCertificatesPresented |
             TLSError::DecryptError |
             TLSError::PeerSentOversizedRecord |
+            TLSError::CertificateRevoked |
+            TLSError::TooManyHandshakes |
+            TLSError::CryptoError |
+            TLSError::WrongTlsVersion |
             TLSError::HandshakeNotComplete => write!(f, "{}", self.description()),
             _ => write!(f, "{}: {:?}", self.description(), self),
         }
@@ -133,11 +181,54 @@ impl Error for TLSError {
             TLSError::WebPKIError(_) => "invalid certificate",
             TLSError::InvalidSCT(_) => "invalid certificate timestamp",
             TLSError::General(_) => "unexpected error", // (please file a bug),
+            TLSError::CryptoError => "cryptographic operation failed",
             TLSError::FailedToGetCurrentTime => "failed to get current time",
             TLSError::InvalidDNSName(_) => "invalid DNS name",
             TLSError::HandshakeNotComplete => "handshake not complete",
             TLSError::PeerSentOversizedRecord => "peer sent excess record size",
+            TLSError::CertificateRevoked => "certificate revoked",
+            TLSError::TooManyHandshakes => "too many handshakes in progress",
+            TLSError::InvalidCRL(_) => "invalid certificate revocation list",
+            TLSError::NoApplicationProtocol { .. } => "no application protocol in common",
+            TLSError::WrongTlsVersion => "not supported for this TLS version",
+        }
+    }
+}
+
+impl From<TLSError> for io::Error {
+    /// Wrap `err` in an `io::Error`, of a kind suggested by the error:
+    /// `ConnectionRefused` if the peer sent no certificates,
+    /// `ConnectionAborted` if it sent us a fatal alert, `InvalidData`
+    /// for a record we couldn't decrypt, and `Other` for anything
+    /// else.
+    fn from(err: TLSError) -> io::Error {
+        let kind = match err {
+            TLSError::NoCertificatesPresented => io::ErrorKind::ConnectionRefused,
+            TLSError::AlertReceived(_) => io::ErrorKind::ConnectionAborted,
+            TLSError::DecryptError => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl From<io::Error> for TLSError {
+    /// Unwrap the `TLSError` inside `err`, if it is one of ours;
+    /// otherwise, describe it with a `TLSError::General`.
+    fn from(err: io::Error) -> TLSError {
+        if let Some(inner) = err.get_ref().and_then(|inner| inner.downcast_ref::<TLSError>()) {
+            return inner.clone();
         }
+
+        TLSError::General(format!("IO error: {}", err))
+    }
+}
+
+impl From<ring::error::Unspecified> for TLSError {
+    /// ring tells us nothing about why an operation failed,
+    /// so this is always `TLSError::CryptoError`.
+    fn from(_: ring::error::Unspecified) -> TLSError {
+        TLSError::CryptoError
     }
 }
 
@@ -169,10 +260,19 @@ mod tests {
                        TLSError::WebPKIError(webpki::Error::ExtensionValueInvalid),
                        TLSError::InvalidSCT(sct::Error::MalformedSCT),
                        TLSError::General("undocumented error".to_string()),
+                       TLSError::CryptoError,
                        TLSError::FailedToGetCurrentTime,
                        TLSError::InvalidDNSName("dns something".to_string()),
                        TLSError::HandshakeNotComplete,
-                       TLSError::PeerSentOversizedRecord];
+                       TLSError::PeerSentOversizedRecord,
+                       TLSError::CertificateRevoked,
+                       TLSError::InvalidCRL("crl something".to_string()),
+                       TLSError::NoApplicationProtocol {
+                           offered: vec![b"h2".to_vec()],
+                           supported: vec![b"http/1.1".to_vec()],
+                       },
+                       TLSError::WrongTlsVersion,
+                       TLSError::TooManyHandshakes];
 
         for err in all {
             println!("{:?}:", err);
@@ -180,4 +280,35 @@ mod tests {
             println!("  fmt '{}'", err);
         }
     }
+
+    #[test]
+    fn io_error_conversions() {
+        use super::TLSError;
+        use std::io;
+        use msgs::enums::AlertDescription;
+
+        let kinds = vec![(TLSError::NoCertificatesPresented, io::ErrorKind::ConnectionRefused),
+                         (TLSError::AlertReceived(AlertDescription::HandshakeFailure),
+                          io::ErrorKind::ConnectionAborted),
+                         (TLSError::DecryptError, io::ErrorKind::InvalidData),
+                         (TLSError::CorruptMessage, io::ErrorKind::Other)];
+
+        for (err, kind) in kinds {
+            let io_err = io::Error::from(err.clone());
+            assert_eq!(io_err.kind(), kind);
+            assert_eq!(TLSError::from(io_err), err);
+        }
+
+        let err = TLSError::from(io::Error::new(io::ErrorKind::TimedOut, "too slow"));
+        assert_eq!(err, TLSError::General("IO error: too slow".to_string()));
+    }
+
+    #[test]
+    fn ring_error_conversion() {
+        use super::TLSError;
+        use ring;
+
+        assert_eq!(TLSError::from(ring::error::Unspecified), TLSError::CryptoError);
+        assert_eq!(TLSError::CryptoError.to_string(), "cryptographic operation failed");
+    }
 }
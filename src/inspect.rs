@@ -0,0 +1,142 @@
+use std::io::{self, Read, Write};
+
+use session::Session;
+use suites::SupportedCipherSuite;
+use msgs::enums::ProtocolVersion;
+use error::TLSError;
+use key;
+
+/// Something which observes the plaintext application data
+/// sent and received on a session, for example for auditing.
+///
+/// The hooks see the data but cannot change it.
+pub trait InspectorMiddleware : Send + Sync {
+    /// Called with plaintext the application has written to the
+    /// session, before it is encrypted.  `data` is exactly the
+    /// part of the write the session accepted.
+    fn on_plaintext_write(&self, data: &[u8]);
+
+    /// Called with plaintext the application has read from the
+    /// session, after it was decrypted.
+    fn on_plaintext_read(&self, data: &[u8]);
+}
+
+/// A `Session` which passes the application data going through
+/// session `S` to the inspector `I`.
+///
+/// Everything else is passed straight through to `S`, so this
+/// can be used anywhere a `Session` can.
+#[derive(Debug)]
+pub struct SessionWithInspector<S: Session, I: InspectorMiddleware> {
+    sess: S,
+    inspector: I,
+}
+
+impl<S: Session, I: InspectorMiddleware> SessionWithInspector<S, I> {
+    /// Wrap `sess`, reporting its application data to `inspector`.
+    pub fn new(sess: S, inspector: I) -> SessionWithInspector<S, I> {
+        SessionWithInspector { sess, inspector }
+    }
+
+    /// The wrapped session.
+    pub fn get_ref(&self) -> &S {
+        &self.sess
+    }
+
+    /// The wrapped session.  Application data read or written
+    /// through this reference is not inspected.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.sess
+    }
+
+    /// The inspector.
+    pub fn get_inspector(&self) -> &I {
+        &self.inspector
+    }
+
+    /// Unwrap, returning the session and inspector.
+    pub fn into_inner(self) -> (S, I) {
+        (self.sess, self.inspector)
+    }
+}
+
+impl<S: Session, I: InspectorMiddleware> Read for SessionWithInspector<S, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.sess.read(buf)?;
+        if len > 0 {
+            self.inspector.on_plaintext_read(&buf[..len]);
+        }
+        Ok(len)
+    }
+}
+
+impl<S: Session, I: InspectorMiddleware> Write for SessionWithInspector<S, I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.sess.write(buf)?;
+        if len > 0 {
+            self.inspector.on_plaintext_write(&buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sess.flush()
+    }
+}
+
+impl<S: Session, I: InspectorMiddleware> Session for SessionWithInspector<S, I> {
+    fn read_tls(&mut self, rd: &mut Read) -> io::Result<usize> {
+        self.sess.read_tls(rd)
+    }
+
+    fn write_tls(&mut self, wr: &mut Write) -> io::Result<usize> {
+        self.sess.write_tls(wr)
+    }
+
+    fn process_new_packets(&mut self) -> Result<(), TLSError> {
+        self.sess.process_new_packets()
+    }
+
+    fn wants_read(&self) -> bool {
+        self.sess.wants_read()
+    }
+
+    fn wants_write(&self) -> bool {
+        self.sess.wants_write()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.sess.is_handshaking()
+    }
+
+    fn set_buffer_limit(&mut self, limit: usize) {
+        self.sess.set_buffer_limit(limit)
+    }
+
+    fn send_close_notify(&mut self) {
+        self.sess.send_close_notify()
+    }
+
+    fn get_peer_certificates(&self) -> Option<key::PeerCertificates> {
+        self.sess.get_peer_certificates()
+    }
+
+    fn get_alpn_protocol(&self) -> Option<&str> {
+        self.sess.get_alpn_protocol()
+    }
+
+    fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+        self.sess.get_protocol_version()
+    }
+
+    fn export_keying_material(&self,
+                              output: &mut [u8],
+                              label: &[u8],
+                              context: Option<&[u8]>) -> Result<(), TLSError> {
+        self.sess.export_keying_material(output, label, context)
+    }
+
+    fn get_negotiated_ciphersuite(&self) -> Option<&'static SupportedCipherSuite> {
+        self.sess.get_negotiated_ciphersuite()
+    }
+}
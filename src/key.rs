@@ -1,4 +1,15 @@
 use std::fmt;
+use std::ops::Deref;
+use std::time::SystemTime;
+
+use base64;
+use ring::digest;
+
+use crl;
+use error::TLSError;
+use msgs::base::read_certificate_chain;
+use msgs::codec::Reader;
+use msgs::enums::ContentType;
 
 /// This type contains a private key by value.
 ///
@@ -24,6 +35,43 @@ impl AsRef<[u8]> for Certificate {
     }
 }
 
+impl Deref for Certificate {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<'a> From<CertificateDer<'a>> for Certificate {
+    fn from(der: CertificateDer<'a>) -> Certificate {
+        Certificate(der.0.to_vec())
+    }
+}
+
+impl Certificate {
+    /// Decode the subject, validity period and serial number of
+    /// this certificate.  This doesn't verify the certificate.
+    pub fn parse_info(&self) -> Result<CertInfo, TLSError> {
+        let parsed = crl::parse_cert(self)?;
+        Ok(CertInfo {
+            subject: crl::name_to_string(&parsed.subject)?,
+            not_before: parsed.not_before,
+            not_after: parsed.not_after,
+            serial: parsed.serial,
+        })
+    }
+}
+
+/// The thumbprint of `cert` used by RFC8705 to bind OAuth access
+/// tokens to a client certificate, as in the `x5t#S256` claim: the
+/// SHA-256 hash of its DER encoding, base64url-encoded without
+/// padding.
+pub fn client_cert_thumbprint(cert: &Certificate) -> String {
+    let hash = digest::digest(&digest::SHA256, &cert.0);
+    base64::encode_config(hash.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
 impl fmt::Debug for Certificate {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use super::bs_debug::BsDebug;
@@ -31,12 +79,193 @@ impl fmt::Debug for Certificate {
     }
 }
 
+/// This type borrows a single certificate from a buffer which
+/// holds it, such as a received message, to avoid copying it.
+///
+/// The certificate must be DER-encoded X.509.  Use
+/// `Certificate::from` to get an owned copy.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct CertificateDer<'a>(pub &'a [u8]);
+
+impl<'a> CertificateDer<'a> {
+    /// Parse `bytes`, a TLS certificate_list (as sent in a TLS1.2
+    /// Certificate message), into its certificates.  They borrow
+    /// from `bytes`.
+    pub fn parse_chain(bytes: &'a [u8]) -> Result<Vec<CertificateDer<'a>>, TLSError> {
+        let mut rd = Reader::init(bytes);
+        match read_certificate_chain(&mut rd) {
+            Some(chain) if !rd.any_left() => Ok(chain),
+            _ => Err(TLSError::CorruptMessagePayload(ContentType::Handshake)),
+        }
+    }
+}
+
+impl<'a> AsRef<[u8]> for CertificateDer<'a> {
+    fn as_ref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+impl<'a> Deref for CertificateDer<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+impl<'a> fmt::Debug for CertificateDer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use super::bs_debug::BsDebug;
+        f.debug_tuple("CertificateDer").field(&BsDebug(self.0)).finish()
+    }
+}
+
+/// The certificate chain sent by our peer: the end-entity
+/// certificate, then any intermediates.  It is never empty.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PeerCertificates(Vec<Certificate>);
+
+impl PeerCertificates {
+    /// Wrap `chain`, or return None if it is empty.
+    pub(crate) fn new(chain: Vec<Certificate>) -> Option<PeerCertificates> {
+        if chain.is_empty() {
+            None
+        } else {
+            Some(PeerCertificates(chain))
+        }
+    }
+
+    /// The peer's end-entity certificate.
+    pub fn end_entity(&self) -> &Certificate {
+        &self.0[0]
+    }
+
+    /// The rest of the chain, after the end-entity certificate.
+    pub fn intermediates(&self) -> &[Certificate] {
+        &self.0[1..]
+    }
+
+    /// The number of certificates in the chain.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Always false: a chain has at least its end-entity certificate.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Unwrap, returning the whole chain.
+    pub fn into_vec(self) -> Vec<Certificate> {
+        self.0
+    }
+}
+
+impl PartialEq<Vec<Certificate>> for PeerCertificates {
+    fn eq(&self, other: &Vec<Certificate>) -> bool {
+        self.0 == *other
+    }
+}
+
+impl IntoIterator for PeerCertificates {
+    type Item = Certificate;
+    type IntoIter = ::std::vec::IntoIter<Certificate>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a PeerCertificates {
+    type Item = &'a Certificate;
+    type IntoIter = ::std::slice::Iter<'a, Certificate>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Some information about a certificate, for display.  See
+/// `Certificate::parse_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertInfo {
+    /// The subject name, formatted as in RFC4514: for example,
+    /// `"CN=example.com,O=Example Inc,C=GB"`.
+    pub subject: String,
+
+    /// The start of the validity period.
+    pub not_before: SystemTime,
+
+    /// The end of the validity period.
+    pub not_after: SystemTime,
+
+    /// The serial number, as the big-endian bytes of the DER
+    /// INTEGER.
+    pub serial: Vec<u8>,
+}
+
 #[cfg(test)]
 mod test {
-    use super::Certificate;
+    use super::{Certificate, CertificateDer};
+    use std::fs;
+    use std::io::Read;
+    use std::time::{Duration, UNIX_EPOCH};
+    use pemfile;
+
+    fn read_file(path: &str) -> Vec<u8> {
+        let mut f = fs::File::open(path).unwrap();
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes).unwrap();
+        bytes
+    }
 
     #[test]
     fn certificate_debug() {
         assert_eq!("Certificate(b\"ab\")", format!("{:?}", Certificate(b"ab".to_vec())));
     }
+
+    #[test]
+    fn certificate_info() {
+        let pem = read_file("test-ca/rsa/end.cert");
+        let cert = pemfile::certs(&mut &pem[..]).unwrap().remove(0);
+        let info = cert.parse_info().unwrap();
+        assert_eq!(info.subject, "CN=testserver.com");
+        assert_eq!(info.not_before, UNIX_EPOCH + Duration::from_secs(1514395185));
+        assert_eq!(info.not_after, UNIX_EPOCH + Duration::from_secs(1687195185));
+        assert_eq!(info.serial, vec![ 0x01, 0xc8 ]);
+    }
+
+    #[test]
+    fn certificate_info_subjects() {
+        let info = |name| Certificate(read_file(name)).parse_info().unwrap().subject;
+        assert_eq!(info("src/testdata/cert-google.0.der"),
+                   "CN=*.google.com,O=Google Inc,L=Mountain View,ST=California,C=US");
+        assert_eq!(info("src/testdata/cert-wikipedia.0.der"),
+                   "CN=*.wikipedia.org,O=Wikimedia Foundation\\, Inc.,L=San Francisco,\
+                    ST=California,C=US");
+        assert_eq!(info("src/testdata/cert-hn.0.der"),
+                   "CN=*.ycombinator.com,OU=PositiveSSL Wildcard,OU=Domain Control Validated");
+    }
+
+    #[test]
+    fn certificate_der_parse_chain() {
+        let chain = [0x00, 0x00, 0x0a,
+                     0x00, 0x00, 0x02, 0xaa, 0xbb,
+                     0x00, 0x00, 0x02, 0xcc, 0xdd];
+        let certs = CertificateDer::parse_chain(&chain).unwrap();
+        assert_eq!(certs, vec![ CertificateDer(&chain[6..8]), CertificateDer(&chain[11..13]) ]);
+        assert_eq!(&*certs[1], &[ 0xcc, 0xdd ]);
+        assert_eq!(Certificate::from(certs[0]), Certificate(vec![ 0xaa, 0xbb ]));
+        assert_eq!("CertificateDer(b\"\\xaa\\xbb\")", format!("{:?}", certs[0]));
+
+        assert!(CertificateDer::parse_chain(&[]).is_err());
+        assert!(CertificateDer::parse_chain(&chain[..12]).is_err());
+        assert!(CertificateDer::parse_chain(&[0x00, 0x00, 0x00]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn certificate_info_rejects_garbage() {
+        assert!(Certificate(vec![ 0x30, 0x00 ]).parse_info().is_err());
+    }
 }
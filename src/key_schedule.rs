@@ -43,6 +43,7 @@ pub struct KeySchedule {
     pub current_client_traffic_secret: Vec<u8>,
     pub current_server_traffic_secret: Vec<u8>,
     pub current_exporter_secret: Vec<u8>,
+    pub resumption_master_secret: Vec<u8>,
 }
 
 impl KeySchedule {
@@ -61,6 +62,7 @@ impl KeySchedule {
             current_server_traffic_secret: Vec::new(),
             current_client_traffic_secret: Vec::new(),
             current_exporter_secret: Vec::new(),
+            resumption_master_secret: Vec::new(),
         }
     }
 
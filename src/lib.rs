@@ -235,10 +235,18 @@ mod cipher;
 mod key_schedule;
 mod session;
 mod stream;
+mod inspect;
+mod meter;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "debug-transcript")]
+mod transcript;
 mod pemfile;
 mod x509;
 mod anchors;
 mod verify;
+mod crl;
+mod pathbuild;
 #[cfg(test)]
 mod verifybench;
 mod handshake;
@@ -266,33 +274,71 @@ pub mod internal {
 // The public interface is:
 pub use msgs::enums::ProtocolVersion;
 pub use msgs::enums::SignatureScheme;
+pub use msgs::enums::SignatureAlgorithm;
 pub use msgs::enums::CipherSuite;
+pub use msgs::enums::NamedGroup;
+pub use msgs::enums::MaxFragmentLen;
+pub use msgs::enums::AlertDescription;
 pub use error::TLSError;
-pub use session::Session;
+pub use session::{Session, AlertPolicy};
 pub use stream::Stream;
+pub use stream::{TlsStream, TlsClientStream, TlsServerStream};
+pub use stream::{connect, accept, TlsAcceptor};
+pub use util::detect_tls;
+pub use inspect::{InspectorMiddleware, SessionWithInspector};
+pub use meter::{BytesMeter, AtomicMeter, MeteredTlsStream};
+#[cfg(feature = "websocket")]
+pub use websocket::WsTlsAdapter;
+#[cfg(feature = "debug-transcript")]
+pub use transcript::{Direction, HandshakeMessageSummary};
+#[cfg(feature = "debug-transcript")]
+pub use msgs::enums::HandshakeType;
 pub use anchors::{DistinguishedNames, RootCertStore};
 pub use client::StoresClientSessions;
 pub use client::handy::{NoClientSessionStorage, ClientSessionMemoryCache};
-pub use client::{ClientConfig, ClientSession};
+pub use client::handy::FileClientSessionStorage;
+pub use client::{ClientConfig, ClientSession, ConnectOptions};
+pub use client::pool::TlsConnectionPool;
 pub use client::ResolvesClientCert;
-pub use server::StoresServerSessions;
+pub use server::{StoresServerSessions, StoredSessions};
 pub use server::handy::{NoServerSessionStorage, ServerSessionMemoryCache};
-pub use server::{ServerConfig, ServerSession};
+pub use server::handy::VirtualHostSessionCache;
+pub use server::handy::EncryptedSessionStore;
+pub use server::{ServerConfig, ServerSession, SessionCheckpoint, ExportedSession};
+pub use server::authenticated::ClientAuthenticatedSession;
 pub use server::handy::ResolvesServerCertUsingSNI;
-pub use server::ResolvesServerCert;
-pub use server::ProducesTickets;
+pub use server::handy::{AcmeTlsAlpnResponder, ACME_TLS_ALPN_NAME};
+pub use server::handy::AlpnResolvesServerCert;
+pub use server::handy::{HandshakeSemaphore, HandshakePermit};
+#[cfg(feature = "dev")]
+#[allow(deprecated)]
+pub use server::devcert::DevCertResolver;
+pub use server::{ResolvesServerCert, ClientHello};
+pub use server::{ConnectionInfo, ConnectionId};
+pub use server::{ProducesTickets, TicketAddressBinding};
 pub use ticketer::Ticketer;
+pub use ticketer::{ClusterTicketRotator, TicketKey, TicketKeySet};
+pub use verify::verify_certificate_chain;
+pub use verify::{parse_sct_list, check_sct, SignedCertificateTimestamp};
+pub use crl::{CertificateRevocationList, DeltaCrlStore};
+pub use pathbuild::{PathBuilder, CertFetcher};
 pub use verify::{NoClientAuth, AllowAnyAuthenticatedClient,
                  AllowAnyAnonymousOrAuthenticatedClient};
 pub use suites::{ALL_CIPHERSUITES, SupportedCipherSuite};
-pub use key::{Certificate, PrivateKey};
+pub use key::{Certificate, CertificateDer, PeerCertificates, PrivateKey, CertInfo};
+pub use key::client_cert_thumbprint;
 
 /// Message signing interfaces and implementations.
 pub mod sign;
 
+pub use verify::VerifiedClientCertChain;
 #[cfg(feature = "dangerous_configuration")]
 pub use verify::{ServerCertVerifier, ServerCertVerified,
     ClientCertVerifier, ClientCertVerified};
 #[cfg(feature = "dangerous_configuration")]
+pub use verify::CrlCertVerifier;
+#[cfg(feature = "dangerous_configuration")]
+pub use verify::{SignatureVerifier, HandshakeSignatureValid};
+#[cfg(feature = "dangerous_configuration")]
 pub use client::danger::DangerousClientConfig;
 
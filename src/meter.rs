@@ -0,0 +1,107 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Something which counts the plaintext bytes read from and
+/// written to a stream, for example for billing.
+pub trait BytesMeter : Send + Sync {
+    /// Called after each successful read, with the number of
+    /// bytes read.
+    fn record_read(&self, bytes: usize);
+
+    /// Called after each successful write, with the number of
+    /// bytes written.
+    fn record_write(&self, bytes: usize);
+}
+
+/// A `BytesMeter` which keeps running totals.
+#[derive(Debug, Default)]
+pub struct AtomicMeter {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl AtomicMeter {
+    /// Make a new `AtomicMeter`, with both totals zero.
+    pub fn new() -> AtomicMeter {
+        AtomicMeter::default()
+    }
+
+    /// The total number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    /// The total number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+impl BytesMeter for AtomicMeter {
+    fn record_read(&self, bytes: usize) {
+        self.read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn record_write(&self, bytes: usize) {
+        self.written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// A stream which reports the plaintext going through `IO`,
+/// usually a `TlsStream`, to the meter `M`.
+///
+/// Reads and writes are passed straight to `IO`; the meter is told
+/// how many bytes each successful one transferred.
+#[derive(Debug)]
+pub struct MeteredTlsStream<IO: Read + Write, M: BytesMeter> {
+    io: IO,
+    meter: M,
+}
+
+impl<IO: Read + Write, M: BytesMeter> MeteredTlsStream<IO, M> {
+    /// Wrap `io`, reporting its traffic to `meter`.
+    pub fn new(io: IO, meter: M) -> MeteredTlsStream<IO, M> {
+        MeteredTlsStream { io, meter }
+    }
+
+    /// The wrapped stream.
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// The wrapped stream.  Data read or written through this
+    /// reference is not metered.
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    /// The meter.
+    pub fn get_meter(&self) -> &M {
+        &self.meter
+    }
+
+    /// Unwrap, returning the stream and meter.
+    pub fn into_inner(self) -> (IO, M) {
+        (self.io, self.meter)
+    }
+}
+
+impl<IO: Read + Write, M: BytesMeter> Read for MeteredTlsStream<IO, M> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.io.read(buf)?;
+        self.meter.record_read(len);
+        Ok(len)
+    }
+}
+
+impl<IO: Read + Write, M: BytesMeter> Write for MeteredTlsStream<IO, M> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.io.write(buf)?;
+        self.meter.record_write(len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
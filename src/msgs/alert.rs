@@ -1,5 +1,7 @@
 use msgs::enums::{AlertLevel, AlertDescription};
 use msgs::codec::{Codec, Reader};
+use std::convert::TryFrom;
+use std::fmt;
 
 #[derive(Debug)]
 pub struct AlertMessagePayload {
@@ -29,3 +31,78 @@ impl AlertMessagePayload {
         1 + 1
     }
 }
+
+impl AlertDescription {
+    /// The alert with IANA code `code`, or None if we don't know
+    /// of one.
+    pub fn from_iana(code: u8) -> Option<AlertDescription> {
+        match AlertDescription::read_bytes(&[code]) {
+            Some(AlertDescription::Unknown(_)) | None => None,
+            known => known,
+        }
+    }
+
+    /// The name of this alert in the TLS RFCs, like
+    /// `handshake_failure`.  None for `Unknown` alerts.
+    fn name(&self) -> Option<&'static str> {
+        Some(match *self {
+            AlertDescription::CloseNotify => "close_notify",
+            AlertDescription::UnexpectedMessage => "unexpected_message",
+            AlertDescription::BadRecordMac => "bad_record_mac",
+            AlertDescription::DecryptionFailed => "decryption_failed",
+            AlertDescription::RecordOverflow => "record_overflow",
+            AlertDescription::DecompressionFailure => "decompression_failure",
+            AlertDescription::HandshakeFailure => "handshake_failure",
+            AlertDescription::NoCertificate => "no_certificate",
+            AlertDescription::BadCertificate => "bad_certificate",
+            AlertDescription::UnsupportedCertificate => "unsupported_certificate",
+            AlertDescription::CertificateRevoked => "certificate_revoked",
+            AlertDescription::CertificateExpired => "certificate_expired",
+            AlertDescription::CertificateUnknown => "certificate_unknown",
+            AlertDescription::IllegalParameter => "illegal_parameter",
+            AlertDescription::UnknownCA => "unknown_ca",
+            AlertDescription::AccessDenied => "access_denied",
+            AlertDescription::DecodeError => "decode_error",
+            AlertDescription::DecryptError => "decrypt_error",
+            AlertDescription::ExportRestriction => "export_restriction",
+            AlertDescription::ProtocolVersion => "protocol_version",
+            AlertDescription::InsufficientSecurity => "insufficient_security",
+            AlertDescription::InternalError => "internal_error",
+            AlertDescription::InappropriateFallback => "inappropriate_fallback",
+            AlertDescription::UserCanceled => "user_canceled",
+            AlertDescription::NoRenegotiation => "no_renegotiation",
+            AlertDescription::MissingExtension => "missing_extension",
+            AlertDescription::UnsupportedExtension => "unsupported_extension",
+            AlertDescription::CertificateUnobtainable => "certificate_unobtainable",
+            AlertDescription::UnrecognisedName => "unrecognized_name",
+            AlertDescription::BadCertificateStatusResponse => "bad_certificate_status_response",
+            AlertDescription::BadCertificateHashValue => "bad_certificate_hash_value",
+            AlertDescription::UnknownPSKIdentity => "unknown_psk_identity",
+            AlertDescription::CertificateRequired => "certificate_required",
+            AlertDescription::NoApplicationProtocol => "no_application_protocol",
+            AlertDescription::Unknown(_) => return None,
+        })
+    }
+}
+
+/// Prints the name the TLS RFCs use for the alert, for
+/// example `handshake_failure`.  Unknown alerts print as
+/// `unknown_alert(0x..)`.
+impl fmt::Display for AlertDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "unknown_alert({:#04x})", self.get_u8()),
+        }
+    }
+}
+
+/// Fails, returning the code, if it isn't a known alert.
+impl TryFrom<u8> for AlertDescription {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<AlertDescription, u8> {
+        AlertDescription::from_iana(code)
+            .ok_or(code)
+    }
+}
@@ -42,13 +42,35 @@ impl Codec for key::Certificate {
     }
 
     fn read(r: &mut Reader) -> Option<key::Certificate> {
-        let len = try_ret!(codec::read_u24(r)) as usize;
-        let mut sub = try_ret!(r.sub(len));
-        let body = sub.rest().to_vec();
-        Some(key::Certificate(body))
+        read_certificate_der(r).map(key::Certificate::from)
     }
 }
 
+/// Read a u24-length-prefixed certificate, borrowing it from `r`.
+pub fn read_certificate_der<'a>(r: &mut Reader<'a>) -> Option<key::CertificateDer<'a>> {
+    let len = try_ret!(codec::read_u24(r)) as usize;
+    let mut sub = try_ret!(r.sub(len));
+    Some(key::CertificateDer(sub.rest()))
+}
+
+/// Read a TLS1.2-style certificate_list, borrowing each certificate
+/// from `r`.
+pub fn read_certificate_chain<'a>(r: &mut Reader<'a>) -> Option<Vec<key::CertificateDer<'a>>> {
+    let len = try_ret!(codec::read_u24(r)) as usize;
+    // 64KB of certificates is plenty, 16MB is obviously silly
+    if len > 0x10000 {
+        return None;
+    }
+
+    let mut sub = try_ret!(r.sub(len));
+    let mut ret = Vec::new();
+    while sub.any_left() {
+        ret.push(try_ret!(read_certificate_der(&mut sub)));
+    }
+
+    Some(ret)
+}
+
 /// An arbitrary, unknown-content, u24-length-prefixed payload
 #[derive(Debug, Clone, PartialEq)]
 pub struct PayloadU24(pub Vec<u8>);
@@ -764,3 +764,17 @@ enum_builder! {@U8
         OCSP => 0x01
     }
 }
+
+/// The `MaxFragmentLength` TLS protocol enum, from RFC6066.
+/// Each variant is named after the base-2 logarithm of the
+/// fragment length it requests.
+/// The `Unknown` item is used when processing unrecognised ordinals.
+enum_builder! {@U8
+    EnumName: MaxFragmentLen;
+    EnumVal{
+        Bits9 => 0x01,
+        Bits10 => 0x02,
+        Bits11 => 0x03,
+        Bits12 => 0x04
+    }
+}
@@ -76,4 +76,42 @@ fn test_enums() {
     test_enum8::<KeyUpdateRequest>(KeyUpdateRequest::UpdateNotRequested,
                                    KeyUpdateRequest::UpdateRequested);
     test_enum8::<CertificateStatusType>(CertificateStatusType::OCSP, CertificateStatusType::OCSP);
+    test_enum8::<MaxFragmentLen>(MaxFragmentLen::Bits9, MaxFragmentLen::Bits12);
+}
+
+#[test]
+fn test_alert_description_names() {
+    use std::convert::TryFrom;
+
+    assert_eq!(AlertDescription::HandshakeFailure.to_string(), "handshake_failure");
+    assert_eq!(AlertDescription::CertificateUnknown.to_string(), "certificate_unknown");
+    assert_eq!(AlertDescription::UnknownPSKIdentity.to_string(), "unknown_psk_identity");
+    assert_eq!(AlertDescription::Unknown(0xfe).to_string(), "unknown_alert(0xfe)");
+
+    assert_eq!(AlertDescription::from_iana(0x2e), Some(AlertDescription::CertificateUnknown));
+    assert_eq!(AlertDescription::from_iana(0xfe), None);
+    assert_eq!(AlertDescription::try_from(0x28), Ok(AlertDescription::HandshakeFailure));
+    assert_eq!(AlertDescription::try_from(0xfe), Err(0xfe));
+}
+
+#[test]
+fn test_enums_hash() {
+    use std::collections::{HashMap, HashSet};
+
+    let mut schemes = HashMap::new();
+    schemes.insert(SignatureScheme::RSA_PSS_SHA256, "rsa");
+    schemes.insert(SignatureScheme::ECDSA_NISTP256_SHA256, "ecdsa");
+    schemes.insert(SignatureScheme::Unknown(0xfefe), "unknown");
+    assert_eq!(schemes.get(&SignatureScheme::ECDSA_NISTP256_SHA256), Some(&"ecdsa"));
+    assert_eq!(schemes.get(&SignatureScheme::read_bytes(&[0x08, 0x04]).unwrap()),
+               Some(&"rsa"));
+    assert_eq!(schemes.get(&SignatureScheme::Unknown(0xfefe)), Some(&"unknown"));
+    assert_eq!(schemes.get(&SignatureScheme::ED25519), None);
+
+    let mut groups = HashSet::new();
+    assert!(groups.insert(NamedGroup::X25519));
+    assert!(groups.insert(NamedGroup::secp256r1));
+    assert!(!groups.insert(NamedGroup::X25519));
+    assert!(groups.contains(&NamedGroup::read_bytes(&[0x00, 0x17]).unwrap()));
+    assert!(!groups.contains(&NamedGroup::secp384r1));
 }
@@ -1,4 +1,5 @@
 
+use std::cmp;
 use std::collections::VecDeque;
 use msgs::message::{BorrowMessage, Message, MessagePayload};
 use msgs::enums::{ContentType, ProtocolVersion};
@@ -20,6 +21,12 @@ impl MessageFragmenter {
         MessageFragmenter { max_frag: max_fragment_len }
     }
 
+    /// Lower the maximum fragment size to `max_fragment_len`,
+    /// unless it is already smaller.
+    pub fn limit_fragment_len(&mut self, max_fragment_len: usize) {
+        self.max_frag = cmp::min(self.max_frag, max_fragment_len);
+    }
+
     /// Take the Message `msg` and re-fragment it into new
     /// messages whose fragment is no more than max_frag.
     /// The new messages are appended to the `out` deque.
@@ -137,4 +144,31 @@ mod tests {
                b"\x01\x02\x03\x04\x05\x06\x07\x08");
         assert_eq!(q.len(), 0);
     }
+
+    #[test]
+    fn limit_only_lowers() {
+        let m = Message {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_2,
+            payload: MessagePayload::new_opaque(b"\x01\x02\x03\x04\x05\x06\x07\x08".to_vec()),
+        };
+
+        let mut frag = MessageFragmenter::new(5);
+        frag.limit_fragment_len(8);
+        frag.limit_fragment_len(4);
+        frag.limit_fragment_len(6);
+        let mut q = VecDeque::new();
+        frag.fragment(m, &mut q);
+        msg_eq(q.pop_front(),
+               PACKET_OVERHEAD + 4,
+               &ContentType::Handshake,
+               &ProtocolVersion::TLSv1_2,
+               b"\x01\x02\x03\x04");
+        msg_eq(q.pop_front(),
+               PACKET_OVERHEAD + 4,
+               &ContentType::Handshake,
+               &ProtocolVersion::TLSv1_2,
+               b"\x05\x06\x07\x08");
+        assert_eq!(q.len(), 0);
+    }
 }
@@ -5,15 +5,19 @@ use msgs::enums::{SignatureScheme, KeyUpdateRequest, NamedGroup};
 use msgs::enums::{ClientCertificateType, CertificateStatusType};
 use msgs::enums::ECCurveType;
 use msgs::enums::PSKKeyExchangeMode;
+use msgs::enums::MaxFragmentLen;
 use msgs::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
+use msgs::base::read_certificate_chain;
 use msgs::codec;
 use msgs::codec::{Codec, Reader};
 use std;
 use std::fmt;
+use std::hash;
 use std::io::Write;
 use std::collections;
 use std::mem;
 use key;
+use error::TLSError;
 use untrusted;
 use webpki;
 
@@ -121,6 +125,14 @@ impl PartialEq for SessionID {
     }
 }
 
+impl Eq for SessionID {}
+
+impl hash::Hash for SessionID {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.data[..self.len].hash(state);
+    }
+}
+
 impl Codec for SessionID {
     fn encode(&self, bytes: &mut Vec<u8>) {
         debug_assert!(self.len <= 32);
@@ -160,6 +172,36 @@ impl SessionID {
         }
     }
 
+    /// Make a `SessionID` from `bytes`, which must be no
+    /// longer than 32 bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SessionID, TLSError> {
+        if bytes.len() > 32 {
+            return Err(TLSError::General("Session ID too long".to_string()));
+        }
+
+        Ok(SessionID::new(bytes))
+    }
+
+    /// Make a `SessionID` from its hex encoding, in either case.
+    pub fn from_hex(s: &str) -> Result<SessionID, TLSError> {
+        let s = s.as_bytes();
+        if s.len() & 1 != 0 {
+            return Err(TLSError::General("Session ID has odd hex length".to_string()));
+        }
+
+        let mut bytes = Vec::with_capacity(s.len() / 2);
+        for pair in s.chunks(2) {
+            let hi = (pair[0] as char).to_digit(16);
+            let lo = (pair[1] as char).to_digit(16);
+            match (hi, lo) {
+                (Some(hi), Some(lo)) => bytes.push((hi << 4 | lo) as u8),
+                _ => return Err(TLSError::General("Session ID has invalid hex".to_string())),
+            }
+        }
+
+        SessionID::from_bytes(&bytes)
+    }
+
     pub fn empty() -> SessionID {
         SessionID {
             data: [0u8; 32],
@@ -574,6 +616,23 @@ impl CertificateStatusRequest {
     }
 }
 
+// ---
+// max_fragment_length
+
+impl MaxFragmentLen {
+    /// The maximum plaintext fragment length this requests, or
+    /// None if this isn't a valid value.
+    pub fn fragment_len(&self) -> Option<usize> {
+        match *self {
+            MaxFragmentLen::Bits9 => Some(512),
+            MaxFragmentLen::Bits10 => Some(1024),
+            MaxFragmentLen::Bits11 => Some(2048),
+            MaxFragmentLen::Bits12 => Some(4096),
+            MaxFragmentLen::Unknown(_) => None,
+        }
+    }
+}
+
 // ---
 // SCTs
 
@@ -602,6 +661,9 @@ pub enum ClientExtension {
     ExtendedMasterSecretRequest,
     CertificateStatusRequest(CertificateStatusRequest),
     SignedCertificateTimestampRequest,
+    Padding(usize),
+    RenegotiationInfo(PayloadU8),
+    MaxFragmentLength(MaxFragmentLen),
     Unknown(UnknownExtension),
 }
 
@@ -623,6 +685,9 @@ impl ClientExtension {
             ClientExtension::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
             ClientExtension::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
             ClientExtension::SignedCertificateTimestampRequest => ExtensionType::SCT,
+            ClientExtension::Padding(_) => ExtensionType::Padding,
+            ClientExtension::RenegotiationInfo(_) => ExtensionType::RenegotiationInfo,
+            ClientExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
             ClientExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -649,6 +714,9 @@ impl Codec for ClientExtension {
             ClientExtension::PresharedKey(ref r) => r.encode(&mut sub),
             ClientExtension::Cookie(ref r) => r.encode(&mut sub),
             ClientExtension::CertificateStatusRequest(ref r) => r.encode(&mut sub),
+            ClientExtension::Padding(len) => sub.resize(len, 0u8),
+            ClientExtension::RenegotiationInfo(ref r) => r.encode(&mut sub),
+            ClientExtension::MaxFragmentLength(ref r) => r.encode(&mut sub),
             ClientExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -708,6 +776,13 @@ impl Codec for ClientExtension {
             ExtensionType::SCT if !sub.any_left() => {
                 ClientExtension::SignedCertificateTimestampRequest
             }
+            ExtensionType::Padding => ClientExtension::Padding(sub.rest().len()),
+            ExtensionType::RenegotiationInfo => {
+                ClientExtension::RenegotiationInfo(try_ret!(PayloadU8::read(&mut sub)))
+            }
+            ExtensionType::MaxFragmentLength => {
+                ClientExtension::MaxFragmentLength(try_ret!(MaxFragmentLen::read(&mut sub)))
+            }
             _ => ClientExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub))),
         })
     }
@@ -738,6 +813,7 @@ pub enum ServerExtension {
     CertificateStatusAck,
     SignedCertificateTimestamp(SCTList),
     SupportedVersions(ProtocolVersion),
+    MaxFragmentLength(MaxFragmentLen),
     Unknown(UnknownExtension),
 }
 
@@ -755,6 +831,7 @@ impl ServerExtension {
             ServerExtension::CertificateStatusAck => ExtensionType::StatusRequest,
             ServerExtension::SignedCertificateTimestamp(_) => ExtensionType::SCT,
             ServerExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            ServerExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
             ServerExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -777,6 +854,7 @@ impl Codec for ServerExtension {
             ServerExtension::PresharedKey(r) => codec::encode_u16(r, &mut sub),
             ServerExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
             ServerExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            ServerExtension::MaxFragmentLength(ref r) => r.encode(&mut sub),
             ServerExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -816,6 +894,9 @@ impl Codec for ServerExtension {
             ExtensionType::SupportedVersions => {
                 ServerExtension::SupportedVersions(try_ret!(ProtocolVersion::read(&mut sub)))
             }
+            ExtensionType::MaxFragmentLength => {
+                ServerExtension::MaxFragmentLength(try_ret!(MaxFragmentLen::read(&mut sub)))
+            }
             _ => ServerExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub))),
         })
     }
@@ -942,10 +1023,30 @@ impl ClientHelloPayload {
         }
     }
 
+    pub fn get_renegotiation_info_extension(&self) -> Option<&PayloadU8> {
+        let ext = try_ret!(self.find_extension(ExtensionType::RenegotiationInfo));
+        match *ext {
+            ClientExtension::RenegotiationInfo(ref req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub fn get_max_fragment_length_extension(&self) -> Option<MaxFragmentLen> {
+        let ext = try_ret!(self.find_extension(ExtensionType::MaxFragmentLength));
+        match *ext {
+            ClientExtension::MaxFragmentLength(mfl) => Some(mfl),
+            _ => None,
+        }
+    }
+
     pub fn get_ticket_extension(&self) -> Option<&ClientExtension> {
         self.find_extension(ExtensionType::SessionTicket)
     }
 
+    pub fn early_data_extension_offered(&self) -> bool {
+        self.find_extension(ExtensionType::EarlyData).is_some()
+    }
+
     pub fn get_versions_extension(&self) -> Option<&ProtocolVersions> {
         let ext = try_ret!(self.find_extension(ExtensionType::SupportedVersions));
         match *ext {
@@ -1282,8 +1383,8 @@ impl Codec for CertificatePayload {
     }
 
     fn read(r: &mut Reader) -> Option<CertificatePayload> {
-        // 64KB of certificates is plenty, 16MB is obviously silly
-        codec::read_vec_u24_limited(r, 0x10000)
+        read_certificate_chain(r)
+            .map(|chain| chain.into_iter().map(key::Certificate::from).collect())
     }
 }
 
@@ -1751,6 +1852,14 @@ pub trait HasServerExtensions {
             _ => None,
         }
     }
+
+    fn get_max_fragment_length(&self) -> Option<MaxFragmentLen> {
+        let ext = try_ret!(self.find_extension(ExtensionType::MaxFragmentLength));
+        match *ext {
+            ServerExtension::MaxFragmentLength(mfl) => Some(mfl),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for EncryptedExtensions {
@@ -1925,12 +2034,14 @@ impl Codec for NewSessionTicketPayload {
 // -- NewSessionTicket electric boogaloo --
 #[derive(Debug)]
 pub enum NewSessionTicketExtension {
+    EarlyData(u32),
     Unknown(UnknownExtension),
 }
 
 impl NewSessionTicketExtension {
     pub fn get_type(&self) -> ExtensionType {
         match *self {
+            NewSessionTicketExtension::EarlyData(_) => ExtensionType::EarlyData,
             NewSessionTicketExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1942,6 +2053,7 @@ impl Codec for NewSessionTicketExtension {
 
         let mut sub: Vec<u8> = Vec::new();
         match *self {
+            NewSessionTicketExtension::EarlyData(r) => codec::encode_u32(r, &mut sub),
             NewSessionTicketExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1955,6 +2067,9 @@ impl Codec for NewSessionTicketExtension {
         let mut sub = try_ret!(r.sub(len));
 
         Some(match typ {
+            ExtensionType::EarlyData => {
+                NewSessionTicketExtension::EarlyData(try_ret!(codec::read_u32(&mut sub)))
+            }
             _ => {
                 NewSessionTicketExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
             }
@@ -1986,6 +2101,18 @@ impl NewSessionTicketPayloadTLS13 {
             exts: vec![],
         }
     }
+
+    pub fn find_extension(&self, ext: ExtensionType) -> Option<&NewSessionTicketExtension> {
+        self.exts.iter().find(|x| x.get_type() == ext)
+    }
+
+    pub fn get_max_early_data_size(&self) -> Option<u32> {
+        let ext = try_ret!(self.find_extension(ExtensionType::EarlyData));
+        match *ext {
+            NewSessionTicketExtension::EarlyData(ref sz) => Some(*sz),
+            _ => None
+        }
+    }
 }
 
 impl Codec for NewSessionTicketPayloadTLS13 {
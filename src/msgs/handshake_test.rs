@@ -4,6 +4,11 @@ use super::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
 use super::codec::{Reader, Codec};
 use webpki::DNSNameRef;
 use key::Certificate;
+use error::TLSError;
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 #[test]
 fn rejects_short_random() {
@@ -65,6 +70,68 @@ fn accepts_empty_sessionid() {
     assert_eq!(rd.any_left(), false);
 }
 
+fn hash_of<T: Hash>(item: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn sessionid_eq_and_hash_are_consistent() {
+    let a = SessionID::new(&[1, 2, 3]);
+    let b = SessionID::read_bytes(&[3, 1, 2, 3]).unwrap();
+    let c = SessionID::new(&[1, 2, 3]);
+
+    // reflexive, symmetric, transitive
+    assert_eq!(a, a);
+    assert!(a == b && b == a);
+    assert!(a == b && b == c && a == c);
+    assert_eq!(hash_of(&a), hash_of(&b));
+    assert_eq!(hash_of(&b), hash_of(&c));
+
+    // trailing zeroes are significant
+    let d = SessionID::new(&[1, 2, 3, 0]);
+    assert!(a != d && d != a);
+    assert_ne!(hash_of(&a), hash_of(&d));
+    assert_ne!(SessionID::new(&[1, 2, 4]), a);
+
+    assert_eq!(SessionID::empty(), SessionID::new(&[]));
+    assert_eq!(hash_of(&SessionID::empty()), hash_of(&SessionID::new(&[])));
+}
+
+#[test]
+fn sessionid_from_bytes() {
+    assert_eq!(SessionID::from_bytes(&[1, 2, 3]), Ok(SessionID::new(&[1, 2, 3])));
+    assert_eq!(SessionID::from_bytes(&[]), Ok(SessionID::empty()));
+    assert_eq!(SessionID::from_bytes(&[0xff; 32]).unwrap().len(), 32);
+    assert_eq!(SessionID::from_bytes(&[0xff; 33]),
+               Err(TLSError::General("Session ID too long".to_string())));
+}
+
+#[test]
+fn sessionid_from_hex() {
+    assert_eq!(SessionID::from_hex("01abCD"), Ok(SessionID::new(&[0x01, 0xab, 0xcd])));
+    assert_eq!(SessionID::from_hex(""), Ok(SessionID::empty()));
+    assert_eq!(SessionID::from_hex(&"00".repeat(32)).unwrap().len(), 32);
+    assert!(SessionID::from_hex(&"00".repeat(33)).is_err());
+    assert!(SessionID::from_hex("abc").is_err());
+    assert!(SessionID::from_hex("0g").is_err());
+    assert!(SessionID::from_hex("+1").is_err());
+}
+
+#[test]
+fn sessionid_can_be_hashmap_key() {
+    let mut map = HashMap::new();
+    map.insert(SessionID::new(&[1, 2]), "first");
+    map.insert(SessionID::new(&[1, 2, 3]), "second");
+    map.insert(SessionID::new(&[1, 2]), "replaced");
+
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get(&SessionID::new(&[1, 2])), Some(&"replaced"));
+    assert_eq!(map.get(&SessionID::new(&[1, 2, 3])), Some(&"second"));
+    assert_eq!(map.get(&SessionID::empty()), None);
+}
+
 #[test]
 fn can_roundtrip_unknown_client_ext() {
     let bytes = [0x12u8, 0x34u8, 0, 3, 1, 2, 3];
@@ -345,6 +412,9 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::ExtendedMasterSecretRequest,
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
             ClientExtension::SignedCertificateTimestampRequest,
+            ClientExtension::Padding(5),
+            ClientExtension::RenegotiationInfo(PayloadU8(vec![ 1, 2 ])),
+            ClientExtension::MaxFragmentLength(MaxFragmentLen::Bits9),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -377,6 +447,7 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::CertificateStatusAck,
             ServerExtension::SignedCertificateTimestamp(vec![ PayloadU16(vec![0]) ]),
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            ServerExtension::MaxFragmentLength(MaxFragmentLen::Bits12),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -479,6 +550,7 @@ fn get_sample_newsessionticketpayloadtls13() -> NewSessionTicketPayloadTLS13 {
         nonce: PayloadU8(vec![ 1, 2, 3 ]),
         ticket: PayloadU16(vec![ 4, 5, 6 ]),
         exts: vec![
+            NewSessionTicketExtension::EarlyData(1234),
             NewSessionTicketExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -671,3 +743,14 @@ fn can_roundtrip_all_tls13_handshake_payloads() {
         println!("{:?}", other);
     }
 }
+
+#[test]
+fn newsessionticket_tls13_max_early_data_size() {
+    let mut nst = NewSessionTicketPayloadTLS13::new(1, 2, vec![], vec![]);
+    assert_eq!(nst.get_max_early_data_size(), None);
+
+    nst.exts.push(NewSessionTicketExtension::EarlyData(1234));
+    let enc = nst.get_encoding();
+    let nst = NewSessionTicketPayloadTLS13::read_bytes(&enc).unwrap();
+    assert_eq!(nst.get_max_early_data_size(), Some(1234));
+}
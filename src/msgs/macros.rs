@@ -10,7 +10,7 @@ macro_rules! enum_builder {
         EnumName: $enum_name: ident;
         EnumVal { $( $enum_var: ident => $enum_val: expr ),* }
     ) => {
-        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
         pub enum $enum_name {
             $( $enum_var),*
             ,Unknown(u8)
@@ -42,7 +42,7 @@ macro_rules! enum_builder {
         EnumName: $enum_name: ident;
         EnumVal { $( $enum_var: ident => $enum_val: expr ),* }
     ) => {
-        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
         pub enum $enum_name {
             $( $enum_var),*
             ,Unknown(u16)
@@ -236,3 +236,87 @@ impl ServerSessionValue {
         self.extended_ms = true;
     }
 }
+
+// --- Server checkpoint types ---
+/// Everything needed to restore a server session in its
+/// traffic phase.
+#[derive(Debug)]
+pub struct ServerCheckpointValue {
+    pub sni: Option<webpki::DNSName>,
+    pub version: ProtocolVersion,
+    pub cipher_suite: CipherSuite,
+    pub write_seq: u64,
+    pub read_seq: u64,
+
+    /// For TLS1.2: the client random, server random and master secret.
+    /// For TLS1.3: the client and server application traffic secrets,
+    /// and the exporter secret.
+    pub secrets: Vec<PayloadU8>,
+    pub alpn_protocol: Option<PayloadU8>,
+    pub client_cert_chain: Option<CertificatePayload>,
+}
+
+impl Codec for ServerCheckpointValue {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        if let Some(ref sni) = self.sni {
+            codec::encode_u8(1, bytes);
+            let sni_bytes: &str = sni.as_ref().into();
+            PayloadU8::new(Vec::from(sni_bytes)).encode(bytes);
+        } else {
+            codec::encode_u8(0, bytes);
+        }
+        self.version.encode(bytes);
+        self.cipher_suite.encode(bytes);
+        codec::encode_u64(self.write_seq, bytes);
+        codec::encode_u64(self.read_seq, bytes);
+        codec::encode_vec_u16(bytes, &self.secrets);
+        if let Some(ref alpn) = self.alpn_protocol {
+            codec::encode_u8(1, bytes);
+            alpn.encode(bytes);
+        } else {
+            codec::encode_u8(0, bytes);
+        }
+        if let Some(ref chain) = self.client_cert_chain {
+            chain.encode(bytes);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<ServerCheckpointValue> {
+        let has_sni = try_ret!(codec::read_u8(r));
+        let sni = if has_sni == 1 {
+            let dns_name = try_ret!(PayloadU8::read(r));
+            let dns_name = try_ret!(webpki::DNSNameRef::try_from_ascii(
+                untrusted::Input::from(&dns_name.0)).ok());
+            Some(dns_name.into())
+        } else {
+            None
+        };
+        let version = try_ret!(ProtocolVersion::read(r));
+        let cipher_suite = try_ret!(CipherSuite::read(r));
+        let write_seq = try_ret!(codec::read_u64(r));
+        let read_seq = try_ret!(codec::read_u64(r));
+        let secrets = try_ret!(codec::read_vec_u16::<PayloadU8>(r));
+        let has_alpn = try_ret!(codec::read_u8(r));
+        let alpn_protocol = if has_alpn == 1 {
+            Some(try_ret!(PayloadU8::read(r)))
+        } else {
+            None
+        };
+        let client_cert_chain = if r.any_left() {
+            Some(try_ret!(CertificatePayload::read(r)))
+        } else {
+            None
+        };
+
+        Some(ServerCheckpointValue {
+            sni,
+            version,
+            cipher_suite,
+            write_seq,
+            read_seq,
+            secrets,
+            alpn_protocol,
+            client_cert_chain,
+        })
+    }
+}
@@ -0,0 +1,301 @@
+// Certificate path building.
+//
+// webpki verifies the chain the server sent, and needs every
+// intermediate certificate to be in it.  This finds missing
+// intermediates by matching each certificate's issuer against the
+// subjects of certificates we already know, or by fetching them from
+// the certificate's authority information access URLs.
+
+use std::sync::Arc;
+
+use webpki;
+
+use anchors::RootCertStore;
+use crl::{parse_cert, ParsedCert};
+use error::TLSError;
+use key::Certificate;
+use verify::{ServerCertVerifier, ServerCertVerified, WebPKIVerifier};
+
+/// The longest path we'll build, including the end-entity
+/// certificate but not the trust anchor.
+const MAX_PATH_LEN: usize = 6;
+
+/// Something that can fetch issuing certificates ("AIA chasing").
+pub trait CertFetcher : Send + Sync {
+    /// Fetch the DER-encoded certificate at `url`, which was named
+    /// by a caIssuers access description.  Returns `None` if it
+    /// couldn't be fetched.
+    fn fetch(&self, url: &str) -> Option<Certificate>;
+}
+
+/// Builds a path from an end-entity certificate to a trust anchor,
+/// for servers which don't send all their intermediate certificates.
+///
+/// Starting from the end-entity certificate, each certificate's
+/// issuer is looked for among the subjects of the other presented
+/// certificates, then the known intermediates, and then by asking
+/// the `CertFetcher` (if any) for the certificate's caIssuers URLs.
+/// This stops when the issuer is one of the trust anchors.
+///
+/// Only names are matched here: the built path is then verified by
+/// webpki as usual.
+///
+/// This is also a `ServerCertVerifier`, which verifies the built
+/// path rather than the presented chain.
+#[derive(Clone, Default)]
+pub struct PathBuilder {
+    intermediates: Vec<Certificate>,
+    fetcher: Option<Arc<CertFetcher>>,
+}
+
+impl PathBuilder {
+    /// Make a new `PathBuilder` with no known intermediates and
+    /// no fetcher.
+    pub fn new() -> PathBuilder {
+        PathBuilder {
+            intermediates: Vec::new(),
+            fetcher: None,
+        }
+    }
+
+    /// Add `cert` to the known intermediate certificates.
+    pub fn add_intermediate(&mut self, cert: Certificate) {
+        self.intermediates.push(cert);
+    }
+
+    /// Fetch missing intermediates with `fetcher`.
+    pub fn set_fetcher(&mut self, fetcher: Arc<CertFetcher>) {
+        self.fetcher = Some(fetcher);
+    }
+
+    /// Build a path from `presented_certs[0]` to one of `roots`.
+    ///
+    /// The result starts with the end-entity certificate, followed
+    /// by each issuer in turn, and does not include the trust anchor.
+    pub fn build_path(&self,
+                      roots: &RootCertStore,
+                      presented_certs: &[Certificate]) -> Result<Vec<Certificate>, TLSError> {
+        if presented_certs.is_empty() {
+            return Err(TLSError::NoCertificatesPresented);
+        }
+
+        let mut path = vec![presented_certs[0].clone()];
+        let mut subjects = Vec::new();
+        let mut current = parse_cert(&presented_certs[0])?;
+
+        while path.len() <= MAX_PATH_LEN {
+            if roots.roots.iter().any(|ta| ta.subject() == &current.issuer[..]) {
+                return Ok(path);
+            }
+
+            subjects.push(current.subject.clone());
+            let (cert, parsed) = match self.find_issuer(&current, &presented_certs[1..]) {
+                Some(found) => found,
+                None => break,
+            };
+
+            // Don't go round in circles.
+            if subjects.contains(&parsed.subject) {
+                break;
+            }
+
+            path.push(cert);
+            current = parsed;
+        }
+
+        Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer))
+    }
+
+    fn find_issuer(&self,
+                   cert: &ParsedCert,
+                   presented: &[Certificate]) -> Option<(Certificate, ParsedCert)> {
+        // A junk certificate in the chain might not be in the path,
+        // so it's not an error here.  webpki gets the final say.
+        for candidate in presented.iter().chain(self.intermediates.iter()) {
+            let parsed = match parse_cert(candidate) {
+                Ok(parsed) => parsed,
+                Err(_) => continue,
+            };
+            if parsed.subject == cert.issuer {
+                return Some((candidate.clone(), parsed));
+            }
+        }
+
+        let fetcher = match self.fetcher {
+            Some(ref fetcher) => fetcher,
+            None => return None,
+        };
+
+        for url in &cert.ca_issuers {
+            debug!("Fetching issuer certificate from {}", url);
+            let fetched = match fetcher.fetch(url) {
+                Some(fetched) => fetched,
+                None => continue,
+            };
+
+            match parse_cert(&fetched) {
+                Ok(ref parsed) if parsed.subject != cert.issuer => {
+                    debug!("Certificate from {} has the wrong subject", url);
+                }
+                Ok(parsed) => return Some((fetched, parsed)),
+                Err(err) => {
+                    debug!("Certificate from {} is unparseable: {:?}", url, err);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl ServerCertVerifier for PathBuilder {
+    fn verify_server_cert(&self,
+                          roots: &RootCertStore,
+                          presented_certs: &[Certificate],
+                          dns_name: webpki::DNSNameRef,
+                          ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let path = self.build_path(roots, presented_certs)?;
+        WebPKIVerifier::new()
+            .verify_server_cert(roots, &path, dns_name, ocsp_response)
+    }
+
+    #[cfg(feature = "dangerous_configuration")]
+    fn verify_server_cert_ignoring_name(&self,
+                                        roots: &RootCertStore,
+                                        presented_certs: &[Certificate],
+                                        ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let path = self.build_path(roots, presented_certs)?;
+        WebPKIVerifier::new()
+            .verify_server_cert_ignoring_name(roots, &path, ocsp_response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+    use std::sync::Mutex;
+    use pemfile;
+
+    fn load_certs(path: &str) -> Vec<Certificate> {
+        let mut f = fs::File::open(path).unwrap();
+        let mut pem = Vec::new();
+        f.read_to_end(&mut pem).unwrap();
+        pemfile::certs(&mut &pem[..]).unwrap()
+    }
+
+    fn cert(path: &str) -> Certificate {
+        load_certs(path).remove(0)
+    }
+
+    fn roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(&cert("test-ca/rsa/ca.cert")).unwrap();
+        roots
+    }
+
+    struct TestFetcher {
+        certs: Vec<(&'static str, Certificate)>,
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl CertFetcher for TestFetcher {
+        fn fetch(&self, url: &str) -> Option<Certificate> {
+            self.fetched.lock().unwrap().push(url.to_string());
+            self.certs.iter()
+                .find(|&&(u, _)| u == url)
+                .map(|&(_, ref cert)| cert.clone())
+        }
+    }
+
+    fn fetcher(certs: Vec<(&'static str, Certificate)>) -> Arc<TestFetcher> {
+        Arc::new(TestFetcher { certs, fetched: Mutex::new(Vec::new()) })
+    }
+
+    const AIA_URL: &str = "http://ponytown.example/inter.cert";
+
+    #[test]
+    fn test_complete_chain() {
+        let chain = load_certs("test-ca/rsa/end.fullchain");
+        let path = PathBuilder::new().build_path(&roots(), &chain).unwrap();
+        assert_eq!(path, chain[..2].to_vec());
+    }
+
+    #[test]
+    fn test_reordered_chain() {
+        let chain = load_certs("test-ca/rsa/end.fullchain");
+        let presented = vec![chain[0].clone(), chain[2].clone(), chain[1].clone()];
+        let path = PathBuilder::new().build_path(&roots(), &presented).unwrap();
+        assert_eq!(path, chain[..2].to_vec());
+    }
+
+    #[test]
+    fn test_unparseable_certificate_skipped() {
+        let chain = load_certs("test-ca/rsa/end.fullchain");
+        let presented = vec![chain[0].clone(), Certificate(vec![ 0x30, 0x00 ]), chain[1].clone()];
+        let path = PathBuilder::new().build_path(&roots(), &presented).unwrap();
+        assert_eq!(path, chain[..2].to_vec());
+    }
+
+    #[test]
+    fn test_known_intermediate() {
+        let end = cert("test-ca/rsa/end.cert");
+        let inter = cert("test-ca/rsa/inter.cert");
+
+        assert_eq!(PathBuilder::new().build_path(&roots(), &[end.clone()]),
+                   Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer)));
+
+        let mut builder = PathBuilder::new();
+        builder.add_intermediate(inter.clone());
+        assert_eq!(builder.build_path(&roots(), &[end.clone()]).unwrap(),
+                   vec![end, inter]);
+    }
+
+    #[test]
+    fn test_fetched_intermediate() {
+        let end = cert("test-ca/rsa/end-aia.cert");
+        let inter = cert("test-ca/rsa/inter.cert");
+
+        let fetcher = fetcher(vec![(AIA_URL, inter.clone())]);
+        let mut builder = PathBuilder::new();
+        builder.set_fetcher(fetcher.clone());
+        assert_eq!(builder.build_path(&roots(), &[end.clone()]).unwrap(),
+                   vec![end, inter]);
+        assert_eq!(*fetcher.fetched.lock().unwrap(), vec![AIA_URL.to_string()]);
+    }
+
+    #[test]
+    fn test_known_intermediate_preferred() {
+        let end = cert("test-ca/rsa/end-aia.cert");
+        let inter = cert("test-ca/rsa/inter.cert");
+
+        let fetcher = fetcher(vec![(AIA_URL, inter.clone())]);
+        let mut builder = PathBuilder::new();
+        builder.add_intermediate(inter.clone());
+        builder.set_fetcher(fetcher.clone());
+        assert_eq!(builder.build_path(&roots(), &[end.clone()]).unwrap(),
+                   vec![end, inter]);
+        assert!(fetcher.fetched.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fetched_wrong_certificate() {
+        let end = cert("test-ca/rsa/end-aia.cert");
+
+        let mut builder = PathBuilder::new();
+        builder.set_fetcher(fetcher(vec![(AIA_URL, cert("test-ca/ecdsa/inter.cert"))]));
+        assert_eq!(builder.build_path(&roots(), &[end.clone()]),
+                   Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer)));
+
+        builder.set_fetcher(fetcher(vec![]));
+        assert_eq!(builder.build_path(&roots(), &[end]),
+                   Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer)));
+    }
+
+    #[test]
+    fn test_no_certificates() {
+        assert_eq!(PathBuilder::new().build_path(&roots(), &[]),
+                   Err(TLSError::NoCertificatesPresented));
+    }
+}
@@ -0,0 +1,145 @@
+use std::io::{self, Read, Write};
+
+use session::Session;
+use suites::SupportedCipherSuite;
+use msgs::enums::ProtocolVersion;
+use error::TLSError;
+use server::ServerSession;
+use key;
+
+/// A `ServerSession` whose client has authenticated with a
+/// certificate: one accepted by the configured `ClientCertVerifier`,
+/// in a handshake which has completed.
+///
+/// The only way to get one is `ClientAuthenticatedSession::new`,
+/// which checks this.  So code that needs an authenticated client
+/// can take one of these, and then has no need to handle sessions
+/// without a client certificate.
+///
+/// Everything else is passed straight through to the session, so this
+/// can be used anywhere a `Session` can.
+#[derive(Debug)]
+pub struct ClientAuthenticatedSession {
+    sess: ServerSession,
+    client_cert: key::PeerCertificates,
+}
+
+impl ClientAuthenticatedSession {
+    /// Wrap `sess`, if its handshake is complete and its client
+    /// authenticated with a certificate.  Otherwise, give it back.
+    ///
+    /// Sessions from `ServerSession::restore` are always given back,
+    /// since a checkpoint can't vouch for the client.  Those from
+    /// `ServerSession::import_session` are accepted if their client
+    /// certificate passes the importing config's verifier.
+    #[allow(clippy::result_large_err)]
+    pub fn new(sess: ServerSession) -> Result<ClientAuthenticatedSession, ServerSession> {
+        if sess.is_handshaking() {
+            return Err(sess);
+        }
+
+        let client_cert = match sess.get_verified_client_cert_chain() {
+            Some(chain) => key::PeerCertificates::new(chain.certificates().to_vec()),
+            None => None,
+        };
+
+        match client_cert {
+            Some(client_cert) => Ok(ClientAuthenticatedSession { sess, client_cert }),
+            None => Err(sess),
+        }
+    }
+
+    /// The client's certificate chain, which was verified during
+    /// the handshake.
+    pub fn get_authenticated_client_cert(&self) -> &key::PeerCertificates {
+        &self.client_cert
+    }
+
+    /// The wrapped session.
+    pub fn get_ref(&self) -> &ServerSession {
+        &self.sess
+    }
+
+    /// The wrapped session.
+    pub fn get_mut(&mut self) -> &mut ServerSession {
+        &mut self.sess
+    }
+
+    /// Unwrap, returning the session.
+    pub fn into_inner(self) -> ServerSession {
+        self.sess
+    }
+}
+
+impl Read for ClientAuthenticatedSession {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.sess.read(buf)
+    }
+}
+
+impl Write for ClientAuthenticatedSession {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sess.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sess.flush()
+    }
+}
+
+impl Session for ClientAuthenticatedSession {
+    fn read_tls(&mut self, rd: &mut Read) -> io::Result<usize> {
+        self.sess.read_tls(rd)
+    }
+
+    fn write_tls(&mut self, wr: &mut Write) -> io::Result<usize> {
+        self.sess.write_tls(wr)
+    }
+
+    fn process_new_packets(&mut self) -> Result<(), TLSError> {
+        self.sess.process_new_packets()
+    }
+
+    fn wants_read(&self) -> bool {
+        self.sess.wants_read()
+    }
+
+    fn wants_write(&self) -> bool {
+        self.sess.wants_write()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.sess.is_handshaking()
+    }
+
+    fn set_buffer_limit(&mut self, limit: usize) {
+        self.sess.set_buffer_limit(limit)
+    }
+
+    fn send_close_notify(&mut self) {
+        self.sess.send_close_notify()
+    }
+
+    fn get_peer_certificates(&self) -> Option<key::PeerCertificates> {
+        self.sess.get_peer_certificates()
+    }
+
+    fn get_alpn_protocol(&self) -> Option<&str> {
+        self.sess.get_alpn_protocol()
+    }
+
+    fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+        self.sess.get_protocol_version()
+    }
+
+    fn export_keying_material(&self,
+                              output: &mut [u8],
+                              label: &[u8],
+                              context: Option<&[u8]>) -> Result<(), TLSError> {
+        self.sess.export_keying_material(output, label, context)
+    }
+
+    fn get_negotiated_ciphersuite(&self) -> Option<&'static SupportedCipherSuite> {
+        self.sess.get_negotiated_ciphersuite()
+    }
+}
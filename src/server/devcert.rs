@@ -0,0 +1,308 @@
+// Self-signed certificates for local development.
+//
+// This writes just enough DER to make an X.509v3 certificate that
+// webpki accepts: a P-256 key, a subjectAltName for the requested
+// name, and the serverAuth extended key usage.
+#![allow(deprecated)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::rand::SystemRandom;
+use ring::signature::{self, ECDSAKeyPair};
+use untrusted;
+
+use error::TLSError;
+use key::{Certificate, PrivateKey};
+use rand;
+use server;
+use sign;
+
+/// The validity period of generated certificates, unless
+/// otherwise configured: 30 days.
+const DEFAULT_VALIDITY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The name used for clients which don't send SNI.
+const DEFAULT_NAME: &str = "localhost";
+
+/// A P-256 public key is the last 65 bytes of the PKCS#8 document
+/// *ring* generates.
+const P256_PUBLIC_KEY_LEN: usize = 65;
+
+/// Something which makes up a self-signed certificate for whatever
+/// name the client asks for.
+///
+/// The certificate and its ECDSA P-256 key are generated the first
+/// time a name is asked for, and then kept in memory (only) for
+/// later requests.  Clients without SNI get a certificate for
+/// `localhost`.
+///
+/// No client will trust these certificates unless told to, so this
+/// is only useful for local development: never use it for anything
+/// else.
+#[deprecated(note = "DevCertResolver is for local development only, \
+                     and must not be used in production")]
+pub struct DevCertResolver {
+    validity: Duration,
+    certs: Mutex<HashMap<String, sign::CertifiedKey>>,
+}
+
+impl DevCertResolver {
+    /// Make a new `DevCertResolver`, whose certificates are valid
+    /// for 30 days.
+    pub fn new() -> DevCertResolver {
+        DevCertResolver::with_validity(DEFAULT_VALIDITY)
+    }
+
+    /// Make a new `DevCertResolver`, whose certificates are valid
+    /// for `validity` from when they are generated.
+    pub fn with_validity(validity: Duration) -> DevCertResolver {
+        warn!("DevCertResolver in use: this is for local development only");
+        DevCertResolver {
+            validity,
+            certs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the certificate and key for `name`, generating them if
+    /// this is the first time it was asked for.  You can use this
+    /// to get a certificate to add to your client's trusted roots.
+    pub fn get_cert(&self, name: &str) -> Result<sign::CertifiedKey, TLSError> {
+        let mut certs = self.certs.lock()
+            .unwrap();
+
+        if let Some(ck) = certs.get(name) {
+            return Ok(ck.clone());
+        }
+
+        let ck = generate(name, self.validity)?;
+        certs.insert(name.to_string(), ck.clone());
+        Ok(ck)
+    }
+}
+
+impl Default for DevCertResolver {
+    fn default() -> DevCertResolver {
+        DevCertResolver::new()
+    }
+}
+
+impl server::ResolvesServerCert for DevCertResolver {
+    fn resolve(&self, client_hello: server::ClientHello) -> Option<sign::CertifiedKey> {
+        let name = client_hello.server_name()
+            .map(|name| name.into())
+            .unwrap_or(DEFAULT_NAME);
+
+        match self.get_cert(name) {
+            Ok(ck) => Some(ck),
+            Err(err) => {
+                warn!("Cannot generate certificate for {}: {:?}", name, err);
+                None
+            }
+        }
+    }
+}
+
+fn generation_failed<E>(_: E) -> TLSError {
+    TLSError::General("certificate generation failed".to_string())
+}
+
+fn generate(name: &str, validity: Duration) -> Result<sign::CertifiedKey, TLSError> {
+    let rng = SystemRandom::new();
+
+    let pkcs8 = ECDSAKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .map_err(generation_failed)?;
+    let pkcs8 = pkcs8.as_ref();
+    let public_key = &pkcs8[pkcs8.len() - P256_PUBLIC_KEY_LEN..];
+    let key_pair = ECDSAKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+                                            untrusted::Input::from(pkcs8))
+        .map_err(generation_failed)?;
+
+    let not_before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| TLSError::FailedToGetCurrentTime)?
+        .as_secs();
+    let not_after = not_before + validity.as_secs();
+
+    let tbs = tbs_certificate(name, public_key, not_before, not_after);
+    let sig = key_pair.sign(untrusted::Input::from(&tbs), &rng)
+        .map_err(generation_failed)?;
+
+    let mut cert = tbs;
+    cert.extend_from_slice(&ecdsa_with_sha256());
+    cert.extend_from_slice(&bit_string(sig.as_ref()));
+    let cert = Certificate(der(0x30, &cert));
+
+    let key = sign::ECDSASigningKey::new(&PrivateKey(pkcs8.to_vec()))
+        .map_err(generation_failed)?;
+    let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
+    Ok(sign::CertifiedKey::new(vec![cert], key))
+}
+
+// The body of the TBSCertificate; the issuer and subject are both
+// CN=`name`.
+fn tbs_certificate(name: &str, public_key: &[u8], not_before: u64, not_after: u64) -> Vec<u8> {
+    let mut serial = [0u8; 16];
+    rand::fill_random(&mut serial);
+    serial[0] &= 0x7f; // keep it positive
+    serial[0] |= 0x01; // and minimally encoded
+
+    let common_name = der(0x30, &[oid(&[0x55, 0x04, 0x03]),
+                                  der(0x0c, name.as_bytes())].concat());
+    let dn = der(0x30, &der(0x31, &common_name));
+
+    let validity = der(0x30, &[time(not_before), time(not_after)].concat());
+
+    let spki = der(0x30, &[
+        der(0x30, &[
+            oid(&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01]), // id-ecPublicKey
+            oid(&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07]), // secp256r1
+        ].concat()),
+        bit_string(public_key),
+    ].concat());
+
+    let subject_alt_name = der(0x30, &[
+        oid(&[0x55, 0x1d, 0x11]),
+        der(0x04, &der(0x30, &der(0x82, name.as_bytes()))),
+    ].concat());
+    let ext_key_usage = der(0x30, &[
+        oid(&[0x55, 0x1d, 0x25]),
+        der(0x04, &der(0x30, &oid(&[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01]))),
+    ].concat());
+    let extensions = der(0xa3, &der(0x30, &[subject_alt_name, ext_key_usage].concat()));
+
+    let tbs = [
+        der(0xa0, &der(0x02, &[0x02])), // v3
+        der(0x02, &serial),
+        ecdsa_with_sha256(),
+        dn.clone(),
+        validity,
+        dn,
+        spki,
+        extensions,
+    ].concat();
+    der(0x30, &tbs)
+}
+
+fn der(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut ret = vec![tag];
+    let len = body.len();
+    if len < 0x80 {
+        ret.push(len as u8);
+    } else if len < 0x100 {
+        ret.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        debug_assert!(len < 0x10000);
+        ret.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]);
+    }
+    ret.extend_from_slice(body);
+    ret
+}
+
+fn oid(encoded: &[u8]) -> Vec<u8> {
+    der(0x06, encoded)
+}
+
+fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    der(0x03, &[&[0x00][..], bytes].concat())
+}
+
+fn ecdsa_with_sha256() -> Vec<u8> {
+    der(0x30, &oid(&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02]))
+}
+
+/// A UTCTime, or a GeneralizedTime from 2050 as RFC5280 requires.
+fn time(unix_secs: u64) -> Vec<u8> {
+    let (year, month, day) = civil_from_days((unix_secs / 86400) as i64);
+    let secs = unix_secs % 86400;
+    let time = format!("{:02}{:02}{:02}{:02}{:02}Z",
+                       month, day, secs / 3600, secs / 60 % 60, secs % 60);
+
+    if year < 2050 {
+        der(0x17, format!("{:02}{}", year % 100, time).as_bytes())
+    } else {
+        der(0x18, format!("{:04}{}", year, time).as_bytes())
+    }
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date.
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anchors::RootCertStore;
+    use crl::parse_cert;
+    use verify::verify_certificate_chain;
+    use webpki;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(29_220), (2050, 1, 1));
+    }
+
+    #[test]
+    fn test_time() {
+        assert_eq!(time(1_514_395_185), der(0x17, b"171227171945Z"));
+        assert_eq!(time(2_524_608_000), der(0x18, b"20500101000000Z"));
+    }
+
+    #[test]
+    fn test_generated_cert_is_self_signed() {
+        let resolver = DevCertResolver::new();
+        let ck = resolver.get_cert("dev.example.com").unwrap();
+        assert_eq!(ck.cert.len(), 1);
+        ck.cross_check_end_entity_cert(
+            Some(webpki::DNSNameRef::try_from_ascii_str("dev.example.com").unwrap()))
+            .unwrap();
+
+        let parsed = parse_cert(&ck.cert[0]).unwrap();
+        assert_eq!(parsed.issuer, parsed.subject);
+
+        // It verifies with itself as the trust anchor, so it is
+        // signed by its own key.
+        let mut roots = RootCertStore::empty();
+        roots.add(&ck.cert[0]).unwrap();
+        let anchors: Vec<webpki::TrustAnchor> = roots.roots
+            .iter()
+            .map(|root| root.to_trust_anchor())
+            .collect();
+        verify_certificate_chain(&ck.cert, &anchors, SystemTime::now(),
+                                 Some("dev.example.com"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_certs_are_cached_by_name() {
+        let resolver = DevCertResolver::new();
+        let a = resolver.get_cert("a.example.com").unwrap();
+        let b = resolver.get_cert("b.example.com").unwrap();
+        assert_eq!(resolver.get_cert("a.example.com").unwrap().cert, a.cert);
+        assert_ne!(a.cert, b.cert);
+    }
+
+    #[test]
+    fn test_validity() {
+        let resolver = DevCertResolver::with_validity(Duration::from_secs(3600));
+        let ck = resolver.get_cert("dev.example.com").unwrap();
+        let parsed = parse_cert(&ck.cert[0]).unwrap();
+        assert_eq!(parsed.not_after.duration_since(parsed.not_before).unwrap(),
+                   Duration::from_secs(3600));
+    }
+}
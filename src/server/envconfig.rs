@@ -0,0 +1,278 @@
+// Making a ServerConfig from environment variables.
+//
+// The variables are read through a lookup function like `env::var`,
+// so the tests don't need to change the process environment.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use anchors::RootCertStore;
+use error::TLSError;
+use key;
+use msgs::enums::ProtocolVersion;
+use pemfile;
+use server::{handy, ServerConfig};
+use sign;
+use suites::ALL_CIPHERSUITES;
+use verify;
+
+/// The versions we support, oldest first.
+static VERSIONS: &[ProtocolVersion] = &[
+    ProtocolVersion::TLSv1_2,
+    ProtocolVersion::TLSv1_3,
+];
+
+fn config_error(msg: String) -> TLSError {
+    TLSError::General(msg)
+}
+
+/// The value of `name`, or None if it's not set.  Values which
+/// aren't unicode are errors, rather than treated as unset.
+fn optional(lookup: &Fn(&str) -> Result<String, env::VarError>,
+            name: &str) -> Result<Option<String>, TLSError> {
+    match lookup(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => {
+            Err(config_error(format!("{} is not valid unicode", name)))
+        }
+    }
+}
+
+fn required(lookup: &Fn(&str) -> Result<String, env::VarError>,
+            name: &str) -> Result<String, TLSError> {
+    optional(lookup, name)?
+        .ok_or_else(|| config_error(format!("{} is not set", name)))
+}
+
+fn open(var: &str, filename: &str) -> Result<io::BufReader<fs::File>, TLSError> {
+    fs::File::open(filename)
+        .map(io::BufReader::new)
+        .map_err(|err| config_error(format!("{}: cannot open {}: {}", var, filename, err)))
+}
+
+fn load_certs(var: &str, filename: &str) -> Result<Vec<key::Certificate>, TLSError> {
+    let certs = pemfile::certs(&mut open(var, filename)?)
+        .map_err(|()| config_error(format!("{}: cannot parse {}", var, filename)))?;
+    if certs.is_empty() {
+        return Err(config_error(format!("{}: no certificates in {}", var, filename)));
+    }
+    Ok(certs)
+}
+
+fn load_key(var: &str, filename: &str) -> Result<key::PrivateKey, TLSError> {
+    let unparseable = || config_error(format!("{}: cannot parse {}", var, filename));
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(var, filename)?)
+        .map_err(|()| unparseable())?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(var, filename)?)
+            .map_err(|()| unparseable())?;
+    }
+
+    if keys.is_empty() {
+        return Err(config_error(format!("{}: no private keys in {}", var, filename)));
+    }
+    Ok(keys.remove(0))
+}
+
+fn load_roots(var: &str, filename: &str) -> Result<RootCertStore, TLSError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(var, filename)? {
+        roots.add(&cert)
+            .map_err(|err| config_error(format!("{}: bad CA certificate in {}: {:?}",
+                                                var, filename, err)))?;
+    }
+    Ok(roots)
+}
+
+/// Split a comma-separated list, ignoring whitespace and empty items.
+fn split_list(value: &str) -> Vec<&str> {
+    value.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Accepts "1.2", "TLSv1.2", "TLSv1_2" and so on.
+fn parse_version(var: &str, value: &str) -> Result<ProtocolVersion, TLSError> {
+    let trimmed = value.trim();
+    let number = if trimmed.len() >= 4 && trimmed[..4].eq_ignore_ascii_case("tlsv") {
+        &trimmed[4..]
+    } else if trimmed.len() >= 3 && trimmed[..3].eq_ignore_ascii_case("tls") {
+        &trimmed[3..]
+    } else {
+        trimmed
+    };
+
+    match number.replace('_', ".").as_str() {
+        "1.2" => Ok(ProtocolVersion::TLSv1_2),
+        "1.3" => Ok(ProtocolVersion::TLSv1_3),
+        _ => Err(config_error(format!("{}: unsupported version {:?}", var, value))),
+    }
+}
+
+fn version_index(version: ProtocolVersion) -> usize {
+    VERSIONS.iter()
+        .position(|v| *v == version)
+        .unwrap()
+}
+
+/// Make a `ServerConfig` from the variables `lookup` finds.
+/// See `ServerConfig::from_env`.
+pub fn from_vars(lookup: &Fn(&str) -> Result<String, env::VarError>)
+                 -> Result<ServerConfig, TLSError> {
+    let cert_file = required(lookup, "RUSTLS_CERT_FILE")?;
+    let key_file = required(lookup, "RUSTLS_KEY_FILE")?;
+
+    let mut config = match optional(lookup, "RUSTLS_CA_FILE")? {
+        Some(ca_file) => {
+            let roots = load_roots("RUSTLS_CA_FILE", &ca_file)?;
+            ServerConfig::with_required_client_auth(
+                verify::AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => ServerConfig::with_no_client_auth(),
+    };
+
+    let certs = load_certs("RUSTLS_CERT_FILE", &cert_file)?;
+    let key = load_key("RUSTLS_KEY_FILE", &key_file)?;
+    let key = sign::any_supported_type(&key)
+        .map_err(|_| config_error(format!("RUSTLS_KEY_FILE: unsupported key in {}",
+                                          key_file)))?;
+    let key = sign::CertifiedKey::new(certs, Arc::new(key));
+    config.cert_resolver = Arc::new(handy::AlwaysResolvesChain::new(key));
+
+    let min = match optional(lookup, "RUSTLS_MIN_VERSION")? {
+        Some(value) => version_index(parse_version("RUSTLS_MIN_VERSION", &value)?),
+        None => 0,
+    };
+    let max = match optional(lookup, "RUSTLS_MAX_VERSION")? {
+        Some(value) => version_index(parse_version("RUSTLS_MAX_VERSION", &value)?),
+        None => VERSIONS.len() - 1,
+    };
+    if min > max {
+        return Err(config_error("RUSTLS_MIN_VERSION is greater than RUSTLS_MAX_VERSION"
+                                .to_string()));
+    }
+    config.versions = VERSIONS[min..max + 1].iter()
+        .rev()
+        .cloned()
+        .collect();
+
+    if let Some(alpn) = optional(lookup, "RUSTLS_ALPN")? {
+        let protocols = split_list(&alpn).iter()
+            .map(|protocol| protocol.to_string())
+            .collect::<Vec<_>>();
+        config.set_protocols(&protocols);
+    }
+
+    if let Some(suites) = optional(lookup, "RUSTLS_CIPHER_SUITES")? {
+        let mut ciphersuites = Vec::new();
+        for name in split_list(&suites) {
+            let suite = ALL_CIPHERSUITES.iter()
+                .find(|suite| format!("{:?}", suite.suite).eq_ignore_ascii_case(name))
+                .ok_or_else(|| config_error(format!("RUSTLS_CIPHER_SUITES: unknown suite {}",
+                                                    name)))?;
+            ciphersuites.push(*suite);
+        }
+        if ciphersuites.is_empty() {
+            return Err(config_error("RUSTLS_CIPHER_SUITES is empty".to_string()));
+        }
+        config.ciphersuites = ciphersuites;
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use msgs::enums::CipherSuite;
+
+    fn from_map(vars: &[(&str, &str)]) -> Result<ServerConfig, TLSError> {
+        let vars = vars.iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        from_vars(&|name| vars.get(name).cloned().ok_or(env::VarError::NotPresent))
+    }
+
+    fn error_of(result: Result<ServerConfig, TLSError>) -> String {
+        match result {
+            Err(TLSError::General(msg)) => msg,
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    const CERT: (&str, &str) = ("RUSTLS_CERT_FILE", "test-ca/rsa/end.fullchain");
+    const KEY: (&str, &str) = ("RUSTLS_KEY_FILE", "test-ca/rsa/end.rsa");
+
+    #[test]
+    fn test_minimal() {
+        let config = from_map(&[CERT, KEY]).unwrap();
+        assert!(!config.get_verifier().offer_client_auth());
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2]);
+        assert_eq!(config.ciphersuites.len(), ALL_CIPHERSUITES.len());
+        assert!(config.alpn_protocols.is_empty());
+    }
+
+    #[test]
+    fn test_everything() {
+        let config = from_map(&[
+            ("RUSTLS_CERT_FILE", "test-ca/ecdsa/end.fullchain"),
+            ("RUSTLS_KEY_FILE", "test-ca/ecdsa/end.key"),
+            ("RUSTLS_CA_FILE", "test-ca/ecdsa/ca.cert"),
+            ("RUSTLS_MIN_VERSION", "TLSv1.3"),
+            ("RUSTLS_MAX_VERSION", "1.3"),
+            ("RUSTLS_ALPN", "h2, http/1.1"),
+            ("RUSTLS_CIPHER_SUITES", "TLS13_CHACHA20_POLY1305_SHA256,tls13_aes_256_gcm_sha384"),
+        ]).unwrap();
+        assert!(config.get_verifier().client_auth_mandatory());
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_3]);
+        assert_eq!(config.alpn_protocols, vec!["h2".to_string(), "http/1.1".to_string()]);
+        assert_eq!(config.ciphersuites.iter().map(|s| s.suite).collect::<Vec<_>>(),
+                   vec![CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+                        CipherSuite::TLS13_AES_256_GCM_SHA384]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error_of(from_map(&[KEY])), "RUSTLS_CERT_FILE is not set");
+        assert_eq!(error_of(from_map(&[CERT])), "RUSTLS_KEY_FILE is not set");
+        assert!(error_of(from_map(&[("RUSTLS_CERT_FILE", "test-ca/missing"), KEY]))
+                .starts_with("RUSTLS_CERT_FILE: cannot open test-ca/missing"));
+        assert_eq!(error_of(from_map(&[CERT, ("RUSTLS_KEY_FILE", "test-ca/rsa/end.cert")])),
+                   "RUSTLS_KEY_FILE: no private keys in test-ca/rsa/end.cert");
+        assert_eq!(error_of(from_map(&[CERT, KEY, ("RUSTLS_MIN_VERSION", "1.1")])),
+                   "RUSTLS_MIN_VERSION: unsupported version \"1.1\"");
+        assert_eq!(error_of(from_map(&[CERT, KEY, ("RUSTLS_MIN_VERSION", "1.3"),
+                                       ("RUSTLS_MAX_VERSION", "1.2")])),
+                   "RUSTLS_MIN_VERSION is greater than RUSTLS_MAX_VERSION");
+        assert_eq!(error_of(from_map(&[CERT, KEY, ("RUSTLS_CIPHER_SUITES", "TLS_NOPE")])),
+                   "RUSTLS_CIPHER_SUITES: unknown suite TLS_NOPE");
+        assert_eq!(error_of(from_map(&[CERT, KEY, ("RUSTLS_CIPHER_SUITES", " , ")])),
+                   "RUSTLS_CIPHER_SUITES is empty");
+    }
+
+    #[test]
+    fn test_not_unicode() {
+        let lookup = |name: &str| match name {
+            "RUSTLS_CERT_FILE" => Ok(CERT.1.to_string()),
+            "RUSTLS_KEY_FILE" => Ok(KEY.1.to_string()),
+            "RUSTLS_CA_FILE" => Err(env::VarError::NotUnicode("\u{fffd}".into())),
+            _ => Err(env::VarError::NotPresent),
+        };
+        assert_eq!(error_of(from_vars(&lookup)), "RUSTLS_CA_FILE is not valid unicode");
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("V", "1.2").unwrap(), ProtocolVersion::TLSv1_2);
+        assert_eq!(parse_version("V", "TLSv1_3").unwrap(), ProtocolVersion::TLSv1_3);
+        assert_eq!(parse_version("V", "tls1.3").unwrap(), ProtocolVersion::TLSv1_3);
+        assert!(parse_version("V", "SSLv3").is_err());
+    }
+}
@@ -1,4 +1,3 @@
-use msgs::enums::SignatureScheme;
 use msgs::handshake::SessionID;
 use rand;
 use sign;
@@ -7,8 +6,77 @@ use webpki;
 use server;
 use error::TLSError;
 
+use msgs::codec::{self, Reader};
+
+use std::borrow::Cow;
 use std::collections;
+use std::fs;
+use std::hint;
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ring::aead;
+use ring::constant_time;
+
+/// Counts the server handshakes in progress, to enforce
+/// `ServerConfig::max_concurrent_handshakes`.
+///
+/// A `ServerConfig` and its clones share one of these.  It can
+/// also be shared between several `ServerConfig`s, so they are
+/// limited together.
+#[derive(Debug, Default)]
+pub struct HandshakeSemaphore {
+    in_progress: AtomicUsize,
+}
+
+impl HandshakeSemaphore {
+    /// Make a new `HandshakeSemaphore`, with no handshakes
+    /// in progress.
+    pub fn new() -> Arc<HandshakeSemaphore> {
+        Arc::new(HandshakeSemaphore::default())
+    }
+
+    /// How many handshakes are currently in progress.
+    pub fn in_progress(&self) -> usize {
+        self.in_progress.load(Ordering::SeqCst)
+    }
+
+    /// Take a permit, unless `limit` are already held.
+    ///
+    /// Handshakes are counted even without a limit, so that a
+    /// limit set later on is accurate.
+    pub fn try_acquire(sem: &Arc<HandshakeSemaphore>,
+                       limit: Option<usize>) -> Option<HandshakePermit> {
+        let mut current = sem.in_progress.load(Ordering::SeqCst);
+        loop {
+            if let Some(limit) = limit {
+                if current >= limit {
+                    return None;
+                }
+            }
+
+            match sem.in_progress.compare_exchange(current, current + 1,
+                                                   Ordering::SeqCst,
+                                                   Ordering::SeqCst) {
+                Ok(_) => return Some(HandshakePermit(sem.clone())),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+/// A handshake counted by a `HandshakeSemaphore`.  The
+/// handshake is no longer counted once this is dropped.
+#[derive(Debug)]
+pub struct HandshakePermit(Arc<HandshakeSemaphore>);
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        self.0.in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
 /// Something which never stores sessions.
 pub struct NoServerSessionStorage {}
@@ -23,14 +91,78 @@ impl server::StoresServerSessions for NoServerSessionStorage {
     fn get(&self, _id: &[u8]) -> Option<Vec<u8>> {
         None
     }
+    fn clear(&self) {}
+    fn iter<'a>(&'a self) -> server::StoredSessions<'a> {
+        Box::new(None.into_iter())
+    }
 }
 
 /// An implementor of `StoresServerSessions` that stores everything
 /// in memory.  If enforces a limit on the number of stored sessions
 /// to bound memory usage.
 pub struct ServerSessionMemoryCache {
-    cache: Mutex<collections::HashMap<Vec<u8>, Vec<u8>>>,
+    cache: Mutex<SessionMap>,
     max_entries: usize,
+    max_bytes: Option<usize>,
+    constant_time_lookup: bool,
+    id_prefix: Option<[u8; 4]>,
+}
+
+/// Our guess at the memory used by each cache entry, beyond
+/// the key and value bytes: the two `Vec`s (24 bytes each on 64-bit
+/// platforms), plus the hash table's own bookkeeping.
+const ENTRY_OVERHEAD: usize = 64;
+
+fn entry_size(key: &[u8], value: &[u8]) -> usize {
+    key.len() + value.len() + ENTRY_OVERHEAD
+}
+
+struct StoredSession {
+    /// When this was stored, as a count of `SessionMap::insert` calls.
+    seq: u64,
+    value: Vec<u8>,
+}
+
+/// The sessions stored by a `ServerSessionMemoryCache`, and the
+/// total of their `entry_size`s, kept up to date as they change.
+/// It also keeps the keys in the order they were stored, so the
+/// oldest session can be found quickly.
+#[derive(Default)]
+struct SessionMap {
+    entries: collections::HashMap<Vec<u8>, StoredSession>,
+    order: collections::BTreeMap<u64, Vec<u8>>,
+    next_seq: u64,
+    bytes: usize,
+}
+
+impl SessionMap {
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.remove(&key);
+        self.bytes += entry_size(&key, &value);
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.insert(seq, key.clone());
+        self.entries.insert(key, StoredSession { seq, value });
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(old) = self.entries.remove(key) {
+            self.order.remove(&old.seq);
+            self.bytes -= entry_size(key, &old.value);
+        }
+    }
+
+    /// The key of the session stored longest ago.
+    fn oldest(&self) -> Option<&Vec<u8>> {
+        self.order.values().next()
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
 }
 
 impl ServerSessionMemoryCache {
@@ -39,15 +171,233 @@ impl ServerSessionMemoryCache {
     pub fn new(size: usize) -> Arc<ServerSessionMemoryCache> {
         debug_assert!(size > 0);
         Arc::new(ServerSessionMemoryCache {
-            cache: Mutex::new(collections::HashMap::new()),
+            cache: Mutex::new(SessionMap::default()),
             max_entries: size,
+            max_bytes: None,
+            constant_time_lookup: false,
+            id_prefix: None,
         })
     }
 
-    fn limit_size(&self) {
-        let mut cache = self.cache.lock().unwrap();
-        while cache.len() > self.max_entries {
-            let k = cache.keys().next().unwrap().clone();
+    /// Like `new`, but the session IDs we generate start with
+    /// `prefix`, followed by 28 random bytes.
+    ///
+    /// This is for processes which share a session store: give each
+    /// one a different prefix, and the IDs they generate can't
+    /// collide.
+    pub fn with_prefix(size: usize, prefix: [u8; 4]) -> Arc<ServerSessionMemoryCache> {
+        debug_assert!(size > 0);
+        Arc::new(ServerSessionMemoryCache {
+            cache: Mutex::new(SessionMap::default()),
+            max_entries: size,
+            max_bytes: None,
+            constant_time_lookup: false,
+            id_prefix: Some(prefix),
+        })
+    }
+
+    /// Make a new ServerSessionMemoryCache which stores any number
+    /// of sessions, as long as `memory_usage_bytes` stays within
+    /// `max_bytes`.  Sessions which would take it over are not
+    /// stored.
+    pub fn with_memory_limit(max_bytes: usize) -> Arc<ServerSessionMemoryCache> {
+        Arc::new(ServerSessionMemoryCache {
+            cache: Mutex::new(SessionMap::default()),
+            max_entries: usize::MAX,
+            max_bytes: Some(max_bytes),
+            constant_time_lookup: false,
+            id_prefix: None,
+        })
+    }
+
+    /// Like `new`, but lookups do the same work whether or not
+    /// the session is present.
+    ///
+    /// Every lookup compares the key against every stored key, in
+    /// time independent of their contents, and copies exactly one
+    /// stored value: the hit, or an arbitrary one on a miss.
+    /// This does not hide the lengths of the keys, nor of the
+    /// value copied, nor the number of stored sessions.
+    ///
+    /// Lookups are O(`size`) rather than O(1), so this is only
+    /// suitable for modest cache sizes.
+    pub fn new_constant_time(size: usize) -> Arc<ServerSessionMemoryCache> {
+        debug_assert!(size > 0);
+        Arc::new(ServerSessionMemoryCache {
+            cache: Mutex::new(SessionMap::default()),
+            max_entries: size,
+            max_bytes: None,
+            constant_time_lookup: true,
+            id_prefix: None,
+        })
+    }
+
+    fn get_constant_time(cache: &SessionMap, key: &[u8]) -> Option<Vec<u8>> {
+        // Find the position of the hit, if any, without branching
+        // on the comparisons.  A miss leaves position 0.
+        let mut hit = 0usize;
+        let mut position = 0usize;
+
+        for (i, k) in cache.entries.keys().enumerate() {
+            let equal = constant_time::verify_slices_are_equal(k, key).is_ok() as usize;
+            let mask = equal.wrapping_neg();
+            position = (i & mask) | (position & !mask);
+            hit |= equal;
+        }
+
+        // Copy the value either way.  black_box stops the copy being
+        // moved after the check, where it would only happen for hits.
+        let value = cache.entries.values()
+            .nth(position)
+            .map(|stored| stored.value.clone());
+        let value = hint::black_box(value);
+
+        if hit == 1 { value } else { None }
+    }
+
+    /// Make a new ServerSessionMemoryCache storing up to `size`
+    /// sessions, and fill it with those in the file at `path`,
+    /// which was written by `save_to_file`.  If the file has more
+    /// than `size` sessions, only `size` of them are kept.
+    ///
+    /// This lets a restarted server resume sessions established
+    /// before it restarted.
+    pub fn load_from_file(path: &Path, size: usize) -> io::Result<Arc<ServerSessionMemoryCache>> {
+        use server::StoresServerSessions;
+
+        let mut bytes = Vec::new();
+        fs::File::open(path)?
+            .read_to_end(&mut bytes)?;
+
+        let entries = ServerSessionMemoryCache::decode_snapshot(&bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                                          "invalid session cache snapshot"))?;
+
+        let cache = ServerSessionMemoryCache::new(size);
+        for (key, value) in entries {
+            cache.put(key, value);
+        }
+        Ok(cache)
+    }
+
+    /// Write all the stored sessions to a new file at `path`, for
+    /// `load_from_file` to read later.  The format is a sequence of
+    /// entries, each a key then a value, each preceded by its length
+    /// as a four byte big-endian integer.  They are written oldest
+    /// first, so `load_from_file` keeps their order.
+    ///
+    /// The file contains the secrets for each session, which are
+    /// enough to resume it and so to impersonate either side or
+    /// decrypt the resumed session's data.  Protect it as you would
+    /// your private key.  On unix it is created readable only by its
+    /// owner.
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        let cache = self.cache.lock().unwrap();
+        for key in cache.order.values() {
+            let value = &cache.entries[key].value;
+            codec::encode_u32(key.len() as u32, &mut bytes);
+            bytes.extend_from_slice(key);
+            codec::encode_u32(value.len() as u32, &mut bytes);
+            bytes.extend_from_slice(value);
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true)
+            .create(true)
+            .truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?
+            .write_all(&bytes)
+    }
+
+    fn decode_snapshot(bytes: &[u8]) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut rd = Reader::init(bytes);
+        let mut entries = Vec::new();
+
+        while rd.any_left() {
+            let key_len = codec::read_u32(&mut rd)? as usize;
+            let key = rd.take(key_len)?.to_vec();
+            let value_len = codec::read_u32(&mut rd)? as usize;
+            let value = rd.take(value_len)?.to_vec();
+            entries.push((key, value));
+        }
+
+        Some(entries)
+    }
+
+    /// Estimate the memory used by the stored sessions, in bytes.
+    /// This counts the keys and values, and a fixed overhead for each
+    /// entry; it's not exact, but about right.
+    pub fn memory_usage_bytes(&self) -> usize {
+        self.cache.lock()
+            .unwrap()
+            .bytes
+    }
+
+    /// Return true if storing `value` under `key` would keep us
+    /// within `max_bytes`.
+    fn fits(&self,
+            cache: &SessionMap,
+            key: &[u8],
+            value: &[u8]) -> bool {
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return true,
+        };
+
+        let replaced = cache.entries.get(key)
+            .map(|old| entry_size(key, &old.value))
+            .unwrap_or(0);
+        cache.bytes - replaced + entry_size(key, value) <= max_bytes
+    }
+
+    /// A copy of the stored sessions.
+    fn snapshot(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.cache.lock()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|(k, stored)| (k.clone(), stored.value.clone()))
+            .collect()
+    }
+
+    /// The number of stored sessions.
+    pub(crate) fn len(&self) -> usize {
+        self.cache.lock()
+            .unwrap()
+            .entries
+            .len()
+    }
+
+    /// Return true if there's a session stored against `key`.
+    pub(crate) fn contains_key(&self, key: &[u8]) -> bool {
+        self.cache.lock()
+            .unwrap()
+            .entries
+            .contains_key(key)
+    }
+
+    /// Forget the oldest session, if there are any.
+    pub(crate) fn evict_one(&self) {
+        let mut cache = self.cache.lock()
+            .unwrap();
+        let k = match cache.oldest() {
+            Some(k) => k.clone(),
+            None => return,
+        };
+        cache.remove(&k);
+    }
+
+    /// Make room for `key`, without evicting `key` itself.  The
+    /// oldest sessions go first.
+    fn limit_size(&self, cache: &mut SessionMap, key: &[u8]) {
+        while cache.entries.len() >= self.max_entries && !cache.entries.contains_key(key) {
+            let k = cache.oldest().unwrap().clone();
             cache.remove(&k);
         }
     }
@@ -56,22 +406,337 @@ impl ServerSessionMemoryCache {
 impl server::StoresServerSessions for ServerSessionMemoryCache {
     fn generate(&self) -> SessionID {
         let mut v = [0u8; 32];
-        rand::fill_random(&mut v);
+        match self.id_prefix {
+            Some(ref prefix) => {
+                v[..4].copy_from_slice(prefix);
+                rand::fill_random(&mut v[4..]);
+            }
+            None => rand::fill_random(&mut v),
+        }
         SessionID::new(&v)
     }
 
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
-        self.cache.lock()
-            .unwrap()
-            .insert(key, value);
-        self.limit_size();
+        let mut cache = self.cache.lock()
+            .unwrap();
+        if !self.fits(&cache, &key, &value) {
+            return false;
+        }
+        self.limit_size(&mut cache, &key);
+        cache.insert(key, value);
         true
     }
 
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let cache = self.cache.lock()
+            .unwrap();
+
+        if self.constant_time_lookup {
+            ServerSessionMemoryCache::get_constant_time(&cache, key)
+        } else {
+            cache.entries.get(key).map(|stored| stored.value.clone())
+        }
+    }
+
+    fn clear(&self) {
         self.cache.lock()
             .unwrap()
-            .get(key).cloned()
+            .clear();
+    }
+
+    fn iter<'a>(&'a self) -> server::StoredSessions<'a> {
+        Box::new(self.snapshot()
+                 .into_iter()
+                 .map(|(k, v)| (Cow::Owned(k), Cow::Owned(v))))
+    }
+}
+
+/// An implementor of `StoresServerSessions` which keeps the
+/// sessions for each virtual host in a separate
+/// `ServerSessionMemoryCache`, so that a busy host can't push out
+/// the sessions of the others.
+///
+/// Each host may store up to `per_host` sessions, unless given its
+/// own limit with `set_host_limit`, and there may be at most
+/// `max_entries` sessions stored in total.  When a host's limit is
+/// reached, its oldest session is dropped.  When the total limit is
+/// reached, the oldest session of the host with the most sessions is
+/// dropped.
+///
+/// rustls's own keys don't say which host a session is for, so
+/// this is for use by a `StoresServerSessions` which knows that and
+/// puts it in the key.  Keys must be the SNI name's bytes, then a
+/// zero byte, then the rest of the key: `key_for` makes them.  Keys
+/// without a zero byte are not stored.
+pub struct VirtualHostSessionCache {
+    hosts: Mutex<collections::HashMap<Vec<u8>, Arc<ServerSessionMemoryCache>>>,
+    host_limits: Mutex<collections::HashMap<Vec<u8>, usize>>,
+    per_host: usize,
+    max_entries: usize,
+}
+
+impl VirtualHostSessionCache {
+    /// Make a new VirtualHostSessionCache.  `per_host` is the
+    /// maximum number of stored sessions for each host, and
+    /// `max_entries` the maximum across all hosts.
+    pub fn new(per_host: usize, max_entries: usize) -> Arc<VirtualHostSessionCache> {
+        debug_assert!(per_host > 0 && max_entries > 0);
+        Arc::new(VirtualHostSessionCache {
+            hosts: Mutex::new(collections::HashMap::new()),
+            host_limits: Mutex::new(collections::HashMap::new()),
+            per_host,
+            max_entries,
+        })
+    }
+
+    /// Let the host named `sni` store up to `limit` sessions, rather
+    /// than `per_host`.  If it has more than that already, its oldest
+    /// sessions are dropped.  `max_entries` still applies.
+    pub fn set_host_limit(&self, sni: Option<&str>, limit: usize) {
+        debug_assert!(limit > 0);
+        let sni = sni.unwrap_or("").as_bytes();
+
+        let hosts = self.hosts.lock()
+            .unwrap();
+        self.host_limits.lock()
+            .unwrap()
+            .insert(sni.to_vec(), limit);
+
+        if let Some(cache) = hosts.get(sni) {
+            while cache.len() > limit {
+                cache.evict_one();
+            }
+        }
+    }
+
+    /// The most sessions the host named `sni` may store.
+    fn host_limit(&self, sni: &[u8]) -> usize {
+        self.host_limits.lock()
+            .unwrap()
+            .get(sni)
+            .cloned()
+            .unwrap_or(self.per_host)
+    }
+
+    /// Make the key to store `key` against for the host named
+    /// `sni`.  Sessions without a SNI name are kept together, as
+    /// if for a host with an empty name.
+    pub fn key_for(sni: Option<&str>, key: &[u8]) -> Vec<u8> {
+        let mut ret = Vec::new();
+        if let Some(sni) = sni {
+            ret.extend_from_slice(sni.as_bytes());
+        }
+        ret.push(0);
+        ret.extend_from_slice(key);
+        ret
+    }
+
+    /// Split `key` into the host name and the rest.  DNS names
+    /// can't contain a zero byte, so the first one ends the name.
+    fn split_key(key: &[u8]) -> Option<(&[u8], &[u8])> {
+        let at = key.iter().position(|&b| b == 0)?;
+        Some((&key[..at], &key[at + 1..]))
+    }
+
+    /// The number of sessions stored for the host named `sni`.
+    pub fn host_len(&self, sni: Option<&str>) -> usize {
+        let sni = sni.unwrap_or("").as_bytes();
+        self.hosts.lock()
+            .unwrap()
+            .get(sni)
+            .map(|cache| cache.len())
+            .unwrap_or(0)
+    }
+
+    /// The number of sessions stored across all hosts.
+    pub fn len(&self) -> usize {
+        self.hosts.lock()
+            .unwrap()
+            .values()
+            .map(|cache| cache.len())
+            .sum()
+    }
+
+    /// Return true if no sessions are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the oldest session from the host with the most sessions.
+    fn evict_one(hosts: &mut collections::HashMap<Vec<u8>, Arc<ServerSessionMemoryCache>>) {
+        let largest = hosts.iter()
+            .max_by_key(|&(_, cache)| cache.len())
+            .map(|(sni, _)| sni.clone());
+
+        if let Some(sni) = largest {
+            hosts[&sni].evict_one();
+            if hosts[&sni].len() == 0 {
+                hosts.remove(&sni);
+            }
+        }
+    }
+}
+
+impl server::StoresServerSessions for VirtualHostSessionCache {
+    fn generate(&self) -> SessionID {
+        let mut v = [0u8; 32];
+        rand::fill_random(&mut v);
+        SessionID::new(&v)
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let (sni, key) = match VirtualHostSessionCache::split_key(&key) {
+            Some(split) => split,
+            None => return false,
+        };
+
+        let limit = self.host_limit(sni);
+        let mut hosts = self.hosts.lock()
+            .unwrap();
+
+        // A full host makes room for itself, so only a new session
+        // for a host with room adds to the total.
+        let adds_entry = match hosts.get(sni) {
+            Some(cache) if cache.contains_key(key) => false,
+            Some(cache) if cache.len() >= limit => {
+                while cache.len() >= limit {
+                    cache.evict_one();
+                }
+                false
+            }
+            _ => true,
+        };
+
+        if adds_entry {
+            let mut total: usize = hosts.values().map(|cache| cache.len()).sum();
+            while total >= self.max_entries {
+                VirtualHostSessionCache::evict_one(&mut hosts);
+                total -= 1;
+            }
+        }
+
+        // Host caches have no limit of their own: we enforce it, as
+        // it can change.
+        hosts.entry(sni.to_vec())
+            .or_insert_with(|| ServerSessionMemoryCache::new(usize::MAX))
+            .put(key.to_vec(), value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let (sni, key) = VirtualHostSessionCache::split_key(key)?;
+        self.hosts.lock()
+            .unwrap()
+            .get(sni)
+            .and_then(|cache| cache.get(key))
+    }
+
+    fn clear(&self) {
+        self.hosts.lock()
+            .unwrap()
+            .clear();
+    }
+
+    /// The keys are as made by `key_for`.
+    fn iter<'a>(&'a self) -> server::StoredSessions<'a> {
+        let mut entries = Vec::new();
+        for (sni, cache) in self.hosts.lock().unwrap().iter() {
+            for (key, value) in cache.snapshot() {
+                let mut full_key = sni.clone();
+                full_key.push(0);
+                full_key.extend_from_slice(&key);
+                entries.push((Cow::Owned(full_key), Cow::Owned(value)));
+            }
+        }
+        Box::new(entries.into_iter())
+    }
+}
+
+/// A `StoresServerSessions` which encrypts session values with
+/// AES-256-GCM before storing them in another one, `S`: for example,
+/// one backed by a cache shared with other servers.
+///
+/// Each value is sealed with a random nonce, and bound to its key,
+/// so it can't be moved to another session.  Values which don't
+/// decrypt, perhaps because they were stored with a different
+/// `key`, are treated as missing, so the client just does a full
+/// handshake.
+///
+/// Keys are not encrypted, and `generate` is passed to `S`.
+pub struct EncryptedSessionStore<S: server::StoresServerSessions + ?Sized> {
+    inner: Arc<S>,
+    enc: aead::SealingKey,
+    dec: aead::OpeningKey,
+}
+
+impl<S: server::StoresServerSessions + ?Sized> EncryptedSessionStore<S> {
+    /// Make a new EncryptedSessionStore, storing values in `inner`
+    /// which are encrypted with `key`.
+    pub fn new(inner: Arc<S>, key: &[u8; 32]) -> Arc<EncryptedSessionStore<S>> {
+        Arc::new(EncryptedSessionStore {
+            inner,
+            enc: aead::SealingKey::new(&aead::AES_256_GCM, key).unwrap(),
+            dec: aead::OpeningKey::new(&aead::AES_256_GCM, key).unwrap(),
+        })
+    }
+
+    fn seal(&self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let alg = &aead::AES_256_GCM;
+        let mut out = vec![0u8; alg.nonce_len()];
+        rand::fill_random(&mut out);
+        out.extend_from_slice(value);
+        out.resize(alg.nonce_len() + value.len() + alg.tag_len(), 0u8);
+
+        let (nonce, body) = out.split_at_mut(alg.nonce_len());
+        aead::seal_in_place(&self.enc, nonce, key, body, alg.tag_len())
+            .ok()?;
+        Some(out)
+    }
+
+    fn open(&self, key: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let alg = &aead::AES_256_GCM;
+        if sealed.len() < alg.nonce_len() + alg.tag_len() {
+            return None;
+        }
+
+        let (nonce, body) = sealed.split_at(alg.nonce_len());
+        let mut out = body.to_vec();
+        let plain_len = aead::open_in_place(&self.dec, nonce, key, 0, &mut out)
+            .ok()?
+            .len();
+        out.truncate(plain_len);
+        Some(out)
+    }
+}
+
+impl<S: server::StoresServerSessions + ?Sized> server::StoresServerSessions
+    for EncryptedSessionStore<S> {
+    fn generate(&self) -> SessionID {
+        self.inner.generate()
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        match self.seal(&key, &value) {
+            Some(sealed) => self.inner.put(key, sealed),
+            None => false,
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key)
+            .and_then(|sealed| self.open(key, &sealed))
+    }
+
+    fn clear(&self) {
+        self.inner.clear()
+    }
+
+    /// Values which don't decrypt are left out.
+    fn iter<'a>(&'a self) -> server::StoredSessions<'a> {
+        Box::new(self.inner.iter()
+            .filter_map(move |(key, sealed)| {
+                let value = self.open(&key, &sealed)?;
+                Some((key, Cow::Owned(value)))
+            }))
     }
 }
 
@@ -97,10 +762,7 @@ impl server::ProducesTickets for NeverProducesTickets {
 pub struct FailResolveChain {}
 
 impl server::ResolvesServerCert for FailResolveChain {
-    fn resolve(&self,
-               _server_name: Option<webpki::DNSNameRef>,
-               _sigschemes: &[SignatureScheme])
-               -> Option<sign::CertifiedKey> {
+    fn resolve(&self, _client_hello: server::ClientHello) -> Option<sign::CertifiedKey> {
         None
     }
 }
@@ -109,6 +771,10 @@ impl server::ResolvesServerCert for FailResolveChain {
 pub struct AlwaysResolvesChain(sign::CertifiedKey);
 
 impl AlwaysResolvesChain {
+    pub fn new(key: sign::CertifiedKey) -> AlwaysResolvesChain {
+        AlwaysResolvesChain(key)
+    }
+
     pub fn new_rsa(chain: Vec<key::Certificate>,
                    priv_key: &key::PrivateKey) -> AlwaysResolvesChain {
         let key = sign::RSASigningKey::new(priv_key)
@@ -133,10 +799,7 @@ impl AlwaysResolvesChain {
 }
 
 impl server::ResolvesServerCert for AlwaysResolvesChain {
-    fn resolve(&self,
-               _server_name: Option<webpki::DNSNameRef>,
-               _sigschemes: &[SignatureScheme])
-               -> Option<sign::CertifiedKey> {
+    fn resolve(&self, _client_hello: server::ClientHello) -> Option<sign::CertifiedKey> {
         Some(self.0.clone())
     }
 }
@@ -169,11 +832,8 @@ impl ResolvesServerCertUsingSNI {
 }
 
 impl server::ResolvesServerCert for ResolvesServerCertUsingSNI {
-    fn resolve(&self,
-               server_name: Option<webpki::DNSNameRef>,
-               _sigschemes: &[SignatureScheme])
-               -> Option<sign::CertifiedKey> {
-        if let Some(name) = server_name {
+    fn resolve(&self, client_hello: server::ClientHello) -> Option<sign::CertifiedKey> {
+        if let Some(name) = client_hello.server_name() {
             self.by_name.get(name.into())
                 .map(|ck| ck.clone())
         } else {
@@ -183,6 +843,129 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSNI {
     }
 }
 
+/// Something that chooses between cert chains/keys based on the
+/// ALPN protocols offered by the client.
+///
+/// The client's offered protocols are tried in the client's
+/// preference order, and the first one with a registered
+/// `sign::CertifiedKey` wins.  If there is none, a fallback is
+/// used.  Note that the negotiated protocol is chosen separately,
+/// using `ServerConfig::alpn_protocols`; make sure the two agree.
+pub struct AlpnResolvesServerCert {
+    by_protocol: collections::HashMap<Vec<u8>, sign::CertifiedKey>,
+    fallback: sign::CertifiedKey,
+}
+
+impl AlpnResolvesServerCert {
+    /// Create a new resolver which uses `fallback` unless told
+    /// otherwise.
+    pub fn new(fallback: sign::CertifiedKey) -> AlpnResolvesServerCert {
+        AlpnResolvesServerCert {
+            by_protocol: collections::HashMap::new(),
+            fallback,
+        }
+    }
+
+    /// Use `ck` for clients offering ALPN protocol `protocol`.
+    ///
+    /// This function fails if the certificate chain is
+    /// syntactically faulty.
+    pub fn add(&mut self, protocol: &[u8], ck: sign::CertifiedKey) -> Result<(), TLSError> {
+        ck.cross_check_end_entity_cert(None)?;
+        self.by_protocol.insert(protocol.to_vec(), ck);
+        Ok(())
+    }
+}
+
+impl server::ResolvesServerCert for AlpnResolvesServerCert {
+    fn resolve(&self, client_hello: server::ClientHello) -> Option<sign::CertifiedKey> {
+        let offered = client_hello.alpn().unwrap_or(&[]);
+        let ck = offered.iter()
+            .filter_map(|proto| self.by_protocol.get(*proto))
+            .next()
+            .unwrap_or(&self.fallback);
+        Some(ck.clone())
+    }
+}
+
+/// The ALPN protocol name used for the ACME TLS-ALPN-01 challenge
+/// (RFC 8737).  A server using `AcmeTlsAlpnResponder` must also
+/// list this in `ServerConfig::alpn_protocols`, so it is negotiated
+/// for challenge connections.
+pub const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+
+/// Something that answers ACME TLS-ALPN-01 challenges (RFC 8737),
+/// and otherwise defers to another resolver.
+///
+/// A connection is treated as a challenge if the client offers
+/// exactly one ALPN protocol: `acme-tls/1`.  For these, the
+/// certificate registered for the SNI name with `add_challenge`
+/// is served, or the handshake fails if there isn't one.  All
+/// other connections are resolved by the fallback resolver.
+///
+/// The challenge certificate is a self-signed certificate for the
+/// name, carrying the key authorization digest in a critical
+/// `id-pe-acmeIdentifier` (1.3.6.1.5.5.7.1.31) extension.  rustls
+/// does not generate certificates, so this must be done by your
+/// ACME client.
+pub struct AcmeTlsAlpnResponder {
+    challenges: Mutex<collections::HashMap<String, sign::CertifiedKey>>,
+    fallback: Arc<server::ResolvesServerCert>,
+}
+
+impl AcmeTlsAlpnResponder {
+    /// Create a new responder with no outstanding challenges,
+    /// which resolves normal connections using `fallback`.
+    pub fn new(fallback: Arc<server::ResolvesServerCert>) -> AcmeTlsAlpnResponder {
+        AcmeTlsAlpnResponder {
+            challenges: Mutex::new(collections::HashMap::new()),
+            fallback,
+        }
+    }
+
+    /// Serve `ck` for TLS-ALPN-01 challenges for SNI `name`,
+    /// replacing any existing challenge for that name.
+    ///
+    /// This function fails if `name` is not a valid DNS name.
+    pub fn add_challenge(&self, name: &str, ck: sign::CertifiedKey) -> Result<(), TLSError> {
+        webpki::DNSNameRef::try_from_ascii_str(name)
+            .map_err(|_| TLSError::General("Bad DNS name".into()))?;
+
+        self.challenges.lock()
+            .unwrap()
+            .insert(name.into(), ck);
+        Ok(())
+    }
+
+    /// Stop serving the challenge for `name`.  Call this once
+    /// the ACME server has validated the challenge.
+    pub fn remove_challenge(&self, name: &str) {
+        self.challenges.lock()
+            .unwrap()
+            .remove(name);
+    }
+}
+
+impl server::ResolvesServerCert for AcmeTlsAlpnResponder {
+    fn resolve(&self, client_hello: server::ClientHello) -> Option<sign::CertifiedKey> {
+        let is_challenge = match client_hello.alpn() {
+            Some(protos) => protos.len() == 1 && protos[0] == ACME_TLS_ALPN_NAME,
+            None => false,
+        };
+
+        if !is_challenge {
+            return self.fallback.resolve(client_hello);
+        }
+
+        // Challenges always need SNI: that's the name being validated.
+        let name: &str = client_hello.server_name()?.into();
+        self.challenges.lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -211,12 +994,32 @@ mod test {
         assert_eq!(c.get(&[0x02]), None);
     }
 
+    #[test]
+    fn test_noserversessionstorage_iterates_nothing() {
+        let c = NoServerSessionStorage {};
+        c.put(vec![0x01], vec![0x02]);
+        assert_eq!(c.iter().count(), 0);
+    }
+
     #[test]
     fn test_serversessionmemorycache_yields_sessid() {
         let c = ServerSessionMemoryCache::new(4);
         assert_eq!(c.generate().len(), 32);
     }
 
+    #[test]
+    fn test_serversessionmemorycache_with_prefix() {
+        use msgs::codec::Codec;
+
+        let c = ServerSessionMemoryCache::with_prefix(4, [1, 2, 3, 4]);
+        let first = c.generate();
+        let second = c.generate();
+        assert_eq!(first.len(), 32);
+        assert_eq!(&first.get_encoding()[1..5], &[1, 2, 3, 4]);
+        assert_eq!(&second.get_encoding()[1..5], &[1, 2, 3, 4]);
+        assert!(first != second);
+    }
+
     #[test]
     fn test_serversessionmemorycache_accepts_put() {
         let c = ServerSessionMemoryCache::new(4);
@@ -239,6 +1042,25 @@ mod test {
         assert_eq!(c.get(&[0x01]), Some(vec![0x04]));
     }
 
+    #[test]
+    fn test_serversessionmemorycache_iter_is_snapshot() {
+        let c = ServerSessionMemoryCache::new(4);
+        c.put(vec![0x01], vec![0x02]);
+        c.put(vec![0x03], vec![0x04]);
+
+        let mut seen = Vec::new();
+        for (k, v) in c.iter() {
+            // Changing the cache mid-iteration neither deadlocks
+            // nor affects the iterator.
+            c.clear();
+            c.put(vec![0x05], vec![0x06]);
+            seen.push((k.into_owned(), v.into_owned()));
+        }
+        seen.sort();
+        assert_eq!(seen, vec![(vec![0x01], vec![0x02]), (vec![0x03], vec![0x04])]);
+        assert_eq!(c.len(), 1);
+    }
+
     #[test]
     fn test_serversessionmemorycache_drops_to_maintain_size_invariant() {
         let c = ServerSessionMemoryCache::new(4);
@@ -257,4 +1079,348 @@ mod test {
 
         assert_eq!(count, 4);
     }
+
+    #[test]
+    fn test_serversessionmemorycache_clear() {
+        let c = ServerSessionMemoryCache::new(4);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        c.clear();
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.get(&[0x03]), None);
+        assert_eq!(c.put(vec![0x01], vec![0x05]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x05]));
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_concurrent_put_get() {
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
+
+        const MAX_ENTRIES: usize = 100;
+        let c = ServerSessionMemoryCache::new(MAX_ENTRIES);
+        let hits = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for t in 0..8u8 {
+                let c = &c;
+                let hits = &hits;
+                scope.spawn(move || {
+                    for i in 0..10_000u32 {
+                        let key = vec![t, (i % 251) as u8];
+                        assert!(c.put(key.clone(), i.to_be_bytes().to_vec()));
+                        if c.get(&key).is_some() {
+                            hits.fetch_add(1, Ordering::Relaxed);
+                        }
+
+                        // put evicts under the lock, so this is exact
+                        // rather than allowing for a race.
+                        assert!(c.len() <= MAX_ENTRIES);
+                    }
+                });
+            }
+        });
+
+        assert!(hits.load(Ordering::Relaxed) > 0);
+        assert!(c.len() <= MAX_ENTRIES);
+        assert!(!c.cache.is_poisoned());
+    }
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("rustls-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_save_and_load() {
+        let c = ServerSessionMemoryCache::new(4);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03; 300], vec![0x04; 70000]), true);
+
+        let path = snapshot_path("save-and-load");
+        c.save_to_file(&path).unwrap();
+        let loaded = ServerSessionMemoryCache::load_from_file(&path, 4).unwrap();
+        assert_eq!(loaded.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(loaded.get(&[0x03; 300]), Some(vec![0x04; 70000]));
+
+        // Only `size` sessions are loaded.
+        let loaded = ServerSessionMemoryCache::load_from_file(&path, 1).unwrap();
+        assert_eq!(loaded.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_load_rejects_bad_snapshot() {
+        let path = snapshot_path("bad-snapshot");
+        fs::File::create(&path).unwrap()
+            .write_all(&[0, 0, 0, 2, 0x01])
+            .unwrap();
+        let err = ServerSessionMemoryCache::load_from_file(&path, 4).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+
+        assert!(ServerSessionMemoryCache::load_from_file(&path, 4).is_err());
+    }
+
+    #[test]
+    fn test_encryptedsessionstore_round_trips() {
+        let inner = ServerSessionMemoryCache::new(4);
+        let c = EncryptedSessionStore::new(inner.clone(), &[1u8; 32]);
+        assert!(c.put(vec![0x01], b"secret".to_vec()));
+        assert_eq!(c.get(&[0x01]), Some(b"secret".to_vec()));
+        assert_eq!(c.get(&[0x02]), None);
+
+        let stored = inner.get(&[0x01]).unwrap();
+        assert_eq!(stored.len(), 12 + 6 + 16);
+        assert!(!stored.windows(6).any(|w| w == b"secret"));
+
+        let all = c.iter().collect::<Vec<_>>();
+        assert_eq!(all.len(), 1);
+        assert_eq!((&*all[0].0, &*all[0].1), (&[0x01][..], &b"secret"[..]));
+    }
+
+    #[test]
+    fn test_encryptedsessionstore_rejects_bad_values() {
+        let inner = ServerSessionMemoryCache::new(4);
+        let c = EncryptedSessionStore::new(inner.clone(), &[1u8; 32]);
+        assert!(c.put(vec![0x01], b"secret".to_vec()));
+        let stored = inner.get(&[0x01]).unwrap();
+
+        // Another key.
+        let other = EncryptedSessionStore::new(inner.clone(), &[2u8; 32]);
+        assert_eq!(other.get(&[0x01]), None);
+        assert_eq!(other.iter().count(), 0);
+
+        // Tampered.
+        let mut tampered = stored.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        inner.put(vec![0x01], tampered);
+        assert_eq!(c.get(&[0x01]), None);
+
+        // Moved to another session, or truncated.
+        inner.put(vec![0x02], stored.clone());
+        assert_eq!(c.get(&[0x02]), None);
+        inner.put(vec![0x03], stored[..20].to_vec());
+        assert_eq!(c.get(&[0x03]), None);
+    }
+
+    fn vhost_key(sni: &str, key: u8) -> Vec<u8> {
+        VirtualHostSessionCache::key_for(Some(sni), &[key])
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_key_format() {
+        assert_eq!(VirtualHostSessionCache::key_for(Some("a.com"), &[0x00, 0x01]),
+                   b"a.com\x00\x00\x01".to_vec());
+        assert_eq!(VirtualHostSessionCache::key_for(None, &[0x01]),
+                   vec![0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_separates_hosts() {
+        let c = VirtualHostSessionCache::new(4, 16);
+        assert_eq!(c.put(vhost_key("a.com", 1), vec![0x0a]), true);
+        assert_eq!(c.put(vhost_key("b.com", 1), vec![0x0b]), true);
+        assert_eq!(c.get(&vhost_key("a.com", 1)), Some(vec![0x0a]));
+        assert_eq!(c.get(&vhost_key("b.com", 1)), Some(vec![0x0b]));
+        assert_eq!(c.get(&vhost_key("c.com", 1)), None);
+        assert_eq!(c.host_len(Some("a.com")), 1);
+        assert_eq!(c.len(), 2);
+
+        c.clear();
+        assert!(c.is_empty());
+        assert_eq!(c.get(&vhost_key("a.com", 1)), None);
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_rejects_keys_without_host() {
+        let c = VirtualHostSessionCache::new(4, 16);
+        assert_eq!(c.put(vec![0x01, 0x02], vec![0x03]), false);
+        assert_eq!(c.get(&[0x01, 0x02]), None);
+        assert!(c.is_empty());
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_per_host_limit() {
+        let c = VirtualHostSessionCache::new(2, 16);
+        assert_eq!(c.put(vhost_key("quiet.com", 1), vec![0x01]), true);
+        for i in 0..10 {
+            assert_eq!(c.put(vhost_key("busy.com", i), vec![i]), true);
+        }
+        assert_eq!(c.host_len(Some("busy.com")), 2);
+        assert_eq!(c.get(&vhost_key("quiet.com", 1)), Some(vec![0x01]));
+
+        // The oldest sessions were dropped.
+        assert_eq!(c.get(&vhost_key("busy.com", 7)), None);
+        assert_eq!(c.get(&vhost_key("busy.com", 8)), Some(vec![8]));
+        assert_eq!(c.get(&vhost_key("busy.com", 9)), Some(vec![9]));
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_host_limit_override() {
+        let c = VirtualHostSessionCache::new(2, 16);
+        c.set_host_limit(Some("big.com"), 5);
+        for i in 0..10 {
+            assert_eq!(c.put(vhost_key("big.com", i), vec![i]), true);
+            assert_eq!(c.put(vhost_key("small.com", i), vec![i]), true);
+        }
+        assert_eq!(c.host_len(Some("big.com")), 5);
+        assert_eq!(c.host_len(Some("small.com")), 2);
+
+        // Lowering a limit drops the oldest sessions straight away.
+        c.set_host_limit(Some("big.com"), 3);
+        assert_eq!(c.host_len(Some("big.com")), 3);
+        assert_eq!(c.get(&vhost_key("big.com", 6)), None);
+        assert_eq!(c.get(&vhost_key("big.com", 7)), Some(vec![7]));
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_iter() {
+        let c = VirtualHostSessionCache::new(4, 8);
+        c.put(vhost_key("a.com", 1), vec![0x01]);
+        c.put(vhost_key("b.com", 2), vec![0x02]);
+
+        let mut seen = c.iter()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect::<Vec<_>>();
+        seen.sort();
+        assert_eq!(seen, vec![(vhost_key("a.com", 1), vec![0x01]),
+                              (vhost_key("b.com", 2), vec![0x02])]);
+    }
+
+    #[test]
+    fn test_virtualhostsessioncache_global_limit() {
+        let c = VirtualHostSessionCache::new(4, 5);
+        for i in 0..4 {
+            assert_eq!(c.put(vhost_key("busy.com", i), vec![i]), true);
+        }
+        assert_eq!(c.put(vhost_key("quiet.com", 1), vec![0x01]), true);
+        assert_eq!(c.len(), 5);
+
+        // The busiest host gives up a session for the new one.
+        assert_eq!(c.put(vhost_key("other.com", 1), vec![0x01]), true);
+        assert_eq!(c.len(), 5);
+        assert_eq!(c.host_len(Some("busy.com")), 3);
+        assert_eq!(c.get(&vhost_key("quiet.com", 1)), Some(vec![0x01]));
+        assert_eq!(c.get(&vhost_key("other.com", 1)), Some(vec![0x01]));
+
+        // Overwriting doesn't evict anything.
+        assert_eq!(c.put(vhost_key("other.com", 1), vec![0x02]), true);
+        assert_eq!(c.len(), 5);
+        assert_eq!(c.host_len(Some("busy.com")), 3);
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_constant_time_lookup() {
+        let c = ServerSessionMemoryCache::new_constant_time(4);
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        assert_eq!(c.put(vec![0x05], vec![0x06]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.get(&[0x03]), Some(vec![0x04]));
+        assert_eq!(c.get(&[0x05]), Some(vec![0x06]));
+        assert_eq!(c.get(&[0x07]), None);
+        assert_eq!(c.get(&[0x01, 0x02]), None);
+        assert_eq!(c.get(&[]), None);
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_memory_usage() {
+        let c = ServerSessionMemoryCache::new(4);
+        assert_eq!(c.memory_usage_bytes(), 0);
+        assert_eq!(c.put(vec![0x01; 32], vec![0x02; 100]), true);
+        let one = c.memory_usage_bytes();
+        assert!(one >= 132 && one < 264);
+        assert_eq!(c.put(vec![0x03; 32], vec![0x04; 100]), true);
+        assert_eq!(c.memory_usage_bytes(), 2 * one);
+        assert_eq!(c.put(vec![0x03; 32], vec![0x04; 50]), true);
+        assert_eq!(c.memory_usage_bytes(), 2 * one - 50);
+        c.clear();
+        assert_eq!(c.memory_usage_bytes(), 0);
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_memory_limit() {
+        let entry = entry_size(&[0x01; 32], &[0x02; 100]);
+        let c = ServerSessionMemoryCache::with_memory_limit(2 * entry + 10);
+        assert_eq!(c.put(vec![0x01; 32], vec![0x02; 100]), true);
+        assert_eq!(c.put(vec![0x03; 32], vec![0x04; 100]), true);
+        assert_eq!(c.put(vec![0x05; 32], vec![0x06; 100]), false);
+        assert_eq!(c.get(&[0x05; 32]), None);
+        assert_eq!(c.memory_usage_bytes(), 2 * entry);
+
+        // Replacing an entry only counts the difference.
+        assert_eq!(c.put(vec![0x03; 32], vec![0x04; 110]), true);
+        assert_eq!(c.put(vec![0x03; 32], vec![0x04; 111]), false);
+        assert_eq!(c.get(&[0x03; 32]), Some(vec![0x04; 110]));
+
+        // Small enough values still fit.
+        let c = ServerSessionMemoryCache::with_memory_limit(2 * entry + 10);
+        assert_eq!(c.put(vec![0x01; 32], vec![0x02; 100]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+    }
+
+    /// A small xorshift generator, so the randomised tests below
+    /// are repeatable.
+    struct TestRng(u64);
+
+    impl TestRng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+            let len = self.below(max_len + 1);
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    #[test]
+    fn test_serversessionmemorycache_random_operations() {
+        let mut rng = TestRng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..10_000 {
+            let max_entries = 1 + rng.below(1000);
+            let c = ServerSessionMemoryCache::new(max_entries);
+            let mut latest = collections::HashMap::new();
+
+            for _ in 0..rng.below(64) {
+                // Small keys, so keys get reused and overwritten.
+                let key = rng.bytes(2);
+
+                match rng.below(3) {
+                    0 => {
+                        let value = rng.bytes(8);
+                        assert!(c.put(key.clone(), value.clone()));
+                        assert_eq!(c.get(&key), Some(value.clone()));
+                        latest.insert(key, value);
+                    }
+                    1 => {
+                        // Either evicted, or the latest value.
+                        let got = c.get(&key);
+                        assert!(got.is_none() || got.as_ref() == latest.get(&key));
+                    }
+                    _ => {
+                        assert_eq!(c.generate().len(), 32);
+                    }
+                }
+
+                let cache = c.cache.lock().unwrap();
+                assert!(cache.entries.len() <= max_entries);
+                assert_eq!(cache.bytes,
+                           cache.entries.iter()
+                               .map(|(k, stored)| entry_size(k, &stored.value))
+                               .sum::<usize>());
+                assert_eq!(cache.order.len(), cache.entries.len());
+            }
+        }
+    }
 }
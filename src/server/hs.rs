@@ -8,12 +8,13 @@ use msgs::handshake::{HandshakePayload, SupportedSignatureSchemes};
 use msgs::handshake::{HandshakeMessagePayload, ServerHelloPayload, Random};
 use msgs::handshake::{ClientHelloPayload, ServerExtension, SessionID};
 use msgs::handshake::{ConvertProtocolNameList, ConvertServerNameList};
-use msgs::handshake::{NamedGroups, SupportedGroups, ClientExtension};
+use msgs::handshake::ClientExtension;
 use msgs::handshake::{ECPointFormatList, SupportedPointFormats};
 use msgs::handshake::{ServerECDHParams, DigitallySignedStruct};
 use msgs::handshake::{ServerKeyExchangePayload, ECDHEServerKeyExchange};
 use msgs::handshake::{CertificateRequestPayload, NewSessionTicketPayload};
 use msgs::handshake::{CertificateRequestPayloadTLS13, NewSessionTicketPayloadTLS13};
+use msgs::handshake::NewSessionTicketExtension;
 use msgs::handshake::{HelloRetryRequest, HelloRetryExtension, KeyShareEntry};
 use msgs::handshake::{CertificatePayloadTLS13, CertificateEntry};
 use msgs::handshake::{CertificateStatus, CertificateExtension};
@@ -23,7 +24,7 @@ use msgs::codec::Codec;
 use msgs::persist;
 use session::SessionSecrets;
 use cipher;
-use server::ServerSessionImpl;
+use server::{ServerSessionImpl, ClientHello};
 use key_schedule::{KeySchedule, SecretKind};
 use suites;
 use verify;
@@ -219,8 +220,18 @@ impl ExpectClientHello {
 
             sess.alpn_protocol = util::first_in_both(our_protocols, &their_proto_strings);
             if let Some(ref selected_protocol) = sess.alpn_protocol {
-                debug!("Chosen ALPN protocol {:?}", selected_protocol);
+                debug!("{}Chosen ALPN protocol {:?}", sess.common.log_prefix, selected_protocol);
                 ret.push(ServerExtension::make_alpn(selected_protocol.clone()));
+            } else if !our_protocols.is_empty() && sess.config.require_alpn_match {
+                sess.common.send_fatal_alert(AlertDescription::NoApplicationProtocol);
+                return Err(TLSError::NoApplicationProtocol {
+                    offered: their_protocols.iter()
+                        .map(|p| p.0.clone())
+                        .collect(),
+                    supported: our_protocols.iter()
+                        .map(|p| p.as_bytes().to_vec())
+                        .collect(),
+                });
             }
         }
 
@@ -229,6 +240,19 @@ impl ExpectClientHello {
             ret.push(ServerExtension::ServerNameAck);
         }
 
+        // Maximum fragment length: we must use exactly what the client
+        // asked for, or refuse the connection.
+        if let Some(mfl) = hello.get_max_fragment_length_extension() {
+            let len = match mfl.fragment_len() {
+                Some(len) => len,
+                None => return Err(illegal_param(sess, "client sent invalid max_fragment_length")),
+            };
+
+            debug!("{}Client requested max fragment length {}", sess.common.log_prefix, len);
+            sess.common.message_fragmenter.limit_fragment_len(len);
+            ret.push(ServerExtension::MaxFragmentLength(mfl));
+        }
+
         // Send status_request response if we have one.  This is not allowed
         // if we're resuming, and is only triggered if we have an OCSP response
         // to send.
@@ -352,7 +376,7 @@ impl ExpectClientHello {
 
         check_aligned_handshake(sess)?;
 
-        trace!("sending server hello {:?}", sh);
+        trace!("{}sending server hello {:?}", sess.common.log_prefix, sh);
         self.handshake.transcript.add_message(&sh);
         sess.common.send_msg(sh, false);
 
@@ -410,7 +434,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("Requesting retry {:?}", m);
+        trace!("{}Requesting retry {:?}", sess.common.log_prefix, m);
         self.handshake.transcript.rollup_for_hrr();
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, false);
@@ -432,7 +456,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("sending encrypted extensions {:?}", ee);
+        trace!("{}sending encrypted extensions {:?}", sess.common.log_prefix, ee);
         self.handshake.transcript.add_message(&ee);
         sess.common.send_msg(ee, true);
         Ok(())
@@ -465,7 +489,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("Sending CertificateRequest {:?}", m);
+        trace!("{}Sending CertificateRequest {:?}", sess.common.log_prefix, m);
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, true);
         true
@@ -517,7 +541,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("sending certificate {:?}", c);
+        trace!("{}sending certificate {:?}", sess.common.log_prefix, c);
         self.handshake.transcript.add_message(&c);
         sess.common.send_msg(c, true);
     }
@@ -537,6 +561,8 @@ impl ExpectClientHello {
             .ok_or_else(|| TLSError::PeerIncompatibleError("no overlapping sigschemes".to_string()))?;
 
         let scheme = signer.get_scheme();
+        debug!("{}signing CertificateVerify with {} key using {:?}",
+               sess.common.log_prefix, signing_key.key_type_name(), scheme);
         let sig = signer.sign(&message)?;
 
         let cv = DigitallySignedStruct::new(scheme, sig);
@@ -550,7 +576,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("sending certificate-verify {:?}", m);
+        trace!("{}sending certificate-verify {:?}", sess.common.log_prefix, m);
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, true);
         Ok(())
@@ -572,7 +598,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("sending finished {:?}", m);
+        trace!("{}sending finished {:?}", sess.common.log_prefix, m);
         self.handshake.transcript.add_message(&m);
         self.handshake.hash_at_server_fin = self.handshake.transcript.get_current_hash();
         sess.common.send_msg(m, true);
@@ -622,7 +648,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("sending server hello {:?}", sh);
+        trace!("{}sending server hello {:?}", sess.common.log_prefix, sh);
         self.handshake.transcript.add_message(&sh);
         sess.common.send_msg(sh, false);
         Ok(())
@@ -687,6 +713,8 @@ impl ExpectClientHello {
         secdh.encode(&mut msg);
 
         let signing_key = &server_certkey.key;
+        debug!("{}signing ServerKeyExchange with {} key using {:?}",
+               sess.common.log_prefix, signing_key.key_type_name(), sigscheme);
         let sig = signing_key.choose_scheme(&[sigscheme])
             .ok_or_else(|| TLSError::General("incompatible signing key".to_string()))
             .and_then(|signer| signer.sign(&msg))?;
@@ -735,7 +763,7 @@ impl ExpectClientHello {
             }),
         };
 
-        trace!("Sending CertificateRequest {:?}", m);
+        trace!("{}Sending CertificateRequest {:?}", sess.common.log_prefix, m);
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, false);
         true
@@ -762,7 +790,7 @@ impl ExpectClientHello {
                         id: &SessionID,
                         resumedata: persist::ServerSessionValue)
                         -> NextStateOrError {
-        debug!("Resuming session");
+        debug!("{}Resuming session", sess.common.log_prefix);
 
         if resumedata.extended_ms && !self.handshake.using_ems {
             return Err(illegal_param(sess, "refusing to resume without ems"));
@@ -802,6 +830,14 @@ impl ExpectClientHello {
             return Err(illegal_param(sess, "client offered wrong compressions"));
         }
 
+        // We don't accept 0-RTT data.  But if our tickets said the
+        // client could send some, it may have done; arrange to skip it.
+        if client_hello.early_data_extension_offered() && sess.early_data_skip.is_none() {
+            if let Some(max) = sess.config.max_early_data_size {
+                sess.early_data_skip = Some(max as usize);
+            }
+        }
+
         let groups_ext = client_hello.get_namedgroups_extension()
             .ok_or_else(|| incompatible(sess, "client didn't describe groups"))?;
 
@@ -823,11 +859,11 @@ impl ExpectClientHello {
             .map(|share| share.group)
             .collect();
 
-        let chosen_group = util::first_in_both(&NamedGroups::supported(), &share_groups);
+        let chosen_group = util::first_in_both(&sess.config.named_groups, &share_groups);
         if chosen_group.is_none() {
             // We don't have a suitable key share.  Choose a suitable group and
             // send a HelloRetryRequest.
-            let retry_group_maybe = util::first_in_both(&NamedGroups::supported(), groups_ext);
+            let retry_group_maybe = util::first_in_both(&sess.config.named_groups, groups_ext);
             self.handshake.transcript.add_message(chm);
 
             if let Some(group) = retry_group_maybe {
@@ -865,10 +901,11 @@ impl ExpectClientHello {
                 return Err(illegal_param(sess, "psk extension mismatched ids/binders"));
             }
 
+            let ticket_aad = sess.ticket_aad();
             for (i, psk_id) in psk_offer.identities.iter().enumerate() {
                 let maybe_resume = sess.config
                     .ticketer
-                    .decrypt(&psk_id.identity.0)
+                    .decrypt_with_aad(&psk_id.identity.0, &ticket_aad)
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain));
 
                 if !can_resume(sess, &self.handshake, &maybe_resume) {
@@ -889,7 +926,7 @@ impl ExpectClientHello {
         }
 
         if !client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
-            warn!("Resumption ignored, DHE_KE not offered");
+            warn!("{}Resumption ignored, DHE_KE not offered", sess.common.log_prefix);
             self.send_ticket = false;
             chosen_psk_index = None;
             resuming_psk = None;
@@ -944,7 +981,7 @@ impl State for ExpectClientHello {
         let client_hello = extract_handshake!(m, HandshakePayload::ClientHello).unwrap();
         let tls13_enabled = sess.config.versions.contains(&ProtocolVersion::TLSv1_3);
         let tls12_enabled = sess.config.versions.contains(&ProtocolVersion::TLSv1_2);
-        trace!("we got a clienthello {:?}", client_hello);
+        trace!("{}we got a clienthello {:?}", sess.common.log_prefix, client_hello);
 
         if !client_hello.compression_methods.contains(&Compression::Null) {
             sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
@@ -956,6 +993,17 @@ impl State for ExpectClientHello {
             return Err(decode_error(sess, "client sent duplicate extensions"));
         }
 
+        // RFC5746: a non-empty renegotiated_connection means the client thinks
+        // this is a renegotiation.  It can't be: this is our first handshake.
+        let renegotiating = client_hello.get_renegotiation_info_extension()
+            .map(|ri| !ri.0.is_empty())
+            .unwrap_or(false);
+        if renegotiating {
+            sess.common.send_fatal_alert(AlertDescription::HandshakeFailure);
+            return Err(TLSError::PeerMisbehavedError("client sent non-empty renegotiation_info"
+                .to_string()));
+        }
+
         // Are we doing TLS1.3?
         let maybe_versions_ext = client_hello.get_versions_extension();
         if let Some(versions) = maybe_versions_ext {
@@ -1004,9 +1052,16 @@ impl State for ExpectClientHello {
         // Choose a certificate.
         let mut certkey = {
             let sni_ref = sni.as_ref().map(|dns_name| dns_name.as_ref());
-            trace!("sni {:?}", sni_ref);
-            trace!("sig schemes {:?}", sigschemes_ext);
-            let certkey = sess.config.cert_resolver.resolve(sni_ref, sigschemes_ext);
+            trace!("{}sni {:?}", sess.common.log_prefix, sni_ref);
+            trace!("{}sig schemes {:?}", sess.common.log_prefix, sigschemes_ext);
+            let alpn_protocols = client_hello.get_alpn_extension()
+                .map(|protos| protos.iter()
+                     .map(|proto| proto.0.as_slice())
+                     .collect::<Vec<&[u8]>>());
+            trace!("{}alpn protocols {:?}", sess.common.log_prefix, alpn_protocols);
+            let hello = ClientHello::new(sni_ref, sigschemes_ext,
+                                         alpn_protocols.as_deref());
+            let certkey = sess.config.cert_resolver.resolve(hello);
             certkey.ok_or_else(|| {
                 sess.common.send_fatal_alert(AlertDescription::AccessDenied);
                 TLSError::General("no server certificate chain resolved".to_string())
@@ -1032,7 +1087,8 @@ impl State for ExpectClientHello {
             return Err(incompatible(sess, "no ciphersuites in common"));
         }
 
-        debug!("decided upon suite {:?}", maybe_ciphersuite.as_ref().unwrap());
+        debug!("{}decided upon suite {:?}",
+               sess.common.log_prefix, maybe_ciphersuite.as_ref().unwrap());
         sess.common.set_suite(maybe_ciphersuite.unwrap());
 
         // Start handshake hash.
@@ -1062,8 +1118,8 @@ impl State for ExpectClientHello {
         let ecpoints_ext = client_hello.get_ecpoints_extension()
             .ok_or_else(|| incompatible(sess, "client didn't describe ec points"))?;
 
-        trace!("namedgroups {:?}", groups_ext);
-        trace!("ecpoints {:?}", ecpoints_ext);
+        trace!("{}namedgroups {:?}", sess.common.log_prefix, groups_ext);
+        trace!("{}ecpoints {:?}", sess.common.log_prefix, ecpoints_ext);
 
         if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
             sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
@@ -1088,11 +1144,11 @@ impl State for ExpectClientHello {
         if let Some(ticket_ext) = client_hello.get_ticket_extension() {
             if let ClientExtension::SessionTicketOffer(ref ticket) = *ticket_ext {
                 ticket_received = true;
-                debug!("Ticket received");
+                debug!("{}Ticket received", sess.common.log_prefix);
 
                 let maybe_resume = sess.config
                     .ticketer
-                    .decrypt(&ticket.0)
+                    .decrypt_with_aad(&ticket.0, &sess.ticket_aad())
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain));
 
                 if can_resume(sess, &self.handshake, &maybe_resume) {
@@ -1101,7 +1157,7 @@ impl State for ExpectClientHello {
                                                  &client_hello.session_id,
                                                  maybe_resume.unwrap());
                 } else {
-                    debug!("Ticket didn't decrypt");
+                    debug!("{}Ticket didn't decrypt", sess.common.log_prefix);
                 }
             }
         }
@@ -1135,7 +1191,7 @@ impl State for ExpectClientHello {
             .resolve_sig_scheme(sigschemes_ext)
             .ok_or_else(|| incompatible(sess, "no supported sig scheme"))?;
 
-        let group = util::first_in_both(NamedGroups::supported().as_slice(),
+        let group = util::first_in_both(sess.config.named_groups.as_slice(),
                                         groups_ext.as_slice())
             .ok_or_else(|| incompatible(sess, "no supported group"))?;
 
@@ -1189,12 +1245,12 @@ impl State for ExpectTLS12Certificate {
 
         if cert_chain.is_empty() &&
            !sess.config.verifier.client_auth_mandatory() {
-            debug!("client auth requested but no certificate supplied");
+            debug!("{}client auth requested but no certificate supplied", sess.common.log_prefix);
             self.handshake.transcript.abandon_client_auth();
             return Ok(self.into_expect_tls12_client_kx(None));
         }
 
-        trace!("certs {:?}", cert_chain);
+        trace!("{}certs {:?}", sess.common.log_prefix, cert_chain);
 
         sess.config.verifier.verify_client_cert(cert_chain)
             .or_else(|err| {
@@ -1250,7 +1306,8 @@ impl State for ExpectTLS13Certificate {
 
         if cert_chain.is_empty() {
             if !sess.config.verifier.client_auth_mandatory() {
-                debug!("client auth requested but no certificate supplied");
+                debug!("{}client auth requested but no certificate supplied",
+                       sess.common.log_prefix);
                 self.handshake.transcript.abandon_client_auth();
                 return Ok(self.into_expect_tls13_finished());
             }
@@ -1367,7 +1424,8 @@ impl State for ExpectTLS12CertificateVerify {
             let handshake_msgs = self.handshake.transcript.take_handshake_buf();
             let certs = &self.client_cert.cert_chain;
 
-            verify::verify_signed_struct(&handshake_msgs, &certs[0], sig)
+            sess.config.get_signature_verifier()
+                .verify_tls12_signature(&handshake_msgs, &certs[0], sig)
         };
 
         if let Err(e) = rc {
@@ -1375,7 +1433,7 @@ impl State for ExpectTLS12CertificateVerify {
             return Err(e);
         }
 
-        trace!("client CertificateVerify OK");
+        trace!("{}client CertificateVerify OK", sess.common.log_prefix);
         sess.client_cert_chain = Some(self.client_cert.take_chain());
 
         self.handshake.transcript.add_message(&m);
@@ -1410,7 +1468,8 @@ impl State for ExpectTLS13CertificateVerify {
             self.handshake.transcript.abandon_client_auth();
             let certs = &self.client_cert.cert_chain;
 
-            verify::verify_tls13(&certs[0],
+            verify::verify_tls13(sess.config.get_signature_verifier(),
+                                 &certs[0],
                                  sig,
                                  &handshake_hash,
                                  b"TLS 1.3, client CertificateVerify\x00")
@@ -1421,7 +1480,7 @@ impl State for ExpectTLS13CertificateVerify {
             return Err(e);
         }
 
-        trace!("client CertificateVerify OK");
+        trace!("{}client CertificateVerify OK", sess.common.log_prefix);
         sess.client_cert_chain = Some(self.client_cert.take_chain());
 
         self.handshake.transcript.add_message(&m);
@@ -1455,7 +1514,7 @@ impl State for ExpectTLS12CCS {
         // CCS should not be received interleaved with fragmented handshake-level
         // message.
         if !sess.common.handshake_joiner.is_empty() {
-            warn!("CCS received interleaved with fragmented handshake");
+            warn!("{}CCS received interleaved with fragmented handshake", sess.common.log_prefix);
             return Err(TLSError::InappropriateMessage {
                 expect_types: vec![ ContentType::Handshake ],
                 got_type: ContentType::ChangeCipherSpec,
@@ -1488,21 +1547,14 @@ fn get_server_session_value_tls12(handshake: &HandshakeDetails,
     v
 }
 
-fn get_server_session_value_tls13(handshake: &HandshakeDetails,
-                                  sess: &ServerSessionImpl,
+fn get_server_session_value_tls13(sess: &ServerSessionImpl,
                                   nonce: &[u8]) -> persist::ServerSessionValue {
     let scs = sess.common.get_suite_assert();
     let version = ProtocolVersion::TLSv1_3;
 
-    let handshake_hash = handshake
-        .transcript
-        .get_current_hash();
-    let resumption_master_secret = sess.common
-        .get_key_schedule()
-        .derive(SecretKind::ResumptionMasterSecret, &handshake_hash);
-    let secret = sess.common
-        .get_key_schedule()
-        .derive_ticket_psk(&resumption_master_secret, nonce);
+    let key_schedule = sess.common.get_key_schedule();
+    let secret = key_schedule
+        .derive_ticket_psk(&key_schedule.resumption_master_secret, nonce);
 
     persist::ServerSessionValue::new(sess.get_sni(), version,
                                      scs.suite, secret,
@@ -1517,7 +1569,7 @@ fn emit_ticket(handshake: &mut HandshakeDetails,
         .get_encoding();
     let ticket = sess.config
         .ticketer
-        .encrypt(&plain)
+        .encrypt_with_aad(&plain, &sess.ticket_aad())
         .unwrap_or_else(Vec::new);
     let ticket_lifetime = sess.config.ticketer.get_lifetime();
 
@@ -1610,9 +1662,9 @@ impl State for ExpectTLS12Finished {
             let worked = sess.config.session_storage
                 .put(self.handshake.session_id.get_encoding(), value.get_encoding());
             if worked {
-                debug!("Session saved");
+                debug!("{}Session saved", sess.common.log_prefix);
             } else {
-                debug!("Session not saved");
+                debug!("{}Session not saved", sess.common.log_prefix);
             }
         }
 
@@ -1651,34 +1703,40 @@ impl ExpectTLS13Finished {
             return;
         }
 
-        let nonce = rand::random_vec(32);
-        let plain = get_server_session_value_tls13(&self.handshake, sess, &nonce)
-            .get_encoding();
-        let maybe_ticket = sess.config
-            .ticketer
-            .encrypt(&plain);
-        let ticket_lifetime = sess.config.ticketer.get_lifetime();
-
-        if maybe_ticket.is_none() {
-            return;
+        if let Some(m) = make_ticket_tls13(sess) {
+            trace!("{}sending new ticket {:?}", sess.common.log_prefix, m);
+            self.handshake.transcript.add_message(&m);
+            sess.common.send_msg(m, true);
         }
+    }
+}
 
-        let ticket = maybe_ticket.unwrap();
-        let age_add = rand::random_u32(); // nb, we don't do 0-RTT data, so whatever
-        let payload = NewSessionTicketPayloadTLS13::new(ticket_lifetime, age_add, nonce, ticket);
-        let m = Message {
-            typ: ContentType::Handshake,
-            version: ProtocolVersion::TLSv1_3,
-            payload: MessagePayload::Handshake(HandshakeMessagePayload {
-                typ: HandshakeType::NewSessionTicket,
-                payload: HandshakePayload::NewSessionTicketTLS13(payload),
-            }),
-        };
+/// Make a NewSessionTicket message for this TLS1.3 session, whose
+/// resumption_master_secret must be known.  Returns None if the
+/// ticketer fails.
+pub fn make_ticket_tls13(sess: &ServerSessionImpl) -> Option<Message> {
+    let nonce = rand::random_vec(32);
+    let plain = get_server_session_value_tls13(sess, &nonce)
+        .get_encoding();
+    let ticket = sess.config
+        .ticketer
+        .encrypt_with_aad(&plain, &sess.ticket_aad())?;
+    let ticket_lifetime = sess.config.ticketer.get_lifetime();
 
-        trace!("sending new ticket {:?}", m);
-        self.handshake.transcript.add_message(&m);
-        sess.common.send_msg(m, true);
+    let age_add = rand::random_u32(); // nb, we don't do 0-RTT data, so whatever
+    let mut payload = NewSessionTicketPayloadTLS13::new(ticket_lifetime, age_add, nonce, ticket);
+    if let Some(max_early_data_size) = sess.config.max_early_data_size {
+        payload.exts.push(NewSessionTicketExtension::EarlyData(max_early_data_size));
     }
+
+    Some(Message {
+        typ: ContentType::Handshake,
+        version: ProtocolVersion::TLSv1_3,
+        payload: MessagePayload::Handshake(HandshakeMessagePayload {
+            typ: HandshakeType::NewSessionTicket,
+            payload: HandshakePayload::NewSessionTicketTLS13(payload),
+        }),
+    })
 }
 
 impl State for ExpectTLS13Finished {
@@ -1697,7 +1755,7 @@ impl State for ExpectTLS13Finished {
         let fin = constant_time::verify_slices_are_equal(&expect_verify_data, &finished.0)
             .map_err(|_| {
                      sess.common.send_fatal_alert(AlertDescription::DecryptError);
-                     warn!("Finished wrong");
+                     warn!("{}Finished wrong", sess.common.log_prefix);
                      TLSError::DecryptError
                      })
             .map(|_| verify::FinishedMessageVerified::assertion())?;
@@ -1706,6 +1764,18 @@ impl State for ExpectTLS13Finished {
         // main application data keying.
         self.handshake.transcript.add_message(&m);
 
+        // Keep the resumption_master_secret if the client can use
+        // tickets, so we can issue more of them later.
+        if self.send_ticket {
+            let handshake_hash = self.handshake.transcript.get_current_hash();
+            let resumption_master_secret = sess.common
+                .get_key_schedule()
+                .derive(SecretKind::ResumptionMasterSecret, &handshake_hash);
+            sess.common
+                .get_mut_key_schedule()
+                .resumption_master_secret = resumption_master_secret;
+        }
+
         // Now move to using application data keys for client traffic.
         // Server traffic is already done.
         let read_key = sess.common
@@ -1731,6 +1801,17 @@ impl State for ExpectTLS13Finished {
 }
 
 // --- Process traffic ---
+/// The state of a session restored from a checkpoint.  Checkpoints
+/// are only taken once the handshake is complete.
+pub fn restored_traffic_state(version: ProtocolVersion) -> NextState {
+    let fin = verify::FinishedMessageVerified::assertion();
+    if version == ProtocolVersion::TLSv1_3 {
+        Box::new(ExpectTLS13Traffic { _fin_verified: fin })
+    } else {
+        Box::new(ExpectTLS12Traffic { _fin_verified: fin })
+    }
+}
+
 pub struct ExpectTLS12Traffic {
     _fin_verified: verify::FinishedMessageVerified,
 }
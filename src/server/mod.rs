@@ -1,22 +1,42 @@
-use session::{Session, SessionCommon};
+use session::{Session, SessionCommon, AlertPolicy};
 use suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
 use msgs::enums::{ContentType, SignatureScheme};
-use msgs::enums::{AlertDescription, HandshakeType, ProtocolVersion};
+use msgs::enums::{AlertDescription, HandshakeType, ProtocolVersion, NamedGroup};
+use msgs::handshake::{NamedGroups, SupportedGroups};
 use msgs::handshake::SessionID;
 use msgs::message::Message;
+use msgs::base::PayloadU8;
+use msgs::codec::Codec;
+use msgs::persist;
+use session::{SessionRandoms, SessionSecrets};
+use key_schedule::KeySchedule;
+use cipher;
 use error::TLSError;
 use sign;
 use verify;
 use key;
+use rand;
 use webpki;
+#[cfg(feature = "debug-transcript")]
+use transcript::{Direction, HandshakeMessageSummary};
 
+use std::any::Any;
+use std::env;
+use std::borrow::Cow;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::io;
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
 
 mod hs;
 mod common;
+mod envconfig;
+pub mod authenticated;
 pub mod handy;
+#[cfg(feature = "dev")]
+pub mod devcert;
 
 /// A trait for the ability to generate Session IDs, and store
 /// server session data. The keys and values are opaque.
@@ -41,8 +61,24 @@ pub trait StoresServerSessions : Send + Sync {
     /// Find a session with the given `id`.  Return it, or None
     /// if it doesn't exist.
     fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Forget all stored sessions.  For example, you should call
+    /// this after replacing a compromised private key, so that
+    /// sessions established with the old key can't be resumed.
+    fn clear(&self);
+
+    /// Iterate over the stored sessions, as `(key, value)` pairs.
+    ///
+    /// The iterator is a snapshot of the sessions stored when this
+    /// is called: it is safe to `put`, `get` or `clear` while using
+    /// it, and those changes won't be seen by it.
+    fn iter<'a>(&'a self) -> StoredSessions<'a>;
 }
 
+/// An iterator over stored sessions' keys and values, as returned by
+/// `StoresServerSessions::iter`.
+pub type StoredSessions<'a> = Box<Iterator<Item = (Cow<'a, [u8]>, Cow<'a, [u8]>)> + 'a>;
+
 /// A trait for the ability to encrypt and decrypt tickets.
 pub trait ProducesTickets : Send + Sync {
     /// Returns true if this implementation will encrypt/decrypt
@@ -74,20 +110,152 @@ pub trait ProducesTickets : Send + Sync {
     /// panic-proof, and otherwise bullet-proof.  If the decryption
     /// fails, return None.
     fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
+
+    /// Like `encrypt`, but also binds the ticket to `aad`, which
+    /// isn't included in it: `decrypt_with_aad` must be given the
+    /// same `aad` to decrypt it.  This is used to bind tickets to
+    /// the client's address: see `TicketAddressBinding`.
+    ///
+    /// The default implementation can't bind tickets, so it calls
+    /// `encrypt` if `aad` is empty and otherwise returns None: no
+    /// tickets are issued.  The ticketers in this crate all
+    /// implement it.
+    fn encrypt_with_aad(&self, plain: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if aad.is_empty() {
+            self.encrypt(plain)
+        } else {
+            None
+        }
+    }
+
+    /// Like `decrypt`, for tickets made by `encrypt_with_aad`.
+    /// Return None if `aad` isn't what the ticket was made with.
+    ///
+    /// The default implementation calls `decrypt` if `aad` is empty,
+    /// and otherwise returns None.
+    fn decrypt_with_aad(&self, cipher: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if aad.is_empty() {
+            self.decrypt(cipher)
+        } else {
+            None
+        }
+    }
 }
 
 /// How to choose a certificate chain and signing key for use
 /// in server authentication.
 pub trait ResolvesServerCert : Send + Sync {
-    /// Choose a certificate chain and matching key given any server DNS
-    /// name provided via SNI, and signature schemes.
+    /// Choose a certificate chain and matching key given simplified
+    /// ClientHello information.
     ///
     /// The certificate chain is returned as a vec of `Certificate`s,
     /// the key is inside a `SigningKey`.
-    fn resolve(&self,
-               server_name: Option<webpki::DNSNameRef>,
-               sigschemes: &[SignatureScheme])
-               -> Option<sign::CertifiedKey>;
+    fn resolve(&self, client_hello: ClientHello) -> Option<sign::CertifiedKey>;
+}
+
+/// A struct representing the received Client Hello, as far
+/// as it is relevant to choosing a certificate.
+pub struct ClientHello<'a> {
+    server_name: Option<webpki::DNSNameRef<'a>>,
+    sigschemes: &'a [SignatureScheme],
+    alpn: Option<&'a [&'a [u8]]>,
+}
+
+impl<'a> ClientHello<'a> {
+    fn new(server_name: Option<webpki::DNSNameRef<'a>>,
+           sigschemes: &'a [SignatureScheme],
+           alpn: Option<&'a [&'a [u8]]>) -> ClientHello<'a> {
+        ClientHello {
+            server_name,
+            sigschemes,
+            alpn,
+        }
+    }
+
+    /// Get the server name supplied via SNI, or `None` if the
+    /// client did not send one.
+    pub fn server_name(&self) -> Option<webpki::DNSNameRef<'a>> {
+        self.server_name
+    }
+
+    /// Get the signature schemes supported by the client.  This is
+    /// the standard-specified default if the client omitted the
+    /// extension.
+    pub fn sigschemes(&self) -> &[SignatureScheme] {
+        self.sigschemes
+    }
+
+    /// Get the ALPN protocol identifiers submitted by the client.
+    ///
+    /// These are in the client's preference order.  Returns `None`
+    /// if the client did not include an ALPN extension.
+    pub fn alpn(&self) -> Option<&'a [&'a [u8]]> {
+        self.alpn
+    }
+}
+
+/// Whether tickets are bound to the address of the client they
+/// were issued to.  See `ServerConfig::ticket_address_binding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TicketAddressBinding {
+    /// Tickets may be used from any address.
+    None,
+
+    /// Tickets may only be used from the IP address they were
+    /// issued to.
+    Exact,
+
+    /// Tickets may be used from the same IPv4 /24 or IPv6 /64 as
+    /// the address they were issued to.
+    Subnet,
+}
+
+/// Identifies a `ServerSession`, for example to tell which log
+/// messages are about it.  Each session in this process gets a
+/// different one: they count up from zero.
+///
+/// This displays as hexadecimal, as it does at the start of
+/// rustls's log messages for the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(pub u64);
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+impl ConnectionId {
+    fn next() -> ConnectionId {
+        ConnectionId(NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Details of a new connection, given to the
+/// `ServerConfig::set_on_connect` hook before its handshake.
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    /// A number identifying this connection among those of this
+    /// `ServerConfig` and its clones.  They count up from zero.
+    pub connection_id: u64,
+
+    /// When the session was made.
+    pub accepted_at: SystemTime,
+
+    /// What was given to `ServerSession::new_with_user_data`, such
+    /// as the client's address.  Otherwise, this is `()`.
+    pub user_data: Arc<Any + Send + Sync>,
+}
+
+impl fmt::Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionInfo")
+            .field("connection_id", &self.connection_id)
+            .field("accepted_at", &self.accepted_at)
+            .finish()
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -113,6 +281,18 @@ pub struct ServerConfig {
     /// How to produce tickets.
     pub ticketer: Arc<ProducesTickets>,
 
+    /// Whether tickets are bound to the client's address, as given
+    /// to `ServerSession::set_peer_address`.  The default is
+    /// `TicketAddressBinding::None`.
+    ///
+    /// The address is passed to the ticketer as additional data,
+    /// so this only works for ticketers which implement
+    /// `ProducesTickets::encrypt_with_aad`, such as `Ticketer`.
+    /// Others issue no tickets to sessions with an address set.
+    /// Sessions without an address set aren't bound: their
+    /// tickets can't be used by sessions which have one.
+    pub ticket_address_binding: TicketAddressBinding,
+
     /// How to choose a server cert and key.
     pub cert_resolver: Arc<ResolvesServerCert>,
 
@@ -120,12 +300,66 @@ pub struct ServerConfig {
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<String>,
 
+    /// If true, fail the handshake with
+    /// `TLSError::NoApplicationProtocol` when a client offers ALPN
+    /// protocols but none of them are in `alpn_protocols`, as
+    /// RFC7301 says we should.  The default is false: like
+    /// OpenSSL, we carry on without an application protocol.
+    pub require_alpn_match: bool,
+
     /// Supported protocol versions, in no particular order.
     /// The default is all supported versions.
     pub versions: Vec<ProtocolVersion>,
 
+    /// Key exchange groups we use, most preferred first.  The
+    /// default is X25519, then P-384, then P-256.
+    ///
+    /// In TLS1.3 we prefer a group the client sent a key share for,
+    /// over asking it to retry with a more preferred group.
+    pub named_groups: Vec<NamedGroup>,
+
+    /// The maximum amount of 0-RTT data, in bytes, that tickets
+    /// we issue allow clients to send.  The default is None, meaning
+    /// clients may not send 0-RTT data.
+    ///
+    /// We don't currently accept 0-RTT data: offered early data is
+    /// rejected, and skipped up to this limit.  Clients which send
+    /// more than this get an `unexpected_message` alert.
+    pub max_early_data_size: Option<u32>,
+
+    /// The maximum number of handshakes in progress at once, across
+    /// all sessions sharing `handshakes`.  The default is None,
+    /// meaning no limit.
+    ///
+    /// Sessions created beyond this limit fail with
+    /// `TLSError::TooManyHandshakes` without doing any expensive
+    /// cryptography.  A session stops counting towards the limit
+    /// once its handshake completes or fails, or when it is dropped.
+    pub max_concurrent_handshakes: Option<usize>,
+
+    /// Counts the handshakes in progress, for
+    /// `max_concurrent_handshakes`.  This is shared by clones
+    /// of this config.
+    pub handshakes: Arc<handy::HandshakeSemaphore>,
+
+    /// What to do with alerts received from clients, by
+    /// description.  Alerts not listed here get
+    /// `AlertPolicy::Terminate`, as do all alerts by default.
+    /// `close_notify` always ends the session as normal.
+    pub alert_policies: Vec<(AlertDescription, AlertPolicy)>,
+
     /// How to verify client certificates.
     verifier: Arc<verify::ClientCertVerifier>,
+
+    /// How to verify client signatures.
+    signature_verifier: Arc<verify::SignatureVerifier>,
+
+    /// Called for each new session.
+    on_connect: Option<Arc<Fn(ConnectionInfo) + Send + Sync>>,
+
+    /// Counts sessions made, for `ConnectionInfo::connection_id`.
+    /// This is shared by clones of this config.
+    connections: Arc<AtomicU64>,
 }
 
 impl ServerConfig {
@@ -149,18 +383,94 @@ impl ServerConfig {
             mtu: None,
             session_storage: handy::ServerSessionMemoryCache::new(256),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
+            ticket_address_binding: TicketAddressBinding::None,
             alpn_protocols: Vec::new(),
+            require_alpn_match: false,
             cert_resolver: Arc::new(handy::FailResolveChain {}),
             versions: vec![ ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2 ],
+            named_groups: NamedGroups::supported(),
+            max_early_data_size: None,
+            max_concurrent_handshakes: None,
+            handshakes: handy::HandshakeSemaphore::new(),
+            alert_policies: Vec::new(),
             verifier: client_cert_verifier,
+            signature_verifier: Arc::new(verify::WebPKIVerifier::new()),
+            on_connect: None,
+            connections: Arc::new(AtomicU64::new(0)),
         }
     }
 
+    /// Make a `ServerConfig` like `new`, which doesn't ask clients
+    /// for certificates.
+    pub fn with_no_client_auth() -> ServerConfig {
+        ServerConfig::new(verify::NoClientAuth::new())
+    }
+
+    /// Make a `ServerConfig` like `new`, which asks clients for a
+    /// certificate and checks it with `verifier` if one is sent, but
+    /// also accepts clients which don't send one.
+    pub fn with_optional_client_auth(verifier: Arc<verify::ClientCertVerifier>) -> ServerConfig {
+        ServerConfig::new(Arc::new(verify::ClientAuthPolicy::new(verifier, false)))
+    }
+
+    /// Make a `ServerConfig` like `new`, which requires clients to
+    /// send a certificate that `verifier` accepts, and rejects those
+    /// which don't.
+    pub fn with_required_client_auth(verifier: Arc<verify::ClientCertVerifier>) -> ServerConfig {
+        ServerConfig::new(Arc::new(verify::ClientAuthPolicy::new(verifier, true)))
+    }
+
+    /// Make a `ServerConfig` from these environment variables:
+    ///
+    /// - `RUSTLS_CERT_FILE` (required): the certificate chain, in PEM.
+    /// - `RUSTLS_KEY_FILE` (required): the RSA or ECDSA private key for
+    ///   the chain, in PEM, as PKCS#8 or (for RSA) PKCS#1.
+    /// - `RUSTLS_CA_FILE`: if set, clients must send a certificate
+    ///   issued by one of the PEM certificates in this file.
+    /// - `RUSTLS_MIN_VERSION` and `RUSTLS_MAX_VERSION`: the range of
+    ///   versions to support, like `1.2` or `TLSv1.3`.
+    /// - `RUSTLS_ALPN`: comma-separated ALPN protocols, most
+    ///   preferred first.
+    /// - `RUSTLS_CIPHER_SUITES`: comma-separated cipher suite names,
+    ///   like `TLS13_AES_128_GCM_SHA256`, most preferred first.
+    ///
+    /// Anything not set has the same default as in `new`.  Errors,
+    /// including a required variable not being set or not being
+    /// valid unicode, are `TLSError::General` naming the variable at
+    /// fault.
+    pub fn from_env() -> Result<ServerConfig, TLSError> {
+        envconfig::from_vars(&|name| env::var(name))
+    }
+
     #[doc(hidden)]
     pub fn get_verifier(&self) -> &verify::ClientCertVerifier {
         self.verifier.as_ref()
     }
 
+    #[doc(hidden)]
+    pub fn get_signature_verifier(&self) -> &verify::SignatureVerifier {
+        self.signature_verifier.as_ref()
+    }
+
+    /// Overrides the default `SignatureVerifier`, which checks
+    /// client signatures, with something else.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn set_signature_verifier(&mut self, verifier: Arc<verify::SignatureVerifier>) {
+        self.signature_verifier = verifier;
+    }
+
+    /// Call `f` whenever a `ServerSession` is made with this config
+    /// (but not when one is restored from a checkpoint), before the
+    /// handshake starts: for example, to log or count connections.
+    ///
+    /// rustls doesn't know the client's address: pass it to
+    /// `ServerSession::new_with_user_data` to see it in `f`.
+    pub fn set_on_connect<F>(&mut self, f: F)
+        where F: Fn(ConnectionInfo) + Send + Sync + 'static
+    {
+        self.on_connect = Some(Arc::new(f));
+    }
+
     /// Sets the session persistence layer to `persist`.
     pub fn set_persistence(&mut self, persist: Arc<StoresServerSessions + Send + Sync>) {
         self.session_storage = persist;
@@ -213,6 +523,45 @@ impl ServerConfig {
         self.alpn_protocols.clear();
         self.alpn_protocols.extend_from_slice(protocols);
     }
+
+    /// Sets MTU to `mtu`.  If None, the default is used.
+    /// If Some(x) then x must be greater than 5 bytes.
+    ///
+    /// Messages longer than this, such as a large Certificate
+    /// message, are split across several records.
+    pub fn set_mtu(&mut self, mtu: &Option<usize>) {
+        // As for ClientConfig::set_mtu, our internal MTU is the
+        // fragment size and excludes the record header.
+        if let Some(x) = *mtu {
+            use msgs::fragmenter;
+            debug_assert!(x > fragmenter::PACKET_OVERHEAD);
+            self.mtu = Some(x - fragmenter::PACKET_OVERHEAD);
+        } else {
+            self.mtu = None;
+        }
+    }
+
+    /// Set the key exchange groups we use, most preferred first.
+    /// Groups we don't support are ignored.
+    pub fn set_named_groups(&mut self, groups: &[NamedGroup]) {
+        let supported = NamedGroups::supported();
+        self.named_groups = groups.iter()
+            .filter(|group| supported.contains(group))
+            .cloned()
+            .collect();
+    }
+
+    /// Handle warning alerts with description `desc` according to
+    /// `policy`, replacing any earlier policy for `desc`.  Fatal
+    /// alerts always end the session.
+    ///
+    /// For example, `AlertPolicy::Log` for `user_canceled` keeps
+    /// the session going when a client cancels and doesn't follow
+    /// up with `close_notify`.
+    pub fn set_alert_policy(&mut self, desc: AlertDescription, policy: AlertPolicy) {
+        self.alert_policies.retain(|&(d, _)| d != desc);
+        self.alert_policies.push((desc, policy));
+    }
 }
 
 pub struct ServerSessionImpl {
@@ -223,6 +572,18 @@ pub struct ServerSessionImpl {
     pub error: Option<TLSError>,
     pub state: Option<Box<hs::State + Send + Sync>>,
     pub client_cert_chain: Option<Vec<key::Certificate>>,
+
+    /// If we're skipping rejected 0-RTT data, how much more
+    /// we're prepared to skip.
+    pub early_data_skip: Option<usize>,
+
+    /// Held while the handshake is in progress.
+    handshake_permit: Option<handy::HandshakePermit>,
+
+    /// The client's address, if we were told it.
+    peer_address: Option<SocketAddr>,
+
+    pub connection_id: ConnectionId,
 }
 
 impl fmt::Debug for ServerSessionImpl {
@@ -232,20 +593,83 @@ impl fmt::Debug for ServerSessionImpl {
 }
 
 impl ServerSessionImpl {
-    pub fn new(server_config: &Arc<ServerConfig>) -> ServerSessionImpl {
+    pub fn new(server_config: &Arc<ServerConfig>,
+               user_data: Arc<Any + Send + Sync>) -> ServerSessionImpl {
+        if let Some(ref on_connect) = server_config.on_connect {
+            on_connect(ConnectionInfo {
+                connection_id: server_config.connections.fetch_add(1, Ordering::Relaxed),
+                accepted_at: SystemTime::now(),
+                user_data,
+            });
+        }
+
+        let mut sess = ServerSessionImpl::new_uncounted(server_config);
+        sess.handshake_permit =
+            handy::HandshakeSemaphore::try_acquire(&server_config.handshakes,
+                                                   server_config.max_concurrent_handshakes);
+        if sess.handshake_permit.is_none() {
+            sess.error = Some(TLSError::TooManyHandshakes);
+        }
+        sess
+    }
+
+    fn new_uncounted(server_config: &Arc<ServerConfig>) -> ServerSessionImpl {
         let perhaps_client_auth = server_config.verifier.offer_client_auth();
+        let connection_id = ConnectionId::next();
+        let mut common = SessionCommon::new(server_config.mtu, false);
+        common.log_prefix = format!("[{}] ", connection_id);
 
         ServerSessionImpl {
             config: server_config.clone(),
-            common: SessionCommon::new(server_config.mtu, false),
+            common,
             sni: None,
             alpn_protocol: None,
             error: None,
             state: Some(Box::new(hs::ExpectClientHello::new(perhaps_client_auth))),
             client_cert_chain: None,
+            early_data_skip: None,
+            handshake_permit: None,
+            peer_address: None,
+            connection_id,
         }
     }
 
+    pub fn set_peer_address(&mut self, addr: SocketAddr) {
+        self.peer_address = Some(addr);
+    }
+
+    /// The additional data binding our tickets to the client's
+    /// address, according to `config.ticket_address_binding`.
+    pub fn ticket_aad(&self) -> Vec<u8> {
+        let addr = match (self.config.ticket_address_binding, self.peer_address) {
+            (TicketAddressBinding::None, _) | (_, None) => return Vec::new(),
+            (_, Some(addr)) => addr.ip(),
+        };
+
+        // Treat IPv4 clients the same whichever socket they came in on.
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4().map_or(addr, IpAddr::V4),
+            IpAddr::V4(_) => addr,
+        };
+
+        let (family, mut bytes, subnet_len) = match addr {
+            IpAddr::V4(v4) => (4u8, v4.octets().to_vec(), 3),
+            IpAddr::V6(v6) => (6u8, v6.octets().to_vec(), 8),
+        };
+
+        let mode = match self.config.ticket_address_binding {
+            TicketAddressBinding::Subnet => {
+                bytes.truncate(subnet_len);
+                2u8
+            }
+            _ => 1u8,
+        };
+
+        let mut aad = vec![mode, family];
+        aad.extend_from_slice(&bytes);
+        aad
+    }
+
     pub fn wants_read(&self) -> bool {
         // We want to read more data all the time, except when we
         // have unprocessed plaintext.  This provides back-pressure
@@ -273,12 +697,29 @@ impl ServerSessionImpl {
         if self.common.is_tls13()
             && msg.is_content_type(ContentType::ChangeCipherSpec)
             && self.is_handshaking() {
-            trace!("Dropping CCS");
+            trace!("{}Dropping CCS", self.common.log_prefix);
             return Ok(());
         }
 
-        // Decrypt if demanded by current state.
-        if self.common.peer_encrypting {
+        // TLS1.3: skip rejected 0-RTT data.  Before we have handshake
+        // keys (ie. after a HelloRetryRequest) it is easily recognised;
+        // afterwards, it's the records that fail to decrypt.
+        if self.early_data_skip.is_some() &&
+           msg.is_content_type(ContentType::ApplicationData) {
+            let len = msg.payload.length();
+
+            if !self.common.peer_encrypting {
+                return self.skip_early_data(len);
+            }
+
+            match self.common.decrypt_incoming_or_skip(msg)? {
+                Some(dm) => {
+                    self.early_data_skip = None;
+                    msg = dm;
+                }
+                None => return self.skip_early_data(len),
+            }
+        } else if self.common.peer_encrypting {
             let dm = self.common.decrypt_incoming(msg)?;
             msg = dm;
         }
@@ -298,14 +739,39 @@ impl ServerSessionImpl {
         msg.decode_payload();
 
         if msg.is_content_type(ContentType::Alert) {
-            return self.common.process_alert(msg);
+            return self.common.process_alert(msg, &self.config.alert_policies);
         }
 
         self.process_main_protocol(msg)
     }
 
+    /// Skip a rejected 0-RTT record whose encrypted payload is `len`
+    /// bytes long.  max_early_data_size counts only the application
+    /// data (RFC 8446 §4.2.10), so allow for the inner content type
+    /// byte and the AEAD tag.  We can't see any padding, so that is
+    /// counted against the budget.
+    fn skip_early_data(&mut self, len: usize) -> Result<(), TLSError> {
+        let tag_len = self.common.get_suite()
+            .map_or(0, |suite| suite.get_aead_alg().tag_len());
+        let len = len.saturating_sub(1 + tag_len);
+        let remaining = self.early_data_skip.unwrap_or(0);
+
+        if len > remaining {
+            self.common.send_fatal_alert(AlertDescription::UnexpectedMessage);
+            return Err(TLSError::PeerMisbehavedError("client sent too much early data"
+                                                     .to_string()));
+        }
+
+        trace!("{}Skipping {} bytes of rejected early data", self.common.log_prefix, len);
+        self.early_data_skip = Some(remaining - len);
+        Ok(())
+    }
+
     fn process_new_handshake_messages(&mut self) -> Result<(), TLSError> {
         while let Some(msg) = self.common.handshake_joiner.frames.pop_front() {
+            #[cfg(feature = "debug-transcript")]
+            self.common.transcript.record(Direction::Received, &msg);
+
             self.process_main_protocol(msg)?;
         }
 
@@ -346,16 +812,21 @@ impl ServerSessionImpl {
                 Ok(_) => {}
                 Err(err) => {
                     self.error = Some(err.clone());
+                    self.handshake_permit = None;
                     return Err(err);
                 }
             }
 
         }
 
+        if !self.is_handshaking() {
+            self.handshake_permit = None;
+        }
+
         Ok(())
     }
 
-    pub fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
+    pub fn get_peer_certificates(&self) -> Option<key::PeerCertificates> {
         if self.client_cert_chain.is_none() {
             return None;
         }
@@ -366,7 +837,19 @@ impl ServerSessionImpl {
             r.push(cert.clone());
         }
 
-        Some(r)
+        key::PeerCertificates::new(r)
+    }
+
+    pub fn get_verified_client_cert_chain(&self) -> Option<verify::VerifiedClientCertChain<'_>> {
+        // client_cert_chain is only set once the chain is verified:
+        // in this handshake, the one being resumed, or by
+        // import_session.  Plain checkpoints don't restore it.
+        match self.client_cert_chain {
+            Some(ref chain) if !chain.is_empty() => {
+                Some(verify::VerifiedClientCertChain::new(chain))
+            }
+            _ => None,
+        }
     }
 
     pub fn get_alpn_protocol(&self) -> Option<&str> {
@@ -390,6 +873,235 @@ impl ServerSessionImpl {
         assert!(self.sni.is_none());
         self.sni = Some(value)
     }
+
+    pub fn checkpoint(&self) -> Result<SessionCheckpoint, TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if self.is_handshaking() {
+            return Err(TLSError::HandshakeNotComplete);
+        }
+
+        if !self.common.is_quiescent() {
+            return Err(TLSError::General("cannot checkpoint a session with buffered data"
+                                         .to_string()));
+        }
+
+        let secrets = if self.common.is_tls13() {
+            let ks = self.common.get_key_schedule();
+            vec![
+                PayloadU8::new(ks.current_client_traffic_secret.clone()),
+                PayloadU8::new(ks.current_server_traffic_secret.clone()),
+                PayloadU8::new(ks.current_exporter_secret.clone()),
+                PayloadU8::new(ks.resumption_master_secret.clone()),
+            ]
+        } else {
+            let secrets = self.common.secrets.as_ref().unwrap();
+            vec![
+                PayloadU8::new(secrets.randoms.client.to_vec()),
+                PayloadU8::new(secrets.randoms.server.to_vec()),
+                PayloadU8::new(secrets.get_master_secret()),
+            ]
+        };
+
+        let (write_seq, read_seq) = self.common.get_sequence_numbers();
+        let value = persist::ServerCheckpointValue {
+            sni: self.sni.clone(),
+            version: self.common.negotiated_version.unwrap(),
+            cipher_suite: self.common.get_suite_assert().suite,
+            write_seq,
+            read_seq,
+            secrets,
+            alpn_protocol: self.alpn_protocol
+                .as_ref()
+                .map(|proto| PayloadU8::new(proto.as_bytes().to_vec())),
+            client_cert_chain: self.client_cert_chain.clone(),
+        };
+
+        Ok(SessionCheckpoint(value.get_encoding()))
+    }
+
+    pub fn generate_session_ticket(&mut self) -> Result<(), TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if self.is_handshaking() {
+            return Err(TLSError::HandshakeNotComplete);
+        }
+
+        if !self.common.is_tls13() {
+            return Err(TLSError::WrongTlsVersion);
+        }
+
+        if !self.config.ticketer.enabled() {
+            return Err(TLSError::General("session tickets are disabled".to_string()));
+        }
+
+        if self.common.get_key_schedule().resumption_master_secret.is_empty() {
+            return Err(TLSError::General("peer cannot use session tickets".to_string()));
+        }
+
+        let m = hs::make_ticket_tls13(self)
+            .ok_or_else(|| TLSError::General("cannot make session ticket".to_string()))?;
+        trace!("{}sending new ticket {:?}", self.common.log_prefix, m);
+        self.common.send_msg(m, true);
+        Ok(())
+    }
+
+    /// Recreate a session from `checkpoint`.  If `trusted`, the
+    /// checkpoint was authenticated (for example, by decrypting an
+    /// `ExportedSession`), and the client's certificate chain in it is
+    /// restored.  Otherwise it's dropped: a `SessionCheckpoint` can be
+    /// made from any bytes, so it can't vouch for the client.
+    pub fn restore(config: &Arc<ServerConfig>,
+                   checkpoint: &SessionCheckpoint,
+                   trusted: bool) -> Result<ServerSessionImpl, TLSError> {
+        let invalid = || TLSError::General("invalid session checkpoint".to_string());
+
+        let value = persist::ServerCheckpointValue::read_bytes(&checkpoint.0)
+            .ok_or_else(invalid)?;
+        let suite = config.ciphersuites
+            .iter()
+            .find(|scs| scs.suite == value.cipher_suite &&
+                  scs.usable_for_version(value.version) &&
+                  config.versions.contains(&value.version))
+            .cloned()
+            .ok_or_else(|| TLSError::General("session checkpoint is for a disabled \
+                                              version or cipher suite".to_string()))?;
+        let alpn_protocol = match value.alpn_protocol {
+            Some(proto) => Some(String::from_utf8(proto.0).map_err(|_| invalid())?),
+            None => None,
+        };
+
+        let mut sess = ServerSessionImpl::new_uncounted(config);
+        sess.common.negotiated_version = Some(value.version);
+        sess.common.set_suite(suite);
+
+        let hash_len = suite.get_hash().output_len;
+        match (value.version, &value.secrets[..]) {
+            // Older checkpoints lack the resumption_master_secret, and
+            // it's empty if we weren't going to send tickets.
+            (ProtocolVersion::TLSv1_3, [client, server, exporter, resumption @ ..])
+                if resumption.len() <= 1 &&
+                   [client, server, exporter].iter().all(|secret| secret.0.len() == hash_len) &&
+                   resumption.iter().all(|secret| secret.0.is_empty() ||
+                                                  secret.0.len() == hash_len) => {
+                let mut ks = KeySchedule::new(suite.get_hash());
+                ks.current_client_traffic_secret = client.0.clone();
+                ks.current_server_traffic_secret = server.0.clone();
+                ks.current_exporter_secret = exporter.0.clone();
+                if let Some(resumption) = resumption.first() {
+                    ks.resumption_master_secret = resumption.0.clone();
+                }
+                sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &server.0));
+                sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &client.0));
+                sess.common.set_key_schedule(ks);
+            }
+            (ProtocolVersion::TLSv1_2, [client, server, master_secret])
+                if client.0.len() == 32 && server.0.len() == 32 &&
+                   master_secret.0.len() == 48 => {
+                let mut randoms = SessionRandoms::for_server();
+                randoms.client.copy_from_slice(&client.0);
+                randoms.server.copy_from_slice(&server.0);
+                let secrets = SessionSecrets::new_resume(&randoms,
+                                                         suite.get_hash(),
+                                                         &master_secret.0);
+                sess.common.start_encryption_tls12(secrets);
+            }
+            _ => return Err(invalid()),
+        }
+
+        sess.common.restore_traffic(value.write_seq, value.read_seq);
+        sess.sni = value.sni;
+        sess.alpn_protocol = alpn_protocol;
+        if trusted {
+            sess.client_cert_chain =
+                ServerSessionImpl::reverify_client_cert_chain(config, value.client_cert_chain)?;
+        }
+        sess.state = Some(hs::restored_traffic_state(value.version));
+        Ok(sess)
+    }
+}
+
+impl ServerSessionImpl {
+    /// Check a restored client certificate chain against `config`,
+    /// which may not be the one it was verified with.  It's dropped
+    /// if `config` doesn't do client auth.
+    fn reverify_client_cert_chain(config: &ServerConfig,
+                                  chain: Option<Vec<key::Certificate>>)
+                                  -> Result<Option<Vec<key::Certificate>>, TLSError> {
+        match chain {
+            Some(chain) => {
+                if chain.is_empty() || !config.verifier.offer_client_auth() {
+                    return Ok(None);
+                }
+                config.verifier.verify_client_cert(&chain)?;
+                Ok(Some(chain))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// A snapshot of a `ServerSession`, from which it can be restored
+/// with `ServerSession::restore`.  See `ServerSession::checkpoint`.
+///
+/// **This contains the session's traffic keys.**  Anyone who can read
+/// it can decrypt and forge the session's traffic, so protect it as
+/// carefully as your private key.
+#[derive(Clone)]
+pub struct SessionCheckpoint(Vec<u8>);
+
+impl SessionCheckpoint {
+    /// Wrap a checkpoint previously serialised with `as_bytes`.
+    pub fn from_bytes(bytes: Vec<u8>) -> SessionCheckpoint {
+        SessionCheckpoint(bytes)
+    }
+
+    /// The serialised checkpoint, for storage.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SessionCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't print the keys.
+        f.debug_struct("SessionCheckpoint").finish()
+    }
+}
+
+/// A `SessionCheckpoint` encrypted for transfer to another server.
+/// See `ServerSession::export_session`.
+#[derive(Clone)]
+pub struct ExportedSession(Vec<u8>);
+
+/// The length of the random ID at the start of an exported session,
+/// which `import_session` records to refuse replays.
+const EXPORT_ID_LEN: usize = 16;
+
+/// Prefixed to export IDs when recording them in `session_storage`,
+/// so they can't collide with session IDs.
+const EXPORT_ID_PREFIX: &[u8] = b"rustls exported session ";
+
+impl ExportedSession {
+    /// Wrap an exported session previously serialised with `as_bytes`.
+    pub fn from_bytes(bytes: Vec<u8>) -> ExportedSession {
+        ExportedSession(bytes)
+    }
+
+    /// The encrypted session, for transmission.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for ExportedSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExportedSession").finish()
+    }
 }
 
 /// This represents a single TLS server session.
@@ -405,8 +1117,31 @@ pub struct ServerSession {
 impl ServerSession {
     /// Make a new ServerSession.  `config` controls how
     /// we behave in the TLS protocol.
+    ///
+    /// If `config.max_concurrent_handshakes` handshakes are
+    /// already in progress, the session's first
+    /// `process_new_packets` fails with `TLSError::TooManyHandshakes`.
+    /// Use `try_new` to find out straight away.
     pub fn new(config: &Arc<ServerConfig>) -> ServerSession {
-        ServerSession { imp: ServerSessionImpl::new(config) }
+        ServerSession::new_with_user_data(config, Arc::new(()))
+    }
+
+    /// Like `new`, but passes `user_data` to the
+    /// `ServerConfig::set_on_connect` hook.
+    pub fn new_with_user_data(config: &Arc<ServerConfig>,
+                              user_data: Arc<Any + Send + Sync>) -> ServerSession {
+        ServerSession { imp: ServerSessionImpl::new(config, user_data) }
+    }
+
+    /// Like `new`, but fails with `TLSError::TooManyHandshakes` if
+    /// `config.max_concurrent_handshakes` handshakes are already
+    /// in progress.
+    pub fn try_new(config: &Arc<ServerConfig>) -> Result<ServerSession, TLSError> {
+        let imp = ServerSessionImpl::new(config, Arc::new(()));
+        match imp.error {
+            Some(ref err) => Err(err.clone()),
+            None => Ok(ServerSession { imp }),
+        }
     }
 
     /// Retrieves the SNI hostname, if any, used to select the certificate and
@@ -428,6 +1163,164 @@ impl ServerSession {
     pub fn get_sni_hostname(&self)-> Option<&str> {
         self.imp.get_sni().map(|s| s.as_ref().into())
     }
+
+    /// Retrieves the client's certificate chain, if it sent one and
+    /// the configured `ClientCertVerifier` accepted it.
+    ///
+    /// This returns the same certificates as `get_peer_certificates`,
+    /// but as a `VerifiedClientCertChain`: code which needs an
+    /// authenticated client can require one of those, and the
+    /// compiler then makes sure this check was done.
+    pub fn get_verified_client_cert_chain(&self) -> Option<verify::VerifiedClientCertChain<'_>> {
+        self.imp.get_verified_client_cert_chain()
+    }
+
+    /// Identifies this session, for example in logs.  See
+    /// `ConnectionId`.
+    pub fn connection_id(&self) -> ConnectionId {
+        self.imp.connection_id
+    }
+
+    /// Tell the session the client's address, for
+    /// `ServerConfig::ticket_address_binding`.  Call this before
+    /// giving the session any data from the client.
+    pub fn set_peer_address(&mut self, addr: SocketAddr) {
+        self.imp.set_peer_address(addr)
+    }
+
+    /// The RFC8705 thumbprint of the client's end-entity
+    /// certificate, if it sent one which was accepted.  See
+    /// `client_cert_thumbprint`.
+    pub fn client_cert_thumbprint(&self) -> Option<String> {
+        self.imp.get_peer_certificates()
+            .map(|certs| key::client_cert_thumbprint(certs.end_entity()))
+    }
+
+    /// The handshake messages this session has sent and
+    /// received so far, in order, for debugging.
+    #[cfg(feature = "debug-transcript")]
+    pub fn get_handshake_transcript(&self) -> Vec<HandshakeMessageSummary> {
+        self.imp.common.transcript.messages()
+    }
+
+    /// Take a checkpoint of this session, from which it can later be
+    /// recreated with `ServerSession::restore`: for example, to carry
+    /// a long-lived connection across a restart of this process.
+    ///
+    /// This is only possible once the handshake is complete, and
+    /// when there is no buffered data in either direction: call this
+    /// after `write_tls` has written everything and `read` has
+    /// returned all received plaintext.
+    ///
+    /// **This is a security-sensitive operation.**  The checkpoint
+    /// contains the session's traffic keys.  And after it is taken,
+    /// this session and any session restored from it share keys *and*
+    /// sequence numbers: if more than one of them sends data, or this
+    /// session sends anything after the checkpoint is stored, the
+    /// record protection nonces are reused and confidentiality and
+    /// integrity are lost.  Restore each checkpoint at most once, and
+    /// only if this session has been discarded without sending.
+    pub fn checkpoint(&self) -> Result<SessionCheckpoint, TLSError> {
+        self.imp.checkpoint()
+    }
+
+    /// Send the client a new TLS1.3 session ticket, made by
+    /// `config.ticketer`, the next time we write: for example, when
+    /// the client will need a fresh one after this connection
+    /// migrates.  One is already sent after the handshake.
+    ///
+    /// Returns `TLSError::WrongTlsVersion` for TLS1.2 sessions,
+    /// and an error if the handshake isn't complete, tickets are
+    /// disabled, or the client didn't offer a ticket-compatible
+    /// PSK mode.
+    pub fn generate_session_ticket(&mut self) -> Result<(), TLSError> {
+        self.imp.generate_session_ticket()
+    }
+
+    /// Recreate a session from `checkpoint`, which was taken by
+    /// `ServerSession::checkpoint`.  `config` should be the same
+    /// configuration as the original session; the checkpoint's
+    /// protocol version and cipher suite must be enabled in it.
+    ///
+    /// The client's certificate chain, if it sent one, is not
+    /// restored: a `SessionCheckpoint` can be made from any bytes, so
+    /// the restored session has no verified client certificates.  Use
+    /// `export_session` and `import_session`, which authenticate the
+    /// checkpoint, to keep them.
+    ///
+    /// See `checkpoint` for the security considerations.
+    pub fn restore(config: &Arc<ServerConfig>,
+                   checkpoint: &SessionCheckpoint) -> Result<ServerSession, TLSError> {
+        ServerSessionImpl::restore(config, checkpoint, false)
+            .map(|imp| ServerSession { imp })
+    }
+
+    /// Take a checkpoint of this session, and encrypt it with `key`
+    /// so it can be sent to another server: for example, to move a
+    /// connection off a backend which is going away.
+    ///
+    /// `key` should be shared across the cluster, and is typically a
+    /// `ClusterTicketRotator` or other ticketer whose keys all the
+    /// servers have.  The receiving server calls `import_session`
+    /// with a ticketer which can decrypt it.
+    ///
+    /// The same conditions and security considerations apply as for
+    /// `checkpoint`.  This session must not send anything once
+    /// exported, so it is consumed.  On error, it is dropped.
+    ///
+    /// Each export has a random ID, which `import_session` records:
+    /// see there for how replays are refused.
+    pub fn export_session(self, key: &ProducesTickets) -> Result<ExportedSession, TLSError> {
+        let checkpoint = self.checkpoint()?;
+
+        let mut plain = rand::random_vec(EXPORT_ID_LEN);
+        plain.extend_from_slice(checkpoint.as_bytes());
+
+        key.encrypt(&plain)
+            .map(ExportedSession)
+            .ok_or_else(|| TLSError::General("cannot encrypt exported session".to_string()))
+    }
+
+    /// Recreate a session exported by `ServerSession::export_session`,
+    /// decrypting it with `key`.
+    ///
+    /// Unlike `restore`, this keeps the client's certificate chain,
+    /// since only a holder of `key` could have made `exported`.  The
+    /// chain is checked again with `config`'s `ClientCertVerifier`,
+    /// and dropped if `config` doesn't do client auth.
+    ///
+    /// An exported session must only be imported once: two servers
+    /// using the same keys and sequence numbers would break the
+    /// connection's security.  So the export's ID is recorded in
+    /// `config.session_storage`, and exports already recorded there
+    /// are refused.  This only covers servers sharing that storage,
+    /// for as long as the storage keeps the ID, and the check isn't
+    /// atomic with recording it.  Storage which can't record it
+    /// (such as `NoServerSessionStorage`) can't import sessions.
+    pub fn import_session(config: &Arc<ServerConfig>,
+                          key: &ProducesTickets,
+                          exported: &ExportedSession) -> Result<ServerSession, TLSError> {
+        let plain = key.decrypt(exported.as_bytes())
+            .ok_or_else(|| TLSError::General("cannot decrypt exported session".to_string()))?;
+        if plain.len() < EXPORT_ID_LEN {
+            return Err(TLSError::General("invalid exported session".to_string()));
+        }
+
+        let (id, checkpoint) = plain.split_at(EXPORT_ID_LEN);
+        let checkpoint = SessionCheckpoint::from_bytes(checkpoint.to_vec());
+        let imp = ServerSessionImpl::restore(config, &checkpoint, true)?;
+
+        let mut stored_id = EXPORT_ID_PREFIX.to_vec();
+        stored_id.extend_from_slice(id);
+        if config.session_storage.get(&stored_id).is_some() {
+            return Err(TLSError::General("exported session already imported".to_string()));
+        }
+        if !config.session_storage.put(stored_id, Vec::new()) {
+            return Err(TLSError::General("cannot record imported session".to_string()));
+        }
+
+        Ok(ServerSession { imp })
+    }
 }
 
 impl Session for ServerSession {
@@ -464,7 +1357,7 @@ impl Session for ServerSession {
         self.imp.common.send_close_notify()
     }
 
-    fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
+    fn get_peer_certificates(&self) -> Option<key::PeerCertificates> {
         self.imp.get_peer_certificates()
     }
 
@@ -504,9 +1397,10 @@ impl io::Write for ServerSession {
     ///
     /// This function buffers plaintext sent before the
     /// TLS handshake completes, and sends it as soon
-    /// as it can.  This buffer is of *unlimited size* so
-    /// writing much data before it can be sent will
-    /// cause excess memory usage.
+    /// as it can.  This buffer, and the buffer of TLS
+    /// records waiting for `write_tls`, are limited by
+    /// `set_buffer_limit`: once they are full, this fails
+    /// with `WouldBlock`.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.imp.common.send_some_plaintext(buf)
     }
@@ -16,6 +16,8 @@ use key;
 use key_schedule::{SecretKind, KeySchedule};
 use prf;
 use rand;
+#[cfg(feature = "debug-transcript")]
+use transcript::{Direction, Transcript};
 
 use std::io;
 use std::collections::VecDeque;
@@ -76,8 +78,13 @@ pub trait Session: Read + Write + Send + Sync {
     /// unsent plaintext (prior to completing the TLS handshake)
     /// and unsent TLS records.
     ///
-    /// By default, there is no limit.  The limit can be set
-    /// at any time, even if the current buffer use is higher.
+    /// Once the limit is reached, writes fail with `WouldBlock`
+    /// until `write_tls` makes room.  Writes which would go over
+    /// the limit are shortened instead.
+    ///
+    /// By default, the limit is 4MB.  A limit of zero means no
+    /// limit.  The limit can be set at any time, even if the
+    /// current buffer use is higher.
     fn set_buffer_limit(&mut self, limit: usize);
 
     /// Queues a close_notify fatal alert to be sent in the next
@@ -93,7 +100,7 @@ pub trait Session: Read + Write + Send + Sync {
     /// if client authentication was completed.
     ///
     /// The return value is None until this value is available.
-    fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>>;
+    fn get_peer_certificates(&self) -> Option<key::PeerCertificates>;
 
     /// Retrieves the protocol agreed with the peer via ALPN.
     ///
@@ -360,11 +367,39 @@ impl SessionSecrets {
 static SEQ_SOFT_LIMIT: u64 = 0xffff_ffff_ffff_0000u64;
 static SEQ_HARD_LIMIT: u64 = 0xffff_ffff_ffff_fffeu64;
 
+/// The default for `Session::set_buffer_limit`: 4MB.
+const DEFAULT_BUFFER_LIMIT: usize = 4 * 1024 * 1024;
+
 enum Limit {
     Yes,
     No
 }
 
+/// What to do when the peer sends us a warning alert.  See
+/// `ServerConfig::set_alert_policy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertPolicy {
+    /// Handle the alert normally.  In TLS1.3 it ends the session
+    /// with `TLSError::AlertReceived`; in TLS1.2 it is logged.
+    Terminate,
+
+    /// Log the alert, and carry on.
+    Log,
+
+    /// Carry on without logging.
+    Ignore,
+}
+
+impl AlertPolicy {
+    fn find(policies: &[(AlertDescription, AlertPolicy)],
+            desc: AlertDescription) -> AlertPolicy {
+        policies.iter()
+            .find(|&&(d, _)| d == desc)
+            .map(|&(_, policy)| policy)
+            .unwrap_or(AlertPolicy::Terminate)
+    }
+}
+
 pub struct SessionCommon {
     pub negotiated_version: Option<ProtocolVersion>,
     pub is_client: bool,
@@ -386,11 +421,17 @@ pub struct SessionCommon {
     received_plaintext: ChunkVecBuffer,
     sendable_plaintext: ChunkVecBuffer,
     pub sendable_tls: ChunkVecBuffer,
+    #[cfg(feature = "debug-transcript")]
+    pub transcript: Transcript,
+
+    /// Goes at the start of our log messages, to say which
+    /// session they're about.
+    pub log_prefix: String,
 }
 
 impl SessionCommon {
     pub fn new(mtu: Option<usize>, client: bool) -> SessionCommon {
-        SessionCommon {
+        let mut common = SessionCommon {
             negotiated_version: None,
             is_client: client,
             suite: None,
@@ -411,7 +452,12 @@ impl SessionCommon {
             received_plaintext: ChunkVecBuffer::new(),
             sendable_plaintext: ChunkVecBuffer::new(),
             sendable_tls: ChunkVecBuffer::new(),
-        }
+            #[cfg(feature = "debug-transcript")]
+            transcript: Transcript::new(),
+            log_prefix: String::new(),
+        };
+        common.set_buffer_limit(DEFAULT_BUFFER_LIMIT);
+        common
     }
 
     pub fn is_tls13(&self) -> bool {
@@ -503,7 +549,29 @@ impl SessionCommon {
         ret
     }
 
-    pub fn process_alert(&mut self, msg: Message) -> Result<(), TLSError> {
+    /// Like `decrypt_incoming`, but a record which is not
+    /// authentic is skipped: `Ok(None)` is returned and the
+    /// read sequence number is unchanged.  This is used by TLS1.3
+    /// servers to skip early data they have rejected.
+    pub fn decrypt_incoming_or_skip(&mut self, encr: Message) -> Result<Option<Message>, TLSError> {
+        match self.message_decrypter.decrypt(encr, self.read_seq) {
+            Ok(msg) => {
+                self.read_seq += 1;
+                Ok(Some(msg))
+            }
+            Err(TLSError::DecryptError) => Ok(None),
+            Err(err) => {
+                if let TLSError::PeerSentOversizedRecord = err {
+                    self.send_fatal_alert(AlertDescription::RecordOverflow);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn process_alert(&mut self,
+                         msg: Message,
+                         policies: &[(AlertDescription, AlertPolicy)]) -> Result<(), TLSError> {
         if let MessagePayload::Alert(ref alert) = msg.payload {
             // Reject unknown AlertLevels.
             match alert.level {
@@ -520,17 +588,31 @@ impl SessionCommon {
                 return Ok(());
             }
 
+            // Policies only apply to warnings: fatal alerts always
+            // end the session.
+            if alert.level == AlertLevel::Warning {
+                match AlertPolicy::find(policies, alert.description) {
+                    AlertPolicy::Terminate => {}
+                    AlertPolicy::Log => {
+                        warn!("{}TLS alert received and ignored by policy: {:#?}",
+                              self.log_prefix, msg);
+                        return Ok(());
+                    }
+                    AlertPolicy::Ignore => return Ok(()),
+                }
+            }
+
             // Warnings are nonfatal for TLS1.2, but outlawed in TLS1.3.
             if alert.level == AlertLevel::Warning {
                 if self.is_tls13() {
                     self.send_fatal_alert(AlertDescription::DecodeError);
                 } else {
-                    warn!("TLS alert warning received: {:#?}", msg);
+                    warn!("{}TLS alert warning received: {:#?}", self.log_prefix, msg);
                     return Ok(());
                 }
             }
 
-            error!("TLS alert received: {:#?}", msg);
+            error!("{}TLS alert received: {:#?}", self.log_prefix, msg);
             Err(TLSError::AlertReceived(alert.description))
         } else {
             Err(TLSError::CorruptMessagePayload(ContentType::Alert))
@@ -563,6 +645,9 @@ impl SessionCommon {
     /// Fragment `m`, encrypt the fragments, and then queue
     /// the encrypted fragments for sending.
     pub fn send_msg_encrypt(&mut self, m: Message) {
+        #[cfg(feature = "debug-transcript")]
+        self.transcript.record(Direction::Sent, &m);
+
         if self.want_write_key_update {
             self.do_write_key_update();
         }
@@ -644,9 +729,14 @@ impl SessionCommon {
     /// encrypting it as it goes out.
     ///
     /// If internal buffers are too small, this function will not accept
-    /// all the data.
+    /// all the data.  If they are full, it fails with `WouldBlock`.
     pub fn send_some_plaintext(&mut self, data: &[u8]) -> io::Result<usize> {
-        self.send_plain(data, Limit::Yes)
+        let len = self.send_plain(data, Limit::Yes)?;
+        if len == 0 && !data.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock,
+                                      "buffer limit reached: call write_tls"));
+        }
+        Ok(len)
     }
 
 
@@ -671,6 +761,36 @@ impl SessionCommon {
         Ok(self.send_appdata_encrypt(data, limit))
     }
 
+    /// Our write and read sequence numbers.
+    pub fn get_sequence_numbers(&self) -> (u64, u64) {
+        (self.write_seq, self.read_seq)
+    }
+
+    /// Returns true if this session has no buffered data: no
+    /// received records or plaintext waiting to be processed or
+    /// read, nothing waiting to be written, and no pending key
+    /// update.
+    pub fn is_quiescent(&self) -> bool {
+        !self.message_deframer.has_pending() &&
+            self.handshake_joiner.is_empty() &&
+            self.received_plaintext.is_empty() &&
+            self.sendable_plaintext.is_empty() &&
+            self.sendable_tls.is_empty() &&
+            !self.want_write_key_update &&
+            !self.peer_eof
+    }
+
+    /// Put this session straight into its traffic phase, after
+    /// it was checkpointed with the given sequence numbers.  The
+    /// record protection must already have been set up.
+    pub fn restore_traffic(&mut self, write_seq: u64, read_seq: u64) {
+        self.write_seq = write_seq;
+        self.read_seq = read_seq;
+        self.peer_now_encrypting();
+        self.we_now_encrypting();
+        self.start_traffic();
+    }
+
     pub fn start_traffic(&mut self) {
         self.traffic = true;
         self.flush_plaintext();
@@ -698,6 +818,9 @@ impl SessionCommon {
     /// Send a raw TLS message, fragmenting it if needed.
     pub fn send_msg(&mut self, m: Message, must_encrypt: bool) {
         if !must_encrypt {
+            #[cfg(feature = "debug-transcript")]
+            self.transcript.record(Direction::Sent, &m);
+
             let mut to_send = VecDeque::new();
             self.message_fragmenter.fragment(m, &mut to_send);
             for mm in to_send {
@@ -739,14 +862,14 @@ impl SessionCommon {
     }
 
     pub fn send_warning_alert(&mut self, desc: AlertDescription) {
-        warn!("Sending warning alert {:?}", desc);
+        warn!("{}Sending warning alert {:?}", self.log_prefix, desc);
         let m = Message::build_alert(AlertLevel::Warning, desc);
         let enc = self.we_encrypting;
         self.send_msg(m, enc);
     }
 
     pub fn send_fatal_alert(&mut self, desc: AlertDescription) {
-        warn!("Sending fatal alert {:?}", desc);
+        warn!("{}Sending fatal alert {:?}", self.log_prefix, desc);
         let m = Message::build_alert(AlertLevel::Fatal, desc);
         let enc = self.we_encrypting;
         self.send_msg(m, enc);
@@ -763,7 +886,7 @@ impl SessionCommon {
         // Mustn't be interleaved with other handshake messages.
         if !self.handshake_joiner.is_empty() {
             let msg = "KeyUpdate received at wrong time".to_string();
-            warn!("{}", msg);
+            warn!("{}{}", self.log_prefix, msg);
             return Err(TLSError::PeerMisbehavedError(msg));
         }
 
@@ -6,11 +6,14 @@ use error::TLSError;
 use untrusted;
 
 use ring;
+use ring::constant_time;
+use ring::digest;
 use ring::signature;
-use ring::signature::RSAKeyPair;
+use ring::signature::{RSAKeyPair, ECDSAKeyPair};
 use webpki;
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::mem;
 
 /// An abstract signing key.
@@ -21,8 +24,184 @@ pub trait SigningKey : Send + Sync {
     /// using the chosen scheme.
     fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>>;
 
+    /// All the `SignatureScheme`s this key can sign with, most
+    /// preferred first.  `choose_scheme` picks from these.
+    fn supported_schemes(&self) -> &[SignatureScheme];
+
     /// What kind of key we have.
     fn algorithm(&self) -> SignatureAlgorithm;
+
+    /// A short description of the key's type, such as "RSA-2048"
+    /// or "ECDSA-P256", for logs and error messages.
+    ///
+    /// The default implementation only names the `algorithm`.
+    fn key_type_name(&self) -> &'static str {
+        match self.algorithm() {
+            SignatureAlgorithm::RSA => "RSA",
+            SignatureAlgorithm::ECDSA => "ECDSA",
+            _ => "unknown",
+        }
+    }
+
+    /// Sign `message` with `scheme`, for keys which may need help
+    /// from `cb` to do so: for example, if the private key is held
+    /// elsewhere, `cb` might forward the message to where it is and
+    /// return the signature.
+    ///
+    /// The default implementation signs with our own `Signer`, and
+    /// doesn't call `cb`.
+    fn sign_with_callback<F>(&self,
+                             scheme: SignatureScheme,
+                             message: &[u8],
+                             cb: F) -> Result<Vec<u8>, TLSError>
+        where F: FnOnce(&[u8]) -> Vec<u8>, Self: Sized
+    {
+        let _ = cb;
+        self.choose_scheme(&[scheme])
+            .ok_or_else(|| TLSError::General("signature scheme not supported by key".to_string()))?
+            .sign(message)
+    }
+}
+
+/// Something which produces the signatures for `CertificateVerify`
+/// (and `ServerKeyExchange`) messages, for any kind of key.
+///
+/// This is for keys which are used outside rustls, such as in an HSM:
+/// wrap it in a `ChallengeSigningKey` to get a `SigningKey`.
+pub trait CertificateVerifyChallenge : Send + Sync {
+    /// Sign `message` using `scheme`, which is one of the schemes
+    /// the `ChallengeSigningKey` was made with.
+    fn respond(&self, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, TLSError>;
+}
+
+/// A `SigningKey` whose signatures are made by a
+/// `CertificateVerifyChallenge`.
+pub struct ChallengeSigningKey {
+    algorithm: SignatureAlgorithm,
+    schemes: Vec<SignatureScheme>,
+    backend: Arc<CertificateVerifyChallenge>,
+}
+
+impl ChallengeSigningKey {
+    /// Make a new `ChallengeSigningKey`, for a key of type `algorithm`
+    /// which can sign with `schemes` (most preferred first).  All
+    /// signing is done by `backend`.
+    pub fn new(algorithm: SignatureAlgorithm,
+               schemes: &[SignatureScheme],
+               backend: Arc<CertificateVerifyChallenge>) -> ChallengeSigningKey {
+        ChallengeSigningKey {
+            algorithm,
+            schemes: schemes.to_vec(),
+            backend,
+        }
+    }
+}
+
+impl SigningKey for ChallengeSigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>> {
+        let scheme = util::first_in_both(&self.schemes, offered)?;
+        Some(Box::new(ChallengeSigner { scheme, backend: self.backend.clone() }))
+    }
+
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        &self.schemes
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
+}
+
+struct ChallengeSigner {
+    scheme: SignatureScheme,
+    backend: Arc<CertificateVerifyChallenge>,
+}
+
+impl Signer for ChallengeSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        self.backend.respond(self.scheme, message)
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Something which can load a private key when it is first
+/// needed, rather than when the configuration is made: for example,
+/// by asking for the passphrase of an encrypted key file.
+pub trait PrivateKeySource : Send + Sync {
+    /// Load the key.
+    fn load(&self) -> Result<Arc<Box<SigningKey>>, TLSError>;
+}
+
+/// A `SigningKey` which loads the real key from a `PrivateKeySource`
+/// the first time a handshake needs to sign something, and then
+/// keeps it for later handshakes.
+///
+/// If loading fails, the handshake fails as if the key supported no
+/// signature schemes, and the next handshake tries again.
+pub struct LazySigningKey {
+    algorithm: SignatureAlgorithm,
+    source: Box<PrivateKeySource>,
+    loaded: Mutex<Option<Arc<Box<SigningKey>>>>,
+}
+
+impl LazySigningKey {
+    /// Make a new `LazySigningKey`, for a key of type `algorithm`
+    /// which is loaded from `source`.  `algorithm` is needed before
+    /// the key is loaded, to choose a cipher suite.
+    pub fn new(algorithm: SignatureAlgorithm,
+               source: Box<PrivateKeySource>) -> LazySigningKey {
+        LazySigningKey {
+            algorithm,
+            source,
+            loaded: Mutex::new(None),
+        }
+    }
+
+    /// Get the key, loading it if this is the first successful call.
+    pub fn get_key(&self) -> Result<Arc<Box<SigningKey>>, TLSError> {
+        let mut loaded = self.loaded.lock()
+            .unwrap();
+
+        if let Some(ref key) = *loaded {
+            return Ok(key.clone());
+        }
+
+        let key = self.source.load()?;
+        if key.algorithm() != self.algorithm {
+            return Err(TLSError::General("loaded key is of the wrong type".to_string()));
+        }
+        *loaded = Some(key.clone());
+        Ok(key)
+    }
+}
+
+impl SigningKey for LazySigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>> {
+        match self.get_key() {
+            Ok(key) => key.choose_scheme(offered),
+            Err(err) => {
+                warn!("Cannot load private key: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// The key may not be loaded yet, so this is every scheme a
+    /// key of its algorithm might support.
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        match self.algorithm {
+            SignatureAlgorithm::RSA => ALL_RSA_SCHEMES,
+            SignatureAlgorithm::ECDSA => ALL_ECDSA_SCHEMES,
+            _ => &[],
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
 }
 
 /// A thing that can sign a message.
@@ -34,6 +213,92 @@ pub trait Signer : Send + Sync {
     fn get_scheme(&self) -> SignatureScheme;
 }
 
+/// A set of certificates shared between many `CertifiedKey`s, so
+/// that each one only needs to be stored once.  This is worthwhile
+/// when many certificates were issued by the same intermediate CA.
+///
+/// Certificates are identified by the SHA-256 hash of their
+/// encoding.  They are never removed from the pool.
+#[derive(Debug, Default)]
+pub struct CertificatePool {
+    certs: Mutex<HashMap<Vec<u8>, Arc<key::Certificate>>>,
+}
+
+impl CertificatePool {
+    /// Make a new, empty, pool.
+    pub fn new() -> CertificatePool {
+        CertificatePool::default()
+    }
+
+    /// Add `cert` to the pool, if it isn't already there, and
+    /// return a reference to the pool's copy.
+    pub fn add(&self, cert: key::Certificate) -> CertRef {
+        let fingerprint = fingerprint(&cert);
+        let mut certs = self.certs.lock()
+            .unwrap();
+        let shared = certs.entry(fingerprint)
+            .or_insert_with(|| Arc::new(cert));
+        CertRef::Pooled(shared.clone())
+    }
+
+    /// Return a reference to the pool's copy of `cert`, or None
+    /// if it isn't in the pool.
+    pub fn get(&self, cert: &key::Certificate) -> Option<CertRef> {
+        self.certs.lock()
+            .unwrap()
+            .get(&fingerprint(cert))
+            .map(|shared| CertRef::Pooled(shared.clone()))
+    }
+
+    /// The number of different certificates in the pool.
+    pub fn len(&self) -> usize {
+        self.certs.lock()
+            .unwrap()
+            .len()
+    }
+
+    /// Returns true if the pool is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn fingerprint(cert: &key::Certificate) -> Vec<u8> {
+    digest::digest(&digest::SHA256, &cert.0)
+        .as_ref()
+        .to_vec()
+}
+
+/// A certificate in a chain: either one of its own, or one in a
+/// `CertificatePool`.
+#[derive(Clone, Debug)]
+pub enum CertRef {
+    /// A certificate not shared with anything else.
+    Owned(key::Certificate),
+
+    /// A certificate in a `CertificatePool`.
+    Pooled(Arc<key::Certificate>),
+}
+
+impl CertRef {
+    fn into_cert(self) -> key::Certificate {
+        match self {
+            CertRef::Owned(cert) => cert,
+            CertRef::Pooled(shared) => Arc::try_unwrap(shared)
+                .unwrap_or_else(|shared| (*shared).clone()),
+        }
+    }
+}
+
+impl AsRef<key::Certificate> for CertRef {
+    fn as_ref(&self) -> &key::Certificate {
+        match *self {
+            CertRef::Owned(ref cert) => cert,
+            CertRef::Pooled(ref shared) => shared,
+        }
+    }
+}
+
 /// A packaged together certificate chain, matching `SigningKey` and
 /// optional stapled OCSP response and/or SCT.
 #[derive(Clone)]
@@ -41,6 +306,10 @@ pub struct CertifiedKey {
     /// The certificate chain.
     pub cert: Vec<key::Certificate>,
 
+    /// More of the certificate chain, which follows `cert`.  This
+    /// is how `with_pool` stores its chain, with `cert` left empty.
+    pub shared_cert: Vec<CertRef>,
+
     /// The certified key.
     pub key: Arc<Box<SigningKey>>,
 
@@ -62,20 +331,69 @@ impl CertifiedKey {
     pub fn new(cert: Vec<key::Certificate>, key: Arc<Box<SigningKey>>) -> CertifiedKey {
         CertifiedKey {
             cert: cert,
+            shared_cert: Vec::new(),
             key: key,
             ocsp: None,
             sct_list: None,
         }
     }
 
+    /// Make a new CertifiedKey, with the given chain and key, sharing
+    /// certificates with others made with the same `pool`.
+    ///
+    /// Any `CertRef::Owned` certificates in `chain` which are
+    /// already in the pool use the pool's copy instead.  Add the
+    /// intermediate certificates to the pool first with
+    /// `CertificatePool::add`.
+    ///
+    /// As with `new`, the first certificate in the chain must be the
+    /// end-entity certificate.
+    pub fn with_pool(chain: Vec<CertRef>,
+                     pool: &Arc<CertificatePool>,
+                     key: Arc<Box<SigningKey>>) -> CertifiedKey {
+        let chain = chain.into_iter()
+            .map(|cert| match cert {
+                CertRef::Owned(cert) => pool.get(&cert)
+                    .unwrap_or(CertRef::Owned(cert)),
+                pooled => pooled,
+            })
+            .collect();
+
+        CertifiedKey {
+            shared_cert: chain,
+            ..CertifiedKey::new(Vec::new(), key)
+        }
+    }
+
+    /// Make a new CertifiedKey from the DER encodings of a chain and
+    /// its private key, end-entity certificate first.  The key may
+    /// be RSA (PKCS#1 or PKCS#8) or ECDSA (PKCS#8).
+    ///
+    /// This fails if the key can't be parsed, the chain is empty, or
+    /// the end-entity certificate is syntactically invalid.
+    pub fn from_der(chain: &[&[u8]], private_key_der: &[u8]) -> Result<CertifiedKey, TLSError> {
+        let key = any_supported_type(&key::PrivateKey(private_key_der.to_vec()))?;
+        let chain = chain.iter()
+            .map(|der| key::Certificate(der.to_vec()))
+            .collect();
+
+        let ck = CertifiedKey::new(chain, Arc::new(key));
+        ck.cross_check_end_entity_cert(None)?;
+        Ok(ck)
+    }
+
     /// The end-entity certificate.
     pub fn end_entity_cert(&self) -> Result<&key::Certificate, ()> {
-        self.cert.get(0).ok_or(())
+        self.cert.first()
+            .or_else(|| self.shared_cert.first().map(|cert| cert.as_ref()))
+            .ok_or(())
     }
 
     /// Steal ownership of the certificate chain.
     pub fn take_cert(&mut self) -> Vec<key::Certificate> {
-        mem::replace(&mut self.cert, Vec::new())
+        let mut chain = mem::replace(&mut self.cert, Vec::new());
+        chain.extend(self.shared_cert.drain(..).map(CertRef::into_cert));
+        chain
     }
 
     /// Return true if there's an OCSP response.
@@ -107,17 +425,7 @@ impl CertifiedKey {
     /// These checks are not security-sensitive.  They are the
     /// *server* attempting to detect accidental misconfiguration.
     pub fn cross_check_end_entity_cert(&self, name: Option<webpki::DNSNameRef>) -> Result<(), TLSError> {
-        // Always reject an empty certificate chain.
-        let end_entity_cert = self.end_entity_cert().map_err(|()| {
-            TLSError::General("No end-entity certificate in certificate chain".to_string())
-        })?;
-
-        // Reject syntactically-invalid end-entity certificates.
-        let end_entity_cert = webpki::EndEntityCert::from(
-            untrusted::Input::from(end_entity_cert.as_ref())).map_err(|_| {
-                TLSError::General("End-entity certificate in certificate \
-                                  chain is syntactically invalid".to_string())
-        })?;
+        let end_entity_cert = self.parse_end_entity_cert()?;
 
         if let Some(name) = name {
             // If SNI was offered then the certificate must be valid for
@@ -133,6 +441,182 @@ impl CertifiedKey {
 
         Ok(())
     }
+
+    /// Like `cross_check_end_entity_cert`, but for a certificate
+    /// which should be valid for at least one of several names.
+    /// Returns the first of `names` the certificate is valid for,
+    /// or an error if there isn't one.
+    pub fn cross_check_for_any_name<'a>(&self, names: &[webpki::DNSNameRef<'a>])
+                                        -> Result<webpki::DNSNameRef<'a>, TLSError> {
+        let end_entity_cert = self.parse_end_entity_cert()?;
+
+        names.iter()
+            .find(|name| end_entity_cert.verify_is_valid_for_dns_name(**name).is_ok())
+            .cloned()
+            .ok_or_else(|| TLSError::General("The server certificate is not \
+                                             valid for any of the given names".to_string()))
+    }
+
+    fn parse_end_entity_cert(&self) -> Result<webpki::EndEntityCert<'_>, TLSError> {
+        // Always reject an empty certificate chain.
+        let end_entity_cert = self.end_entity_cert().map_err(|()| {
+            TLSError::General("No end-entity certificate in certificate chain".to_string())
+        })?;
+
+        // Reject syntactically-invalid end-entity certificates.
+        webpki::EndEntityCert::from(untrusted::Input::from(end_entity_cert.as_ref()))
+            .map_err(|_| {
+                TLSError::General("End-entity certificate in certificate \
+                                  chain is syntactically invalid".to_string())
+            })
+    }
+
+    /// Return true if the end-entity certificate is valid for
+    /// DNS name `name`; ie. `cross_check_end_entity_cert` passes
+    /// for it.  Returns false if `name` is not a valid DNS name.
+    pub fn valid_for_name(&self, name: &str) -> bool {
+        match webpki::DNSNameRef::try_from_ascii_str(name) {
+            Ok(name) => self.cross_check_end_entity_cert(Some(name)).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Return the first of `names` for which `valid_for_name`
+    /// is true, or None if there isn't one.
+    pub fn valid_for_any_name<'a>(&self, names: &[&'a str]) -> Option<&'a str> {
+        names.iter()
+            .find(|name| self.valid_for_name(name))
+            .cloned()
+    }
+}
+
+/// Parse `der` as any supported type of private key: RSA, then
+/// ECDSA.
+pub fn any_supported_type(der: &key::PrivateKey) -> Result<Box<SigningKey>, TLSError> {
+    if let Ok(rsa) = RSASigningKey::new(der) {
+        return Ok(Box::new(rsa));
+    }
+
+    let ecdsa = ECDSASigningKey::new(der)
+        .map_err(|()| TLSError::General("invalid private key".to_string()))?;
+    Ok(Box::new(ecdsa))
+}
+
+/// The webpki algorithm for checking signatures made with `scheme`.
+fn webpki_algorithm(scheme: SignatureScheme) -> Option<&'static webpki::SignatureAlgorithm> {
+    match scheme {
+        SignatureScheme::RSA_PSS_SHA256 => Some(&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
+        SignatureScheme::RSA_PSS_SHA384 => Some(&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY),
+        SignatureScheme::RSA_PSS_SHA512 => Some(&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY),
+        SignatureScheme::RSA_PKCS1_SHA256 => Some(&webpki::RSA_PKCS1_2048_8192_SHA256),
+        SignatureScheme::RSA_PKCS1_SHA384 => Some(&webpki::RSA_PKCS1_2048_8192_SHA384),
+        SignatureScheme::RSA_PKCS1_SHA512 => Some(&webpki::RSA_PKCS1_2048_8192_SHA512),
+        SignatureScheme::ECDSA_NISTP256_SHA256 => Some(&webpki::ECDSA_P256_SHA256),
+        SignatureScheme::ECDSA_NISTP384_SHA384 => Some(&webpki::ECDSA_P384_SHA384),
+        _ => None,
+    }
+}
+
+/// Is `private_key` the key for the public key in `cert`?  This
+/// catches loading the key for one certificate with another.
+///
+/// *ring* can't give us the public half of a private key, so this
+/// signs a message with `private_key` and checks the signature
+/// using `cert`.  Returns an error if either can't be parsed.
+pub fn key_matches_cert(private_key: &key::PrivateKey,
+                        cert: &key::Certificate) -> Result<bool, TLSError> {
+    let key = any_supported_type(private_key)?;
+    let cert = webpki::EndEntityCert::from(untrusted::Input::from(&cert.0))
+        .map_err(TLSError::WebPKIError)?;
+
+    let (scheme, alg) = key.supported_schemes()
+        .iter()
+        .filter_map(|scheme| webpki_algorithm(*scheme).map(|alg| (*scheme, alg)))
+        .next()
+        .ok_or_else(|| TLSError::General("no signature scheme to test key with".to_string()))?;
+
+    let message = b"rustls key_matches_cert";
+    let sig = key.choose_scheme(&[scheme])
+        .ok_or_else(|| TLSError::General("no signature scheme to test key with".to_string()))?
+        .sign(message)?;
+
+    match cert.verify_signature(alg,
+                                untrusted::Input::from(message),
+                                untrusted::Input::from(&sig)) {
+        Ok(()) => Ok(true),
+        Err(webpki::Error::InvalidSignatureForPublicKey) |
+        Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => Ok(false),
+        Err(err) => Err(TLSError::WebPKIError(err)),
+    }
+}
+
+/// Return true if `old` and `new` have different end-entity
+/// certificates.  The rest of the chain, the keys, and any OCSP
+/// response or SCTs are not compared.
+///
+/// The comparison is constant-time in the certificate contents.
+pub fn cert_changed(old: &CertifiedKey, new: &CertifiedKey) -> bool {
+    match (old.end_entity_cert(), new.end_entity_cert()) {
+        (Ok(old), Ok(new)) => {
+            constant_time::verify_slices_are_equal(old.as_ref(), new.as_ref()).is_err()
+        }
+        (Err(()), Err(())) => false,
+        _ => true,
+    }
+}
+
+/// The kinds of public key a certificate can have, as far as
+/// choosing a `SignatureScheme` is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// An RSA key, of any size.
+    Rsa,
+
+    /// An ECDSA key on the NIST P-256 curve.
+    EcdsaP256,
+
+    /// An ECDSA key on the NIST P-384 curve.
+    EcdsaP384,
+
+    /// An ECDSA key on the NIST P-521 curve.
+    EcdsaP521,
+
+    /// An Ed25519 key.
+    Ed25519,
+}
+
+/// The `SignatureScheme`s which can be used with a key of type
+/// `key_type`, most preferred first.  The legacy SHA-1 schemes
+/// are not included.
+pub fn compatible_schemes_for_key_type(key_type: KeyType) -> &'static [SignatureScheme] {
+    match key_type {
+        KeyType::Rsa => ALL_RSA_SCHEMES,
+        KeyType::EcdsaP256 => &[SignatureScheme::ECDSA_NISTP256_SHA256],
+        KeyType::EcdsaP384 => &[SignatureScheme::ECDSA_NISTP384_SHA384],
+        KeyType::EcdsaP521 => &[SignatureScheme::ECDSA_NISTP521_SHA512],
+        KeyType::Ed25519 => &[SignatureScheme::ED25519],
+    }
+}
+
+/// The type of key which signs with `scheme`.
+///
+/// Returns None for `ECDSA_SHA1_Legacy`, which doesn't fix the
+/// curve, and for schemes whose keys `KeyType` doesn't cover.
+pub fn key_type_for_scheme(scheme: SignatureScheme) -> Option<KeyType> {
+    match scheme {
+        SignatureScheme::RSA_PKCS1_SHA1 |
+        SignatureScheme::RSA_PKCS1_SHA256 |
+        SignatureScheme::RSA_PKCS1_SHA384 |
+        SignatureScheme::RSA_PKCS1_SHA512 |
+        SignatureScheme::RSA_PSS_SHA256 |
+        SignatureScheme::RSA_PSS_SHA384 |
+        SignatureScheme::RSA_PSS_SHA512 => Some(KeyType::Rsa),
+        SignatureScheme::ECDSA_NISTP256_SHA256 => Some(KeyType::EcdsaP256),
+        SignatureScheme::ECDSA_NISTP384_SHA384 => Some(KeyType::EcdsaP384),
+        SignatureScheme::ECDSA_NISTP521_SHA512 => Some(KeyType::EcdsaP521),
+        SignatureScheme::ED25519 => Some(KeyType::Ed25519),
+        _ => None,
+    }
 }
 
 /// A `SigningKey` for RSA-PKCS1 or RSA-PSS
@@ -166,10 +650,25 @@ impl RSASigningKey {
 
 impl SigningKey for RSASigningKey {
     fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>> {
-        util::first_in_both(ALL_RSA_SCHEMES, offered)
+        util::first_in_both(self.supported_schemes(), offered)
             .map(|scheme| RSASigner::new(self.key.clone(), scheme))
     }
 
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        compatible_schemes_for_key_type(KeyType::Rsa)
+    }
+
+    fn key_type_name(&self) -> &'static str {
+        match self.key.public_modulus_len() * 8 {
+            1024 => "RSA-1024",
+            2048 => "RSA-2048",
+            3072 => "RSA-3072",
+            4096 => "RSA-4096",
+            8192 => "RSA-8192",
+            _ => "RSA",
+        }
+    }
+
     fn algorithm(&self) -> SignatureAlgorithm {
         SignatureAlgorithm::RSA
     }
@@ -202,12 +701,81 @@ impl Signer for RSASigner {
         let mut sig = vec![0; self.key.public_modulus_len()];
 
         let rng = ring::rand::SystemRandom::new();
-        let mut signer = signature::RSASigningState::new(self.key.clone())
-            .map_err(|_| TLSError::General("signing state creation failed".to_string()))?;
+        let mut signer = signature::RSASigningState::new(self.key.clone())?;
+        signer.sign(self.encoding, &rng, message, &mut sig)?;
+        Ok(sig)
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+static ALL_ECDSA_SCHEMES: &[SignatureScheme] = &[
+     SignatureScheme::ECDSA_NISTP384_SHA384,
+     SignatureScheme::ECDSA_NISTP256_SHA256,
+];
+
+/// A `SigningKey` for ECDSA, on the P-256 or P-384 curves.
+pub struct ECDSASigningKey {
+    key: Arc<ECDSAKeyPair>,
+    key_type: KeyType,
+}
 
-        signer.sign(self.encoding, &rng, message, &mut sig)
-            .map(|_| sig)
-            .map_err(|_| TLSError::General("signing failed".to_string()))
+impl ECDSASigningKey {
+    /// Make a new `ECDSASigningKey` from a DER encoding in PKCS#8
+    /// format, which must include the public key.
+    pub fn new(der: &key::PrivateKey) -> Result<ECDSASigningKey, ()> {
+        let input = untrusted::Input::from(&der.0);
+        ECDSAKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, input)
+            .map(|kp| (kp, KeyType::EcdsaP256))
+            .or_else(|_| {
+                ECDSAKeyPair::from_pkcs8(&signature::ECDSA_P384_SHA384_ASN1_SIGNING, input)
+                    .map(|kp| (kp, KeyType::EcdsaP384))
+            })
+            .map(|(kp, key_type)| {
+                ECDSASigningKey {
+                    key: Arc::new(kp),
+                    key_type,
+                }
+            })
+            .map_err(|_| ())
+    }
+}
+
+impl SigningKey for ECDSASigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<Signer>> {
+        let scheme = util::first_in_both(self.supported_schemes(), offered)?;
+        Some(Box::new(ECDSASigner { key: self.key.clone(), scheme }))
+    }
+
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        compatible_schemes_for_key_type(self.key_type)
+    }
+
+    fn key_type_name(&self) -> &'static str {
+        match self.key_type {
+            KeyType::EcdsaP256 => "ECDSA-P256",
+            KeyType::EcdsaP384 => "ECDSA-P384",
+            _ => "ECDSA",
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA
+    }
+}
+
+struct ECDSASigner {
+    key: Arc<ECDSAKeyPair>,
+    scheme: SignatureScheme,
+}
+
+impl Signer for ECDSASigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        let rng = ring::rand::SystemRandom::new();
+        let sig = self.key.sign(untrusted::Input::from(message), &rng)?;
+        Ok(sig.as_ref().to_vec())
     }
 
     fn get_scheme(&self) -> SignatureScheme {
@@ -1,5 +1,13 @@
-use std::io::{Read, Write, Result};
+use std::io::{self, Read, Write, Result};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use session::Session;
+use client::{ClientConfig, ClientSession};
+use server::{ServerConfig, ServerSession};
+use server::handy::HandshakeSemaphore;
+use error::TLSError;
+use webpki;
 
 /// This type implements `io::Read` and `io::Write`, encapsulating
 /// a Session `S` and an underlying blocking transport `T`, such as
@@ -68,6 +76,260 @@ impl<'a, S, T> Write for Stream<'a, S, T> where S: 'a + Session, T: 'a + Read +
     }
 }
 
+/// This type implements `io::Read` and `io::Write`, encapsulating
+/// and owning a Session `S` and an underlying blocking transport
+/// `T`, such as a socket.
+///
+/// This is like `Stream`, except it owns both halves, so it can be
+/// returned from functions and stored in other structs.
+pub struct TlsStream<S: Session, T: Read + Write> {
+    sess: S,
+    sock: T,
+}
+
+/// A `TlsStream` for the client side.
+pub type TlsClientStream<T> = TlsStream<ClientSession, T>;
+
+/// A `TlsStream` for the server side.
+pub type TlsServerStream<T> = TlsStream<ServerSession, T>;
+
+impl<S, T> TlsStream<S, T> where S: Session, T: Read + Write {
+    /// Make a new TlsStream taking the Session `sess` and socket-like
+    /// object `sock`.  This does not fail and does no IO.
+    pub fn new(sess: S, sock: T) -> TlsStream<S, T> {
+        TlsStream { sess, sock }
+    }
+
+    /// Get a reference to the session, for example to inspect
+    /// negotiated parameters.
+    pub fn session(&self) -> &S {
+        &self.sess
+    }
+
+    /// Get a mutable reference to the session.
+    pub fn session_mut(&mut self) -> &mut S {
+        &mut self.sess
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.sock
+    }
+
+    /// Get a mutable reference to the underlying transport.
+    ///
+    /// Reading from or writing to the transport directly will
+    /// corrupt the TLS session.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.sock
+    }
+
+    /// Take apart this stream, returning the session and transport.
+    pub fn into_inner(self) -> (S, T) {
+        (self.sess, self.sock)
+    }
+
+    fn as_stream(&mut self) -> Stream<'_, S, T> {
+        Stream::new(&mut self.sess, &mut self.sock)
+    }
+}
+
+/// Make a new `ClientSession` for `hostname`, and complete its
+/// handshake over the blocking transport `sock`.
+///
+/// TLS errors are returned as `io::ErrorKind::InvalidData` errors
+/// wrapping the `TLSError`.
+pub fn connect<T>(config: &Arc<ClientConfig>,
+                  hostname: webpki::DNSNameRef,
+                  sock: T) -> Result<TlsClientStream<T>>
+    where T: Read + Write
+{
+    handshake(ClientSession::new(config, hostname), sock)
+}
+
+/// Make a new `ServerSession`, and complete its handshake over the
+/// blocking transport `sock`.
+///
+/// TLS errors are returned as `io::ErrorKind::InvalidData` errors
+/// wrapping the `TLSError`.
+pub fn accept<T>(config: &Arc<ServerConfig>, sock: T) -> Result<TlsServerStream<T>>
+    where T: Read + Write
+{
+    handshake(ServerSession::new(config), sock)
+}
+
+/// Accepts TCP connections from a `TcpListener`, and completes a
+/// TLS handshake on each with `accept`.
+///
+/// `accept` may be called from several threads at once, to run
+/// several handshakes at once.  At most `max_pending_handshakes`
+/// handshakes run at a time: connections accepted beyond that are
+/// closed straight away, before anything is read from them, so a
+/// flood of handshakes can't use up every thread.  Each handshake
+/// must also finish within a timeout, so clients which stop sending
+/// can't hold a thread forever.
+pub struct TlsAcceptor {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    handshakes: Arc<HandshakeSemaphore>,
+    max_pending_handshakes: usize,
+    handshake_timeout: Option<Duration>,
+}
+
+/// How long `TlsAcceptor` gives each handshake, by default.
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl TlsAcceptor {
+    /// Make a new `TlsAcceptor`, accepting connections from
+    /// `listener` using `config`.
+    pub fn new(listener: TcpListener,
+               config: &Arc<ServerConfig>,
+               max_pending_handshakes: usize) -> TlsAcceptor {
+        TlsAcceptor {
+            listener,
+            config: config.clone(),
+            handshakes: HandshakeSemaphore::new(),
+            max_pending_handshakes,
+            handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+        }
+    }
+
+    /// Set how long each handshake may take, from accepting the
+    /// connection until the handshake completes.  None means no
+    /// limit.  The default is 30 seconds.
+    pub fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// The address we're listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The number of handshakes in progress.
+    pub fn pending_handshakes(&self) -> usize {
+        self.handshakes.in_progress()
+    }
+
+    /// Accept the next connection, and complete its handshake.
+    ///
+    /// If `max_pending_handshakes` handshakes are already in progress,
+    /// the connection is closed and this fails with a
+    /// `TLSError::TooManyHandshakes`.  If the handshake takes longer
+    /// than the handshake timeout, the connection is closed and this
+    /// fails with an `io::ErrorKind::TimedOut` error.  As for `accept`,
+    /// TLS errors are returned as `io::ErrorKind::InvalidData` errors
+    /// wrapping the `TLSError`.
+    pub fn accept(&self) -> Result<TlsServerStream<TcpStream>> {
+        let (sock, addr) = self.listener.accept()?;
+
+        let _permit = HandshakeSemaphore::try_acquire(&self.handshakes,
+                                                      Some(self.max_pending_handshakes))
+            .ok_or_else(|| {
+                debug!("Too many handshakes pending, dropping connection from {}", addr);
+                io::Error::new(io::ErrorKind::InvalidData, TLSError::TooManyHandshakes)
+            })?;
+
+        let deadline = match self.handshake_timeout {
+            Some(timeout) => Instant::now() + timeout,
+            None => return accept(&self.config, sock),
+        };
+
+        let mut sess = ServerSession::new(&self.config);
+        complete_handshake(&mut sess, &mut Deadline { sock: &sock, deadline })
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    debug!("Handshake with {} timed out", addr);
+                    io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")
+                }
+                _ => err,
+            })?;
+
+        sock.set_read_timeout(None)?;
+        sock.set_write_timeout(None)?;
+        Ok(TlsStream::new(sess, sock))
+    }
+}
+
+/// A `TcpStream` whose reads and writes time out at `deadline`.
+struct Deadline<'a> {
+    sock: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    fn set_timeouts(&self) -> Result<()> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+        }
+
+        let timeout = Some(self.deadline - now);
+        self.sock.set_read_timeout(timeout)?;
+        self.sock.set_write_timeout(timeout)
+    }
+}
+
+impl<'a> Read for Deadline<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.set_timeouts()?;
+        let mut sock = self.sock;
+        sock.read(buf)
+    }
+}
+
+impl<'a> Write for Deadline<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.set_timeouts()?;
+        let mut sock = self.sock;
+        sock.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut sock = self.sock;
+        sock.flush()
+    }
+}
+
+fn handshake<S, T>(mut sess: S, mut sock: T) -> Result<TlsStream<S, T>>
+    where S: Session, T: Read + Write
+{
+    complete_handshake(&mut sess, &mut sock)?;
+    Ok(TlsStream::new(sess, sock))
+}
+
+fn complete_handshake<S, T>(sess: &mut S, sock: &mut T) -> Result<()>
+    where S: Session, T: Read + Write
+{
+    while sess.is_handshaking() {
+        sess.complete_io(sock)?;
+    }
+
+    // Flush anything written at the end of the handshake,
+    // such as TLS1.3 session tickets.
+    while sess.wants_write() {
+        sess.write_tls(sock)?;
+    }
+
+    Ok(())
+}
+
+impl<S, T> Read for TlsStream<S, T> where S: Session, T: Read + Write {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.as_stream().read(buf)
+    }
+}
+
+impl<S, T> Write for TlsStream<S, T> where S: Session, T: Read + Write {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.as_stream().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.as_stream().flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Stream;
@@ -75,7 +75,7 @@ impl KeyExchange {
 
     fn decode_client_params(&self, kx_params: &[u8]) -> Option<ClientECDHParams> {
         let mut rd = Reader::init(kx_params);
-        let ecdh_params = ClientECDHParams::read(&mut rd).unwrap();
+        let ecdh_params = try_ret!(ClientECDHParams::read(&mut rd));
         if rd.any_left() {
             None
         } else {
@@ -154,6 +154,38 @@ impl PartialEq for SupportedCipherSuite {
 }
 
 impl SupportedCipherSuite {
+    /// The IANA-assigned value which identifies this suite on
+    /// the wire.
+    pub fn iana_value(&self) -> u16 {
+        self.suite.get_u16()
+    }
+
+    /// The IANA-registered name of this suite, for example
+    /// `"TLS_AES_128_GCM_SHA256"`.  This is None for suites rustls
+    /// doesn't implement, which can only come from a
+    /// `SupportedCipherSuite` made outside rustls.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self.suite {
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256 =>
+                "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+            CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 =>
+                "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 =>
+                "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 =>
+                "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256 =>
+                "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384 =>
+                "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256 => "TLS_CHACHA20_POLY1305_SHA256",
+            CipherSuite::TLS13_AES_256_GCM_SHA384 => "TLS_AES_256_GCM_SHA384",
+            CipherSuite::TLS13_AES_128_GCM_SHA256 => "TLS_AES_128_GCM_SHA256",
+            _ => return None,
+        };
+        Some(name)
+    }
+
     /// Which hash function to use with this suite.
     pub fn get_hash(&self) -> &'static ring::digest::Algorithm {
         match self.hash {
@@ -443,4 +475,69 @@ mod test {
         assert_eq!(chosen.unwrap(),
                    &super::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384);
     }
+
+    #[test]
+    fn test_names() {
+        use msgs::enums::{HashAlgorithm, SignatureAlgorithm};
+        use msgs::handshake::KeyExchangeAlgorithm;
+
+        for suite in super::ALL_CIPHERSUITES.iter() {
+            assert_eq!(suite.iana_value(), suite.suite.get_u16());
+            assert!(suite.name().unwrap().starts_with("TLS_"));
+        }
+
+        assert_eq!(super::TLS13_AES_128_GCM_SHA256.iana_value(), 0x1301);
+        assert_eq!(super::TLS13_AES_128_GCM_SHA256.name(), Some("TLS_AES_128_GCM_SHA256"));
+        assert_eq!(super::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384.iana_value(), 0xc030);
+        assert_eq!(super::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384.name(),
+                   Some("TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"));
+
+        let unknown = super::SupportedCipherSuite {
+            suite: CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA,
+            kx: KeyExchangeAlgorithm::BulkOnly,
+            sign: SignatureAlgorithm::Anonymous,
+            bulk: super::BulkAlgorithm::AES_128_GCM,
+            hash: HashAlgorithm::SHA256,
+            enc_key_len: 16,
+            fixed_iv_len: 12,
+            explicit_nonce_len: 0,
+        };
+        assert_eq!(unknown.name(), None);
+    }
+
+    #[test]
+    fn test_malformed_client_kx_params() {
+        use msgs::enums::NamedGroup;
+        let kx = super::KeyExchange::start_ecdhe(NamedGroup::X25519).unwrap();
+        assert!(!kx.check_client_params(&[]));
+        assert!(!kx.check_client_params(&[ 32, 1, 2, 3 ]));
+        assert!(kx.server_complete(&[]).is_none());
+    }
+
+    #[test]
+    fn test_ecdhe_agreement() {
+        use msgs::base::PayloadU8;
+        use msgs::codec::Codec;
+        use msgs::handshake::{ClientECDHParams, ServerECDHParams};
+        use msgs::handshake::{NamedGroups, SupportedGroups};
+
+        for group in NamedGroups::supported() {
+            let server = super::KeyExchange::start_ecdhe(group).unwrap();
+
+            // every exchange uses a fresh key pair
+            let other = super::KeyExchange::start_ecdhe(group).unwrap();
+            assert_ne!(server.pubkey, other.pubkey);
+
+            let server_params = ServerECDHParams::new(&group, &server.pubkey);
+            let client = super::KeyExchange::client_ecdhe(&server_params.get_encoding())
+                .unwrap();
+
+            let client_params = ClientECDHParams { public: PayloadU8::new(client.pubkey) };
+            let server_result = server.server_complete(&client_params.get_encoding())
+                .unwrap();
+
+            assert!(!client.premaster_secret.is_empty());
+            assert_eq!(client.premaster_secret, server_result.premaster_secret);
+        }
+    }
 }
@@ -1,9 +1,11 @@
 
 use server::ProducesTickets;
+use msgs::codec::{self, Codec, Reader};
 use rand;
 
+use std::fmt;
 use std::mem;
-use std::sync::{Mutex, Arc};
+use std::sync::{Mutex, Arc, RwLock};
 use std::time;
 use ring::aead;
 
@@ -16,6 +18,30 @@ pub fn timebase() -> u64 {
         .as_secs()
 }
 
+/// The format of tickets made by `AEADTicketer` and `TicketKey`.
+/// This is the first byte of each ticket, in the clear; tickets made
+/// in another format are rejected without trying to decrypt them, so
+/// the client just does a full handshake.  Change this whenever
+/// either format (or the encoding of the sessions inside) changes.
+const TICKET_VERSION: u8 = 1;
+
+/// The additional data for a ticket: its cleartext `header`, then
+/// whatever the caller binds it to.
+fn ticket_aad(header: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut out = header.to_vec();
+    out.extend_from_slice(aad);
+    out
+}
+
+/// Split off the `TICKET_VERSION` byte at the start of `ticket`,
+/// if it's there.
+fn strip_version(ticket: &[u8]) -> Option<&[u8]> {
+    match ticket.split_first() {
+        Some((&TICKET_VERSION, rest)) => Some(rest),
+        _ => None,
+    }
+}
+
 /// This is a `ProducesTickets` implementation which uses
 /// any *ring* `aead::Algorithm` to encrypt and authentication
 /// the ticket payload.  It does not enforce any lifetime
@@ -62,25 +88,36 @@ impl ProducesTickets for AEADTicketer {
 
     /// Encrypt `message` and return the ciphertext.
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        self.encrypt_with_aad(message, &[])
+    }
+
+    /// Decrypt `ciphertext` and recover the original message.
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_aad(ciphertext, &[])
+    }
+
+    fn encrypt_with_aad(&self, message: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
         // Random nonce, because a counter is a privacy leak.
         let mut nonce = [0u8; 12];
         rand::fill_random(&mut nonce);
 
         let mut out = Vec::new();
+        out.push(TICKET_VERSION);
         out.extend_from_slice(&nonce);
+        let header_len = out.len();
         out.extend_from_slice(message);
-        out.resize(nonce.len() + message.len() + self.alg.tag_len(), 0u8);
+        out.resize(header_len + message.len() + self.alg.tag_len(), 0u8);
 
         let rc = aead::seal_in_place(&self.enc,
                                      &nonce,
-                                     &[],
-                                     &mut out[nonce.len()..],
+                                     &ticket_aad(&[TICKET_VERSION], aad),
+                                     &mut out[header_len..],
                                      self.alg.tag_len());
         if rc.is_err() { None } else { Some(out) }
     }
 
-    /// Decrypt `ciphertext` and recover the original message.
-    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    fn decrypt_with_aad(&self, ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        let ciphertext = strip_version(ciphertext)?;
         let nonce_len = self.alg.nonce_len();
         let tag_len = self.alg.tag_len();
 
@@ -92,7 +129,11 @@ impl ProducesTickets for AEADTicketer {
         let mut out = Vec::new();
         out.extend_from_slice(&ciphertext[nonce_len..]);
 
-        let plain_len = match aead::open_in_place(&self.dec, nonce, &[], 0, &mut out) {
+        let plain_len = match aead::open_in_place(&self.dec,
+                                                  nonce,
+                                                  &ticket_aad(&[TICKET_VERSION], aad),
+                                                  0,
+                                                  &mut out) {
             Ok(plaintext) => plaintext.len(),
             Err(..) => { return None; }
         };
@@ -162,23 +203,31 @@ impl ProducesTickets for TicketSwitcher {
     }
 
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        self.encrypt_with_aad(message, &[])
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_aad(ciphertext, &[])
+    }
+
+    fn encrypt_with_aad(&self, message: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
         self.maybe_roll();
 
         self.state
             .lock()
             .unwrap()
             .current
-            .encrypt(message)
+            .encrypt_with_aad(message, aad)
     }
 
-    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    fn decrypt_with_aad(&self, ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
         self.maybe_roll();
 
         let state = self.state.lock().unwrap();
-        let rc = state.current.decrypt(ciphertext);
+        let rc = state.current.decrypt_with_aad(ciphertext, aad);
 
         if rc.is_none() && state.previous.is_some() {
-            state.previous.as_ref().unwrap().decrypt(ciphertext)
+            state.previous.as_ref().unwrap().decrypt_with_aad(ciphertext, aad)
         } else {
             rc
         }
@@ -201,3 +250,366 @@ impl Ticketer {
         Arc::new(TicketSwitcher::new(6 * 60 * 60, generate_inner))
     }
 }
+
+/// A ticket encryption key which can be shared between servers.
+///
+/// Each key has an `id`, which is sent in the clear at the start of
+/// each ticket so the right key can be found to decrypt it.  The
+/// `id` must be unique among the keys in use by a cluster.
+#[derive(Clone)]
+pub struct TicketKey {
+    id: u32,
+    key: [u8; 32],
+}
+
+impl TicketKey {
+    /// Make a `TicketKey` from the given `id` and key material.
+    pub fn new(id: u32, key: [u8; 32]) -> TicketKey {
+        TicketKey {
+            id,
+            key,
+        }
+    }
+
+    /// Make a `TicketKey` with the given `id` and a random key.
+    pub fn generate(id: u32) -> TicketKey {
+        let mut key = [0u8; 32];
+        rand::fill_random(&mut key);
+        TicketKey::new(id, key)
+    }
+
+    /// This key's identifier.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Encode this key for distribution to other servers.  The
+    /// result contains secret key material.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.get_encoding()
+    }
+
+    /// Decode a key produced by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<TicketKey> {
+        TicketKey::read_bytes(bytes)
+    }
+
+    fn seal(&self, message: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        let alg = &aead::CHACHA20_POLY1305;
+        let key = aead::SealingKey::new(alg, &self.key).ok()?;
+
+        let mut nonce = [0u8; 12];
+        rand::fill_random(&mut nonce);
+
+        let mut out = Vec::new();
+        out.push(TICKET_VERSION);
+        codec::encode_u32(self.id, &mut out);
+        out.extend_from_slice(&nonce);
+        let header_len = out.len();
+        out.extend_from_slice(message);
+        out.resize(header_len + message.len() + alg.tag_len(), 0u8);
+
+        // The version and key id are authenticated, as well as readable.
+        let (header, body) = out.split_at_mut(header_len);
+        aead::seal_in_place(&key, &nonce, &ticket_aad(&header[..5], aad), body, alg.tag_len())
+            .ok()?;
+        Some(out)
+    }
+
+    fn open(&self, ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        let alg = &aead::CHACHA20_POLY1305;
+        let header_len = 5 + alg.nonce_len();
+        if ciphertext.len() < header_len + alg.tag_len() {
+            return None;
+        }
+
+        let key = aead::OpeningKey::new(alg, &self.key).ok()?;
+        let (header, body) = ciphertext.split_at(header_len);
+        let mut out = body.to_vec();
+
+        let plain_len = aead::open_in_place(&key, &header[5..], &ticket_aad(&header[..5], aad),
+                                            0, &mut out)
+            .ok()?
+            .len();
+        out.truncate(plain_len);
+        Some(out)
+    }
+}
+
+impl fmt::Debug for TicketKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't print the key material.
+        f.debug_struct("TicketKey")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Codec for TicketKey {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        codec::encode_u32(self.id, bytes);
+        bytes.extend_from_slice(&self.key);
+    }
+
+    fn read(r: &mut Reader) -> Option<TicketKey> {
+        let id = codec::read_u32(r)?;
+        let mut key = [0u8; 32];
+        key.copy_from_slice(r.take(32)?);
+        Some(TicketKey::new(id, key))
+    }
+}
+
+/// The set of ticket keys in use by a `ClusterTicketRotator`.
+///
+/// The `current` key is used to encrypt new tickets.  The
+/// `previous` key, if any, can only be used to decrypt tickets.
+#[derive(Clone, Debug)]
+pub struct TicketKeySet {
+    current: TicketKey,
+    previous: Option<TicketKey>,
+}
+
+impl TicketKeySet {
+    /// Make a new set, using `current` for new tickets.
+    pub fn new(current: TicketKey) -> TicketKeySet {
+        TicketKeySet {
+            current,
+            previous: None,
+        }
+    }
+
+    /// The key used to encrypt new tickets.
+    pub fn current(&self) -> &TicketKey {
+        &self.current
+    }
+
+    /// The decryption-only key, if any.
+    pub fn previous(&self) -> Option<&TicketKey> {
+        self.previous.as_ref()
+    }
+
+    /// Make `new_key` current, demoting the current key to
+    /// decryption-only.  Any previous decryption-only key is
+    /// erased.
+    pub fn rotate(&mut self, new_key: TicketKey) {
+        self.previous = Some(mem::replace(&mut self.current, new_key));
+    }
+
+    /// Encode the whole set; eg. to bring a new server up to date
+    /// with the rest of the cluster.  The result contains secret
+    /// key material.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.get_encoding()
+    }
+
+    /// Decode a set produced by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<TicketKeySet> {
+        TicketKeySet::read_bytes(bytes)
+    }
+
+    fn find(&self, id: u32) -> Option<&TicketKey> {
+        if self.current.id == id {
+            Some(&self.current)
+        } else {
+            self.previous
+                .as_ref()
+                .and_then(|prev| if prev.id == id { Some(prev) } else { None })
+        }
+    }
+}
+
+impl Codec for TicketKeySet {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.current.encode(bytes);
+        match self.previous {
+            Some(ref prev) => {
+                codec::encode_u8(1, bytes);
+                prev.encode(bytes);
+            }
+            None => codec::encode_u8(0, bytes),
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<TicketKeySet> {
+        let current = TicketKey::read(r)?;
+        let previous = match codec::read_u8(r)? {
+            0 => None,
+            1 => Some(TicketKey::read(r)?),
+            _ => return None,
+        };
+
+        if r.any_left() {
+            return None;
+        }
+
+        Some(TicketKeySet {
+            current,
+            previous,
+        })
+    }
+}
+
+/// A ticketer for clusters of servers sharing ticket keys, so that
+/// a session established with one server can be resumed on another.
+///
+/// Keys are held in a `TicketKeySet` shared through an
+/// `Arc<RwLock<_>>`.  Unlike `TicketSwitcher`, this does not
+/// generate keys itself: one node should periodically make a new
+/// `TicketKey` and broadcast it (along with `TicketKeySet::to_bytes`
+/// for nodes that join later) to all servers, each of which then
+/// calls `rotate_key`.  How keys are distributed is up to you.
+pub struct ClusterTicketRotator {
+    keys: Arc<RwLock<TicketKeySet>>,
+    lifetime: u32,
+}
+
+impl ClusterTicketRotator {
+    /// Make a new rotator using `keys`.  `lifetime` is the
+    /// advertised ticket lifetime in seconds; keys should be
+    /// rotated at least this often.
+    pub fn new(keys: Arc<RwLock<TicketKeySet>>, lifetime: u32) -> ClusterTicketRotator {
+        ClusterTicketRotator {
+            keys,
+            lifetime,
+        }
+    }
+
+    /// The shared key set.
+    pub fn keys(&self) -> Arc<RwLock<TicketKeySet>> {
+        self.keys.clone()
+    }
+
+    /// Start using `new_key` for new tickets.  The current key
+    /// is only used for decryption hereafter.
+    pub fn rotate_key(&self, new_key: TicketKey) {
+        self.keys.write()
+            .unwrap()
+            .rotate(new_key);
+    }
+}
+
+impl ProducesTickets for ClusterTicketRotator {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn get_lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
+    fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        self.encrypt_with_aad(message, &[])
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_aad(ciphertext, &[])
+    }
+
+    fn encrypt_with_aad(&self, message: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        self.keys.read()
+            .unwrap()
+            .current
+            .seal(message, aad)
+    }
+
+    fn decrypt_with_aad(&self, ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        let id = codec::decode_u32(strip_version(ciphertext)?.get(..4)?)?;
+        self.keys.read()
+            .unwrap()
+            .find(id)?
+            .open(ciphertext, aad)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rotator() -> ClusterTicketRotator {
+        let keys = TicketKeySet::new(TicketKey::generate(1));
+        ClusterTicketRotator::new(Arc::new(RwLock::new(keys)), 3600)
+    }
+
+    #[test]
+    fn cluster_rotator_roundtrip() {
+        let t = rotator();
+        let ticket = t.encrypt(b"hello").unwrap();
+        assert_eq!(t.decrypt(&ticket), Some(b"hello".to_vec()));
+
+        let mut bad = ticket.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        assert_eq!(t.decrypt(&bad), None);
+        assert_eq!(t.decrypt(&ticket[..10]), None);
+        assert_eq!(t.decrypt(&[]), None);
+    }
+
+    #[test]
+    fn cluster_rotator_demotes_old_key() {
+        let t = rotator();
+        let old = t.encrypt(b"old").unwrap();
+
+        t.rotate_key(TicketKey::generate(2));
+        let new = t.encrypt(b"new").unwrap();
+        assert_eq!(t.decrypt(&old), Some(b"old".to_vec()));
+        assert_eq!(t.decrypt(&new), Some(b"new".to_vec()));
+
+        t.rotate_key(TicketKey::generate(3));
+        assert_eq!(t.decrypt(&old), None);
+        assert_eq!(t.decrypt(&new), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn cluster_rotator_shares_keys() {
+        let a = rotator();
+        let bytes = a.keys().read().unwrap().to_bytes();
+        let b = ClusterTicketRotator::new(
+            Arc::new(RwLock::new(TicketKeySet::from_bytes(&bytes).unwrap())), 3600);
+
+        let ticket = a.encrypt(b"hello").unwrap();
+        assert_eq!(b.decrypt(&ticket), Some(b"hello".to_vec()));
+
+        let new_key = TicketKey::generate(2);
+        let new_key_bytes = new_key.to_bytes();
+        a.rotate_key(new_key);
+        b.rotate_key(TicketKey::from_bytes(&new_key_bytes).unwrap());
+
+        let ticket = b.encrypt(b"again").unwrap();
+        assert_eq!(a.decrypt(&ticket), Some(b"again".to_vec()));
+    }
+
+    #[test]
+    fn tickets_are_versioned() {
+        let ticketers: Vec<Box<ProducesTickets>> = vec![
+            Box::new(AEADTicketer::new()),
+            Box::new(rotator()),
+        ];
+
+        for t in &ticketers {
+            let ticket = t.encrypt(b"hello").unwrap();
+            assert_eq!(ticket[0], TICKET_VERSION);
+            assert_eq!(t.decrypt(&ticket), Some(b"hello".to_vec()));
+
+            // Another format isn't decrypted, even if it would work.
+            let mut other = ticket.clone();
+            other[0] = TICKET_VERSION + 1;
+            assert_eq!(t.decrypt(&other), None);
+            assert_eq!(t.decrypt(&ticket[1..]), None);
+            assert_eq!(t.decrypt(&ticket[..1]), None);
+        }
+    }
+
+    #[test]
+    fn ticket_key_set_rejects_bad_encodings() {
+        let mut set = TicketKeySet::new(TicketKey::generate(1));
+        set.rotate(TicketKey::generate(2));
+        let mut bytes = set.to_bytes();
+        assert!(TicketKeySet::from_bytes(&bytes).is_some());
+
+        bytes.push(0);
+        assert!(TicketKeySet::from_bytes(&bytes).is_none());
+        bytes.truncate(bytes.len() - 2);
+        assert!(TicketKeySet::from_bytes(&bytes).is_none());
+        assert!(TicketKey::from_bytes(&[0u8; 35]).is_none());
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use msgs::enums::HandshakeType;
+use msgs::message::{Message, MessagePayload};
+
+/// Which way a handshake message went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// We sent the message to the peer.
+    Sent,
+
+    /// We received the message from the peer.
+    Received,
+}
+
+/// One handshake message sent or received by a session, as
+/// returned by `get_handshake_transcript`.
+#[derive(Debug, Clone)]
+pub struct HandshakeMessageSummary {
+    /// Which way the message went.
+    pub direction: Direction,
+
+    /// The message's type.
+    pub message_type: HandshakeType,
+
+    /// The length of the message, including its four byte
+    /// header but not any record framing.
+    pub length: usize,
+
+    /// When the message was sent or processed.
+    pub timestamp: Instant,
+}
+
+/// The handshake messages a session has sent and received, in order.
+#[derive(Debug, Default)]
+pub struct Transcript {
+    messages: Vec<HandshakeMessageSummary>,
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript::default()
+    }
+
+    /// Add `msg` if it is a handshake message.
+    pub fn record(&mut self, direction: Direction, msg: &Message) {
+        if let MessagePayload::Handshake(ref hs) = msg.payload {
+            self.messages.push(HandshakeMessageSummary {
+                direction,
+                message_type: hs.typ,
+                length: msg.payload.length(),
+                timestamp: Instant::now(),
+            });
+        }
+    }
+
+    pub fn messages(&self) -> Vec<HandshakeMessageSummary> {
+        self.messages.clone()
+    }
+}
@@ -8,3 +8,35 @@ pub fn first_in_both<T: Clone + PartialEq>(prefs: &[T], avail: &[T]) -> Option<T
 
     None
 }
+
+/// Does `first_bytes`, the start of a stream, look like a TLS
+/// record?  This is for servers that accept TLS and plaintext
+/// on the same port, and need to tell which they've been sent.
+///
+/// The record's content type must be change_cipher_spec, alert,
+/// handshake or application_data, and its version must be SSLv3
+/// or a TLS version up to TLS1.3.  Up to three bytes are needed to
+/// decide: this returns None if there are too few bytes to say
+/// yet, in which case read more and try again.
+///
+/// Nothing is consumed: the bytes should be peeked, or passed on
+/// to the session afterwards.
+pub fn detect_tls(first_bytes: &[u8]) -> Option<bool> {
+    // change_cipher_spec(20) to application_data(23).
+    match first_bytes.first() {
+        None => return None,
+        Some(ty) if !(0x14..=0x17).contains(ty) => return Some(false),
+        _ => {}
+    }
+
+    // Clients often put TLS1.0 in their first record, whatever
+    // version they support; TLS1.3 records claim to be TLS1.2.
+    match first_bytes.get(1) {
+        None => return None,
+        Some(&major) if major != 0x03 => return Some(false),
+        _ => {}
+    }
+
+    first_bytes.get(2)
+        .map(|&minor| minor <= 0x04)
+}
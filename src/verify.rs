@@ -1,5 +1,8 @@
 use webpki;
 use untrusted;
+use ring;
+use ring::der;
+use ring::digest;
 use sct;
 use std;
 use std::sync::Arc;
@@ -7,9 +10,13 @@ use std::sync::Arc;
 use key::Certificate;
 use msgs::handshake::DigitallySignedStruct;
 use msgs::handshake::SCTList;
+use msgs::base::PayloadU16;
+use msgs::codec::{self, Codec, Reader};
 use msgs::enums::SignatureScheme;
 use error::TLSError;
 use anchors::{DistinguishedNames, RootCertStore};
+#[cfg(feature = "dangerous_configuration")]
+use crl::CertificateRevocationList;
 
 type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 
@@ -38,7 +45,10 @@ static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
 /// means their origins can be precisely determined by looking
 /// for their `assertion` constructors.
 pub struct HandshakeSignatureValid(());
-impl HandshakeSignatureValid { pub fn assertion() -> Self { Self { 0: () } } }
+impl HandshakeSignatureValid {
+    /// Make a `HandshakeSignatureValid`
+    pub fn assertion() -> Self { Self { 0: () } }
+}
 
 pub struct FinishedMessageVerified(());
 impl FinishedMessageVerified { pub fn assertion() -> Self { Self { 0: () } } }
@@ -57,6 +67,37 @@ impl ClientCertVerified {
     pub fn assertion() -> Self { Self { 0: () } }
 }
 
+/// The client certificate chain of a `ServerSession`, which the
+/// configured `ClientCertVerifier` accepted.
+///
+/// Unlike `ClientCertVerified`, only rustls can make one of these:
+/// see `ServerSession::get_verified_client_cert_chain`.  So a
+/// function which only makes sense for authenticated clients can
+/// take one as a parameter, rather than checking for itself.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedClientCertChain<'a> {
+    chain: &'a [Certificate],
+}
+
+impl<'a> VerifiedClientCertChain<'a> {
+    /// `chain` must be non-empty, and have been accepted by a
+    /// `ClientCertVerifier`.
+    pub(crate) fn new(chain: &'a [Certificate]) -> VerifiedClientCertChain<'a> {
+        debug_assert!(!chain.is_empty());
+        VerifiedClientCertChain { chain }
+    }
+
+    /// The client's end-entity certificate.
+    pub fn end_entity(&self) -> &'a Certificate {
+        &self.chain[0]
+    }
+
+    /// The whole chain, starting with the end-entity certificate.
+    pub fn certificates(&self) -> &'a [Certificate] {
+        self.chain
+    }
+}
+
 /// Something that can verify a server certificate chain
 pub trait ServerCertVerifier : Send + Sync {
     /// Verify a the certificate chain `presented_certs` against the roots
@@ -67,6 +108,22 @@ pub trait ServerCertVerifier : Send + Sync {
                           presented_certs: &[Certificate],
                           dns_name: webpki::DNSNameRef,
                           ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError>;
+
+    /// Verify `presented_certs` as `verify_server_cert` does, but
+    /// without checking which names the top certificate is valid
+    /// for.  This is used instead of `verify_server_cert` when
+    /// `DangerousClientConfig::set_allow_domain_fronting` is set.
+    ///
+    /// The default implementation fails, so that verifiers which
+    /// don't know about this can't be made to skip the name check.
+    #[cfg(feature = "dangerous_configuration")]
+    fn verify_server_cert_ignoring_name(&self,
+                                        roots: &RootCertStore,
+                                        presented_certs: &[Certificate],
+                                        ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let _ = (roots, presented_certs, ocsp_response);
+        Err(TLSError::General("certificate verifier does not support domain fronting".to_string()))
+    }
 }
 
 /// Something that can verify a client certificate chain
@@ -100,9 +157,39 @@ impl ServerCertVerifier for WebPKIVerifier {
                           dns_name: webpki::DNSNameRef,
                           ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
         let (cert, chain, trustroots) = prepare(roots, presented_certs)?;
+        let cert = self.verify_chain(cert, &chain, &trustroots, ocsp_response)?;
+
+        cert.verify_is_valid_for_dns_name(dns_name)
+            .map_err(TLSError::WebPKIError)
+            .map(|_| ServerCertVerified::assertion())
+    }
+
+    #[cfg(feature = "dangerous_configuration")]
+    fn verify_server_cert_ignoring_name(&self,
+                                        roots: &RootCertStore,
+                                        presented_certs: &[Certificate],
+                                        ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let (cert, chain, trustroots) = prepare(roots, presented_certs)?;
+        self.verify_chain(cert, &chain, &trustroots, ocsp_response)
+            .map(|_| ServerCertVerified::assertion())
+    }
+}
+
+impl WebPKIVerifier {
+    pub fn new() -> WebPKIVerifier {
+        WebPKIVerifier {
+            time: try_now,
+        }
+    }
+
+    fn verify_chain<'a>(&self,
+                        cert: webpki::EndEntityCert<'a>,
+                        chain: &[untrusted::Input],
+                        trustroots: &[webpki::TrustAnchor],
+                        ocsp_response: &[u8]) -> Result<webpki::EndEntityCert<'a>, TLSError> {
         let now = (self.time)()?;
         let cert = cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS,
-                &webpki::TLSServerTrustAnchors(&trustroots), &chain, now)
+                &webpki::TLSServerTrustAnchors(trustroots), chain, now)
             .map_err(TLSError::WebPKIError)
             .map(|_| cert)?;
 
@@ -110,17 +197,74 @@ impl ServerCertVerifier for WebPKIVerifier {
             debug!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
         }
 
-        cert.verify_is_valid_for_dns_name(dns_name)
-            .map_err(TLSError::WebPKIError)
-            .map(|_| ServerCertVerified::assertion())
+        Ok(cert)
     }
 }
 
-impl WebPKIVerifier {
-    pub fn new() -> WebPKIVerifier {
-        WebPKIVerifier {
-            time: try_now,
+/// A `ServerCertVerifier` which additionally checks the server's
+/// certificate against certificate revocation lists.
+///
+/// The chain is first verified by the wrapped verifier.  Then the
+/// end-entity certificate is checked against each CRL: this fails
+/// if it is revoked, or if a CRL from its issuer is not current.
+/// Certificates whose issuer has no CRL here are not checked.
+#[cfg(feature = "dangerous_configuration")]
+pub struct CrlCertVerifier {
+    inner: Arc<ServerCertVerifier>,
+    crls: Vec<CertificateRevocationList>,
+}
+
+#[cfg(feature = "dangerous_configuration")]
+impl CrlCertVerifier {
+    /// Verify chains as normal (using webpki and the configured
+    /// roots), and then check them against `crls`.
+    pub fn new(crls: Vec<CertificateRevocationList>) -> CrlCertVerifier {
+        CrlCertVerifier::wrap(Arc::new(WebPKIVerifier::new()), crls)
+    }
+
+    /// Verify chains with `inner`, and then check them against `crls`.
+    pub fn wrap(inner: Arc<ServerCertVerifier>,
+                crls: Vec<CertificateRevocationList>) -> CrlCertVerifier {
+        CrlCertVerifier { inner, crls }
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+impl ServerCertVerifier for CrlCertVerifier {
+    fn verify_server_cert(&self,
+                          roots: &RootCertStore,
+                          presented_certs: &[Certificate],
+                          dns_name: webpki::DNSNameRef,
+                          ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert(roots, presented_certs,
+                                                     dns_name, ocsp_response)?;
+        self.check_crls(presented_certs)?;
+        Ok(verified)
+    }
+
+    fn verify_server_cert_ignoring_name(&self,
+                                        roots: &RootCertStore,
+                                        presented_certs: &[Certificate],
+                                        ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert_ignoring_name(roots, presented_certs,
+                                                                   ocsp_response)?;
+        self.check_crls(presented_certs)?;
+        Ok(verified)
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+impl CrlCertVerifier {
+    fn check_crls(&self, presented_certs: &[Certificate]) -> Result<(), TLSError> {
+        let end_entity = presented_certs.first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let now = std::time::SystemTime::now();
+
+        for crl in &self.crls {
+            crl.check_certificate(end_entity, now)?;
         }
+
+        Ok(())
     }
 }
 
@@ -150,6 +294,50 @@ fn prepare<'a, 'b>(roots: &'b RootCertStore, presented_certs: &'a [Certificate])
     Ok((cert, chain, trustroots))
 }
 
+/// Verify a certificate chain outside of a TLS handshake.
+///
+/// `chain` is the end-entity certificate followed by any intermediate
+/// certificates; it must chain to one of `trust_anchors` and be valid
+/// at `now`.  The certificate is validated as a TLS server certificate.
+/// If `server_name` is given, the end-entity certificate must also be
+/// valid for that DNS name.
+pub fn verify_certificate_chain(chain: &[Certificate],
+                                trust_anchors: &[webpki::TrustAnchor],
+                                now: std::time::SystemTime,
+                                server_name: Option<&str>) -> Result<(), TLSError> {
+    if chain.is_empty() {
+        return Err(TLSError::NoCertificatesPresented);
+    }
+
+    let name = match server_name {
+        Some(name) => Some(webpki::DNSNameRef::try_from_ascii_str(name)
+            .map_err(|_| TLSError::InvalidDNSName(name.to_string()))?),
+        None => None,
+    };
+
+    let now = webpki::Time::try_from(now)
+        .map_err(|_| TLSError::FailedToGetCurrentTime)?;
+
+    let cert = webpki::EndEntityCert::from(untrusted::Input::from(&chain[0].0))
+        .map_err(TLSError::WebPKIError)?;
+    let intermediates: Vec<untrusted::Input> = chain.iter()
+        .skip(1)
+        .map(|cert| untrusted::Input::from(&cert.0))
+        .collect();
+
+    cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS,
+                                         &webpki::TLSServerTrustAnchors(trust_anchors),
+                                         &intermediates,
+                                         now)
+        .map_err(TLSError::WebPKIError)?;
+
+    match name {
+        Some(name) => cert.verify_is_valid_for_dns_name(name)
+            .map_err(TLSError::WebPKIError),
+        None => Ok(()),
+    }
+}
+
 fn try_now() -> Result<webpki::Time, TLSError> {
     webpki::Time::try_from(std::time::SystemTime::now())
         .map_err( |_ | TLSError::FailedToGetCurrentTime)
@@ -227,6 +415,36 @@ impl ClientCertVerifier for AllowAnyAnonymousOrAuthenticatedClient {
     }
 }
 
+/// A `ClientCertVerifier` which always offers client authentication,
+/// and decides itself whether it is mandatory, but otherwise defers
+/// to `inner`.  This is how `ServerConfig::with_optional_client_auth`
+/// and `with_required_client_auth` work.
+pub(crate) struct ClientAuthPolicy {
+    inner: Arc<ClientCertVerifier>,
+    mandatory: bool,
+}
+
+impl ClientAuthPolicy {
+    pub(crate) fn new(inner: Arc<ClientCertVerifier>, mandatory: bool) -> ClientAuthPolicy {
+        ClientAuthPolicy { inner, mandatory }
+    }
+}
+
+impl ClientCertVerifier for ClientAuthPolicy {
+    fn offer_client_auth(&self) -> bool { true }
+
+    fn client_auth_mandatory(&self) -> bool { self.mandatory }
+
+    fn client_auth_root_subjects(&self) -> DistinguishedNames {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(&self, presented_certs: &[Certificate])
+                          -> Result<ClientCertVerified, TLSError> {
+        self.inner.verify_client_cert(presented_certs)
+    }
+}
+
 /// Turns off client authentication.
 pub struct NoClientAuth;
 
@@ -339,29 +557,83 @@ fn convert_alg_tls13(scheme: SignatureScheme)
     }
 }
 
-pub fn verify_tls13(cert: &Certificate,
-                    dss: &DigitallySignedStruct,
-                    handshake_hash: &[u8],
-                    context_string_with_0: &[u8])
-                    -> Result<HandshakeSignatureValid, TLSError> {
+fn verify_tls13_message(message: &[u8],
+                        cert: &Certificate,
+                        dss: &DigitallySignedStruct)
+                        -> Result<HandshakeSignatureValid, TLSError> {
     let alg = convert_alg_tls13(dss.scheme)?;
 
-    let mut msg = Vec::new();
-    msg.resize(64, 0x20u8);
-    msg.extend_from_slice(context_string_with_0);
-    msg.extend_from_slice(handshake_hash);
-
     let cert_in = untrusted::Input::from(&cert.0);
     let cert = webpki::EndEntityCert::from(cert_in)
         .map_err(TLSError::WebPKIError)?;
 
     cert.verify_signature(alg,
-                          untrusted::Input::from(&msg),
+                          untrusted::Input::from(message),
                           untrusted::Input::from(&dss.sig.0))
         .map_err(TLSError::WebPKIError)
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+pub fn verify_tls13(verifier: &SignatureVerifier,
+                    cert: &Certificate,
+                    dss: &DigitallySignedStruct,
+                    handshake_hash: &[u8],
+                    context_string_with_0: &[u8])
+                    -> Result<HandshakeSignatureValid, TLSError> {
+    let mut msg = Vec::new();
+    msg.resize(64, 0x20u8);
+    msg.extend_from_slice(context_string_with_0);
+    msg.extend_from_slice(handshake_hash);
+
+    verifier.verify_tls13_signature(&msg, cert, dss)
+}
+
+/// Something that can verify the signatures made by peers on
+/// handshake messages: the TLS1.2 `ServerKeyExchange`, and
+/// `CertificateVerify` messages.  The default is `WebPKIVerifier`,
+/// which uses webpki and *ring*.  Something else might use a
+/// different cryptography implementation.
+///
+/// In each case `cert` is the peer's end-entity certificate, which
+/// has already been verified, and `dss` has the signature scheme and
+/// signature.
+pub trait SignatureVerifier : Send + Sync {
+    /// Verify a TLS1.2 signature over `message`, which may be made
+    /// with any scheme we support.
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, TLSError>;
+
+    /// Verify a TLS1.3 signature over `message`.  This is the whole
+    /// message signed, including the padding and context string, and
+    /// the scheme must be one allowed in TLS1.3.
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, TLSError>;
+}
+
+impl SignatureVerifier for WebPKIVerifier {
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, TLSError> {
+        verify_signed_struct(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, TLSError> {
+        verify_tls13_message(message, cert, dss)
+    }
+}
+
 fn unix_time_millis() -> Result<u64, TLSError> {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -371,6 +643,139 @@ fn unix_time_millis() -> Result<u64, TLSError> {
                   .ok_or(TLSError::FailedToGetCurrentTime))
 }
 
+/// A single signed certificate timestamp, from RFC6962 section 3.2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedCertificateTimestamp {
+    /// The SCT version: zero for v1.
+    pub version: u8,
+
+    /// The SHA-256 hash of the log's public key.
+    pub log_id: [u8; 32],
+
+    /// When the log saw the certificate, in milliseconds since
+    /// the Unix epoch.
+    pub timestamp: u64,
+
+    /// The encoded `CtExtensions`.
+    pub extensions: Vec<u8>,
+
+    /// How the log signed this SCT.
+    pub signature_scheme: SignatureScheme,
+
+    /// The log's signature.
+    pub signature: Vec<u8>,
+}
+
+impl SignedCertificateTimestamp {
+    fn read(r: &mut Reader) -> Option<SignedCertificateTimestamp> {
+        let version = try_ret!(codec::read_u8(r));
+        let mut log_id = [0u8; 32];
+        log_id.copy_from_slice(try_ret!(r.take(32)));
+        let timestamp = try_ret!(codec::read_u64(r));
+        let extensions = try_ret!(PayloadU16::read(r));
+        let signed = try_ret!(DigitallySignedStruct::read(r));
+
+        Some(SignedCertificateTimestamp {
+            version,
+            log_id,
+            timestamp,
+            extensions: extensions.0,
+            signature_scheme: signed.scheme,
+            signature: signed.sig.0,
+        })
+    }
+}
+
+/// Parse `raw`, a `SignedCertificateTimestampList` encoding as
+/// found in `CertifiedKey::sct_list`, into its SCTs.
+///
+/// This only decodes the SCTs: it doesn't check their signatures.
+pub fn parse_sct_list(raw: &[u8]) -> Result<Vec<SignedCertificateTimestamp>, TLSError> {
+    let malformed = TLSError::InvalidSCT(sct::Error::MalformedSCT);
+    let mut rd = Reader::init(raw);
+    let list = SCTList::read(&mut rd).ok_or_else(|| malformed.clone())?;
+    if rd.any_left() {
+        return Err(malformed);
+    }
+
+    let mut ret = Vec::new();
+    for item in list {
+        let mut rd = Reader::init(&item.0);
+        match SignedCertificateTimestamp::read(&mut rd) {
+            Some(sct) if !rd.any_left() => ret.push(sct),
+            _ => return Err(malformed),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Check `sct` is a valid timestamp for `cert`, signed with
+/// ECDSA-P256-SHA256 by the log whose public key is
+/// `log_public_key_der` (a DER-encoded SubjectPublicKeyInfo), and
+/// that its timestamp is not in the future.
+///
+/// This is for SCTs about `cert` itself, as sent in the TLS extension
+/// or an OCSP response, not those embedded in a certificate (which
+/// are about a precertificate).
+pub fn check_sct(sct: &SignedCertificateTimestamp,
+                 cert: &Certificate,
+                 log_public_key_der: &[u8]) -> Result<(), TLSError> {
+    if sct.version != 0 {
+        return Err(TLSError::InvalidSCT(sct::Error::UnsupportedSCTVersion));
+    }
+
+    let log_id = digest::digest(&digest::SHA256, log_public_key_der);
+    if log_id.as_ref() != sct.log_id {
+        return Err(TLSError::InvalidSCT(sct::Error::UnknownLog));
+    }
+
+    if sct.signature_scheme != SignatureScheme::ECDSA_NISTP256_SHA256 {
+        return Err(TLSError::InvalidSCT(sct::Error::InvalidSignature));
+    }
+
+    // The digitally-signed struct from RFC6962 section 3.2, for an
+    // x509_entry.
+    let mut signed = Vec::new();
+    codec::encode_u8(0, &mut signed); // v1
+    codec::encode_u8(0, &mut signed); // certificate_timestamp
+    codec::encode_u64(sct.timestamp, &mut signed);
+    codec::encode_u16(0, &mut signed); // x509_entry
+    codec::encode_u24(cert.0.len() as u32, &mut signed);
+    signed.extend_from_slice(&cert.0);
+    PayloadU16::new(sct.extensions.clone()).encode(&mut signed);
+
+    let public_key = spki_public_key(log_public_key_der)
+        .ok_or(TLSError::InvalidSCT(sct::Error::MalformedSCT))?;
+    ring::signature::verify(&ring::signature::ECDSA_P256_SHA256_ASN1,
+                            public_key,
+                            untrusted::Input::from(&signed),
+                            untrusted::Input::from(&sct.signature))
+        .map_err(|_| TLSError::InvalidSCT(sct::Error::InvalidSignature))?;
+
+    if sct.timestamp > unix_time_millis()? {
+        return Err(TLSError::InvalidSCT(sct::Error::TimestampInFuture));
+    }
+
+    Ok(())
+}
+
+/// The public key inside the SubjectPublicKeyInfo `spki`.
+fn spki_public_key(spki: &[u8]) -> Option<untrusted::Input<'_>> {
+    untrusted::Input::from(spki)
+        .read_all((), |r| {
+            der::expect_tag_and_get_value(r, der::Tag::Sequence)
+                .map_err(|_| ())?
+                .read_all((), |r| {
+                    der::expect_tag_and_get_value(r, der::Tag::Sequence)
+                        .map_err(|_| ())?;
+                    der::bit_string_with_no_unused_bits(r)
+                        .map_err(|_| ())
+                })
+        })
+        .ok()
+}
+
 pub fn verify_scts(cert: &Certificate,
                    scts: &SCTList,
                    logs: &[&sct::Log]) -> Result<(), TLSError> {
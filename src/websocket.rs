@@ -0,0 +1,449 @@
+// The server side of RFC6455 WebSockets.
+//
+// This does the opening handshake and then carries a byte stream in
+// binary frames.  Messages aren't exposed: the payloads of data frames
+// are read as one stream, whatever their boundaries.  Extensions and
+// subprotocols aren't supported.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use base64;
+use ring::digest;
+
+/// Appended to the client's key to make Sec-WebSocket-Accept.
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The longest upgrade request we'll read.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// The longest control frame payload allowed.
+const MAX_CONTROL_LEN: u64 = 125;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// The Sec-WebSocket-Accept value for the client's Sec-WebSocket-Key.
+fn accept_key(key: &str) -> String {
+    let mut ctx = digest::Context::new(&digest::SHA1);
+    ctx.update(key.as_bytes());
+    ctx.update(ACCEPT_GUID);
+    base64::encode(ctx.finish().as_ref())
+}
+
+/// Read the upgrade request, up to and including the empty line.
+fn read_request<IO: Read>(io: &mut IO) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_LEN {
+            return Err(invalid_data("WebSocket upgrade request too long"));
+        }
+        if io.read(&mut byte)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "EOF during WebSocket upgrade request"));
+        }
+        request.push(byte[0]);
+    }
+
+    String::from_utf8(request)
+        .map_err(|_| invalid_data("WebSocket upgrade request isn't UTF-8"))
+}
+
+/// Check `request` is a valid upgrade request, and return the
+/// client's Sec-WebSocket-Key.
+fn parse_request(request: &str) -> Result<&str, &'static str> {
+    let mut lines = request.split("\r\n");
+
+    let request_line = lines.next().unwrap_or("");
+    if !request_line.starts_with("GET ") || !request_line.ends_with(" HTTP/1.1") {
+        return Err("not an HTTP/1.1 GET request");
+    }
+
+    let mut upgrade = false;
+    let mut connection = false;
+    let mut version = false;
+    let mut key = None;
+
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let colon = line.find(':').ok_or("malformed header")?;
+        let (name, value) = (&line[..colon], line[colon + 1..].trim());
+
+        if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("connection") {
+            connection = value.split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+        } else if name.eq_ignore_ascii_case("sec-websocket-version") {
+            version = value == "13";
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            key = Some(value);
+        }
+    }
+
+    if !upgrade || !connection {
+        return Err("not a WebSocket upgrade request");
+    }
+    if !version {
+        return Err("unsupported WebSocket version");
+    }
+    key.ok_or("missing Sec-WebSocket-Key")
+}
+
+/// An `io::Read` and `io::Write` which carries data in WebSocket
+/// binary frames over `IO`.
+///
+/// This is the server side: make one with `accept`, which does
+/// the upgrade handshake, usually with a `TlsServerStream` over
+/// the client's socket for wss:// URLs.
+///
+/// Each write is sent as one binary frame.  Reads return the
+/// payload of the client's text, binary and continuation frames
+/// as one stream.  Pings are answered, and a close frame is
+/// echoed and then reads return EOF.
+#[derive(Debug)]
+pub struct WsTlsAdapter<IO: Read + Write> {
+    io: IO,
+    /// Payload bytes left to read in the current frame.
+    remaining: u64,
+    mask: [u8; 4],
+    /// Offset into the current frame's payload, for unmasking.
+    offset: usize,
+    closed: bool,
+}
+
+impl<IO: Read + Write> WsTlsAdapter<IO> {
+    /// Read the client's upgrade request from `io`, and accept it.
+    ///
+    /// If the request isn't one we can accept, a 400 response is
+    /// sent and an `io::ErrorKind::InvalidData` error is returned.
+    pub fn accept(mut io: IO) -> io::Result<WsTlsAdapter<IO>> {
+        let request = read_request(&mut io)?;
+
+        let key = match parse_request(&request) {
+            Ok(key) => key,
+            Err(err) => {
+                io.write_all(b"HTTP/1.1 400 Bad Request\r\n\
+                               Sec-WebSocket-Version: 13\r\n\
+                               Content-Length: 0\r\n\r\n")?;
+                io.flush()?;
+                return Err(invalid_data(err));
+            }
+        };
+
+        let response = format!("HTTP/1.1 101 Switching Protocols\r\n\
+                                Upgrade: websocket\r\n\
+                                Connection: Upgrade\r\n\
+                                Sec-WebSocket-Accept: {}\r\n\r\n",
+                               accept_key(key));
+        io.write_all(response.as_bytes())?;
+        io.flush()?;
+
+        Ok(WsTlsAdapter {
+            io,
+            remaining: 0,
+            mask: [0; 4],
+            offset: 0,
+            closed: false,
+        })
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    ///
+    /// Reading from or writing to it directly will corrupt the
+    /// WebSocket framing.
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    /// Take apart this adapter, returning the underlying stream.
+    pub fn into_inner(self) -> IO {
+        self.io
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        let len = payload.len();
+
+        if len < 126 {
+            frame.push(len as u8);
+        } else if len <= 0xffff {
+            frame.push(126);
+            frame.extend_from_slice(&[(len >> 8) as u8, len as u8]);
+        } else {
+            frame.push(127);
+            for i in (0..8).rev() {
+                frame.push((len as u64 >> (i * 8)) as u8);
+            }
+        }
+
+        frame.extend_from_slice(payload);
+        self.io.write_all(&frame)
+    }
+
+    /// Read a frame header, handling any control frames, until
+    /// we're at the payload of a data frame.  Returns false at
+    /// the end of the stream.
+    fn next_data_frame(&mut self) -> io::Result<bool> {
+        loop {
+            let mut header = [0u8; 2];
+            self.io.read_exact(&mut header)?;
+
+            let opcode = header[0] & 0x0f;
+            if header[0] & 0x70 != 0 {
+                return Err(invalid_data("WebSocket frame has reserved bits set"));
+            }
+            if header[1] & 0x80 == 0 {
+                return Err(invalid_data("WebSocket client frame isn't masked"));
+            }
+
+            let len = match header[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    self.io.read_exact(&mut len)?;
+                    (u64::from(len[0]) << 8) | u64::from(len[1])
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    self.io.read_exact(&mut len)?;
+                    len.iter().fold(0, |acc, &b| (acc << 8) | u64::from(b))
+                }
+                len => u64::from(len),
+            };
+
+            self.io.read_exact(&mut self.mask)?;
+            self.remaining = len;
+            self.offset = 0;
+
+            match opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => return Ok(true),
+                OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG => {}
+                _ => return Err(invalid_data("unknown WebSocket opcode")),
+            }
+
+            if len > MAX_CONTROL_LEN || header[0] & 0x80 == 0 {
+                return Err(invalid_data("invalid WebSocket control frame"));
+            }
+
+            let mut payload = vec![0u8; len as usize];
+            self.io.read_exact(&mut payload)?;
+            self.unmask(&mut payload);
+            self.remaining = 0;
+
+            match opcode {
+                OPCODE_PING => self.write_frame(OPCODE_PONG, &payload)?,
+                OPCODE_CLOSE => {
+                    self.write_frame(OPCODE_CLOSE, &payload)?;
+                    self.io.flush()?;
+                    self.closed = true;
+                    return Ok(false);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn unmask(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte ^= self.mask[self.offset & 3];
+            self.offset += 1;
+        }
+    }
+}
+
+impl<IO: Read + Write> Read for WsTlsAdapter<IO> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        while self.remaining == 0 {
+            if self.closed || !self.next_data_frame()? {
+                return Ok(0);
+            }
+        }
+
+        let want = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let len = self.io.read(&mut buf[..want])?;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "EOF during WebSocket frame"));
+        }
+
+        self.unmask(&mut buf[..len]);
+        self.remaining -= len as u64;
+        Ok(len)
+    }
+}
+
+impl<IO: Read + Write> Write for WsTlsAdapter<IO> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.write_frame(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const REQUEST: &[u8] = b"GET /chat HTTP/1.1\r\n\
+                             Host: server.example.com\r\n\
+                             Upgrade: websocket\r\n\
+                             Connection: keep-alive, Upgrade\r\n\
+                             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                             Sec-WebSocket-Version: 13\r\n\r\n";
+
+    /// Reads from `input`, and writes to `output`.
+    struct Duplex {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Duplex {
+        fn new(input: &[u8]) -> Duplex {
+            Duplex { input: io::Cursor::new(input.to_vec()), output: Vec::new() }
+        }
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter()
+                     .enumerate()
+                     .map(|(i, b)| b ^ mask[i & 3]));
+        frame
+    }
+
+    fn response_len() -> usize {
+        let mut ws = WsTlsAdapter::accept(Duplex::new(REQUEST)).unwrap();
+        ws.get_mut().output.len()
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // From RFC6455 section 1.3.
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+                   "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_accept() {
+        let ws = WsTlsAdapter::accept(Duplex::new(REQUEST)).unwrap();
+        let response = String::from_utf8(ws.into_inner().output).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_accept_rejects_bad_requests() {
+        let bad = [
+            "POST /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: x\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            "GET /chat HTTP/1.1\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: x\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            "GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: x\r\nSec-WebSocket-Version: 8\r\n\r\n",
+            "GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+        ];
+
+        for request in bad.iter() {
+            let mut io = Duplex::new(request.as_bytes());
+            let err = WsTlsAdapter::accept(&mut io).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(io.output.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+        }
+
+        let err = WsTlsAdapter::accept(Duplex::new(&REQUEST[..20])).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_frames() {
+        let mut input = REQUEST.to_vec();
+        input.extend(client_frame(0x02, b"hello "));
+        input.extend(client_frame(0x89, b"ping!"));
+        input.extend(client_frame(0x00, b"wor"));
+        input.extend(client_frame(0x80, b"ld"));
+        input.extend(client_frame(0x88, &[0x03, 0xe8]));
+
+        let mut ws = WsTlsAdapter::accept(Duplex::new(&input)).unwrap();
+        let skip = response_len();
+
+        let mut data = Vec::new();
+        ws.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello world".to_vec());
+
+        let output = &ws.get_ref().output[skip..];
+        assert_eq!(output, &b"\x8a\x05ping!\x88\x02\x03\xe8"[..]);
+    }
+
+    #[test]
+    fn test_read_rejects_unmasked_frames() {
+        let mut input = REQUEST.to_vec();
+        input.extend_from_slice(b"\x82\x02hi");
+
+        let mut ws = WsTlsAdapter::accept(Duplex::new(&input)).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(ws.read(&mut buf).err().unwrap().kind(),
+                   io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_write_frames() {
+        let mut ws = WsTlsAdapter::accept(Duplex::new(REQUEST)).unwrap();
+        let skip = response_len();
+
+        assert_eq!(ws.write(b"hello").unwrap(), 5);
+        assert_eq!(ws.write(&[0x55; 300]).unwrap(), 300);
+        assert_eq!(ws.write(&[0xaa; 70000]).unwrap(), 70000);
+
+        let output = &ws.get_ref().output[skip..];
+        assert_eq!(&output[..7], b"\x82\x05hello");
+        assert_eq!(&output[7..11], &[0x82, 126, 0x01, 0x2c]);
+        assert_eq!(&output[311..321],
+                   &[0x82, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]);
+        assert_eq!(output.len(), 321 + 70000);
+    }
+}
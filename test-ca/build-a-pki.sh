@@ -109,6 +109,16 @@ for kt in rsa ecdsa ; do
             -set_serial 456 \
             -extensions v3_end -extfile openssl.cnf
 
+  openssl x509 -req \
+            -in $kt/end.req \
+            -out $kt/end-aia.cert \
+            -CA $kt/inter.cert \
+            -CAkey $kt/inter.key \
+            -sha256 \
+            -days 2000 \
+            -set_serial 457 \
+            -extensions v3_end_aia -extfile openssl.cnf
+
   openssl x509 -req \
             -in $kt/client.req \
             -out $kt/client.cert \
@@ -127,3 +137,40 @@ for kt in rsa ecdsa ; do
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
 done
+
+# CRLs issued by the RSA intermediate.
+mkdir -p crl
+gencrl() {
+  openssl ca -gencrl \
+            -config openssl.cnf \
+            -name crl_ca \
+            -cert rsa/inter.cert \
+            -keyfile rsa/inter.key \
+            -crl_lastupdate $2 \
+            -crl_nextupdate $3 \
+            ${4:+-crlexts $4} \
+            -out crl/$1.pem
+  openssl crl -in crl/$1.pem -outform DER -out rsa/$1.crl.der
+}
+
+: > crl/index.txt
+echo 01 > crl/crlnumber
+gencrl inter.empty 20171201000000Z 20491231000000Z
+gencrl inter.expired 20171201000000Z 20180101000000Z
+
+openssl ca -config openssl.cnf -name crl_ca \
+          -cert rsa/inter.cert -keyfile rsa/inter.key \
+          -revoke rsa/end.cert -crl_reason keyCompromise
+gencrl inter.revoked 20171201000000Z 20491231000000Z
+
+# Delta CRLs against inter.empty (CRL number 1).
+gencrl inter.delta1 20180101000000Z 20491231000000Z crl_delta_ext
+
+: > crl/index.txt
+openssl ca -config openssl.cnf -name crl_ca \
+          -cert rsa/inter.cert -keyfile rsa/inter.key \
+          -revoke rsa/end.cert -crl_reason removeFromCRL
+gencrl inter.delta2 20180201000000Z 20491231000000Z crl_delta_ext
+gencrl inter.delta-wrong-base 20180201000000Z 20491231000000Z crl_delta_wrong_base_ext
+
+rm -rf crl/
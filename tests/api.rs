@@ -1,24 +1,54 @@
 // Assorted public API tests.
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic;
 use std::fs;
+use std::net;
+use std::time;
+use std::thread;
 use std::io::{self, Write, Read};
 
 extern crate rustls;
 
-use rustls::{ClientConfig, ClientSession, ResolvesClientCert};
-use rustls::{ServerConfig, ServerSession, ResolvesServerCert};
+use rustls::{ClientConfig, ClientSession, ConnectOptions, ResolvesClientCert};
+use rustls::TlsConnectionPool;
+use rustls::{ServerConfig, ServerSession, ResolvesServerCert, ClientHello};
+use rustls::{SessionCheckpoint, ExportedSession};
+use rustls::ClientAuthenticatedSession;
+use rustls::{Ticketer, TicketKey, TicketKeySet, ClusterTicketRotator};
+use rustls::{AcmeTlsAlpnResponder, AlpnResolvesServerCert};
 use rustls::Session;
 use rustls::Stream;
-use rustls::{ProtocolVersion, SignatureScheme, CipherSuite};
+use rustls::detect_tls;
+use rustls::{TlsStream, TlsClientStream, TlsServerStream};
+use rustls::{InspectorMiddleware, SessionWithInspector};
+use rustls::{AtomicMeter, MeteredTlsStream};
+use rustls::{ProtocolVersion, SignatureScheme, SignatureAlgorithm, CipherSuite};
+use rustls::{parse_sct_list, check_sct};
+use rustls::MaxFragmentLen;
+use rustls::{AlertDescription, AlertPolicy};
 use rustls::TLSError;
 use rustls::sign;
 use rustls::{ALL_CIPHERSUITES, SupportedCipherSuite};
 use rustls::{Certificate, PrivateKey};
 use rustls::internal::pemfile;
+use rustls::internal::msgs::codec::{Codec, Reader};
+use rustls::internal::msgs::base::{Payload, PayloadU8};
+use rustls::internal::msgs::enums::{ExtensionType, NamedGroup};
+use rustls::internal::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+use rustls::internal::msgs::handshake::{ClientHelloPayload, ClientExtension, ServerExtension};
+use rustls::internal::msgs::handshake::{KeyShareEntry, SessionID};
+use rustls::internal::msgs::handshake::{UnknownExtension, NewSessionTicketExtension};
+use rustls::internal::msgs::handshake::{KeyExchangeAlgorithm, ServerKeyExchangePayload};
+use rustls::internal::msgs::handshake::ECDHEServerKeyExchange;
+#[cfg(feature = "dangerous_configuration")]
+use rustls::internal::msgs::handshake::DigitallySignedStruct;
+use rustls::internal::msgs::message::{Message, MessagePayload};
+use rustls::internal::msgs::persist::ServerCheckpointValue;
 use rustls::{RootCertStore, NoClientAuth, AllowAnyAuthenticatedClient};
 
 extern crate webpki;
+extern crate untrusted;
+extern crate sct;
 
 fn transfer(left: &mut Session, right: &mut Session) {
     let mut buf = [0u8; 262144];
@@ -153,6 +183,105 @@ fn alpn() {
     alpn_test(vec!["PROTO".to_string()], vec!["proto".to_string()], None);
 }
 
+#[test]
+fn connect_options_override_alpn() {
+    let mut server_config = make_server_config();
+    server_config.alpn_protocols = vec!["server-proto".to_string()];
+    let client_config = Arc::new(make_client_config());
+
+    let options = ConnectOptions::new(dns_name("localhost"))
+        .alpn_protocols(vec!["server-proto".to_string()])
+        .timeout(time::Duration::from_secs(5));
+    assert_eq!(options.get_timeout(), Some(time::Duration::from_secs(5)));
+
+    let mut client = options.build_session(&client_config);
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_alpn_protocol(), Some("server-proto"));
+    assert_eq!(server.get_alpn_protocol(), Some("server-proto"));
+
+    // without an override, the config's (empty) list is used
+    let mut client = ConnectOptions::new(dns_name("localhost"))
+        .build_session(&client_config);
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_alpn_protocol(), None);
+}
+
+fn alpn_mismatch_test(server_protos: Vec<String>, client_protos: Vec<String>) {
+    let mut client_config = make_client_config();
+    let mut server_config = make_server_config();
+
+    client_config.alpn_protocols = client_protos.clone();
+    server_config.alpn_protocols = server_protos.clone();
+    server_config.require_alpn_match = true;
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    let to_bytes = |protos: Vec<String>| protos.into_iter()
+        .map(String::into_bytes)
+        .collect();
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(TLSError::NoApplicationProtocol {
+                   offered: to_bytes(client_protos),
+                   supported: to_bytes(server_protos),
+               })));
+
+    transfer(&mut server, &mut client);
+    assert_eq!(client.process_new_packets(),
+               Err(TLSError::AlertReceived(AlertDescription::NoApplicationProtocol)));
+}
+
+#[test]
+fn alpn_mismatch_reports_non_utf8_protocols() {
+    let mut server_config = make_server_config();
+    server_config.set_protocols(&["server-proto".to_string()]);
+    server_config.require_alpn_match = true;
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    let mut client_config = make_client_config();
+    client_config.set_protocols(&["client-proto".to_string()]);
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        for ext in ch.extensions.iter_mut() {
+            if let ClientExtension::Protocols(ref mut protocols) = *ext {
+                protocols.push(PayloadU8::new(vec![ 0xff, 0xfe ]));
+            }
+        }
+    });
+
+    server.read_tls(&mut &buf[..]).unwrap();
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::NoApplicationProtocol {
+                   offered: vec![ b"client-proto".to_vec(), vec![ 0xff, 0xfe ] ],
+                   supported: vec![ b"server-proto".to_vec() ],
+               }));
+}
+
+#[test]
+fn alpn_required() {
+    // no overlap
+    alpn_mismatch_test(vec!["server-proto".to_string()],
+                       vec!["client-proto".to_string()]);
+
+    // case sensitive
+    alpn_mismatch_test(vec!["PROTO".to_string()], vec!["proto".to_string()]);
+
+    // a client without ALPN is still fine
+    let client_config = make_client_config();
+    let mut server_config = make_server_config();
+    server_config.alpn_protocols = vec!["server-proto".to_string()];
+    server_config.require_alpn_match = true;
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.get_alpn_protocol(), None);
+}
+
 fn version_test(client_versions: Vec<ProtocolVersion>,
                 server_versions: Vec<ProtocolVersion>,
                 result: Option<ProtocolVersion>) {
@@ -291,8 +420,17 @@ fn client_can_get_server_cert() {
 
     do_handshake(&mut client, &mut server);
 
-    let certs = client.get_peer_certificates();
-    assert_eq!(certs, Some(get_chain()));
+    let certs = client.get_peer_certificates().unwrap();
+    assert_eq!(certs, get_chain());
+
+    let chain = get_chain();
+    assert_eq!(certs.len(), chain.len());
+    assert!(!certs.is_empty());
+    assert_eq!(certs.end_entity(), &chain[0]);
+    assert_eq!(certs.intermediates(), &chain[1..]);
+    assert_eq!((&certs).into_iter().count(), chain.len());
+    assert_eq!(certs.clone().into_iter().collect::<Vec<_>>(), chain);
+    assert_eq!(certs.into_vec(), chain);
 }
 
 #[test]
@@ -307,7 +445,140 @@ fn server_can_get_client_cert() {
     do_handshake(&mut client, &mut server);
 
     let certs = server.get_peer_certificates();
-    assert_eq!(certs, Some(get_chain()));
+    assert_eq!(certs.map(|certs| certs.into_vec()), Some(get_chain()));
+}
+
+fn client_subject(chain: rustls::VerifiedClientCertChain) -> String {
+    chain.end_entity()
+        .parse_info()
+        .unwrap()
+        .subject
+}
+
+#[test]
+fn server_can_get_verified_client_cert_chain() {
+    let mut client_config = make_client_config();
+    let server_config = make_server_config_with_mandatory_client_auth();
+    client_config.set_single_client_cert(get_chain(), get_key());
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    assert!(server.get_verified_client_cert_chain().is_none());
+
+    do_handshake(&mut client, &mut server);
+
+    let chain = server.get_verified_client_cert_chain().unwrap();
+    assert_eq!(chain.certificates(), &get_chain()[..]);
+    assert_eq!(client_subject(chain), "CN=testserver.com");
+}
+
+#[test]
+fn server_has_no_verified_client_cert_chain_without_client_auth() {
+    let mut client_config = make_client_config();
+    client_config.set_single_client_cert(get_chain(), get_key());
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+    assert!(server.get_verified_client_cert_chain().is_none());
+}
+
+#[test]
+fn client_authenticated_session() {
+    let mut client_config = make_client_config();
+    client_config.set_single_client_cert(get_chain(), get_key());
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let server = ServerSession::new(&Arc::new(make_server_config_with_mandatory_client_auth()));
+
+    // Not until the handshake is done.
+    let mut server = ClientAuthenticatedSession::new(server).unwrap_err();
+    do_handshake(&mut client, &mut server);
+
+    let mut server = ClientAuthenticatedSession::new(server).unwrap();
+    assert_eq!(server.get_authenticated_client_cert().end_entity(), &get_chain()[0]);
+    assert_eq!(server.get_authenticated_client_cert().intermediates(), &get_chain()[1..]);
+
+    // Nor from a checkpoint, which anyone could have made.
+    let checkpoint = server.get_ref().checkpoint().unwrap();
+    let restored = ServerSession::restore(&Arc::new(make_server_config_with_mandatory_client_auth()),
+                                          &SessionCheckpoint::from_bytes(checkpoint.as_bytes().to_vec()))
+        .unwrap();
+    assert!(ClientAuthenticatedSession::new(restored).is_err());
+
+    server.write_all(b"hello").unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"hello");
+
+    // Nor without a client certificate.
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+    assert!(ClientAuthenticatedSession::new(server).is_err());
+}
+
+fn client_auth_roots() -> RootCertStore {
+    let mut client_auth_roots = RootCertStore::empty();
+    for root in get_chain() {
+        client_auth_roots.add(&root).unwrap();
+    }
+    client_auth_roots
+}
+
+fn client_auth_handshake(mut server_config: ServerConfig, client_cert: bool)
+                         -> Result<Option<Vec<Certificate>>, TLSErrorFromPeer> {
+    server_config.set_single_cert(get_chain(), get_key());
+    let mut client_config = make_client_config();
+    if client_cert {
+        client_config.set_single_client_cert(get_chain(), get_key());
+    }
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake_until_error(&mut client, &mut server)?;
+    Ok(server.get_peer_certificates().map(|certs| certs.into_vec()))
+}
+
+#[test]
+fn client_cert_thumbprint() {
+    let thumbprint = "WNuyrb9TNjBHsoE-TgYwEJM9L2NxfeeNp_4QsgdwbEE";
+    assert_eq!(rustls::client_cert_thumbprint(&get_chain()[0]), thumbprint);
+
+    let mut server_config = ServerConfig::with_optional_client_auth(
+        AllowAnyAuthenticatedClient::new(client_auth_roots()));
+    server_config.set_single_cert(get_chain(), get_key());
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config();
+    client_config.set_single_client_cert(get_chain(), get_key());
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(server.client_cert_thumbprint(), None);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.client_cert_thumbprint(), Some(thumbprint.to_string()));
+
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.client_cert_thumbprint(), None);
+}
+
+#[test]
+fn server_config_client_auth_constructors() {
+    let none = || ServerConfig::with_no_client_auth();
+    assert_eq!(client_auth_handshake(none(), false), Ok(None));
+    assert_eq!(client_auth_handshake(none(), true), Ok(None));
+
+    let optional = || ServerConfig::with_optional_client_auth(
+        AllowAnyAuthenticatedClient::new(client_auth_roots()));
+    assert_eq!(client_auth_handshake(optional(), false), Ok(None));
+    assert_eq!(client_auth_handshake(optional(), true), Ok(Some(get_chain())));
+
+    let required = || ServerConfig::with_required_client_auth(
+        AllowAnyAuthenticatedClient::new(client_auth_roots()));
+    assert!(client_auth_handshake(required(), false).is_err());
+    assert_eq!(client_auth_handshake(required(), true), Ok(Some(get_chain())));
 }
 
 fn check_read_and_close(reader: &mut io::Read, expect: &[u8]) {
@@ -386,11 +657,8 @@ impl ServerCheckCertResolve {
 }
 
 impl ResolvesServerCert for ServerCheckCertResolve {
-    fn resolve(&self,
-               server_name: Option<webpki::DNSNameRef>,
-               sigschemes: &[SignatureScheme])
-        -> Option<sign::CertifiedKey> {
-        if let Some(got_dns_name) = server_name {
+    fn resolve(&self, client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        if let Some(got_dns_name) = client_hello.server_name() {
             let got: &str = got_dns_name.into();
             if got != self.expected {
                 panic!("unexpected dns name (wanted '{}' got '{:?}')", &self.expected, got_dns_name);
@@ -399,7 +667,7 @@ impl ResolvesServerCert for ServerCheckCertResolve {
             panic!("dns name not provided (wanted '{}')", &self.expected);
         }
 
-        if sigschemes.len() == 0 {
+        if client_hello.sigschemes().is_empty() {
             panic!("no signature schemes shared by client");
         }
 
@@ -424,11 +692,8 @@ fn server_cert_resolve_with_sni() {
 struct ServerCheckNoSNI {}
 
 impl ResolvesServerCert for ServerCheckNoSNI {
-    fn resolve(&self,
-               server_name: Option<webpki::DNSNameRef>,
-               _sigschemes: &[SignatureScheme])
-        -> Option<sign::CertifiedKey> {
-        assert!(server_name.is_none());
+    fn resolve(&self, client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        assert!(client_hello.server_name().is_none());
 
         None
     }
@@ -449,125 +714,631 @@ fn client_with_sni_disabled_does_not_send_sni() {
     assert_eq!(err.is_err(), true);
 }
 
+fn make_certified_key() -> sign::CertifiedKey {
+    let key = sign::RSASigningKey::new(&get_key()).unwrap();
+    sign::CertifiedKey::new(get_chain(), Arc::new(Box::new(key)))
+}
+
 #[test]
-fn client_checks_server_certificate_with_given_name() {
-    let client_config = make_client_config();
-    let server_config = make_server_config();
+fn certified_key_with_pool() {
+    let pool = Arc::new(sign::CertificatePool::new());
+    let chain = get_chain();
+    let intermediates = chain[1..].iter()
+        .map(|cert| pool.add(cert.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(pool.len(), chain.len() - 1);
+
+    let make_key = || {
+        let key = sign::RSASigningKey::new(&get_key()).unwrap();
+        let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
+        let owned = chain.iter()
+            .map(|cert| sign::CertRef::Owned(cert.clone()))
+            .collect();
+        sign::CertifiedKey::with_pool(owned, &pool, key)
+    };
+    let first = make_key();
+    let second = make_key();
+
+    // the end-entity cert isn't in the pool, so stays owned; the
+    // intermediates are shared.
+    assert_eq!(pool.len(), chain.len() - 1);
+    match (&first.shared_cert[0], &second.shared_cert[0]) {
+        (sign::CertRef::Owned(_), sign::CertRef::Owned(_)) => {}
+        _ => panic!("end-entity cert was pooled"),
+    }
+    for (i, pooled) in intermediates.iter().enumerate() {
+        match (pooled, &first.shared_cert[i + 1], &second.shared_cert[i + 1]) {
+            (sign::CertRef::Pooled(a),
+             sign::CertRef::Pooled(b),
+             sign::CertRef::Pooled(c)) => {
+                assert!(Arc::ptr_eq(a, b));
+                assert!(Arc::ptr_eq(a, c));
+            }
+            _ => panic!("intermediate cert not pooled"),
+        }
+    }
 
-    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("not-the-right-hostname.com"));
-    let mut server = ServerSession::new(&Arc::new(server_config));
+    assert!(first.valid_for_name("testserver.com"));
+    assert_eq!(first.clone().take_cert(), chain);
 
-    let err = do_handshake_until_error(&mut client, &mut server);
-    assert_eq!(err,
-               Err(TLSErrorFromPeer::Client(
-                       TLSError::WebPKIError(webpki::Error::CertNotValidForName))
-                   )
-               );
-}
+    let mut resolver = rustls::ResolvesServerCertUsingSNI::new();
+    resolver.add("localhost", first).unwrap();
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-struct ClientCheckCertResolve {
-    query_count: atomic::AtomicUsize,
-    expect_queries: usize
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+    }
 }
 
-impl ClientCheckCertResolve {
-    fn new(expect_queries: usize) -> ClientCheckCertResolve {
-        ClientCheckCertResolve {
-            query_count: atomic::AtomicUsize::new(0),
-            expect_queries: expect_queries
-        }
+#[test]
+fn certified_key_from_der() {
+    let chain = get_chain();
+    let chain_der = chain.iter()
+        .map(|cert| &cert.0[..])
+        .collect::<Vec<_>>();
+
+    let ck = sign::CertifiedKey::from_der(&chain_der, &get_key().0).unwrap();
+    assert_eq!(ck.cert, chain);
+    assert_eq!(ck.key.algorithm(), SignatureAlgorithm::RSA);
+
+    let ecdsa_chain = pemfile::certs(&mut io::BufReader::new(
+        fs::File::open("test-ca/ecdsa/end.fullchain").unwrap())).unwrap();
+    let ecdsa_chain_der = ecdsa_chain.iter()
+        .map(|cert| &cert.0[..])
+        .collect::<Vec<_>>();
+    let ecdsa_key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+        fs::File::open("test-ca/ecdsa/end.key").unwrap())).unwrap().remove(0);
+    let ck = sign::CertifiedKey::from_der(&ecdsa_chain_der, &ecdsa_key.0).unwrap();
+    assert_eq!(ck.key.algorithm(), SignatureAlgorithm::ECDSA);
+
+    assert!(sign::CertifiedKey::from_der(&chain_der, b"not a key").is_err());
+    assert!(sign::CertifiedKey::from_der(&[], &get_key().0).is_err());
+    assert!(sign::CertifiedKey::from_der(&[b"not a cert"], &get_key().0).is_err());
+}
+
+struct CountingChallenge {
+    key: sign::RSASigningKey,
+    responses: Mutex<Vec<SignatureScheme>>,
+}
+
+impl sign::CertificateVerifyChallenge for CountingChallenge {
+    fn respond(&self, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        use sign::SigningKey;
+        self.responses.lock().unwrap().push(scheme);
+        self.key.choose_scheme(&[scheme]).unwrap().sign(message)
     }
 }
 
-impl Drop for ClientCheckCertResolve {
-    fn drop(&mut self) {
-        let count = self.query_count.load(atomic::Ordering::SeqCst);
-        assert_eq!(count, self.expect_queries);
+#[test]
+fn challenge_signing_key() {
+    use sign::SigningKey;
+
+    let backend = Arc::new(CountingChallenge {
+        key: sign::RSASigningKey::new(&get_key()).unwrap(),
+        responses: Mutex::new(Vec::new()),
+    });
+    let key = sign::ChallengeSigningKey::new(backend.key.algorithm(),
+                                             &[SignatureScheme::RSA_PSS_SHA256,
+                                               SignatureScheme::RSA_PKCS1_SHA512,
+                                               SignatureScheme::RSA_PKCS1_SHA384,
+                                               SignatureScheme::RSA_PKCS1_SHA256],
+                                             backend.clone());
+    assert!(key.choose_scheme(&[SignatureScheme::RSA_PSS_SHA512]).is_none());
+
+    let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
+    let mut resolver = rustls::ResolvesServerCertUsingSNI::new();
+    resolver.add("localhost", sign::CertifiedKey::new(get_chain(), key))
+        .unwrap();
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![*version];
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
     }
+
+    // One signature for each handshake, which for TLS1.3 must use PSS.
+    let responses = backend.responses.lock().unwrap();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[1], SignatureScheme::RSA_PSS_SHA256);
 }
 
-impl ResolvesClientCert for ClientCheckCertResolve {
-    fn resolve(&self,
-               acceptable_issuers: &[&[u8]],
-               sigschemes: &[SignatureScheme])
-        -> Option<sign::CertifiedKey> {
-        self.query_count.fetch_add(1, atomic::Ordering::SeqCst);
+struct CountingKeySource {
+    loads: atomic::AtomicUsize,
+    fail: bool,
+}
 
-        if acceptable_issuers.len() == 0 {
-            panic!("no issuers offered by server");
+impl sign::PrivateKeySource for CountingKeySource {
+    fn load(&self) -> Result<Arc<Box<sign::SigningKey>>, TLSError> {
+        self.loads.fetch_add(1, atomic::Ordering::SeqCst);
+        if self.fail {
+            return Err(TLSError::General("no passphrase".to_string()));
         }
+        let key = sign::RSASigningKey::new(&get_key()).unwrap();
+        Ok(Arc::new(Box::new(key)))
+    }
+}
 
-        if sigschemes.len() == 0 {
-            panic!("no signature schemes shared by server");
+fn lazy_key_server_config(source: Arc<CountingKeySource>) -> Arc<ServerConfig> {
+    struct SharedSource(Arc<CountingKeySource>);
+    impl sign::PrivateKeySource for SharedSource {
+        fn load(&self) -> Result<Arc<Box<sign::SigningKey>>, TLSError> {
+            self.0.load()
         }
-
-        None
     }
 
-    fn has_certs(&self) -> bool {
-        true
+    let key = sign::LazySigningKey::new(SignatureAlgorithm::RSA,
+                                        Box::new(SharedSource(source)));
+    let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
+    let mut resolver = rustls::ResolvesServerCertUsingSNI::new();
+    resolver.add("localhost", sign::CertifiedKey::new(get_chain(), key))
+        .unwrap();
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = Arc::new(resolver);
+    Arc::new(server_config)
+}
+
+#[test]
+fn lazy_signing_key_loads_once() {
+    let source = Arc::new(CountingKeySource { loads: atomic::AtomicUsize::new(0), fail: false });
+    let server_config = lazy_key_server_config(source.clone());
+    assert_eq!(source.loads.load(atomic::Ordering::SeqCst), 0);
+
+    for _ in 0..2 {
+        let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
     }
+    assert_eq!(source.loads.load(atomic::Ordering::SeqCst), 1);
 }
 
 #[test]
-fn client_cert_resolve() {
-    let mut client_config = make_client_config();
-    let server_config = make_server_config_with_mandatory_client_auth();
+fn lazy_signing_key_load_failure() {
+    let source = Arc::new(CountingKeySource { loads: atomic::AtomicUsize::new(0), fail: true });
+    let server_config = lazy_key_server_config(source.clone());
+
+    for _ in 0..2 {
+        let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+    }
+    // failures are not cached
+    assert_eq!(source.loads.load(atomic::Ordering::SeqCst), 2);
+}
 
-    client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(1));
+#[test]
+fn sign_with_callback_defaults_to_signer() {
+    use sign::SigningKey;
 
-    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
-    let mut server = ServerSession::new(&Arc::new(server_config));
+    let key = sign::RSASigningKey::new(&get_key()).unwrap();
+    let sig = key.sign_with_callback(SignatureScheme::RSA_PKCS1_SHA256, b"hello",
+                                     |_| panic!("callback called"))
+        .unwrap();
+    assert_eq!(sig.len(), 256);
 
-    assert_eq!(
-        do_handshake_until_error(&mut client, &mut server),
-        Err(TLSErrorFromPeer::Server(TLSError::NoCertificatesPresented)));
+    assert!(key.sign_with_callback(SignatureScheme::ECDSA_NISTP256_SHA256, b"hello",
+                                   |_| panic!("callback called"))
+        .is_err());
 }
 
 #[test]
-fn client_error_is_sticky() {
-    let client_config = make_client_config();
-    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
-    client.read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref()).unwrap();
-    let mut err = client.process_new_packets();
-    assert_eq!(err.is_err(), true);
-    err = client.process_new_packets();
-    assert_eq!(err.is_err(), true);
+fn signing_key_supported_schemes() {
+    use sign::SigningKey;
+
+    let rsa = sign::RSASigningKey::new(&get_key()).unwrap();
+    assert!(rsa.supported_schemes().contains(&SignatureScheme::RSA_PSS_SHA256));
+    assert!(rsa.supported_schemes().contains(&SignatureScheme::RSA_PKCS1_SHA256));
+    assert!(!rsa.supported_schemes().contains(&SignatureScheme::ECDSA_NISTP256_SHA256));
+
+    let ecdsa_key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+        fs::File::open("test-ca/ecdsa/end.key").unwrap())).unwrap().remove(0);
+    let ecdsa = sign::ECDSASigningKey::new(&ecdsa_key).unwrap();
+    let scheme = ecdsa.supported_schemes()[0];
+    assert_eq!(ecdsa.supported_schemes().len(), 1);
+    assert_eq!(ecdsa.choose_scheme(&[scheme]).unwrap().get_scheme(), scheme);
 }
 
 #[test]
-fn server_error_is_sticky() {
-    let server_config = make_server_config();
-    let mut server = ServerSession::new(&Arc::new(server_config));
-    server.read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref()).unwrap();
-    let mut err = server.process_new_packets();
-    assert_eq!(err.is_err(), true);
-    err = server.process_new_packets();
-    assert_eq!(err.is_err(), true);
+fn signing_key_type_names() {
+    use sign::SigningKey;
+
+    let rsa = sign::RSASigningKey::new(&get_key()).unwrap();
+    assert_eq!(rsa.key_type_name(), "RSA-2048");
+
+    let ecdsa_key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+        fs::File::open("test-ca/ecdsa/end.key").unwrap())).unwrap().remove(0);
+    let ecdsa = sign::ECDSASigningKey::new(&ecdsa_key).unwrap();
+    assert_eq!(ecdsa.key_type_name(), "ECDSA-P256");
 }
 
 #[test]
-fn server_is_send_and_sync() {
-    let server_config = make_server_config();
-    let server = ServerSession::new(&Arc::new(server_config));
-    &server as &Send;
-    &server as &Sync;
+fn key_matches_cert() {
+    let rsa_cert = get_chain().remove(0);
+    let ecdsa_cert = pemfile::certs(&mut io::BufReader::new(
+        fs::File::open("test-ca/ecdsa/end.cert").unwrap())).unwrap().remove(0);
+    let client_cert = pemfile::certs(&mut io::BufReader::new(
+        fs::File::open("test-ca/rsa/client.cert").unwrap())).unwrap().remove(0);
+    let ecdsa_key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+        fs::File::open("test-ca/ecdsa/end.key").unwrap())).unwrap().remove(0);
+
+    assert!(sign::key_matches_cert(&get_key(), &rsa_cert).unwrap());
+    assert!(sign::key_matches_cert(&ecdsa_key, &ecdsa_cert).unwrap());
+    assert!(!sign::key_matches_cert(&get_key(), &client_cert).unwrap());
+    assert!(!sign::key_matches_cert(&get_key(), &ecdsa_cert).unwrap());
+    assert!(!sign::key_matches_cert(&ecdsa_key, &rsa_cert).unwrap());
+
+    assert!(sign::key_matches_cert(&PrivateKey(vec![0; 16]), &rsa_cert).is_err());
+    assert!(sign::key_matches_cert(&get_key(), &Certificate(vec![0; 16])).is_err());
 }
 
 #[test]
-fn client_is_send_and_sync() {
-    let client_config = make_client_config();
-    let client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
-    &client as &Send;
-    &client as &Sync;
+fn signature_scheme_key_types() {
+    use sign::{KeyType, compatible_schemes_for_key_type, key_type_for_scheme};
+
+    for key_type in &[KeyType::Rsa, KeyType::EcdsaP256, KeyType::EcdsaP384,
+                      KeyType::EcdsaP521, KeyType::Ed25519] {
+        let schemes = compatible_schemes_for_key_type(*key_type);
+        assert!(!schemes.is_empty());
+        for scheme in schemes {
+            assert_eq!(key_type_for_scheme(*scheme), Some(*key_type));
+        }
+    }
+
+    assert_eq!(key_type_for_scheme(SignatureScheme::RSA_PKCS1_SHA1), Some(KeyType::Rsa));
+    assert_eq!(key_type_for_scheme(SignatureScheme::ECDSA_SHA1_Legacy), None);
+    assert_eq!(key_type_for_scheme(SignatureScheme::ED448), None);
 }
 
 #[test]
-fn server_respects_buffer_limit_pre_handshake() {
-    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
-    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+fn acme_tls_alpn_responder() {
+    let responder = Arc::new(AcmeTlsAlpnResponder::new(
+            Arc::new(ServerCheckCertResolve::new("testserver.com"))));
+    assert!(responder.add_challenge("not a dns name", make_certified_key()).is_err());
 
-    server.set_buffer_limit(32);
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = responder.clone();
+    server_config.alpn_protocols = vec!["acme-tls/1".to_string()];
+    let server_config = Arc::new(server_config);
+
+    let mut acme_config = make_client_config();
+    acme_config.alpn_protocols = vec!["acme-tls/1".to_string()];
+    let acme_config = Arc::new(acme_config);
+    let normal_config = Arc::new(make_client_config());
+
+    // no challenge yet
+    let mut client = ClientSession::new(&acme_config, dns_name("testserver.com"));
+    let mut server = ServerSession::new(&server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+
+    responder.add_challenge("testserver.com", make_certified_key()).unwrap();
+    let mut client = ClientSession::new(&acme_config, dns_name("testserver.com"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.get_alpn_protocol(), Some("acme-tls/1"));
+
+    // normal connections go to the fallback resolver
+    let mut client = ClientSession::new(&normal_config, dns_name("testserver.com"));
+    let mut server = ServerSession::new(&server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+
+    responder.remove_challenge("testserver.com");
+    let mut client = ClientSession::new(&acme_config, dns_name("testserver.com"));
+    let mut server = ServerSession::new(&server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
+#[test]
+fn certified_key_valid_for_name() {
+    let ck = make_certified_key();
+    assert!(ck.valid_for_name("testserver.com"));
+    assert!(ck.valid_for_name("second.testserver.com"));
+    assert!(!ck.valid_for_name("other.com"));
+    assert!(!ck.valid_for_name("not a dns name"));
+    assert!(!ck.valid_for_name(""));
+
+    assert_eq!(ck.valid_for_any_name(&["other.com", "localhost", "testserver.com"]),
+               Some("localhost"));
+    assert_eq!(ck.valid_for_any_name(&["other.com", "not a dns name"]), None);
+    assert_eq!(ck.valid_for_any_name(&[]), None);
+
+    let no_chain = sign::CertifiedKey::new(vec![], ck.key.clone());
+    assert!(!no_chain.valid_for_name("testserver.com"));
+}
+
+#[test]
+fn certified_key_cross_check_for_any_name() {
+    let ck = make_certified_key();
+    let matched = ck.cross_check_for_any_name(&[dns_name("other.com"),
+                                                dns_name("second.testserver.com"),
+                                                dns_name("testserver.com")])
+        .unwrap();
+    let matched: &str = matched.into();
+    assert_eq!(matched, "second.testserver.com");
+
+    assert!(ck.cross_check_for_any_name(&[dns_name("other.com")]).is_err());
+    assert!(ck.cross_check_for_any_name(&[]).is_err());
+
+    let no_chain = sign::CertifiedKey::new(vec![], ck.key.clone());
+    assert!(no_chain.cross_check_for_any_name(&[dns_name("testserver.com")]).is_err());
+}
+
+#[test]
+fn certified_key_change_detection() {
+    let ck = make_certified_key();
+    assert!(!sign::cert_changed(&ck, &ck));
+    assert!(!sign::cert_changed(&ck, &make_certified_key()));
+
+    // Only the end-entity certificate matters.
+    let ee_only = sign::CertifiedKey::new(vec![ get_chain().remove(0) ], ck.key.clone());
+    assert!(!sign::cert_changed(&ck, &ee_only));
+
+    let ecdsa_chain = pemfile::certs(&mut io::BufReader::new(
+            fs::File::open("test-ca/ecdsa/end.fullchain").unwrap()))
+        .unwrap();
+    let other = sign::CertifiedKey::new(ecdsa_chain, ck.key.clone());
+    assert!(sign::cert_changed(&ck, &other));
+    assert!(sign::cert_changed(&other, &ck));
+
+    let no_chain = sign::CertifiedKey::new(vec![], ck.key.clone());
+    assert!(sign::cert_changed(&ck, &no_chain));
+    assert!(sign::cert_changed(&no_chain, &ck));
+    assert!(!sign::cert_changed(&no_chain, &no_chain));
+}
+
+fn encode_sct(log_id: u8, timestamp: u64, extensions: &[u8], sig: &[u8]) -> Vec<u8> {
+    let mut sct = vec![ 0x00 ];
+    sct.extend_from_slice(&[log_id; 32]);
+    for i in (0..8).rev() {
+        sct.push((timestamp >> (i * 8)) as u8);
+    }
+    sct.extend_from_slice(&[ 0x00, extensions.len() as u8 ]);
+    sct.extend_from_slice(extensions);
+    sct.extend_from_slice(&[ 0x04, 0x03, 0x00, sig.len() as u8 ]);
+    sct.extend_from_slice(sig);
+    sct
+}
+
+fn encode_sct_list(scts: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for sct in scts {
+        body.extend_from_slice(&[ 0x00, sct.len() as u8 ]);
+        body.extend_from_slice(sct);
+    }
+    let mut list = vec![ 0x00, body.len() as u8 ];
+    list.extend_from_slice(&body);
+    list
+}
+
+#[test]
+fn parses_sct_list() {
+    let list = encode_sct_list(&[
+        encode_sct(0xaa, 0x0102030405060708, &[], &[ 1, 2, 3 ]),
+        encode_sct(0xbb, 1234, &[ 9, 9 ], &[ 4, 5 ]),
+    ]);
+
+    let scts = parse_sct_list(&list).unwrap();
+    assert_eq!(scts.len(), 2);
+    assert_eq!(scts[0].version, 0);
+    assert_eq!(scts[0].log_id, [0xaa; 32]);
+    assert_eq!(scts[0].timestamp, 0x0102030405060708);
+    assert_eq!(scts[0].extensions, Vec::<u8>::new());
+    assert_eq!(scts[0].signature_scheme, SignatureScheme::ECDSA_NISTP256_SHA256);
+    assert_eq!(scts[0].signature, vec![ 1, 2, 3 ]);
+    assert_eq!(scts[1].log_id, [0xbb; 32]);
+    assert_eq!(scts[1].timestamp, 1234);
+    assert_eq!(scts[1].extensions, vec![ 9, 9 ]);
+    assert_eq!(scts[1].signature, vec![ 4, 5 ]);
+
+    assert_eq!(parse_sct_list(&encode_sct_list(&[])).unwrap(), vec![]);
+}
+
+fn google_pilot_spki() -> Vec<u8> {
+    // SubjectPublicKeyInfo header for an uncompressed P-256 point.
+    let mut spki = vec![ 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce,
+                         0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+                         0x03, 0x01, 0x07, 0x03, 0x42, 0x00 ];
+    spki.extend_from_slice(&fs::read("src/testdata/ct-google-pilot-pubkey.raw").unwrap());
+    spki
+}
+
+#[test]
+fn checks_sct() {
+    let cert = Certificate(fs::read("src/testdata/sct-google-cert.der").unwrap());
+    let raw_sct = fs::read("src/testdata/sct-google-pilot.bin").unwrap();
+    let sct = parse_sct_list(&encode_sct_list(&[ raw_sct ])).unwrap().remove(0);
+    let log_key = google_pilot_spki();
+    assert_eq!(check_sct(&sct, &cert, &log_key), Ok(()));
+
+    assert_eq!(check_sct(&sct, &get_chain()[0], &log_key),
+               Err(TLSError::InvalidSCT(sct::Error::InvalidSignature)));
+
+    let mut tampered = sct.clone();
+    tampered.timestamp += 1;
+    assert_eq!(check_sct(&tampered, &cert, &log_key),
+               Err(TLSError::InvalidSCT(sct::Error::InvalidSignature)));
+
+    let mut other_log = sct.clone();
+    other_log.log_id[0] ^= 1;
+    assert_eq!(check_sct(&other_log, &cert, &log_key),
+               Err(TLSError::InvalidSCT(sct::Error::UnknownLog)));
+
+    let mut v2 = sct.clone();
+    v2.version = 1;
+    assert_eq!(check_sct(&v2, &cert, &log_key),
+               Err(TLSError::InvalidSCT(sct::Error::UnsupportedSCTVersion)));
+}
+
+#[test]
+fn rejects_malformed_sct_list() {
+    let malformed = Err(TLSError::InvalidSCT(sct::Error::MalformedSCT));
+    let sct = encode_sct(0xaa, 1, &[], &[ 1, 2, 3 ]);
+
+    assert_eq!(parse_sct_list(&[]), malformed);
+
+    let mut trailing = encode_sct_list(&[ sct.to_vec() ]);
+    trailing.push(0);
+    assert_eq!(parse_sct_list(&trailing), malformed);
+
+    let mut long_sct = sct.clone();
+    long_sct.push(0);
+    assert_eq!(parse_sct_list(&encode_sct_list(&[ long_sct ])), malformed);
+
+    let short_sct = sct[..sct.len() - 1].to_vec();
+    assert_eq!(parse_sct_list(&encode_sct_list(&[ short_sct ])), malformed);
+}
+
+#[test]
+fn client_checks_server_certificate_with_given_name() {
+    let client_config = make_client_config();
+    let server_config = make_server_config();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("not-the-right-hostname.com"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err,
+               Err(TLSErrorFromPeer::Client(
+                       TLSError::WebPKIError(webpki::Error::CertNotValidForName))
+                   )
+               );
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn client_allow_domain_fronting_skips_only_name_check() {
+    let server_config = Arc::new(make_server_config());
+
+    let mut client_config = make_client_config();
+    client_config.dangerous().set_allow_domain_fronting(true);
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("front.example.com"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.get_sni_hostname(), Some("front.example.com"));
+
+    // The chain must still be trusted.
+    let mut client_config = make_client_config();
+    client_config.dangerous().set_allow_domain_fronting(true);
+    client_config.root_store = RootCertStore::empty();
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("front.example.com"));
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(
+                       TLSError::WebPKIError(webpki::Error::UnknownIssuer))));
+}
+
+struct ClientCheckCertResolve {
+    query_count: atomic::AtomicUsize,
+    expect_queries: usize
+}
+
+impl ClientCheckCertResolve {
+    fn new(expect_queries: usize) -> ClientCheckCertResolve {
+        ClientCheckCertResolve {
+            query_count: atomic::AtomicUsize::new(0),
+            expect_queries: expect_queries
+        }
+    }
+}
+
+impl Drop for ClientCheckCertResolve {
+    fn drop(&mut self) {
+        let count = self.query_count.load(atomic::Ordering::SeqCst);
+        assert_eq!(count, self.expect_queries);
+    }
+}
+
+impl ResolvesClientCert for ClientCheckCertResolve {
+    fn resolve(&self,
+               acceptable_issuers: &[&[u8]],
+               sigschemes: &[SignatureScheme])
+        -> Option<sign::CertifiedKey> {
+        self.query_count.fetch_add(1, atomic::Ordering::SeqCst);
+
+        if acceptable_issuers.len() == 0 {
+            panic!("no issuers offered by server");
+        }
+
+        if sigschemes.len() == 0 {
+            panic!("no signature schemes shared by server");
+        }
+
+        None
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn client_cert_resolve() {
+    let mut client_config = make_client_config();
+    let server_config = make_server_config_with_mandatory_client_auth();
+
+    client_config.client_auth_cert_resolver = Arc::new(ClientCheckCertResolve::new(1));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(TLSErrorFromPeer::Server(TLSError::NoCertificatesPresented)));
+}
+
+#[test]
+fn client_error_is_sticky() {
+    let client_config = make_client_config();
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    client.read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref()).unwrap();
+    let mut err = client.process_new_packets();
+    assert_eq!(err.is_err(), true);
+    err = client.process_new_packets();
+    assert_eq!(err.is_err(), true);
+}
+
+#[test]
+fn server_error_is_sticky() {
+    let server_config = make_server_config();
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    server.read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref()).unwrap();
+    let mut err = server.process_new_packets();
+    assert_eq!(err.is_err(), true);
+    err = server.process_new_packets();
+    assert_eq!(err.is_err(), true);
+}
+
+#[test]
+fn server_is_send_and_sync() {
+    let server_config = make_server_config();
+    let server = ServerSession::new(&Arc::new(server_config));
+    &server as &Send;
+    &server as &Sync;
+}
+
+#[test]
+fn client_is_send_and_sync() {
+    let client_config = make_client_config();
+    let client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    &client as &Send;
+    &client as &Sync;
+}
+
+#[test]
+fn server_respects_buffer_limit_pre_handshake() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    server.set_buffer_limit(32);
 
     assert_eq!(server.write(b"01234567890123456789").unwrap(), 20);
     assert_eq!(server.write(b"01234567890123456789").unwrap(), 12);
@@ -631,6 +1402,36 @@ fn client_respects_buffer_limit_post_handshake() {
     check_read(&mut server, b"01234567890123456789012345");
 }
 
+#[test]
+fn write_would_block_when_buffer_full() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    server.set_buffer_limit(16);
+    assert_eq!(server.write(b"0123456789012345").unwrap(), 16);
+    assert_eq!(server.write(b"more").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(server.write(b"").unwrap(), 0);
+
+    server.set_buffer_limit(0);
+    assert_eq!(server.write(b"more").unwrap(), 4);
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"0123456789012345more");
+}
+
+#[test]
+fn default_buffer_limit() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+
+    let data = vec![0u8; 1024 * 1024];
+    for _ in 0..4 {
+        assert_eq!(client.write(&data).unwrap(), data.len());
+    }
+    assert_eq!(client.write(b"x").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+}
+
 struct OtherSession<'a> {
     sess: &'a mut Session,
     pub reads: usize,
@@ -843,64 +1644,607 @@ fn server_stream_read() {
 }
 
 #[test]
-fn server_config_is_clone() {
-    make_server_config().clone();
-}
+fn client_owned_stream() {
+    let client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
 
-#[test]
-fn client_config_is_clone() {
-    make_client_config().clone();
-}
+    server.write_all(b"world").unwrap();
 
-#[test]
-fn client_session_is_debug() {
-    let client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
-    println!("{:?}", client);
+    {
+        let pipe = OtherSession::new(&mut server);
+        let mut stream: TlsClientStream<_> = TlsStream::new(client, pipe);
+        assert_eq!(stream.write(b"hello").unwrap(), 5);
+        check_read(&mut stream, b"world");
+        assert!(!stream.session().is_handshaking());
+    }
+    check_read(&mut server, b"hello");
 }
 
 #[test]
-fn server_session_is_debug() {
+fn server_owned_stream() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
     let server = ServerSession::new(&Arc::new(make_server_config()));
-    println!("{:?}", server);
+
+    client.write_all(b"world").unwrap();
+
+    let (server, _) = {
+        let pipe = OtherSession::new(&mut client);
+        let mut stream: TlsServerStream<_> = TlsStream::new(server, pipe);
+        check_read(&mut stream, b"world");
+        assert_eq!(stream.write(b"hello").unwrap(), 5);
+        stream.into_inner()
+    };
+    assert!(!server.is_handshaking());
+    check_read(&mut client, b"hello");
 }
 
 #[test]
-fn server_complete_io_for_handshake_ending_with_alert() {
-    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
-    let mut server_config = make_server_config();
-    server_config.ciphersuites = vec![];
-    let mut server = ServerSession::new(&Arc::new(server_config));
+fn metered_stream_counts_plaintext() {
+    let client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
 
-    assert_eq!(true, server.is_handshaking());
+    server.write_all(b"world").unwrap();
 
-    let mut pipe = OtherSession::new_fails(&mut client);
-    let rc = server.complete_io(&mut pipe);
-    assert!(rc.is_err(),
-            "server io failed due to handshake failure");
-    assert!(!server.wants_write(),
-            "but server did send its alert");
-    assert_eq!(format!("{:?}", pipe.last_error),
-               "Some(AlertReceived(HandshakeFailure))",
-               "which was received by client");
+    {
+        let pipe = OtherSession::new(&mut server);
+        let stream = TlsStream::new(client, pipe);
+        let mut stream = MeteredTlsStream::new(stream, AtomicMeter::new());
+        assert_eq!(stream.write(b"hello").unwrap(), 5);
+        assert_eq!(stream.write(b" there").unwrap(), 6);
+        check_read(&mut stream, b"world");
+        assert_eq!(stream.get_meter().bytes_written(), 11);
+        assert_eq!(stream.get_meter().bytes_read(), 5);
+    }
+    check_read(&mut server, b"hello there");
 }
 
 #[test]
-fn server_exposes_offered_sni() {
-    let mut client = ClientSession::new(&Arc::new(make_client_config()),
-                                        dns_name("second.testserver.com"));
+fn client_connect() {
+    let client_config = Arc::new(make_client_config());
     let mut server = ServerSession::new(&Arc::new(make_server_config()));
 
-    assert_eq!(None, server.get_sni_hostname());
-    do_handshake(&mut client, &mut server);
-    assert_eq!(Some("second.testserver.com"), server.get_sni_hostname());
+    {
+        let pipe = OtherSession::new(&mut server);
+        let mut stream = rustls::connect(&client_config, dns_name("localhost"), pipe).unwrap();
+        assert!(!stream.session().is_handshaking());
+        stream.write_all(b"hello").unwrap();
+    }
+    assert!(!server.is_handshaking());
+    check_read(&mut server, b"hello");
 }
 
 #[test]
-fn sni_resolver_works() {
-    let mut resolver = rustls::ResolvesServerCertUsingSNI::new();
-    let signing_key = sign::RSASigningKey::new(&get_key())
+fn client_connect_error() {
+    let client_config = Arc::new(make_client_config());
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    let pipe = OtherSession::new_fails(&mut server);
+    let err = rustls::connect(&client_config, dns_name("example.com"), pipe)
+        .err()
         .unwrap();
-    let signing_key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(signing_key));
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.get_ref().unwrap().downcast_ref::<TLSError>(),
+               Some(&TLSError::WebPKIError(webpki::Error::CertNotValidForName)));
+}
+
+#[test]
+fn tls_acceptor() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let acceptor = rustls::TlsAcceptor::new(listener, &Arc::new(make_server_config()), 8);
+    let addr = acceptor.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let sock = net::TcpStream::connect(addr).unwrap();
+        let mut stream = rustls::connect(&Arc::new(make_client_config()),
+                                         dns_name("localhost"), sock)
+            .unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    });
+
+    let mut stream = acceptor.accept().unwrap();
+    assert!(!stream.session().is_handshaking());
+    assert_eq!(acceptor.pending_handshakes(), 0);
+    stream.write_all(b"hello").unwrap();
+    assert_eq!(&client.join().unwrap(), b"hello");
+}
+
+#[test]
+fn tls_acceptor_limits_pending_handshakes() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let acceptor = Arc::new(rustls::TlsAcceptor::new(listener,
+                                                     &Arc::new(make_server_config()), 1));
+    let addr = acceptor.local_addr().unwrap();
+
+    // A client which connects, but never sends its ClientHello.
+    let idle = net::TcpStream::connect(addr).unwrap();
+    let pending = {
+        let acceptor = acceptor.clone();
+        thread::spawn(move || acceptor.accept().is_err())
+    };
+    while acceptor.pending_handshakes() == 0 {
+        thread::yield_now();
+    }
+
+    let _rejected = net::TcpStream::connect(addr).unwrap();
+    let err = acceptor.accept().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.get_ref().unwrap().downcast_ref::<TLSError>(),
+               Some(&TLSError::TooManyHandshakes));
+
+    drop(idle);
+    assert!(pending.join().unwrap());
+    assert_eq!(acceptor.pending_handshakes(), 0);
+}
+
+#[test]
+fn tls_acceptor_times_out_handshakes() {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut acceptor = rustls::TlsAcceptor::new(listener, &Arc::new(make_server_config()), 8);
+    acceptor.set_handshake_timeout(Some(time::Duration::from_millis(100)));
+    let addr = acceptor.local_addr().unwrap();
+
+    // A client which connects, but never sends its ClientHello.
+    let _idle = net::TcpStream::connect(addr).unwrap();
+    let err = acceptor.accept().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(acceptor.pending_handshakes(), 0);
+}
+
+#[test]
+fn server_accept() {
+    let server_config = Arc::new(make_server_config());
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+
+    let server = {
+        let pipe = OtherSession::new(&mut client);
+        let mut stream = rustls::accept(&server_config, pipe).unwrap();
+        assert!(!stream.session().is_handshaking());
+        stream.write_all(b"hello").unwrap();
+        stream.into_inner().0
+    };
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut client, b"hello");
+}
+
+#[test]
+fn server_accept_eof() {
+    let server_config = Arc::new(make_server_config());
+    let err = rustls::accept(&server_config, io::Cursor::new(Vec::new()))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn server_on_connect_hook() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut server_config = make_server_config();
+    {
+        let seen = seen.clone();
+        server_config.set_on_connect(move |info| seen.lock().unwrap().push(info));
+    }
+    let server_config = Arc::new(server_config);
+    assert!(seen.lock().unwrap().is_empty());
+
+    let before = time::SystemTime::now();
+    let _server = ServerSession::new(&server_config);
+    let addr: Arc<std::any::Any + Send + Sync> = Arc::new("192.0.2.1:443".to_string());
+    let _server = ServerSession::new_with_user_data(&server_config, addr);
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.iter().map(|info| info.connection_id).collect::<Vec<_>>(), vec![0, 1]);
+    assert!(seen[0].accepted_at >= before);
+    assert!(seen[0].user_data.downcast_ref::<()>().is_some());
+    assert_eq!(seen[1].user_data.downcast_ref::<String>().map(|s| s.as_str()),
+               Some("192.0.2.1:443"));
+}
+
+#[test]
+fn server_config_is_clone() {
+    make_server_config().clone();
+}
+
+#[test]
+fn client_config_is_clone() {
+    make_client_config().clone();
+}
+
+#[test]
+fn client_session_is_debug() {
+    let client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    println!("{:?}", client);
+}
+
+#[test]
+fn server_session_is_debug() {
+    let server = ServerSession::new(&Arc::new(make_server_config()));
+    println!("{:?}", server);
+}
+
+fn send_and_check(from: &mut Session, to: &mut Session, data: &[u8]) {
+    from.write_all(data).unwrap();
+    transfer(from, to);
+    to.process_new_packets().unwrap();
+    check_read(to, data);
+}
+
+#[derive(Default)]
+struct RecordingInspector {
+    written: Mutex<Vec<u8>>,
+    read: Mutex<Vec<u8>>,
+}
+
+impl InspectorMiddleware for RecordingInspector {
+    fn on_plaintext_write(&self, data: &[u8]) {
+        self.written.lock().unwrap().extend_from_slice(data);
+    }
+
+    fn on_plaintext_read(&self, data: &[u8]) {
+        self.read.lock().unwrap().extend_from_slice(data);
+    }
+}
+
+#[test]
+fn inspector_sees_plaintext() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let server = ServerSession::new(&Arc::new(make_server_config()));
+    let mut server = SessionWithInspector::new(server, RecordingInspector::default());
+    do_handshake(&mut client, server.get_mut());
+    assert!(server.get_inspector().written.lock().unwrap().is_empty());
+    assert!(server.get_inspector().read.lock().unwrap().is_empty());
+
+    send_and_check(&mut client, &mut server, b"hello");
+    send_and_check(&mut client, &mut server, b" world");
+    send_and_check(&mut server, &mut client, b"goodbye");
+
+    let (_, inspector) = server.into_inner();
+    assert_eq!(*inspector.read.lock().unwrap(), b"hello world".to_vec());
+    assert_eq!(*inspector.written.lock().unwrap(), b"goodbye".to_vec());
+}
+
+fn checkpoint_test(version: ProtocolVersion, enable_tickets: bool) {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ version ];
+    client_config.enable_tickets = enable_tickets;
+    client_config.set_protocols(&["foo".to_string()]);
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config();
+    server_config.set_protocols(&["foo".to_string()]);
+    let server_config = Arc::new(server_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    send_and_check(&mut client, &mut server, b"hello");
+    send_and_check(&mut server, &mut client, b"world");
+
+    let checkpoint = server.checkpoint().unwrap();
+    let checkpoint = SessionCheckpoint::from_bytes(checkpoint.as_bytes().to_vec());
+    drop(server);
+
+    let mut server = ServerSession::restore(&server_config, &checkpoint).unwrap();
+    assert!(!server.is_handshaking());
+    assert_eq!(server.get_protocol_version(), Some(version));
+    assert_eq!(server.get_alpn_protocol(), Some("foo"));
+    assert_eq!(server.get_sni_hostname(), Some("localhost"));
+
+    send_and_check(&mut client, &mut server, b"hello again");
+    send_and_check(&mut server, &mut client, b"world again");
+
+    server.write_all(b"bye").unwrap();
+    server.send_close_notify();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read_and_close(&mut client, b"bye");
+}
+
+#[test]
+fn server_checkpoint_and_restore_tls12() {
+    checkpoint_test(ProtocolVersion::TLSv1_2, true);
+    checkpoint_test(ProtocolVersion::TLSv1_2, false);
+}
+
+#[test]
+fn server_checkpoint_and_restore_tls13() {
+    checkpoint_test(ProtocolVersion::TLSv1_3, true);
+    // No tickets, so no resumption_master_secret.
+    checkpoint_test(ProtocolVersion::TLSv1_3, false);
+}
+
+#[test]
+fn server_checkpoint_needs_idle_session() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert_eq!(server.checkpoint().unwrap_err(), TLSError::HandshakeNotComplete);
+
+    do_handshake(&mut client, &mut server);
+    let buffered = TLSError::General("cannot checkpoint a session with buffered data".to_string());
+
+    // unprocessed records
+    client.write_all(b"hello").unwrap();
+    transfer(&mut client, &mut server);
+    assert_eq!(server.checkpoint().unwrap_err(), buffered);
+
+    // unread plaintext
+    server.process_new_packets().unwrap();
+    assert_eq!(server.checkpoint().unwrap_err(), buffered);
+    check_read(&mut server, b"hello");
+
+    // unwritten records
+    server.write_all(b"world").unwrap();
+    assert_eq!(server.checkpoint().unwrap_err(), buffered);
+    transfer(&mut server, &mut client);
+
+    assert!(server.checkpoint().is_ok());
+}
+
+#[test]
+fn server_restore_rejects_bad_checkpoints() {
+    let server_config = Arc::new(make_server_config());
+    let invalid = TLSError::General("invalid session checkpoint".to_string());
+    assert_eq!(ServerSession::restore(&server_config,
+                                      &SessionCheckpoint::from_bytes(vec![])).unwrap_err(),
+               invalid);
+
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    let checkpoint = server.checkpoint().unwrap();
+
+    let mut truncated = checkpoint.as_bytes().to_vec();
+    truncated.truncate(20);
+    assert_eq!(ServerSession::restore(&server_config,
+                                      &SessionCheckpoint::from_bytes(truncated)).unwrap_err(),
+               invalid);
+
+    // TLS1.3 secrets must be as long as the suite's hash.
+    let mut value = ServerCheckpointValue::read_bytes(checkpoint.as_bytes()).unwrap();
+    assert_eq!(value.version, ProtocolVersion::TLSv1_3);
+    value.secrets[0].0.pop();
+    assert_eq!(ServerSession::restore(&server_config,
+                                      &SessionCheckpoint::from_bytes(value.get_encoding()))
+                   .unwrap_err(),
+               invalid);
+
+    // TLS1.3 isn't enabled here.
+    let mut tls12_config = make_server_config();
+    tls12_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    assert!(ServerSession::restore(&Arc::new(tls12_config), &checkpoint).is_err());
+}
+
+fn cluster_ticketer(keys: &TicketKeySet) -> ClusterTicketRotator {
+    let keys = TicketKeySet::from_bytes(&keys.to_bytes()).unwrap();
+    ClusterTicketRotator::new(Arc::new(RwLock::new(keys)), 3600)
+}
+
+#[test]
+fn server_export_and_import_session() {
+    let keys = TicketKeySet::new(TicketKey::generate(1));
+    let server_config = Arc::new(make_server_config());
+
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    send_and_check(&mut client, &mut server, b"hello");
+
+    let checkpoint = server.checkpoint().unwrap();
+    let exported = server.export_session(&cluster_ticketer(&keys)).unwrap();
+    let exported = ExportedSession::from_bytes(exported.as_bytes().to_vec());
+    assert_eq!(format!("{:?}", exported), "ExportedSession");
+    assert!(!exported.as_bytes()
+            .windows(checkpoint.as_bytes().len())
+            .any(|w| w == checkpoint.as_bytes()));
+
+    // Another server without the cluster key can't use it.
+    assert_eq!(ServerSession::import_session(&server_config, &*Ticketer::new(), &exported)
+                   .unwrap_err(),
+               TLSError::General("cannot decrypt exported session".to_string()));
+
+    let mut server = ServerSession::import_session(&server_config,
+                                                   &cluster_ticketer(&keys),
+                                                   &exported).unwrap();
+    send_and_check(&mut client, &mut server, b"hello again");
+    send_and_check(&mut server, &mut client, b"world");
+
+    // It can only be imported once.
+    assert_eq!(ServerSession::import_session(&server_config, &cluster_ticketer(&keys), &exported)
+                   .unwrap_err(),
+               TLSError::General("exported session already imported".to_string()));
+
+    let mut no_storage = make_server_config();
+    no_storage.session_storage = Arc::new(rustls::NoServerSessionStorage {});
+    assert_eq!(ServerSession::import_session(&Arc::new(no_storage),
+                                             &cluster_ticketer(&keys),
+                                             &exported)
+                   .unwrap_err(),
+               TLSError::General("cannot record imported session".to_string()));
+}
+
+#[test]
+fn only_imported_sessions_keep_client_certs() {
+    let keys = TicketKeySet::new(TicketKey::generate(1));
+    let server_config = Arc::new(make_server_config_with_mandatory_client_auth());
+    let mut client_config = make_client_config();
+    client_config.set_single_client_cert(get_chain(), get_key());
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(server.get_verified_client_cert_chain().is_some());
+
+    // Anyone can make a checkpoint, so it can't vouch for the client.
+    let checkpoint = SessionCheckpoint::from_bytes(server.checkpoint().unwrap().as_bytes().to_vec());
+    let restored = ServerSession::restore(&server_config, &checkpoint).unwrap();
+    assert!(restored.get_verified_client_cert_chain().is_none());
+
+    let exported = server.export_session(&cluster_ticketer(&keys)).unwrap();
+    let imported = ServerSession::import_session(&server_config,
+                                                 &cluster_ticketer(&keys),
+                                                 &exported).unwrap();
+    assert_eq!(imported.get_verified_client_cert_chain().unwrap().certificates(),
+               &get_chain()[..]);
+
+    // A server not doing client auth doesn't get them.
+    let imported = ServerSession::import_session(&Arc::new(make_server_config()),
+                                                 &cluster_ticketer(&keys),
+                                                 &exported).unwrap();
+    assert!(imported.get_verified_client_cert_chain().is_none());
+}
+
+#[test]
+fn server_checkpoint_is_debug() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(format!("{:?}", server.checkpoint().unwrap()), "SessionCheckpoint");
+}
+
+#[test]
+fn server_connection_ids_differ() {
+    let server_config = Arc::new(make_server_config());
+    let first = ServerSession::new(&server_config).connection_id();
+    let second = ServerSession::new(&server_config).connection_id();
+    assert!(second > first);
+
+    let id = rustls::ConnectionId(0x2a);
+    assert_eq!(id.to_string(), "000000000000002a");
+    assert_eq!(format!("{:?}", id), "ConnectionId(42)");
+}
+
+#[test]
+fn server_limits_concurrent_handshakes() {
+    let mut server_config = make_server_config();
+    server_config.max_concurrent_handshakes = Some(2);
+    let server_config = Arc::new(server_config);
+    let client_config = Arc::new(make_client_config());
+    let handshakes = server_config.handshakes.clone();
+
+    let mut server1 = ServerSession::try_new(&server_config).unwrap();
+    let server2 = ServerSession::try_new(&server_config).unwrap();
+    assert_eq!(handshakes.in_progress(), 2);
+    assert_eq!(ServerSession::try_new(&server_config).unwrap_err(),
+               TLSError::TooManyHandshakes);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server3 = ServerSession::new(&server_config);
+    transfer(&mut client, &mut server3);
+    assert_eq!(server3.process_new_packets(), Err(TLSError::TooManyHandshakes));
+    assert_eq!(handshakes.in_progress(), 2);
+
+    // completing a handshake frees its place
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    do_handshake(&mut client, &mut server1);
+    assert_eq!(handshakes.in_progress(), 1);
+
+    // as does dropping the session
+    drop(server2);
+    assert_eq!(handshakes.in_progress(), 0);
+
+    // and failing the handshake
+    let mut server4 = ServerSession::try_new(&server_config).unwrap();
+    assert_eq!(handshakes.in_progress(), 1);
+    server4.read_tls(&mut io::Cursor::new(b"\x16\x03\x01\x00\x04\x01\x00\x00\x00")).unwrap();
+    assert!(server4.process_new_packets().is_err());
+    assert_eq!(handshakes.in_progress(), 0);
+}
+
+#[test]
+fn server_complete_io_for_handshake_ending_with_alert() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server_config = make_server_config();
+    server_config.ciphersuites = vec![];
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    assert_eq!(true, server.is_handshaking());
+
+    let mut pipe = OtherSession::new_fails(&mut client);
+    let rc = server.complete_io(&mut pipe);
+    assert!(rc.is_err(),
+            "server io failed due to handshake failure");
+    assert!(!server.wants_write(),
+            "but server did send its alert");
+    assert_eq!(format!("{:?}", pipe.last_error),
+               "Some(AlertReceived(HandshakeFailure))",
+               "which was received by client");
+}
+
+#[test]
+fn server_exposes_offered_sni() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()),
+                                        dns_name("second.testserver.com"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    assert_eq!(None, server.get_sni_hostname());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("second.testserver.com"), server.get_sni_hostname());
+}
+
+#[test]
+fn ecdsa_server_key() {
+    let chain = pemfile::certs(&mut io::BufReader::new(
+            fs::File::open("test-ca/ecdsa/end.fullchain").unwrap()))
+        .unwrap();
+    let key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+            fs::File::open("test-ca/ecdsa/end.key").unwrap()))
+        .unwrap()
+        .remove(0);
+    let signing_key = sign::ECDSASigningKey::new(&key)
+        .unwrap();
+    let signing_key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(signing_key));
+    let mut resolver = rustls::ResolvesServerCertUsingSNI::new();
+    resolver.add("localhost", sign::CertifiedKey::new(chain, signing_key))
+        .unwrap();
+
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        let mut client_config = ClientConfig::new();
+        let mut rootbuf = io::BufReader::new(fs::File::open("test-ca/ecdsa/ca.cert").unwrap());
+        client_config.root_store.add_pem_file(&mut rootbuf).unwrap();
+        client_config.versions = vec![*version];
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_protocol_version(), Some(*version));
+    }
+}
+
+#[cfg(feature = "dev")]
+#[test]
+#[allow(deprecated)]
+fn dev_cert_resolver() {
+    let resolver = Arc::new(rustls::DevCertResolver::new());
+
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+
+    for name in &["localhost", "dev.testserver.com"] {
+        let mut client_config = ClientConfig::new();
+        client_config.root_store.add(&resolver.get_cert(name).unwrap().cert[0]).unwrap();
+        let client_config = Arc::new(client_config);
+
+        let mut client = ClientSession::new(&client_config, dns_name(name));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_peer_certificates().unwrap(),
+                   resolver.get_cert(name).unwrap().cert);
+    }
+}
+
+#[test]
+fn sni_resolver_works() {
+    let mut resolver = rustls::ResolvesServerCertUsingSNI::new();
+    let signing_key = sign::RSASigningKey::new(&get_key())
+        .unwrap();
+    let signing_key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(signing_key));
     resolver.add("localhost",
                  sign::CertifiedKey::new(get_chain(), signing_key.clone()))
         .unwrap();
@@ -1121,3 +2465,1267 @@ fn negotiated_ciphersuite_server() {
                       version);
     }
 }
+
+#[test]
+fn tls13_resumption_with_max_early_data_size() {
+    let ticketer = Arc::new(CountingTicketer {
+        inner: rustls::Ticketer::new(),
+        decrypts: atomic::AtomicUsize::new(0),
+    });
+    let mut server_config = make_server_config();
+    server_config.ticketer = ticketer.clone();
+    server_config.max_early_data_size = Some(1024);
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    let client_config = Arc::new(client_config);
+
+    for _ in 0..2 {
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+
+        server.write_all(b"hello").unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        check_read(&mut client, b"hello");
+    }
+
+    // The second session resumed.
+    assert_eq!(ticketer.decrypts.load(atomic::Ordering::SeqCst), 1);
+}
+
+/// The length of the record carrying a NewSessionTicket issued
+/// after a TLS1.3 handshake with `server_config`.
+fn tls13_ticket_record_len(server_config: ServerConfig) -> usize {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    server.generate_session_ticket().unwrap();
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    buf.len()
+}
+
+#[test]
+fn tls13_ticket_carries_max_early_data_size() {
+    let mut server_config = make_server_config();
+    server_config.ticketer = rustls::Ticketer::new();
+    let without = tls13_ticket_record_len(server_config);
+
+    let mut server_config = make_server_config();
+    server_config.ticketer = rustls::Ticketer::new();
+    server_config.max_early_data_size = Some(1024);
+    let with = tls13_ticket_record_len(server_config);
+
+    // The ticket is encrypted, so all we can see is that it got
+    // longer by exactly one early_data extension.
+    let ext = NewSessionTicketExtension::EarlyData(1024).get_encoding();
+    assert_eq!(with, without + ext.len());
+}
+
+/// Start a TLS1.3 handshake whose ClientHello offers 0-RTT data,
+/// then send the server opaque early data records carrying
+/// `plaintext_lens` bytes of application data each.
+fn send_tls13_early_data(plaintext_lens: &[usize]) -> Result<(), TLSError> {
+    let mut server_config = make_server_config();
+    server_config.max_early_data_size = Some(1024);
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        ch.extensions.push(ClientExtension::Unknown(UnknownExtension {
+            typ: ExtensionType::EarlyData,
+            payload: Payload::new(Vec::new()),
+        }));
+    });
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+
+    for len in plaintext_lens {
+        // Inner content type and AEAD tag.
+        let len = len + 1 + 16;
+        let mut record = vec![ 0x17, 0x03, 0x03, (len >> 8) as u8, len as u8 ];
+        record.resize(5 + len, 0xaa);
+        server.read_tls(&mut &record[..]).unwrap();
+        server.process_new_packets()?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tls13_server_skips_early_data_within_limit() {
+    assert_eq!(send_tls13_early_data(&[ 1024 ]), Ok(()));
+    assert_eq!(send_tls13_early_data(&[ 512, 256, 256 ]), Ok(()));
+}
+
+#[test]
+fn tls13_server_rejects_early_data_over_limit() {
+    let err = Err(TLSError::PeerMisbehavedError("client sent too much early data".to_string()));
+    assert_eq!(send_tls13_early_data(&[ 1025 ]), err);
+    assert_eq!(send_tls13_early_data(&[ 512, 256, 256, 1 ]), err);
+}
+
+/// Counts the tickets `inner` successfully decrypts.
+struct CountingTicketer {
+    inner: Arc<rustls::ProducesTickets>,
+    decrypts: atomic::AtomicUsize,
+}
+
+impl rustls::ProducesTickets for CountingTicketer {
+    fn enabled(&self) -> bool { self.inner.enabled() }
+    fn get_lifetime(&self) -> u32 { self.inner.get_lifetime() }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.encrypt_with_aad(plain, &[])
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_with_aad(cipher, &[])
+    }
+
+    fn encrypt_with_aad(&self, plain: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        self.inner.encrypt_with_aad(plain, aad)
+    }
+
+    fn decrypt_with_aad(&self, cipher: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        let plain = self.inner.decrypt_with_aad(cipher, aad);
+        if plain.is_some() {
+            self.decrypts.fetch_add(1, atomic::Ordering::SeqCst);
+        }
+        plain
+    }
+}
+
+#[test]
+fn tickets_bound_to_peer_address() {
+    fn addr(s: &str) -> net::SocketAddr {
+        s.parse().unwrap()
+    }
+
+    // Does a handshake from `peer`, and returns whether it resumed.
+    fn connect(client_config: &Arc<ClientConfig>,
+               server_config: &Arc<ServerConfig>,
+               ticketer: &CountingTicketer,
+               peer: &str) -> bool {
+        let before = ticketer.decrypts.load(atomic::Ordering::SeqCst);
+        let mut client = ClientSession::new(client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(server_config);
+        server.set_peer_address(addr(peer));
+        do_handshake(&mut client, &mut server);
+        ticketer.decrypts.load(atomic::Ordering::SeqCst) > before
+    }
+
+    for &version in &[ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+        for &binding in &[rustls::TicketAddressBinding::None,
+                          rustls::TicketAddressBinding::Exact,
+                          rustls::TicketAddressBinding::Subnet] {
+            let ticketer = Arc::new(CountingTicketer {
+                inner: Ticketer::new(),
+                decrypts: atomic::AtomicUsize::new(0),
+            });
+            let mut server_config = make_server_config();
+            server_config.ticketer = ticketer.clone();
+            server_config.session_storage = Arc::new(rustls::NoServerSessionStorage {});
+            server_config.ticket_address_binding = binding;
+            let server_config = Arc::new(server_config);
+
+            let mut client_config = make_client_config();
+            client_config.versions = vec![version];
+            let client_config = Arc::new(client_config);
+
+            assert!(!connect(&client_config, &server_config, &ticketer, "192.0.2.1:1000"));
+            assert!(connect(&client_config, &server_config, &ticketer, "192.0.2.1:2000"));
+            assert!(connect(&client_config, &server_config, &ticketer, "[::ffff:192.0.2.1]:3000"));
+            assert_eq!(connect(&client_config, &server_config, &ticketer, "192.0.2.2:1000"),
+                       binding != rustls::TicketAddressBinding::Exact);
+            assert_eq!(connect(&client_config, &server_config, &ticketer, "198.51.100.1:1000"),
+                       binding == rustls::TicketAddressBinding::None);
+        }
+    }
+}
+
+/// Implements only the required methods of `ProducesTickets`.
+struct UnboundTicketer(Arc<rustls::ProducesTickets>);
+
+impl rustls::ProducesTickets for UnboundTicketer {
+    fn enabled(&self) -> bool { self.0.enabled() }
+    fn get_lifetime(&self) -> u32 { self.0.get_lifetime() }
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> { self.0.encrypt(plain) }
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> { self.0.decrypt(cipher) }
+}
+
+#[test]
+fn default_ticket_aad_methods_fail_closed() {
+    use rustls::ProducesTickets;
+
+    let ticketer = UnboundTicketer(Ticketer::new());
+    let ticket = ticketer.encrypt_with_aad(b"hello", &[]).unwrap();
+    assert_eq!(ticketer.decrypt_with_aad(&ticket, &[]).unwrap(), b"hello");
+    assert_eq!(ticketer.decrypt_with_aad(&ticket, b"aad"), None);
+    assert_eq!(ticketer.encrypt_with_aad(b"hello", b"aad"), None);
+
+    // So address-bound sessions just don't get tickets.
+    for &binding in &[rustls::TicketAddressBinding::None,
+                      rustls::TicketAddressBinding::Exact] {
+        let ticketer = Arc::new(CountingTicketer {
+            inner: Arc::new(UnboundTicketer(Ticketer::new())),
+            decrypts: atomic::AtomicUsize::new(0),
+        });
+        let mut server_config = make_server_config();
+        server_config.ticketer = ticketer.clone();
+        server_config.session_storage = Arc::new(rustls::NoServerSessionStorage {});
+        server_config.ticket_address_binding = binding;
+        let server_config = Arc::new(server_config);
+        let client_config = Arc::new(make_client_config());
+
+        for _ in 0..2 {
+            let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            server.set_peer_address("192.0.2.1:1000".parse().unwrap());
+            do_handshake(&mut client, &mut server);
+        }
+
+        let resumed = ticketer.decrypts.load(atomic::Ordering::SeqCst) > 0;
+        assert_eq!(resumed, binding == rustls::TicketAddressBinding::None);
+    }
+}
+
+#[derive(Default)]
+struct CountingClientStorage {
+    puts: atomic::AtomicUsize,
+    latest: Mutex<Option<Vec<u8>>>,
+}
+
+impl rustls::StoresClientSessions for CountingClientStorage {
+    fn put(&self, _key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.puts.fetch_add(1, atomic::Ordering::SeqCst);
+        *self.latest.lock().unwrap() = Some(value);
+        true
+    }
+
+    fn get(&self, _key: &[u8]) -> Option<Vec<u8>> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn server_generate_session_ticket() {
+    let ticketer = Arc::new(CountingTicketer {
+        inner: rustls::Ticketer::new(),
+        decrypts: atomic::AtomicUsize::new(0),
+    });
+    let mut server_config = make_server_config();
+    server_config.ticketer = ticketer.clone();
+    let server_config = Arc::new(server_config);
+
+    let storage = Arc::new(CountingClientStorage::default());
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_3];
+    client_config.set_persistence(storage.clone());
+    let client_config = Arc::new(client_config);
+
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(server.generate_session_ticket(), Err(TLSError::HandshakeNotComplete));
+    do_handshake(&mut client, &mut server);
+    let puts = storage.puts.load(atomic::Ordering::SeqCst);
+    let first = storage.latest.lock().unwrap().clone();
+
+    server.generate_session_ticket().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert_eq!(storage.puts.load(atomic::Ordering::SeqCst), puts + 1);
+    assert_ne!(*storage.latest.lock().unwrap(), first);
+
+    // The new ticket works.
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    send_and_check(&mut client, &mut server, b"hello");
+    assert_eq!(ticketer.decrypts.load(atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn server_generate_session_ticket_needs_tls13() {
+    let mut server_config = make_server_config();
+    server_config.ticketer = rustls::Ticketer::new();
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ProtocolVersion::TLSv1_2];
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.generate_session_ticket(), Err(TLSError::WrongTlsVersion));
+}
+
+#[test]
+fn connection_pool_reuses_sessions() {
+    let client_config = Arc::new(make_client_config());
+    let server_config = Arc::new(make_server_config());
+    let mut pool = TlsConnectionPool::new(2);
+
+    assert!(pool.acquire(dns_name("localhost")).is_none());
+
+    // handshaking sessions aren't pooled
+    pool.release(ClientSession::new(&client_config, dns_name("localhost")));
+    assert_eq!(pool.idle_count(dns_name("localhost")), 0);
+
+    let mut servers = vec![];
+    for _ in 0..3 {
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        pool.release(client);
+        servers.push(server);
+    }
+
+    // capped at two per host
+    assert_eq!(pool.idle_count(dns_name("localhost")), 2);
+    assert_eq!(pool.idle_count(dns_name("testserver.com")), 0);
+    assert!(pool.acquire(dns_name("testserver.com")).is_none());
+
+    // most recently released first; the third was dropped
+    let mut client = pool.acquire(dns_name("localhost")).unwrap();
+    assert_eq!(pool.idle_count(dns_name("localhost")), 1);
+    let mut server = servers.remove(1);
+    client.write_all(b"again").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"again");
+
+    // closed sessions are dropped
+    server.send_close_notify();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    pool.release(client);
+    assert_eq!(pool.idle_count(dns_name("localhost")), 1);
+
+    assert!(pool.acquire(dns_name("localhost")).is_some());
+    assert!(pool.acquire(dns_name("localhost")).is_none());
+}
+
+#[test]
+fn standalone_chain_verification() {
+    let ca = fs::read("test-ca/rsa/ca.der").unwrap();
+    let anchors = vec![
+        webpki::trust_anchor_util::cert_der_as_trust_anchor(untrusted::Input::from(&ca))
+            .unwrap()
+    ];
+    let chain = get_chain();
+    let valid_time = time::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000);
+
+    assert_eq!(rustls::verify_certificate_chain(&chain, &anchors, valid_time, None),
+               Ok(()));
+    assert_eq!(rustls::verify_certificate_chain(&chain, &anchors, valid_time,
+                                                Some("testserver.com")),
+               Ok(()));
+    assert_eq!(rustls::verify_certificate_chain(&chain, &anchors, valid_time,
+                                                Some("example.com")),
+               Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName)));
+    assert_eq!(rustls::verify_certificate_chain(&chain, &anchors, valid_time,
+                                                Some("not a name")),
+               Err(TLSError::InvalidDNSName("not a name".to_string())));
+
+    let expired_time = time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000);
+    assert_eq!(rustls::verify_certificate_chain(&chain, &anchors, expired_time, None),
+               Err(TLSError::WebPKIError(webpki::Error::CertExpired)));
+    assert_eq!(rustls::verify_certificate_chain(&chain[..1], &anchors, valid_time, None),
+               Err(TLSError::WebPKIError(webpki::Error::UnknownIssuer)));
+    assert_eq!(rustls::verify_certificate_chain(&[], &anchors, valid_time, None),
+               Err(TLSError::NoCertificatesPresented));
+}
+
+fn client_hello_len(client_config: &Arc<ClientConfig>) -> usize {
+    let mut client = ClientSession::new(client_config, dns_name("localhost"));
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    // one record, containing only the ClientHello
+    assert_eq!(buf.len(), 5 + ((buf[3] as usize) << 8 | buf[4] as usize));
+    buf.len() - 5
+}
+
+#[test]
+fn client_hello_padding() {
+    let unpadded = client_hello_len(&Arc::new(make_client_config()));
+    assert!(unpadded < 512);
+
+    for &target in &[512usize, unpadded + 4, unpadded + 5] {
+        let mut client_config = make_client_config();
+        client_config.set_client_hello_size(target);
+        let client_config = Arc::new(client_config);
+        assert_eq!(client_hello_len(&client_config), target);
+
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        do_handshake(&mut client, &mut server);
+    }
+
+    // too close to pad exactly; we overshoot
+    let mut client_config = make_client_config();
+    client_config.set_client_hello_size(unpadded + 1);
+    assert_eq!(client_hello_len(&Arc::new(client_config)), unpadded + 4);
+
+    // never shrink
+    let mut client_config = make_client_config();
+    client_config.set_client_hello_size(unpadded - 10);
+    assert_eq!(client_hello_len(&Arc::new(client_config)), unpadded);
+
+    // padding goes before a TLS1.3 PSK offer
+    let mut client_config = make_client_config();
+    client_config.set_client_hello_size(1024);
+    let client_config = Arc::new(client_config);
+    let mut server_config = make_server_config();
+    server_config.ticketer = rustls::Ticketer::new();
+    let server_config = Arc::new(server_config);
+    for _ in 0..2 {
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+        // receive the ticket
+        server.write_all(b"hello").unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+    }
+    assert_eq!(client_hello_len(&client_config), 1024);
+}
+
+#[test]
+fn alpn_cert_resolver() {
+    let key = sign::RSASigningKey::new(&get_key()).unwrap();
+    let key: Arc<Box<sign::SigningKey>> = Arc::new(Box::new(key));
+    let mut short_chain = get_chain();
+    short_chain.pop();
+
+    let mut resolver = AlpnResolvesServerCert::new(
+        sign::CertifiedKey::new(get_chain(), key.clone()));
+    resolver.add(b"h2", sign::CertifiedKey::new(short_chain, key.clone())).unwrap();
+    assert!(resolver.add(b"bad", sign::CertifiedKey::new(vec![], key.clone())).is_err());
+
+    let mut server_config = make_server_config();
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    let chain_len = |client_protos: &[&str]| {
+        let mut client_config = make_client_config();
+        client_config.alpn_protocols = client_protos.iter()
+            .map(|proto| proto.to_string())
+            .collect();
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        client.get_peer_certificates().unwrap().len()
+    };
+
+    assert_eq!(chain_len(&[]), 3);
+    assert_eq!(chain_len(&["http/1.1"]), 3);
+    assert_eq!(chain_len(&["h2"]), 2);
+    assert_eq!(chain_len(&["http/1.1", "h2"]), 2);
+}
+
+fn server_hello_extensions(tls: &[u8]) -> (Vec<u8>, Vec<ServerExtension>) {
+    let mut msg = Message::read_bytes(tls).unwrap();
+    assert!(msg.decode_payload());
+    match msg.payload {
+        MessagePayload::Handshake(HandshakeMessagePayload {
+            payload: HandshakePayload::ServerHello(sh), ..
+        }) => (sh.session_id.get_encoding(), sh.extensions),
+        _ => panic!("expected ServerHello"),
+    }
+}
+
+fn edit_client_hello<F>(tls: &[u8], edit: F) -> Vec<u8>
+    where F: FnOnce(&mut ClientHelloPayload)
+{
+    let mut msg = Message::read_bytes(tls).unwrap();
+    assert!(msg.decode_payload());
+    match msg.payload {
+        MessagePayload::Handshake(HandshakeMessagePayload {
+            payload: HandshakePayload::ClientHello(ref mut ch), ..
+        }) => edit(ch),
+        _ => panic!("expected ClientHello"),
+    }
+    msg.get_encoding()
+}
+
+fn server_key_share_group(exts: &[ServerExtension]) -> Option<NamedGroup> {
+    exts.iter()
+        .filter_map(|ext| match *ext {
+            ServerExtension::KeyShare(ref share) => Some(share.group),
+            _ => None,
+        })
+        .next()
+}
+
+#[test]
+fn server_sends_empty_renegotiation_info() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config());
+
+    let mut session_ids = Vec::new();
+    for _ in 0..2 {
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+
+        let mut buf = Vec::new();
+        server.write_tls(&mut buf).unwrap();
+        let (session_id, exts) = server_hello_extensions(&buf);
+        let reneg: Vec<_> = exts.iter()
+            .filter(|ext| ext.get_type() == ExtensionType::RenegotiationInfo)
+            .map(|ext| ext.get_encoding())
+            .collect();
+        assert_eq!(reneg, vec![ vec![ 0xff, 0x01, 0x00, 0x01, 0x00 ] ]);
+        session_ids.push(session_id);
+
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+
+    // second handshake was a resumption; it also carries the extension.
+    assert_eq!(session_ids[0], session_ids[1]);
+}
+
+#[derive(Default)]
+struct CountingServerStorage {
+    generated: atomic::AtomicUsize,
+    puts: atomic::AtomicUsize,
+    gets: atomic::AtomicUsize,
+}
+
+impl CountingServerStorage {
+    fn uses(&self) -> usize {
+        self.generated.load(atomic::Ordering::SeqCst) +
+            self.puts.load(atomic::Ordering::SeqCst) +
+            self.gets.load(atomic::Ordering::SeqCst)
+    }
+}
+
+impl rustls::StoresServerSessions for CountingServerStorage {
+    fn generate(&self) -> SessionID {
+        self.generated.fetch_add(1, atomic::Ordering::SeqCst);
+        SessionID::new(&[ 0x99; 32 ])
+    }
+
+    fn put(&self, _key: Vec<u8>, _value: Vec<u8>) -> bool {
+        self.puts.fetch_add(1, atomic::Ordering::SeqCst);
+        true
+    }
+
+    fn get(&self, _key: &[u8]) -> Option<Vec<u8>> {
+        self.gets.fetch_add(1, atomic::Ordering::SeqCst);
+        None
+    }
+
+    fn clear(&self) {}
+
+    fn iter<'a>(&'a self) -> rustls::StoredSessions<'a> {
+        Box::new(None.into_iter())
+    }
+}
+
+#[test]
+fn tls13_server_does_not_use_session_ids() {
+    let storage = Arc::new(CountingServerStorage::default());
+    let mut server_config = make_server_config();
+    server_config.set_persistence(storage.clone());
+    server_config.ticketer = rustls::Ticketer::new();
+    let server_config = Arc::new(server_config);
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    let client_config = Arc::new(client_config);
+
+    // A legacy session ID from the client is echoed, not looked up.
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| ch.session_id = SessionID::new(&[ 0x42; 32 ]));
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    let (session_id, _) = server_hello_extensions(&buf);
+    assert_eq!(session_id, SessionID::new(&[ 0x42; 32 ]).get_encoding());
+    assert_eq!(storage.uses(), 0);
+
+    // Nor are they used for full or resumed handshakes: the
+    // second of these offers the ticket from the first.
+    for _ in 0..2 {
+        let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+        send_and_check(&mut client, &mut server, b"hello");
+    }
+    assert_eq!(storage.uses(), 0);
+
+    // Whereas TLS1.2 makes one.
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(storage.generated.load(atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn server_rejects_nonempty_renegotiation_info() {
+    let client_config = Arc::new(make_client_config());
+    let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        ch.extensions.push(ClientExtension::RenegotiationInfo(PayloadU8::new(vec![ 1; 12 ])));
+    });
+
+    server.read_tls(&mut &buf[..]).unwrap();
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::PeerMisbehavedError("client sent non-empty renegotiation_info"
+                                                 .to_string())));
+
+    // fatal handshake_failure alert
+    let mut alert = Vec::new();
+    server.write_tls(&mut alert).unwrap();
+    assert_eq!(alert, vec![ 0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28 ]);
+}
+
+#[test]
+fn tls13_handshake_uses_x25519() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    let (_, exts) = server_hello_extensions(&buf);
+    assert_eq!(server_key_share_group(&exts), Some(NamedGroup::X25519));
+
+    client.read_tls(&mut &buf[..]).unwrap();
+    client.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+}
+
+#[test]
+fn tls13_server_prefers_x25519() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    // Offer a P-256 share ahead of the client's X25519 one.  The server
+    // never uses the P-256 share, so its contents don't matter.
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        for ext in &mut ch.extensions {
+            if let ClientExtension::KeyShare(ref mut shares) = *ext {
+                shares.insert(0, KeyShareEntry::new(NamedGroup::secp256r1, &[ 4; 65 ]));
+            }
+        }
+    });
+
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    let (_, exts) = server_hello_extensions(&buf);
+    assert_eq!(server_key_share_group(&exts), Some(NamedGroup::X25519));
+}
+
+#[test]
+fn server_fragments_handshake_to_mtu() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let mut server_config = make_server_config();
+    server_config.set_mtu(&Some(512));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let mut buf = Vec::new();
+    while server.wants_write() {
+        server.write_tls(&mut buf).unwrap();
+    }
+
+    // Every record fits, and the Certificate message needs several.
+    let mut records = 0;
+    let mut handshake = Vec::new();
+    let mut rd = Reader::init(&buf);
+    while let Some(mut msg) = Message::read(&mut rd) {
+        let payload = msg.take_opaque_payload().unwrap().0;
+        assert!(payload.len() + 5 <= 512);
+        handshake.extend_from_slice(&payload);
+        records += 1;
+    }
+    assert!(!rd.any_left());
+    assert!(records > 6);
+
+    let mut rd = Reader::init(&handshake);
+    let mut found_certs = false;
+    while rd.any_left() {
+        let hs = HandshakeMessagePayload::read_version(&mut rd, ProtocolVersion::TLSv1_2)
+            .unwrap();
+        if let HandshakePayload::Certificate(chain) = hs.payload {
+            assert_eq!(chain, get_chain());
+            found_certs = true;
+        }
+    }
+    assert!(found_certs);
+
+    client.read_tls(&mut &buf[..]).unwrap();
+    client.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+    send_and_check(&mut client, &mut server, b"hello");
+}
+
+fn count_records(sess: &mut Session) -> usize {
+    let mut buf = Vec::new();
+    while sess.wants_write() {
+        sess.write_tls(&mut buf).unwrap();
+    }
+
+    let mut records = 0;
+    let mut rd = Reader::init(&buf);
+    while Message::read(&mut rd).is_some() {
+        records += 1;
+    }
+    assert!(!rd.any_left());
+    records
+}
+
+#[test]
+fn max_fragment_length_limits_records() {
+    for version in &[ ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3 ] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![ *version ];
+        client_config.set_max_fragment_length(MaxFragmentLen::Bits9);
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(make_server_config()));
+        do_handshake(&mut client, &mut server);
+
+        server.write_all(&[0u8; 2048]).unwrap();
+        assert_eq!(count_records(&mut server), 4);
+        client.write_all(&[0u8; 2048]).unwrap();
+        assert_eq!(count_records(&mut client), 4);
+    }
+}
+
+#[test]
+fn records_not_limited_without_max_fragment_length() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake(&mut client, &mut server);
+
+    server.write_all(&[0u8; 2048]).unwrap();
+    assert_eq!(count_records(&mut server), 1);
+}
+
+#[test]
+fn server_rejects_invalid_max_fragment_length() {
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        ch.extensions.push(ClientExtension::MaxFragmentLength(MaxFragmentLen::Unknown(5)));
+    });
+
+    server.read_tls(&mut &buf[..]).unwrap();
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::PeerMisbehavedError("client sent invalid max_fragment_length"
+                                                 .to_string())));
+}
+
+#[test]
+fn client_rejects_different_max_fragment_length() {
+    // TLS1.2 only: in TLS1.3 the edit breaks the transcript, so the
+    // client can't decrypt the server's EncryptedExtensions.
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    client_config.set_max_fragment_length(MaxFragmentLen::Bits9);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        for ext in ch.extensions.iter_mut() {
+            if let ClientExtension::MaxFragmentLength(ref mut mfl) = *ext {
+                *mfl = MaxFragmentLen::Bits10;
+            }
+        }
+    });
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+
+    transfer(&mut server, &mut client);
+    assert_eq!(client.process_new_packets(),
+               Err(TLSError::PeerMisbehavedError("server sent different max_fragment_length"
+                                                 .to_string())));
+}
+
+fn server_receives_alert(server: &mut ServerSession, level: u8, desc: u8) -> Result<(), TLSError> {
+    // An alert before the handshake starts.
+    let alert = [ 0x15, 0x03, 0x03, 0x00, 0x02, level, desc ];
+    server.read_tls(&mut &alert[..]).unwrap();
+    server.process_new_packets()
+}
+
+#[test]
+fn server_alerts_terminate_by_default() {
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert_eq!(server_receives_alert(&mut server, 0x02, 0x5a),
+               Err(TLSError::AlertReceived(AlertDescription::UserCanceled)));
+}
+
+#[test]
+fn server_alert_policy() {
+    let mut server_config = make_server_config();
+    server_config.set_alert_policy(AlertDescription::UserCanceled, AlertPolicy::Log);
+    server_config.set_alert_policy(AlertDescription::UserCanceled, AlertPolicy::Ignore);
+    server_config.set_alert_policy(AlertDescription::NoRenegotiation, AlertPolicy::Log);
+    assert_eq!(server_config.alert_policies,
+               vec![ (AlertDescription::UserCanceled, AlertPolicy::Ignore),
+                     (AlertDescription::NoRenegotiation, AlertPolicy::Log) ]);
+    let server_config = Arc::new(server_config);
+
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(server_receives_alert(&mut server, 0x01, 0x5a), Ok(()));
+    assert_eq!(server_receives_alert(&mut server, 0x01, 0x64), Ok(()));
+    do_handshake(&mut client, &mut server);
+    send_and_check(&mut client, &mut server, b"hello");
+
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(server_receives_alert(&mut server, 0x02, 0x28),
+               Err(TLSError::AlertReceived(AlertDescription::HandshakeFailure)));
+
+    // Fatal alerts end the session whatever the policy.
+    let mut server = ServerSession::new(&server_config);
+    assert_eq!(server_receives_alert(&mut server, 0x02, 0x5a),
+               Err(TLSError::AlertReceived(AlertDescription::UserCanceled)));
+}
+
+fn tls12_server_group(server_groups: &[NamedGroup],
+                      client_groups: Option<Vec<NamedGroup>>) -> NamedGroup {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let mut server_config = make_server_config();
+    server_config.set_named_groups(server_groups);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    if let Some(ref groups) = client_groups {
+        buf = edit_client_hello(&buf, |ch| {
+            for ext in &mut ch.extensions {
+                if let ClientExtension::NamedGroups(ref mut offered) = *ext {
+                    *offered = groups.clone();
+                }
+            }
+        });
+    }
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+
+    let mut buf = Vec::new();
+    while server.wants_write() {
+        server.write_tls(&mut buf).unwrap();
+    }
+    let group = server_key_exchange(&buf).params.curve_params.named_group;
+
+    if client_groups.is_none() {
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+
+    group
+}
+
+#[test]
+fn server_named_group_preference() {
+    let client_order = Some(vec![ NamedGroup::secp256r1, NamedGroup::X25519 ]);
+    assert_eq!(tls12_server_group(&[ NamedGroup::X25519, NamedGroup::secp256r1 ],
+                                  client_order.clone()),
+               NamedGroup::X25519);
+    assert_eq!(tls12_server_group(&[ NamedGroup::secp256r1, NamedGroup::X25519 ], None),
+               NamedGroup::secp256r1);
+    assert_eq!(tls12_server_group(&[ NamedGroup::secp384r1 ], None),
+               NamedGroup::secp384r1);
+
+    // unsupported groups are ignored
+    assert_eq!(tls12_server_group(&[ NamedGroup::secp521r1, NamedGroup::secp256r1 ],
+                                  client_order),
+               NamedGroup::secp256r1);
+}
+
+#[test]
+fn server_named_groups_with_no_overlap() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let mut server_config = make_server_config();
+    server_config.set_named_groups(&[ NamedGroup::secp384r1 ]);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let buf = edit_client_hello(&buf, |ch| {
+        for ext in &mut ch.extensions {
+            if let ClientExtension::NamedGroups(ref mut offered) = *ext {
+                *offered = vec![ NamedGroup::X25519 ];
+            }
+        }
+    });
+    server.read_tls(&mut &buf[..]).unwrap();
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::PeerIncompatibleError("no supported group".to_string())));
+}
+
+#[test]
+fn tls13_server_uses_offered_share_over_preference() {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    let mut server_config = make_server_config();
+    server_config.set_named_groups(&[ NamedGroup::secp256r1, NamedGroup::X25519 ]);
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    let mut buf = Vec::new();
+    server.write_tls(&mut buf).unwrap();
+    let (_, exts) = server_hello_extensions(&buf);
+    assert_eq!(server_key_share_group(&exts), Some(NamedGroup::X25519));
+}
+
+fn server_key_exchange(tls: &[u8]) -> ECDHEServerKeyExchange {
+    let mut rd = Reader::init(tls);
+    while let Some(mut msg) = Message::read(&mut rd) {
+        assert!(msg.decode_payload());
+        if let MessagePayload::Handshake(HandshakeMessagePayload {
+            payload: HandshakePayload::ServerKeyExchange(ref skx), ..
+        }) = msg.payload {
+            match skx.unwrap_given_kxa(&KeyExchangeAlgorithm::ECDHE) {
+                Some(ServerKeyExchangePayload::ECDHE(ecdhe)) => return ecdhe,
+                _ => panic!("undecodable ServerKeyExchange"),
+            }
+        }
+    }
+    panic!("no ServerKeyExchange");
+}
+
+#[test]
+fn tls12_ecdhe_uses_fresh_keys() {
+    let server_config = Arc::new(make_server_config());
+
+    for suite in &[CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                   CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                   CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256] {
+        let mut client_config = make_client_config();
+        client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+        client_config.ciphersuites = vec![ find_suite(*suite) ];
+        client_config.enable_tickets = false;
+        client_config.set_persistence(Arc::new(rustls::NoClientSessionStorage {}));
+        let client_config = Arc::new(client_config);
+
+        let mut public_keys = Vec::new();
+        for _ in 0..2 {
+            let mut client = ClientSession::new(&client_config, dns_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+
+            let mut buf = Vec::new();
+            while server.wants_write() {
+                server.write_tls(&mut buf).unwrap();
+            }
+            let skx = server_key_exchange(&buf);
+            assert_eq!(skx.params.curve_params.named_group, NamedGroup::X25519);
+            public_keys.push(skx.params.public.0);
+
+            client.read_tls(&mut &buf[..]).unwrap();
+            client.process_new_packets().unwrap();
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.get_negotiated_ciphersuite(), Some(find_suite(*suite)));
+
+            // and the derived keys agree
+            client.write_all(b"hello").unwrap();
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            check_read(&mut server, b"hello");
+        }
+
+        assert_ne!(public_keys[0], public_keys[1]);
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+fn crl_verifier_test(crl_name: &str) -> Result<(), TLSErrorFromPeer> {
+    let inter = get_chain().remove(1);
+    let mut der = Vec::new();
+    fs::File::open(format!("test-ca/rsa/{}.crl.der", crl_name)).unwrap()
+        .read_to_end(&mut der)
+        .unwrap();
+    let crl = rustls::CertificateRevocationList::from_der(&der, &inter).unwrap();
+
+    let mut client_config = make_client_config();
+    client_config.dangerous()
+        .set_certificate_verifier(Arc::new(rustls::CrlCertVerifier::new(vec![ crl ])));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    do_handshake_until_error(&mut client, &mut server)
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn client_checks_crl() {
+    assert_eq!(crl_verifier_test("inter.empty"), Ok(()));
+    assert_eq!(crl_verifier_test("inter.revoked"),
+               Err(TLSErrorFromPeer::Client(TLSError::CertificateRevoked)));
+    assert_eq!(crl_verifier_test("inter.expired"),
+               Err(TLSErrorFromPeer::Client(TLSError::InvalidCRL("CRL is not current"
+                                                                 .to_string()))));
+}
+
+#[cfg(feature = "dangerous_configuration")]
+struct AcceptAnyServerCert;
+
+#[cfg(feature = "dangerous_configuration")]
+impl rustls::ServerCertVerifier for AcceptAnyServerCert {
+    fn verify_server_cert(&self,
+                          _roots: &RootCertStore,
+                          _presented_certs: &[Certificate],
+                          _dns_name: webpki::DNSNameRef,
+                          _ocsp_response: &[u8]) -> Result<rustls::ServerCertVerified, TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn crl_verifier_rejects_empty_chain() {
+    let verifier = rustls::CrlCertVerifier::wrap(Arc::new(AcceptAnyServerCert), vec![]);
+    let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let result = rustls::ServerCertVerifier::verify_server_cert(&verifier,
+                                                                &RootCertStore::empty(),
+                                                                &[], dns_name, &[]);
+    assert_eq!(result.err(), Some(TLSError::NoCertificatesPresented));
+}
+
+#[cfg(feature = "dangerous_configuration")]
+struct FixedFetcher(Certificate);
+
+#[cfg(feature = "dangerous_configuration")]
+impl rustls::CertFetcher for FixedFetcher {
+    fn fetch(&self, url: &str) -> Option<Certificate> {
+        assert_eq!(url, "http://ponytown.example/inter.cert");
+        Some(self.0.clone())
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+fn path_builder_test(fetcher: Option<FixedFetcher>) -> Result<(), TLSErrorFromPeer> {
+    // The server sends only its end-entity certificate.
+    let end = pemfile::certs(&mut io::BufReader::new(fs::File::open("test-ca/rsa/end-aia.cert")
+                                                     .unwrap()))
+        .unwrap();
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.set_single_cert(end, get_key());
+
+    let mut builder = rustls::PathBuilder::new();
+    if let Some(fetcher) = fetcher {
+        builder.set_fetcher(Arc::new(fetcher));
+    }
+    let mut client_config = make_client_config();
+    client_config.dangerous()
+        .set_certificate_verifier(Arc::new(builder));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake_until_error(&mut client, &mut server)
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn client_fetches_missing_intermediate() {
+    let inter = get_chain().remove(1);
+    assert_eq!(path_builder_test(Some(FixedFetcher(inter))), Ok(()));
+    assert_eq!(path_builder_test(None),
+               Err(TLSErrorFromPeer::Client(TLSError::WebPKIError(webpki::Error::UnknownIssuer))));
+}
+
+#[cfg(feature = "dangerous_configuration")]
+struct CountingSignatureVerifier {
+    calls: Mutex<Vec<&'static str>>,
+    accept: bool,
+}
+
+#[cfg(feature = "dangerous_configuration")]
+impl CountingSignatureVerifier {
+    fn new(accept: bool) -> Arc<CountingSignatureVerifier> {
+        Arc::new(CountingSignatureVerifier { calls: Mutex::new(Vec::new()), accept })
+    }
+
+    fn result(&self, call: &'static str) -> Result<rustls::HandshakeSignatureValid, TLSError> {
+        self.calls.lock().unwrap().push(call);
+        if self.accept {
+            Ok(rustls::HandshakeSignatureValid::assertion())
+        } else {
+            Err(TLSError::General("bad signature".to_string()))
+        }
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+impl rustls::SignatureVerifier for CountingSignatureVerifier {
+    fn verify_tls12_signature(&self,
+                              _message: &[u8],
+                              _cert: &Certificate,
+                              _dss: &DigitallySignedStruct)
+                              -> Result<rustls::HandshakeSignatureValid, TLSError> {
+        self.result("tls12")
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              _cert: &Certificate,
+                              _dss: &DigitallySignedStruct)
+                              -> Result<rustls::HandshakeSignatureValid, TLSError> {
+        assert_eq!(&message[..64], &[0x20u8; 64][..]);
+        self.result("tls13")
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn custom_signature_verifier() {
+    for &(version, name) in &[(ProtocolVersion::TLSv1_2, "tls12"),
+                              (ProtocolVersion::TLSv1_3, "tls13")] {
+        let client_verifier = CountingSignatureVerifier::new(true);
+        let server_verifier = CountingSignatureVerifier::new(true);
+
+        let mut client_config = make_client_config();
+        client_config.versions = vec![version];
+        client_config.set_single_client_cert(get_chain(), get_key());
+        client_config.dangerous()
+            .set_signature_verifier(client_verifier.clone());
+        let mut server_config = make_server_config_with_mandatory_client_auth();
+        server_config.set_signature_verifier(server_verifier.clone());
+
+        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut server = ServerSession::new(&Arc::new(server_config));
+        do_handshake(&mut client, &mut server);
+        assert_eq!(*client_verifier.calls.lock().unwrap(), vec![name]);
+        assert_eq!(*server_verifier.calls.lock().unwrap(), vec![name]);
+    }
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn custom_signature_verifier_rejects() {
+    let mut client_config = make_client_config();
+    client_config.dangerous()
+        .set_signature_verifier(CountingSignatureVerifier::new(false));
+
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(TLSError::General("bad signature".to_string()))));
+}
+
+#[test]
+fn detect_tls_on_client_hello() {
+    let client_config = make_client_config();
+    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut hello = Vec::new();
+    client.write_tls(&mut hello).unwrap();
+
+    assert_eq!(detect_tls(&hello), Some(true));
+    assert_eq!(detect_tls(&hello[..3]), Some(true));
+    assert_eq!(detect_tls(b"GET / HTTP/1.1\r\n"), Some(false));
+    assert_eq!(detect_tls(b"PROXY TCP4 127.0.0.1"), Some(false));
+    assert_eq!(detect_tls(&[0x16, 0x02, 0x00]), Some(false));
+    assert_eq!(detect_tls(&[0x18, 0x03, 0x03]), Some(false));
+
+    // Too short to tell, unless it's already wrong.
+    assert_eq!(detect_tls(&hello[..2]), None);
+    assert_eq!(detect_tls(&hello[..1]), None);
+    assert_eq!(detect_tls(&[]), None);
+    assert_eq!(detect_tls(b"G"), Some(false));
+    assert_eq!(detect_tls(&[0x16, 0x02]), Some(false));
+}
+
+#[cfg(feature = "debug-transcript")]
+#[test]
+fn handshake_transcript() {
+    use rustls::{Direction, HandshakeType};
+
+    let mut client = ClientSession::new(&Arc::new(make_client_config()), dns_name("localhost"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config()));
+    assert!(server.get_handshake_transcript().is_empty());
+
+    // the ClientHello is sent straight away
+    assert_eq!(client.get_handshake_transcript().len(), 1);
+
+    do_handshake(&mut client, &mut server);
+
+    let client_transcript = client.get_handshake_transcript();
+    let server_transcript = server.get_handshake_transcript();
+    assert_eq!(client_transcript.len(), server_transcript.len());
+
+    let first = &client_transcript[0];
+    assert_eq!(first.direction, Direction::Sent);
+    assert_eq!(first.message_type, HandshakeType::ClientHello);
+    assert_eq!(server_transcript[0].direction, Direction::Received);
+    assert_eq!(server_transcript[0].message_type, HandshakeType::ClientHello);
+    assert_eq!(server_transcript[0].length, first.length);
+
+    // each side sees the other's messages, in the same order
+    for (c, s) in client_transcript.iter().zip(server_transcript.iter()) {
+        assert_eq!(c.message_type, s.message_type);
+        assert_ne!(c.direction, s.direction);
+        assert_eq!(c.length, s.length);
+    }
+    assert!(client_transcript.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+    assert_eq!(client_transcript.last().unwrap().message_type, HandshakeType::Finished);
+}